@@ -1,6 +1,6 @@
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
-use filestorage_core::FileStorage;
-use std::time::Duration;
+use filestorage_core::{FileStorage, StorageOptions};
+use std::{num::NonZeroUsize, time::Duration};
 use tempfile::tempdir;
 
 // Helper to generate test data of specific size
@@ -8,6 +8,20 @@ fn generate_data(size: usize) -> Vec<u8> {
     vec![0xAB; size]
 }
 
+// Helper to generate pseudo-random bytes that resemble already-compressed data
+fn generate_incompressible_data(size: usize) -> Vec<u8> {
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    (0..size)
+        .map(|_| {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 56) as u8
+        })
+        .collect()
+}
+
 // Benchmark PUT operations with varying data sizes
 fn bench_put(c: &mut Criterion) {
     let mut group = c.benchmark_group("put");
@@ -102,6 +116,54 @@ fn bench_get(c: &mut Criterion) {
     group.finish();
 }
 
+// Benchmark range reads at small, medium, and large offsets against a full read
+fn bench_get_range(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_range");
+
+    const OBJECT_SIZE: usize = 16 * 1024 * 1024;
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let tmp = tempdir().unwrap();
+    let storage = runtime.block_on(FileStorage::new(tmp.path())).unwrap();
+    runtime
+        .block_on(storage.put("object", &generate_incompressible_data(OBJECT_SIZE)))
+        .unwrap();
+
+    let ranges = vec![
+        ("4KB@start", 0, 4 * 1024),
+        ("4KB@1MB", 1024 * 1024, 4 * 1024),
+        ("4KB@end", OBJECT_SIZE as u64 - 4 * 1024, 4 * 1024),
+        ("1MB@start", 0, 1024 * 1024),
+        ("1MB@8MB", 8 * 1024 * 1024, 1024 * 1024),
+        ("8MB@8MB", 8 * 1024 * 1024, 8 * 1024 * 1024),
+    ];
+
+    for (name, offset, len) in ranges {
+        group.throughput(Throughput::Bytes(len));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(name),
+            &(offset, len),
+            |b, &(offset, len)| {
+                b.to_async(&runtime).iter(|| async {
+                    black_box(
+                        storage
+                            .get_range(black_box("object"), offset, len)
+                            .await
+                            .unwrap(),
+                    )
+                });
+            },
+        );
+    }
+
+    group.throughput(Throughput::Bytes(OBJECT_SIZE as u64));
+    group.bench_function("full", |b| {
+        b.to_async(&runtime)
+            .iter(|| async { black_box(storage.get(black_box("object")).await.unwrap()) });
+    });
+
+    group.finish();
+}
+
 // Benchmark DELETE operations
 fn bench_delete(c: &mut Criterion) {
     let mut group = c.benchmark_group("delete");
@@ -181,14 +243,156 @@ fn bench_round_trip(c: &mut Criterion) {
     group.finish();
 }
 
+// Benchmark PUT -> GET of highly compressible vs incompressible payloads with
+// compression on
+fn bench_compressibility(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compressibility");
+    let size = 1024 * 1024;
+
+    let inputs = vec![
+        ("compressible", generate_data(size)),
+        ("incompressible", generate_incompressible_data(size)),
+    ];
+
+    for (name, data) in inputs {
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &data, |b, data| {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            let tmp = tempdir().unwrap();
+            let options = StorageOptions {
+                compression: true,
+                ..StorageOptions::default()
+            };
+            let storage = runtime
+                .block_on(FileStorage::with_options(tmp.path(), options))
+                .unwrap();
+
+            b.to_async(&runtime).iter(|| async {
+                storage.put(black_box("object"), black_box(data)).await.unwrap();
+                black_box(storage.get(black_box("object")).await.unwrap());
+            });
+        });
+    }
+
+    group.finish();
+}
+
+// Benchmark listing a deep, wide tree with serial and parallel directory walks
+fn bench_walk(c: &mut Criterion) {
+    let mut group = c.benchmark_group("walk");
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let tmp = tempdir().unwrap();
+    let data = generate_data(64);
+    runtime.block_on(async {
+        let storage = FileStorage::new(tmp.path()).await.unwrap();
+        // 4 levels of 4 directories each, with a few objects per leaf
+        for i in 0..(4 * 4 * 4 * 4 * 4) {
+            let key = format!("{}/{}/{}/{}/obj{}", i % 4, i / 4 % 4, i / 16 % 4, i / 64 % 4, i);
+            storage.put(&key, &data).await.unwrap();
+        }
+    });
+
+    for parallelism in [1, 4, 16] {
+        let options = StorageOptions {
+            walk_parallelism: NonZeroUsize::new(parallelism),
+            ..StorageOptions::default()
+        };
+        let storage = runtime
+            .block_on(FileStorage::with_options(tmp.path(), options))
+            .unwrap();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(parallelism),
+            &parallelism,
+            |b, _| {
+                b.to_async(&runtime)
+                    .iter(|| async { black_box(storage.list("").await.unwrap()) });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+// Benchmark writing and deleting a 1000-object batch at different concurrency limits
+fn bench_batch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("batch");
+    group.sample_size(10);
+
+    let data = generate_data(4 * 1024);
+    let keys: Vec<String> = (0..1000).map(|i| format!("{}/obj{}", i % 16, i)).collect();
+    let objects: Vec<(&str, &[u8])> = keys.iter().map(|key| (key.as_str(), &data[..])).collect();
+    let names: Vec<&str> = keys.iter().map(String::as_str).collect();
+
+    for concurrency in [1, 4, 16, 64] {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let tmp = tempdir().unwrap();
+        let options = StorageOptions {
+            batch_concurrency: NonZeroUsize::new(concurrency),
+            ..StorageOptions::default()
+        };
+        let storage = runtime
+            .block_on(FileStorage::with_options(tmp.path(), options))
+            .unwrap();
+        group.throughput(Throughput::Elements(keys.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(concurrency),
+            &concurrency,
+            |b, _| {
+                b.to_async(&runtime).iter(|| async {
+                    storage.put_many(black_box(&objects)).await.unwrap();
+                    black_box(storage.delete_many(black_box(&names)).await.unwrap());
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+// Benchmark repeated PUTs under one deep prefix, whose directories are created once
+// and then remembered, against PUTs that each need a new directory
+fn bench_put_hot_prefix(c: &mut Criterion) {
+    let mut group = c.benchmark_group("put_hot_prefix");
+
+    let data = generate_data(1024);
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let tmp = tempdir().unwrap();
+    let storage = runtime.block_on(FileStorage::new(tmp.path())).unwrap();
+    runtime
+        .block_on(storage.ensure_prefixes(&["a/b/c/d/e/f/g/h"]))
+        .unwrap();
+
+    group.bench_function("hot", |b| {
+        b.to_async(&runtime).iter(|| async {
+            storage
+                .put(black_box("a/b/c/d/e/f/g/h/object.bin"), black_box(&data))
+                .await
+                .unwrap()
+        });
+    });
+
+    let counter = std::sync::atomic::AtomicU64::new(0);
+    group.bench_function("cold", |b| {
+        b.to_async(&runtime).iter(|| async {
+            let n = counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let key = format!("a/b/c/d/e/f/g/{n}/object.bin");
+            storage.put(black_box(&key), black_box(&data)).await.unwrap()
+        });
+    });
+
+    group.finish();
+}
+
 // Configure criterion
 criterion_group! {
     name = benches;
     config = Criterion::default()
         .measurement_time(Duration::from_secs(10))
         .sample_size(50);
-    targets = bench_put, bench_put_nested_keys, bench_get, bench_delete,
-              bench_key_validation, bench_round_trip
+    targets = bench_put, bench_put_nested_keys, bench_put_hot_prefix, bench_get, bench_get_range,
+              bench_delete, bench_key_validation, bench_round_trip, bench_compressibility,
+              bench_walk, bench_batch
 }
 
 criterion_main!(benches);
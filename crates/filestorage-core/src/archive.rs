@@ -0,0 +1,336 @@
+//! Export of stored objects as a tar archive, and import of one.
+
+use std::{
+    future::poll_fn,
+    io::{self, ErrorKind},
+    pin::Pin,
+    task::{Context, Poll},
+    time::UNIX_EPOCH,
+};
+
+use bytes::Bytes;
+use futures_core::Stream;
+use tar::{EntryType, Header, PaxExtensions};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use crate::{FileStorage, PutCondition, StorageError, validate_key};
+
+/// Size of a tar header, and of the blocks entry contents are padded to.
+const BLOCK: usize = 512;
+
+/// Longest name that fits in a tar header; longer keys get a GNU long-name entry.
+const NAME_LEN: usize = 100;
+
+/// Largest GNU long-name or PAX entry an import reads into memory.
+const MAX_EXTENSION_LEN: u64 = 64 * 1024;
+
+/// Size of the chunks imported entries are streamed in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Outcome of [`FileStorage::import_tar`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// Keys of the files stored as objects, in archive order.
+    pub imported: Vec<String>,
+    /// Files left out because their key already held an object.
+    pub skipped: usize,
+}
+
+impl FileStorage {
+    /// Writes the objects whose keys start with `prefix` to `out` as a tar
+    /// archive of regular files named by their keys, and returns how many
+    /// objects it holds.
+    ///
+    /// Objects are streamed one at a time, so the archive is never held in
+    /// memory. Objects deleted while the export runs are left out, and
+    /// attributes such as content types are not exported. If writing fails
+    /// part way, `out` is left holding an archive without its end marker.
+    pub async fn export_tar<W>(&self, prefix: &str, mut out: W) -> Result<usize, StorageError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut exported = 0;
+        for key in self.list_all(prefix).await? {
+            let modified = match self.head(&key).await {
+                Ok(metadata) => metadata.modified,
+                Err(StorageError::NotFound(_)) => continue,
+                Err(err) => return Err(err),
+            };
+            let (mut reader, len) = match self.read_range(&key, 0, u64::MAX).await {
+                Ok(opened) => opened,
+                Err(StorageError::NotFound(_)) => continue,
+                Err(err) => return Err(err),
+            };
+
+            if key.len() > NAME_LEN {
+                let mut long_name = key.clone().into_bytes();
+                long_name.push(0);
+                let header = header(EntryType::GNULongName, b"././@LongLink", long_name.len());
+                out.write_all(header.as_bytes()).await?;
+                out.write_all(&long_name).await?;
+                pad(&mut out, long_name.len() as u64).await?;
+            }
+            let mut header = header(EntryType::Regular, key.as_bytes(), 0);
+            header.set_size(len);
+            header.set_mtime(
+                modified
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |elapsed| elapsed.as_secs()),
+            );
+            header.set_cksum();
+            out.write_all(header.as_bytes()).await?;
+            let copied = tokio::io::copy(&mut reader, &mut out).await?;
+            if copied != len {
+                return Err(StorageError::from(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("`{key}` shrank from {len} to {copied} bytes while being exported"),
+                )));
+            }
+            pad(&mut out, len).await?;
+            exported += 1;
+        }
+        out.write_all(&[0; 2 * BLOCK]).await?;
+        out.flush().await?;
+        Ok(exported)
+    }
+
+    /// Reads a tar archive from `input`, such as one written by
+    /// [`export_tar`](Self::export_tar), and stores each regular file under
+    /// the key named by its path.
+    ///
+    /// Files are streamed into place one at a time through
+    /// [`put_stream`](Self::put_stream). GNU long names and PAX `path` records
+    /// are honoured, a leading `./` is dropped, and directories and other
+    /// entry types are passed over. Unless `overwrite` is set, files whose key
+    /// already exists are skipped, checked under the key's lock so that an
+    /// object created during the import is never replaced. A path that is not
+    /// a valid key fails the import with [`StorageError::InvalidKey`] and a
+    /// damaged or truncated archive with [`StorageError::InvalidArchive`];
+    /// objects imported before the failure are kept.
+    pub async fn import_tar<R>(
+        &self,
+        mut input: R,
+        overwrite: bool,
+    ) -> Result<ImportReport, StorageError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut report = ImportReport::default();
+        let mut next_name: Option<Vec<u8>> = None;
+        let mut block = [0; BLOCK];
+        while read_block(&mut input, &mut block).await? {
+            if block.iter().all(|&byte| byte == 0) {
+                break;
+            }
+            let header = Header::from_byte_slice(&block);
+            if header.cksum().ok() != Some(checksum(&block)) {
+                return Err(invalid_archive("entry header checksum does not match"));
+            }
+            let size = header
+                .entry_size()
+                .map_err(|_| invalid_archive("entry header has an unreadable size"))?;
+            let kind = header.entry_type();
+
+            if kind.is_gnu_longname() || kind.is_pax_local_extensions() {
+                if size > MAX_EXTENSION_LEN {
+                    return Err(invalid_archive("extended header is too long"));
+                }
+                let mut data = vec![0; size as usize];
+                input.read_exact(&mut data).await.map_err(truncated)?;
+                skip_padding(&mut input, size).await?;
+                if kind.is_gnu_longname() {
+                    let len = data
+                        .iter()
+                        .position(|&byte| byte == 0)
+                        .unwrap_or(data.len());
+                    data.truncate(len);
+                    next_name = Some(data);
+                } else {
+                    for extension in PaxExtensions::new(&data) {
+                        let extension =
+                            extension.map_err(|_| invalid_archive("PAX header is malformed"))?;
+                        if extension.key_bytes() == b"path" {
+                            next_name = Some(extension.value_bytes().to_vec());
+                        }
+                    }
+                }
+                continue;
+            }
+
+            let name = next_name
+                .take()
+                .unwrap_or_else(|| header.path_bytes().into_owned());
+            let mut entry = EntryStream::new(&mut input, size);
+            if kind.is_file() {
+                let key = entry_key(&name)?;
+                let condition = match overwrite {
+                    true => PutCondition::Always,
+                    false => PutCondition::IfAbsent,
+                };
+                match self.put_stream_if(&key, &mut entry, &condition).await {
+                    Err(_) if entry.cut_short => {
+                        return Err(invalid_archive("archive ends inside an entry"));
+                    }
+                    // Conflicts can also come from the key's path, such as a
+                    // directory of keys in the way, which must not be skipped.
+                    Err(StorageError::Conflict(_)) if !overwrite && self.exists(&key).await? => {
+                        report.skipped += 1;
+                    }
+                    result => {
+                        result?;
+                        report.imported.push(key);
+                    }
+                }
+            }
+            entry.drain().await?;
+            skip_padding(&mut input, size).await?;
+        }
+        Ok(report)
+    }
+}
+
+/// Returns the key a file entry named `name` is imported under.
+fn entry_key(name: &[u8]) -> Result<String, StorageError> {
+    let name = name.strip_prefix(b"./").unwrap_or(name);
+    let key = String::from_utf8(name.to_vec())
+        .map_err(|_| invalid_archive("entry name is not valid UTF-8"))?;
+    validate_key(&key)?;
+    Ok(key)
+}
+
+/// Reads the next header block, returning `false` if `input` ended cleanly
+/// before it.
+async fn read_block<R: AsyncRead + Unpin>(
+    input: &mut R,
+    block: &mut [u8; BLOCK],
+) -> Result<bool, StorageError> {
+    let mut filled = 0;
+    while filled < BLOCK {
+        match input.read(&mut block[filled..]).await? {
+            0 if filled == 0 => return Ok(false),
+            0 => return Err(invalid_archive("archive ends inside an entry header")),
+            read => filled += read,
+        }
+    }
+    Ok(true)
+}
+
+/// Computes a header's checksum, counting its checksum field as spaces.
+fn checksum(block: &[u8; BLOCK]) -> u32 {
+    block
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| if (148..156).contains(&i) { b' ' } else { byte })
+        .map(u32::from)
+        .sum()
+}
+
+/// Consumes the padding after an entry of `len` bytes.
+async fn skip_padding<R: AsyncRead + Unpin>(input: &mut R, len: u64) -> Result<(), StorageError> {
+    let remainder = (len % BLOCK as u64) as usize;
+    if remainder == 0 {
+        return Ok(());
+    }
+    let mut padding = [0; BLOCK];
+    input
+        .read_exact(&mut padding[remainder..])
+        .await
+        .map_err(truncated)?;
+    Ok(())
+}
+
+fn invalid_archive(msg: &str) -> StorageError {
+    StorageError::InvalidArchive(msg.to_string())
+}
+
+/// Maps a failed read of `input`, turning an early end into [`StorageError::InvalidArchive`].
+fn truncated(err: io::Error) -> StorageError {
+    match err.kind() {
+        ErrorKind::UnexpectedEof => invalid_archive("archive ends inside an entry"),
+        _ => StorageError::from(err),
+    }
+}
+
+/// Contents of one archive entry, failing rather than ending early if the
+/// archive is cut short so a truncated file is never stored.
+struct EntryStream<'a, R> {
+    input: &'a mut R,
+    /// Bytes of the entry not yet read.
+    remaining: u64,
+    /// Whether `input` ended before the entry did.
+    cut_short: bool,
+    buf: Box<[u8]>,
+}
+
+impl<'a, R: AsyncRead + Unpin> EntryStream<'a, R> {
+    fn new(input: &'a mut R, len: u64) -> Self {
+        Self {
+            input,
+            remaining: len,
+            cut_short: false,
+            buf: vec![0; CHUNK_SIZE].into_boxed_slice(),
+        }
+    }
+
+    /// Reads and discards whatever is left of the entry.
+    async fn drain(&mut self) -> Result<(), StorageError> {
+        while let Some(chunk) = poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await {
+            chunk.map_err(truncated)?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for EntryStream<'_, R> {
+    type Item = Result<Bytes, io::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.remaining == 0 {
+            return Poll::Ready(None);
+        }
+        let len = this
+            .buf
+            .len()
+            .min(this.remaining.try_into().unwrap_or(usize::MAX));
+        let mut buf = ReadBuf::new(&mut this.buf[..len]);
+        match Pin::new(&mut *this.input).poll_read(cx, &mut buf) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Err(err)) => Poll::Ready(Some(Err(err))),
+            Poll::Ready(Ok(())) if buf.filled().is_empty() => {
+                this.cut_short = true;
+                Poll::Ready(Some(Err(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "archive ends inside an entry",
+                ))))
+            }
+            Poll::Ready(Ok(())) => {
+                let read = buf.filled().len();
+                this.remaining -= read as u64;
+                Poll::Ready(Some(Ok(Bytes::copy_from_slice(buf.filled()))))
+            }
+        }
+    }
+}
+
+/// Builds a header for an entry named by the first [`NAME_LEN`] bytes of `name`.
+fn header(kind: EntryType, name: &[u8], size: usize) -> Header {
+    let mut header = Header::new_gnu();
+    let len = name.len().min(NAME_LEN);
+    header.as_old_mut().name[..len].copy_from_slice(&name[..len]);
+    header.set_entry_type(kind);
+    header.set_mode(0o644);
+    header.set_size(size as u64);
+    header.set_mtime(0);
+    header.set_cksum();
+    header
+}
+
+/// Pads an entry of `len` bytes out to a whole number of blocks.
+async fn pad<W: AsyncWrite + Unpin>(out: &mut W, len: u64) -> io::Result<()> {
+    let remainder = (len % BLOCK as u64) as usize;
+    if remainder == 0 {
+        return Ok(());
+    }
+    out.write_all(&[0; BLOCK][remainder..]).await
+}
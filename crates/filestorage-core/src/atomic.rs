@@ -0,0 +1,115 @@
+//! Temp-file-and-rename helpers that keep readers from observing partial writes.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::RenameStrategy;
+
+/// File name prefix reserved for the store's own bookkeeping files.
+pub(crate) const RESERVED_PREFIX: &str = ".filestorage-";
+
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Returns a unique scratch path in the same directory as `path`.
+pub(crate) fn temp_path_for(path: &Path) -> PathBuf {
+    let n = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!("{RESERVED_PREFIX}tmp-{}-{n}", std::process::id()))
+}
+
+/// Writes `data` to a temp file and renames it over `path`.
+pub(crate) async fn write_atomic(
+    path: &Path,
+    data: &[u8],
+    strategy: RenameStrategy,
+) -> io::Result<()> {
+    let tmp = temp_path_for(path);
+    if let Err(err) = fs::write(&tmp, data).await {
+        let _ = fs::remove_file(&tmp).await;
+        return Err(err);
+    }
+    rename_or_discard(&tmp, path, strategy).await
+}
+
+/// Like [`write_atomic`], but flushes the temp file to disk before the rename.
+pub(crate) async fn write_atomic_synced(
+    path: &Path,
+    data: &[u8],
+    strategy: RenameStrategy,
+) -> io::Result<()> {
+    let tmp = temp_path_for(path);
+    let written = async {
+        let mut file = fs::File::create(&tmp).await?;
+        file.write_all(data).await?;
+        file.sync_all().await
+    }
+    .await;
+    if let Err(err) = written {
+        let _ = fs::remove_file(&tmp).await;
+        return Err(err);
+    }
+    rename_or_discard(&tmp, path, strategy).await
+}
+
+/// Atomically replaces `dst` with the current content of `src`.
+///
+/// Uses a hard link when possible so large objects are not duplicated, and
+/// falls back to a full copy when linking is unsupported.
+pub(crate) async fn copy_atomic(
+    src: &Path,
+    dst: &Path,
+    strategy: RenameStrategy,
+) -> io::Result<()> {
+    let tmp = temp_path_for(dst);
+    link_or_copy(src, &tmp).await?;
+    rename_or_discard(&tmp, dst, strategy).await
+}
+
+/// Hard-links `src` to `dst`, copying instead if linking fails.
+pub(crate) async fn link_or_copy(src: &Path, dst: &Path) -> io::Result<()> {
+    match fs::hard_link(src, dst).await {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Err(err),
+        Err(_) => match fs::copy(src, dst).await {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                let _ = fs::remove_file(dst).await;
+                Err(err)
+            }
+        },
+    }
+}
+
+/// Renames `tmp` to `dst`, removing `tmp` if the rename fails.
+pub(crate) async fn rename_or_discard(
+    tmp: &Path,
+    dst: &Path,
+    strategy: RenameStrategy,
+) -> io::Result<()> {
+    match rename(tmp, dst, strategy).await {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            let _ = fs::remove_file(tmp).await;
+            Err(err)
+        }
+    }
+}
+
+/// Renames `src` over `dst` using `strategy`.
+///
+/// With [`RenameStrategy::Fallback`], `dst` is removed before the rename, so
+/// it is briefly missing and stays missing if the rename then fails.
+pub(crate) async fn rename(src: &Path, dst: &Path, strategy: RenameStrategy) -> io::Result<()> {
+    if strategy == RenameStrategy::Fallback {
+        match fs::remove_file(dst).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+    }
+    fs::rename(src, dst).await
+}
@@ -0,0 +1,223 @@
+//! Reads, copies, and deletes of many keys at once, run a bounded number at a time.
+
+use std::{io, path::Path};
+
+use tokio::{fs, task::JoinSet};
+
+use crate::{
+    FileStorage, Metadata, StorageError, atomic, create_parent, io_error, sidecar::Sidecar,
+};
+
+impl FileStorage {
+    /// Reads several objects, returning their contents in the order of
+    /// `keys`, with `None` for keys that do not exist.
+    ///
+    /// Up to [`StorageOptions::batch_concurrency`](crate::StorageOptions::batch_concurrency)
+    /// objects are read at once.
+    pub async fn get_many(&self, keys: &[&str]) -> Result<Vec<Option<Vec<u8>>>, StorageError> {
+        let reads = keys.iter().map(|key| {
+            let (storage, key) = (self.clone(), key.to_string());
+            async move {
+                match storage.get(&key).await {
+                    Ok(data) => Ok(Some(data)),
+                    Err(StorageError::NotFound(_)) => Ok(None),
+                    Err(err) => Err(err),
+                }
+            }
+        });
+        self.run_bounded(reads).await
+    }
+
+    /// Looks up the metadata of several objects, returning each key with its
+    /// own result in the order of `keys`, so a missing key does not fail the
+    /// rest.
+    ///
+    /// Up to [`StorageOptions::batch_concurrency`](crate::StorageOptions::batch_concurrency)
+    /// objects are looked up at once.
+    pub async fn head_many(
+        &self,
+        keys: &[&str],
+    ) -> Result<Vec<(String, Result<Metadata, StorageError>)>, StorageError> {
+        let heads = keys.iter().map(|key| {
+            let (storage, key) = (self.clone(), key.to_string());
+            async move {
+                let metadata = storage.head(&key).await;
+                Ok((key, metadata))
+            }
+        });
+        self.run_bounded(heads).await
+    }
+
+    /// Deletes several objects as [`delete`](Self::delete) would, skipping
+    /// missing keys, and returns how many were deleted.
+    ///
+    /// Up to [`StorageOptions::batch_concurrency`](crate::StorageOptions::batch_concurrency)
+    /// objects are deleted at once. The deletes are independent, so a failure
+    /// may leave some of the other objects deleted.
+    pub async fn delete_many(&self, keys: &[&str]) -> Result<usize, StorageError> {
+        let deletes = keys.iter().map(|key| {
+            let (storage, key) = (self.clone(), key.to_string());
+            async move {
+                match storage.delete(&key).await {
+                    Ok(()) => Ok(true),
+                    Err(StorageError::NotFound(_)) => Ok(false),
+                    Err(err) => Err(err),
+                }
+            }
+        });
+        let deleted = self.run_bounded(deletes).await?;
+        Ok(deleted.into_iter().filter(|&deleted| deleted).count())
+    }
+
+    /// Copies each `(src, dst)` pair of keys, replacing any object at `dst`
+    /// with a copy of `src` and its attributes.
+    ///
+    /// Every key is validated before anything is copied. Up to
+    /// [`StorageOptions::batch_concurrency`](crate::StorageOptions::batch_concurrency)
+    /// copies run at once, each holding the locks of both its keys. The first
+    /// failure, such as [`StorageError::NotFound`] naming a missing source,
+    /// stops further copies from starting, but copies already made are kept.
+    pub async fn copy_many(&self, pairs: &[(&str, &str)]) -> Result<(), StorageError> {
+        for (src, dst) in pairs {
+            for key in [src, dst] {
+                let path = self.path_for(key)?;
+                self.ensure_within_root(key, &path).await?;
+            }
+        }
+        let copies = pairs.iter().map(|(src, dst)| {
+            let (storage, src, dst) = (self.clone(), src.to_string(), dst.to_string());
+            async move {
+                // Taking the locks in key order keeps overlapping copies from deadlocking.
+                let (first, second) = if src <= dst {
+                    (&src, &dst)
+                } else {
+                    (&dst, &src)
+                };
+                let _first = storage.lock_key(first).await;
+                let _second = if first == second {
+                    None
+                } else {
+                    Some(storage.lock_key(second).await)
+                };
+                storage.copy_routed(&src, &dst).await?;
+                if let Some(replica) = &storage.replica {
+                    let result = replica.copy_routed(&src, &dst).await;
+                    storage.apply_replica_policy(&dst, result)?;
+                }
+                Ok(())
+            }
+        });
+        self.run_bounded(copies).await?;
+        Ok(())
+    }
+
+    /// Copies `src` from whichever tier holds it to `dst` in the tier its
+    /// size calls for, removing any other copy of `dst`.
+    pub(crate) async fn copy_routed(&self, src: &str, dst: &str) -> Result<(), StorageError> {
+        let source = self.holder(src).await?;
+        let src_path = source.path_for(src)?;
+        source.ensure_live(src).await?;
+        let len = fs::metadata(&src_path)
+            .await
+            .map_err(|err| io_error(src, err))?
+            .len();
+        if src == dst {
+            return Ok(());
+        }
+        let tier = self.tier_for_size(len);
+        let target = tier.map_or(self, |i| self.tiers[i].1.as_ref());
+        target.copy_local(&src_path, src, dst, len).await?;
+        self.evict_from_other_tiers(dst, tier).await
+    }
+
+    /// Replaces `dst` with a copy of the `len`-byte object `src` stored at
+    /// `src_path`, which may be in another tier.
+    async fn copy_local(
+        &self,
+        src_path: &Path,
+        src: &str,
+        dst: &str,
+        len: u64,
+    ) -> Result<(), StorageError> {
+        let dst_path = self.path_for(dst)?;
+        self.ensure_within_root(dst, &dst_path).await?;
+        let reservation = self.reserve_quota(dst, &dst_path, |_| len).await?;
+        self.index_insert(dst);
+        create_parent(dst, &dst_path).await?;
+        // A real copy rather than a hard link, so in-place writes to one
+        // object never show through the other.
+        let tmp = atomic::temp_path_for(&dst_path);
+        let copied = async {
+            fs::copy(src_path, &tmp).await?;
+            if self.durability.syncs_files() {
+                fs::File::open(&tmp).await?.sync_all().await?;
+            }
+            Ok(())
+        }
+        .await;
+        if let Err(err) = copied {
+            let _ = fs::remove_file(&tmp).await;
+            return Err(io_error(src, err));
+        }
+        atomic::rename_or_discard(&tmp, &dst_path, self.rename_strategy)
+            .await
+            .map_err(|err| io_error(dst, err))?;
+        if let Some(reservation) = reservation {
+            reservation.settle();
+        }
+        for sidecar in Sidecar::ALL {
+            let contents = sidecar.read(src_path).await?;
+            sidecar
+                .write(&dst_path, contents.as_deref(), self.rename_strategy)
+                .await?;
+        }
+        self.record_key(dst);
+        self.forget_checksum(&dst_path).await?;
+        self.durability.sync_parent(&dst_path).await?;
+        Ok(())
+    }
+
+    /// Runs `tasks`, at most `batch_concurrency` at a time, and returns their
+    /// outputs in order.
+    ///
+    /// After a failure no further tasks are started, but those already running
+    /// are waited for, so none is left behind when the first error is returned.
+    pub(crate) async fn run_bounded<T, F>(
+        &self,
+        tasks: impl IntoIterator<Item = F>,
+    ) -> Result<Vec<T>, StorageError>
+    where
+        F: Future<Output = Result<T, StorageError>> + Send + 'static,
+        T: Send + 'static,
+    {
+        let mut pending = tasks.into_iter().enumerate();
+        let mut running = JoinSet::new();
+        let mut outputs = Vec::new();
+        let mut failure = None;
+        loop {
+            while failure.is_none() && running.len() < self.batch_concurrency {
+                let Some((i, task)) = pending.next() else {
+                    break;
+                };
+                running.spawn(async move { (i, task.await) });
+            }
+            let Some(joined) = running.join_next().await else {
+                break;
+            };
+            match joined {
+                Ok((i, Ok(output))) => outputs.push((i, output)),
+                Ok((_, Err(err))) => {
+                    failure.get_or_insert(err);
+                }
+                Err(err) => {
+                    failure.get_or_insert(StorageError::from(io::Error::other(err)));
+                }
+            }
+        }
+        if let Some(err) = failure {
+            return Err(err);
+        }
+        outputs.sort_unstable_by_key(|(i, _)| *i);
+        Ok(outputs.into_iter().map(|(_, output)| output).collect())
+    }
+}
@@ -0,0 +1,94 @@
+//! In-memory counting bloom filter used to answer lookups for absent keys.
+//!
+//! The filter never reports a present key as absent as long as every write
+//! goes through the store: keys are added before their file appears and only
+//! removed after it is gone. Objects created behind the store's back after the
+//! filter was built are invisible to it.
+
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::BuildHasher,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+/// Counters allotted per expected key, which keeps false positives near 1%.
+const COUNTERS_PER_KEY: usize = 10;
+/// Counters touched per key.
+const HASHES: usize = 7;
+/// Smallest number of keys a filter is sized for.
+const MIN_CAPACITY: usize = 1024;
+
+pub(crate) struct ExistenceIndex {
+    counters: Vec<AtomicU8>,
+    hasher: RandomState,
+}
+
+impl ExistenceIndex {
+    /// Creates an empty filter sized for `capacity` keys.
+    ///
+    /// The filter does not grow; holding more keys only raises the rate of
+    /// false positives.
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        let len = capacity.max(MIN_CAPACITY) * COUNTERS_PER_KEY;
+        Self {
+            counters: (0..len).map(|_| AtomicU8::new(0)).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    pub(crate) fn insert(&self, key: &str) {
+        for index in self.indexes(key) {
+            // Saturated counters stay pinned so they can never undercount.
+            let counter = &self.counters[index];
+            let _ = counter.fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                (count < u8::MAX).then_some(count + 1)
+            });
+        }
+    }
+
+    /// Forgets one earlier [`insert`](Self::insert) of `key`.
+    ///
+    /// A key the filter shows was never inserted, such as an object written
+    /// behind the store's back, is ignored: decrementing its counters would
+    /// take them from keys that share them and hide those keys. Saturated
+    /// counters are never decremented.
+    pub(crate) fn remove(&self, key: &str) {
+        let indexes = self.indexes(key);
+        if indexes
+            .iter()
+            .any(|&index| self.counters[index].load(Ordering::Acquire) == 0)
+        {
+            return;
+        }
+        for index in indexes {
+            let counter = &self.counters[index];
+            let _ = counter.fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                (count > 0 && count < u8::MAX).then(|| count - 1)
+            });
+        }
+    }
+
+    /// Returns `false` only if `key` was definitely never inserted.
+    pub(crate) fn may_contain(&self, key: &str) -> bool {
+        self.indexes(key)
+            .into_iter()
+            .all(|index| self.counters[index].load(Ordering::Acquire) > 0)
+    }
+
+    /// Picks the counters for `key` by double hashing.
+    fn indexes(&self, key: &str) -> [usize; HASHES] {
+        let first = self.hasher.hash_one(key);
+        let step = self.hasher.hash_one((key, HASHES)) | 1;
+        let len = self.counters.len() as u64;
+        std::array::from_fn(|i| (first.wrapping_add((i as u64).wrapping_mul(step)) % len) as usize)
+    }
+}
+
+impl fmt::Debug for ExistenceIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExistenceIndex")
+            .field("counters", &self.counters.len())
+            .finish()
+    }
+}
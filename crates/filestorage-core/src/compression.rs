@@ -0,0 +1,237 @@
+//! At-rest compression of the objects [`FileStorage::put_with`] writes when
+//! [`StorageOptions::compression`](crate::StorageOptions::compression) is on.
+//!
+//! Such an object's file starts with a header holding a magic number, how
+//! the rest of the file is stored, and the object's length. Only the first
+//! [`SAMPLE_LEN`] bytes are compressed to decide: objects whose sample does
+//! not shrink enough, such as JPEGs or zips, are stored as given behind a
+//! header flagging them uncompressed, so no CPU is spent on them again when
+//! they are read. Files without the magic number are read as they are.
+
+use std::{
+    io::{self, Read, SeekFrom, Write},
+    path::Path,
+};
+
+use flate2::{Compression, read::DeflateDecoder, write::DeflateEncoder};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+
+use crate::{FileStorage, StorageError, atomic, io_error, streaming::ObjectReader};
+
+/// Marks a file written with a header; the bytes a PNG starts with make it
+/// unlikely to turn up at the start of ordinary content.
+const MAGIC: [u8; 8] = *b"\x89FSZ\r\n\x1a\n";
+/// The magic number, a mode byte, and the object length as a little-endian `u64`.
+const HEADER_LEN: usize = MAGIC.len() + 1 + 8;
+const MODE_STORED: u8 = 0;
+const MODE_DEFLATE: u8 = 1;
+/// Bytes from the start of an object compressed to judge the whole of it.
+const SAMPLE_LEN: usize = 64 * 1024;
+/// Percentage of its size the sample must shrink to for the object to be
+/// stored compressed.
+const MAX_SAMPLE_RATIO: usize = 90;
+
+/// Returns the file contents that store `data`: compressed unless its first
+/// [`SAMPLE_LEN`] bytes compress poorly, after a header flagging which.
+pub(crate) fn encode(data: &[u8]) -> Vec<u8> {
+    let sample = &data[..data.len().min(SAMPLE_LEN)];
+    let compressed_sample = deflate(sample);
+    let compressible = compressed_sample.len() * 100 <= sample.len() * MAX_SAMPLE_RATIO;
+    let (mode, compressed) = match compressible {
+        // The sample already covers all of `data`.
+        true if sample.len() == data.len() => (MODE_DEFLATE, compressed_sample),
+        true => (MODE_DEFLATE, deflate(data)),
+        false => (MODE_STORED, Vec::new()),
+    };
+    let body = if mode == MODE_DEFLATE {
+        &compressed
+    } else {
+        data
+    };
+    let mut encoded = Vec::with_capacity(HEADER_LEN + body.len());
+    encoded.extend_from_slice(&MAGIC);
+    encoded.push(mode);
+    encoded.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    encoded.extend_from_slice(body);
+    encoded
+}
+
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+    // Writing to a `Vec` cannot fail.
+    let _ = encoder.write_all(data);
+    encoder.finish().unwrap_or_default()
+}
+
+/// An object file opened for reading, past any header.
+pub(crate) enum Stored {
+    /// `len` bytes of content stored as they are, starting `start` bytes
+    /// into `file`.
+    Plain {
+        file: fs::File,
+        start: u64,
+        len: u64,
+    },
+    /// Content compressed into the rest of `file`, `len` bytes once
+    /// decompressed.
+    Deflated { file: fs::File, len: u64 },
+}
+
+impl Stored {
+    /// Wraps `file`, reading its header if `decode` is set and it has one.
+    pub(crate) async fn open(mut file: fs::File, decode: bool) -> io::Result<Self> {
+        let physical = file.metadata().await?.len();
+        let plain = |file| Stored::Plain {
+            file,
+            start: 0,
+            len: physical,
+        };
+        if !decode || physical < HEADER_LEN as u64 {
+            return Ok(plain(file));
+        }
+        let mut header = [0; HEADER_LEN];
+        file.read_exact(&mut header).await?;
+        match parse_header(&header) {
+            None => {
+                file.seek(SeekFrom::Start(0)).await?;
+                Ok(plain(file))
+            }
+            Some((MODE_STORED, len)) => Ok(Stored::Plain {
+                file,
+                start: HEADER_LEN as u64,
+                len: len.min(physical - HEADER_LEN as u64),
+            }),
+            Some((MODE_DEFLATE, len)) => Ok(Stored::Deflated { file, len }),
+            Some(_) => Err(corrupt()),
+        }
+    }
+
+    /// Returns the length of the object's content.
+    pub(crate) fn len(&self) -> u64 {
+        match self {
+            Stored::Plain { len, .. } | Stored::Deflated { len, .. } => *len,
+        }
+    }
+
+    /// Returns whether the content is read from the file as it is, with no
+    /// header in front.
+    pub(crate) fn is_raw(&self) -> bool {
+        matches!(self, Stored::Plain { start: 0, .. })
+    }
+
+    /// Returns a reader over up to `len` bytes of the content starting at
+    /// `offset`, and the number of bytes it yields.
+    pub(crate) async fn into_range(self, offset: u64, len: u64) -> io::Result<(ObjectReader, u64)> {
+        let len = len.min(self.len().saturating_sub(offset));
+        match self {
+            Stored::Plain {
+                mut file, start, ..
+            } => {
+                file.seek(SeekFrom::Start(start.saturating_add(offset)))
+                    .await?;
+                Ok((Box::new(file.take(len)), len))
+            }
+            deflated @ Stored::Deflated { .. } => {
+                let mut content = deflated.into_bytes().await?;
+                let start = offset.min(content.len() as u64) as usize;
+                content.truncate(start + len as usize);
+                content.drain(..start);
+                Ok((Box::new(io::Cursor::new(content)), len))
+            }
+        }
+    }
+
+    /// Reads the whole content.
+    pub(crate) async fn into_bytes(self) -> io::Result<Vec<u8>> {
+        match self {
+            Stored::Plain {
+                mut file,
+                start,
+                len,
+            } => {
+                file.seek(SeekFrom::Start(start)).await?;
+                let mut content = Vec::with_capacity(len as usize);
+                file.take(len).read_to_end(&mut content).await?;
+                Ok(content)
+            }
+            Stored::Deflated { mut file, len } => {
+                let mut compressed = Vec::new();
+                file.read_to_end(&mut compressed).await?;
+                inflate(&compressed, len)
+            }
+        }
+    }
+}
+
+/// Returns the mode and object length recorded in `header`, or `None` if it
+/// does not start with the magic number.
+fn parse_header(header: &[u8; HEADER_LEN]) -> Option<(u8, u64)> {
+    let rest = header.strip_prefix(&MAGIC)?;
+    let len = u64::from_le_bytes(rest[1..].try_into().ok()?);
+    Some((rest[0], len))
+}
+
+/// Decompresses `compressed`, which must yield exactly `len` bytes.
+fn inflate(compressed: &[u8], len: u64) -> io::Result<Vec<u8>> {
+    let mut content = Vec::with_capacity(len.min(compressed.len() as u64 * 4) as usize);
+    DeflateDecoder::new(compressed)
+        .take(len.saturating_add(1))
+        .read_to_end(&mut content)?;
+    if content.len() as u64 != len {
+        return Err(corrupt());
+    }
+    Ok(content)
+}
+
+fn corrupt() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "compressed object is corrupt")
+}
+
+impl FileStorage {
+    /// Opens the file of `key` at `path` for reading its content, decoding
+    /// the header of a compressed object if compression is on.
+    pub(crate) async fn open_stored(&self, key: &str, path: &Path) -> Result<Stored, StorageError> {
+        let file = fs::File::open(path)
+            .await
+            .map_err(|err| io_error(key, err))?;
+        if !file.metadata().await?.is_file() {
+            return Err(StorageError::NotFound(key.to_string()));
+        }
+        Ok(Stored::open(file, self.compression).await?)
+    }
+
+    /// Rewrites a compressed object at `path` with its plain content, so it
+    /// can be written to in place.
+    pub(crate) async fn decompress_in_place(
+        &self,
+        key: &str,
+        path: &Path,
+    ) -> Result<(), StorageError> {
+        if !self.compression {
+            return Ok(());
+        }
+        let file = match fs::File::open(path).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(io_error(key, err)),
+        };
+        let stored = Stored::open(file, true).await?;
+        if stored.is_raw() {
+            return Ok(());
+        }
+        let content = stored.into_bytes().await?;
+        let reservation = self
+            .reserve_quota(key, path, |_| content.len() as u64)
+            .await?;
+        atomic::write_atomic(path, &content, self.rename_strategy)
+            .await
+            .map_err(|err| io_error(key, err))?;
+        if let Some(reservation) = reservation {
+            reservation.settle();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+//! Directories known to exist, so puts under a hot prefix skip recreating
+//! their parent directory every time.
+//!
+//! The cache is only a hint. The store forgets directories it removes, and a
+//! write that still finds its cached parent missing, because something else
+//! removed it, recreates the parent and tries again.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+/// Directories remembered before the cache starts over, bounding its memory.
+const CAPACITY: usize = 4096;
+
+#[derive(Debug, Default)]
+pub(crate) struct DirCache {
+    known: Mutex<HashSet<PathBuf>>,
+}
+
+impl DirCache {
+    pub(crate) fn contains(&self, dir: &Path) -> bool {
+        self.lock().contains(dir)
+    }
+
+    /// Remembers that `dir`, and so every directory above it, exists.
+    pub(crate) fn insert(&self, dir: &Path) {
+        let mut known = self.lock();
+        if known.len() >= CAPACITY {
+            known.clear();
+        }
+        known.insert(dir.to_path_buf());
+    }
+
+    /// Forgets `dir` and every directory below it, after they were removed or moved.
+    pub(crate) fn forget_under(&self, dir: &Path) {
+        self.lock().retain(|known| !known.starts_with(dir));
+    }
+
+    pub(crate) fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashSet<PathBuf>> {
+        self.known
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
@@ -0,0 +1,124 @@
+//! Flushing of object writes to stable storage.
+//!
+//! A rename only survives a crash once the directory holding it has been
+//! synced. With group commit those directory syncs are queued and issued
+//! together on a timer, so a burst of writes to one directory shares a single
+//! sync at the cost of a bounded durability window.
+
+use std::{
+    collections::BTreeSet,
+    io::{self, ErrorKind},
+    mem,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{fs, time::MissedTickBehavior};
+
+#[derive(Clone, Debug, Default)]
+pub(crate) enum Durability {
+    /// Leave flushing to the operating system.
+    #[default]
+    None,
+    /// Sync each written file and its directory before returning.
+    Immediate,
+    /// Sync each written file before returning and its directory on the next tick.
+    Grouped(Arc<GroupCommit>),
+}
+
+impl Durability {
+    /// Makes the entry for `path` durable, or queues that for the next group commit.
+    pub(crate) async fn sync_parent(&self, path: &Path) -> io::Result<()> {
+        let Some(dir) = path.parent() else {
+            return Ok(());
+        };
+        match self {
+            Durability::None => Ok(()),
+            Durability::Immediate => sync_dir(dir).await,
+            Durability::Grouped(group) => {
+                group.defer(dir);
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns whether file contents must be synced before they are renamed into place.
+    pub(crate) fn syncs_files(&self) -> bool {
+        !matches!(self, Durability::None)
+    }
+
+    /// Issues any queued directory syncs now.
+    pub(crate) async fn flush(&self) -> io::Result<()> {
+        match self {
+            Durability::Grouped(group) => group.flush().await,
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Directories with renames that have not been synced yet.
+#[derive(Debug, Default)]
+pub(crate) struct GroupCommit {
+    dirty: Mutex<BTreeSet<PathBuf>>,
+}
+
+impl GroupCommit {
+    /// Starts a background task that flushes queued syncs every `interval`.
+    ///
+    /// The task holds only a weak reference and exits once the group is dropped.
+    pub(crate) fn start(interval: Duration) -> Arc<Self> {
+        let group = Arc::new(Self::default());
+        let weak = Arc::downgrade(&group);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                let Some(group) = weak.upgrade() else {
+                    break;
+                };
+                if let Err(err) = group.flush().await {
+                    eprintln!("group commit failed: {err}");
+                }
+            }
+        });
+        group
+    }
+
+    fn defer(&self, dir: &Path) {
+        self.lock().insert(dir.to_path_buf());
+    }
+
+    /// Syncs every queued directory, requeueing the ones that fail.
+    async fn flush(&self) -> io::Result<()> {
+        let dirs = mem::take(&mut *self.lock());
+        let mut pending = dirs.into_iter();
+        while let Some(dir) = pending.next() {
+            if let Err(err) = sync_dir(&dir).await {
+                let mut dirty = self.lock();
+                dirty.insert(dir);
+                dirty.extend(pending);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeSet<PathBuf>> {
+        self.dirty
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Syncs a directory so renames into it survive a crash.
+///
+/// Directories removed in the meantime have nothing left to sync.
+pub(crate) async fn sync_dir(dir: &Path) -> io::Result<()> {
+    match fs::File::open(dir).await {
+        Ok(file) => file.sync_all().await,
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
@@ -0,0 +1,156 @@
+//! Background removal of objects past their expiry time.
+
+use std::{
+    collections::{HashSet, hash_map::RandomState},
+    hash::BuildHasher,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use tokio::task::JoinHandle;
+
+use crate::{FileStorage, StorageError, sidecar::Sidecar};
+
+/// Pacing of the sweeper started by [`FileStorage::spawn_expiry_sweeper`].
+#[derive(Clone, Copy, Debug)]
+pub struct SweepSchedule {
+    /// Delay before the next cycle after one that removed objects.
+    pub min_interval: Duration,
+    /// Longest delay the sweeper backs off to while cycles find nothing.
+    pub max_interval: Duration,
+    /// Most objects one cycle removes; the rest wait for the next cycle.
+    pub max_per_cycle: usize,
+}
+
+impl Default for SweepSchedule {
+    fn default() -> Self {
+        Self {
+            min_interval: Duration::from_secs(1),
+            max_interval: Duration::from_secs(300),
+            max_per_cycle: 1000,
+        }
+    }
+}
+
+/// Handle to a running expiry sweeper; dropping it stops the sweeper.
+#[derive(Debug)]
+pub struct ExpirySweeper {
+    task: JoinHandle<()>,
+    stats: Arc<SweepStats>,
+}
+
+#[derive(Debug, Default)]
+struct SweepStats {
+    cycles: AtomicU64,
+    swept: AtomicU64,
+}
+
+impl ExpirySweeper {
+    /// Returns how many cycles have finished.
+    pub fn cycles(&self) -> u64 {
+        self.stats.cycles.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many expired objects the sweeper has removed.
+    pub fn swept(&self) -> u64 {
+        self.stats.swept.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for ExpirySweeper {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl FileStorage {
+    /// Deletes up to `limit` objects whose expiry time has passed, under the
+    /// main root and each tier's, and returns how many were deleted.
+    ///
+    /// Only objects with an expiry time have their sidecar read, so a sweep
+    /// of a store with few expiring objects costs little more than a listing.
+    pub async fn sweep_expired(&self, limit: usize) -> Result<usize, StorageError> {
+        let mut swept = 0;
+        for (store, tree) in self.select_everywhere("").await? {
+            let expiring: HashSet<_> = tree.reserved.iter().collect();
+            for (key, path) in &tree.files {
+                if swept == limit {
+                    return Ok(swept);
+                }
+                if !expiring.contains(&Sidecar::Expiry.path_for(path)) {
+                    continue;
+                }
+                let _guard = self.lock_key(key).await;
+                // Checked again under the lock in case the key was just stored again.
+                match store.expires_at_here(key, path).await? {
+                    Some(expires_at) if expires_at <= SystemTime::now() => {}
+                    _ => continue,
+                }
+                match store.delete_local(key).await {
+                    Ok(()) => {}
+                    Err(StorageError::NotFound(_)) => continue,
+                    Err(err) => return Err(err),
+                }
+                if let Some(replica) = &self.replica {
+                    let result = match replica.delete_local(key).await {
+                        Err(StorageError::NotFound(_)) => Ok(()),
+                        result => result,
+                    };
+                    self.apply_replica_policy(key, result)?;
+                }
+                swept += 1;
+            }
+        }
+        Ok(swept)
+    }
+
+    /// Spawns a task that runs [`sweep_expired`](Self::sweep_expired) on an
+    /// adaptive schedule, logging failures.
+    ///
+    /// A cycle that removes objects is followed by another after
+    /// `min_interval`. Each idle cycle doubles the delay, up to
+    /// `max_interval`, and the delay is jittered so sweepers started together
+    /// drift apart. Each cycle still walks the whole store to find expired
+    /// objects, so its cost is O(store size); backing off only makes cycles
+    /// rarer while nothing expires.
+    pub fn spawn_expiry_sweeper(&self, schedule: SweepSchedule) -> ExpirySweeper {
+        let storage = self.clone();
+        let stats = Arc::new(SweepStats::default());
+        let min = schedule.min_interval.max(Duration::from_millis(1));
+        let max = schedule.max_interval.max(min);
+        let limit = schedule.max_per_cycle.max(1);
+        let task = tokio::spawn({
+            let stats = stats.clone();
+            async move {
+                let mut delay = min;
+                loop {
+                    tokio::time::sleep(jitter(delay)).await;
+                    let swept = match storage.sweep_expired(limit).await {
+                        Ok(swept) => swept,
+                        Err(err) => {
+                            eprintln!("expiry sweep failed: {err}");
+                            0
+                        }
+                    };
+                    stats.cycles.fetch_add(1, Ordering::Relaxed);
+                    stats.swept.fetch_add(swept as u64, Ordering::Relaxed);
+                    delay = if swept > 0 {
+                        min
+                    } else {
+                        delay.saturating_mul(2).min(max)
+                    };
+                }
+            }
+        });
+        ExpirySweeper { task, stats }
+    }
+}
+
+/// Picks a delay between half of `delay` and all of it.
+fn jitter(delay: Duration) -> Duration {
+    let fraction = RandomState::new().hash_one(0u8) as f64 / u64::MAX as f64;
+    delay.mul_f64(0.5 + fraction / 2.0)
+}
@@ -0,0 +1,89 @@
+//! Cleanup of sidecars left behind by interrupted operations.
+
+use std::{
+    io::ErrorKind,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use tokio::{fs, task::JoinHandle, time::MissedTickBehavior};
+
+use crate::{FileStorage, StorageError, integrity, sidecar::Sidecar};
+
+/// Sidecars modified more recently than this are left alone, since their
+/// object may be in the middle of being written or moved.
+const GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+impl FileStorage {
+    /// Removes sidecars whose object no longer exists, along with checksum
+    /// index entries for missing objects, under the main root and each tier's,
+    /// and returns how many were removed.
+    ///
+    /// A sidecar is only removed once its object has been missing for the
+    /// whole grace period, so sidecars of objects that are present or being
+    /// written are never touched.
+    pub async fn gc_sidecars(&self) -> Result<usize, StorageError> {
+        let mut removed = 0;
+        for (store, tree) in self.select_everywhere("").await? {
+            for path in &tree.reserved {
+                if integrity::is_checksum_index(path) {
+                    removed += store.prune_checksums(path).await?;
+                    continue;
+                }
+                let Some(object) = Sidecar::object_for(path) else {
+                    continue;
+                };
+                if is_orphaned(path, &object).await? && remove_if_present(path).await? {
+                    removed += 1;
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Spawns a task that runs [`gc_sidecars`](Self::gc_sidecars) every
+    /// `interval`, logging failures. Abort the returned handle to stop it.
+    pub fn spawn_sidecar_gc(&self, interval: Duration) -> JoinHandle<()> {
+        let storage = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                match storage.gc_sidecars().await {
+                    Ok(0) => {}
+                    Ok(removed) => eprintln!("sidecar gc removed {removed} orphaned entries"),
+                    Err(err) => eprintln!("sidecar gc failed: {err}"),
+                }
+            }
+        })
+    }
+}
+
+/// Returns whether the sidecar at `path` is past the grace period and its `object` is gone.
+async fn is_orphaned(path: &Path, object: &Path) -> Result<bool, StorageError> {
+    let modified = match fs::symlink_metadata(path).await {
+        Ok(metadata) => metadata.modified()?,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(StorageError::from(err)),
+    };
+    let settled = SystemTime::now()
+        .duration_since(modified)
+        .is_ok_and(|age| age >= GRACE_PERIOD);
+    if !settled {
+        return Ok(false);
+    }
+    match fs::symlink_metadata(object).await {
+        Ok(_) => Ok(false),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(true),
+        Err(err) => Err(StorageError::from(err)),
+    }
+}
+
+async fn remove_if_present(path: &Path) -> Result<bool, StorageError> {
+    match fs::remove_file(path).await {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(StorageError::from(err)),
+    }
+}
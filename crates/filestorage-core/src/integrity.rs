@@ -0,0 +1,568 @@
+//! Content digests, per-directory checksum indexes, and manifest-driven repair.
+//!
+//! With [`StorageOptions::checksum_index`](crate::StorageOptions::checksum_index)
+//! enabled, every put records the object's digest and modification time in a
+//! reserved JSON-lines file in its directory. Verification trusts an entry
+//! until the object is modified after it was recorded.
+//!
+//! Puts and deletes append a line to the index, later lines overriding
+//! earlier ones for the same object, and an index is rewritten compacted
+//! once it holds more appended lines than live entries.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+    task::JoinSet,
+};
+
+use crate::{FileStorage, RenameStrategy, StorageError, atomic, compression::Stored, io_error};
+
+/// Size of the buffer objects are streamed through while hashing.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Objects [`FileStorage::verify_all`] hashes at the same time.
+const VERIFY_CONCURRENCY: usize = 8;
+
+/// Lines appended to a checksum index before it is first compacted.
+const MIN_APPENDS_BEFORE_COMPACTION: usize = 256;
+
+/// Expected SHA-256 digest of one object.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub key: String,
+    /// Lowercase hex-encoded SHA-256 of the object's content.
+    pub sha256: String,
+}
+
+/// Outcome of [`FileStorage::repair_from`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    /// Keys that failed verification and were restored from the replica.
+    pub healed: Vec<String>,
+    /// Keys that failed verification but had no good copy on the replica.
+    pub unrepairable: Vec<String>,
+    /// Keys that already matched the manifest.
+    pub skipped: Vec<String>,
+}
+
+/// Space content-addressed storage would save, as estimated by
+/// [`FileStorage::dedup_report`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DedupReport {
+    /// Bytes taken by every object.
+    pub total_bytes: u64,
+    /// Bytes taken if each distinct content were stored once.
+    pub unique_bytes: u64,
+    /// Objects sharing their content with at least one other, ordered by
+    /// their first key.
+    pub duplicates: Vec<DuplicateGroup>,
+}
+
+impl DedupReport {
+    /// Bytes deduplication would free.
+    pub fn saved_bytes(&self) -> u64 {
+        self.total_bytes - self.unique_bytes
+    }
+}
+
+/// Keys whose objects have identical content.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    /// Lowercase hex-encoded SHA-256 of the shared content.
+    pub sha256: String,
+    /// Size of each copy.
+    pub size: u64,
+    /// Keys holding a copy, sorted.
+    pub keys: Vec<String>,
+}
+
+/// One line of a directory's checksum index.
+#[derive(Debug, Serialize, Deserialize)]
+struct ChecksumEntry {
+    /// File name of the object within the directory.
+    name: String,
+    sha256: String,
+    /// Object modification time, in nanoseconds since the Unix epoch, when
+    /// the digest was recorded.
+    modified: u64,
+    /// Marks the object as deleted, dropping earlier lines for it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    removed: bool,
+}
+
+/// Appends made to each directory's checksum index since it was last
+/// rewritten, guarded by the store's checksum lock.
+#[derive(Debug, Default)]
+pub(crate) struct IndexAppends {
+    dirs: HashMap<PathBuf, Appends>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Appends {
+    /// Lines appended since the index was last rewritten.
+    lines: usize,
+    /// Entries the index held when it was last rewritten.
+    live: usize,
+}
+
+impl FileStorage {
+    /// Checks each object in `manifest` and returns the keys that are missing
+    /// or whose content does not match, in manifest order.
+    ///
+    /// Digests come from the checksum index when it is enabled and still
+    /// current, and are recomputed otherwise.
+    pub async fn verify(&self, manifest: &[ManifestEntry]) -> Result<Vec<String>, StorageError> {
+        let mut failed = Vec::new();
+        for entry in manifest {
+            match self.indexed_sha256(&entry.key).await {
+                Ok(digest) if digest == entry.sha256 => {}
+                Ok(_) | Err(StorageError::NotFound(_)) => failed.push(entry.key.clone()),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(failed)
+    }
+
+    /// Hashes every object in the store and returns `(key, hex_digest)` pairs
+    /// sorted by key, for periodic scrubbing.
+    ///
+    /// Digests are always recomputed, ignoring the checksum index. Objects are
+    /// streamed through the hasher a few at a time, and objects deleted while
+    /// the pass runs are left out.
+    pub async fn verify_all(&self) -> Result<Vec<(String, String)>, StorageError> {
+        let hashed = self.hash_all().await?;
+        Ok(hashed
+            .into_iter()
+            .map(|(key, digest, _)| (key, digest))
+            .collect())
+    }
+
+    /// Hashes every object and reports how much space storing each distinct
+    /// content only once would save, without changing anything.
+    ///
+    /// Objects are hashed the same way as by [`verify_all`](Self::verify_all).
+    pub async fn dedup_report(&self) -> Result<DedupReport, StorageError> {
+        let mut report = DedupReport::default();
+        let mut groups: BTreeMap<String, DuplicateGroup> = BTreeMap::new();
+        for (key, sha256, size) in self.hash_all().await? {
+            report.total_bytes += size;
+            groups
+                .entry(sha256.clone())
+                .or_insert_with(|| {
+                    report.unique_bytes += size;
+                    DuplicateGroup {
+                        sha256,
+                        size,
+                        keys: Vec::new(),
+                    }
+                })
+                .keys
+                .push(key);
+        }
+        report.duplicates = groups
+            .into_values()
+            .filter(|group| group.keys.len() > 1)
+            .collect();
+        report.duplicates.sort_by(|a, b| a.keys.cmp(&b.keys));
+        Ok(report)
+    }
+
+    /// Streams `key` through SHA-256 and returns the lowercase hex digest.
+    pub async fn sha256(&self, key: &str) -> Result<String, StorageError> {
+        if let Some(tier) = self.tier_holding(key).await? {
+            return Box::pin(tier.sha256(key)).await;
+        }
+        let (digest, _) = self.hash_object(key).await?;
+        Ok(digest)
+    }
+
+    /// Hashes every object a few at a time and returns `(key, hex_digest,
+    /// size)` sorted by key, leaving out objects deleted during the pass.
+    async fn hash_all(&self) -> Result<Vec<(String, String, u64)>, StorageError> {
+        let mut keys = self.keys_everywhere("").await?.into_iter();
+        let mut tasks = JoinSet::new();
+        let mut hashed = Vec::new();
+        loop {
+            while tasks.len() < VERIFY_CONCURRENCY {
+                let Some(key) = keys.next() else { break };
+                let storage = self.clone();
+                tasks.spawn(async move {
+                    let hash = match storage.holder(&key).await {
+                        Ok(holder) => holder.hash_object(&key).await,
+                        Err(err) => Err(err),
+                    };
+                    (key, hash)
+                });
+            }
+            let Some(joined) = tasks.join_next().await else {
+                break;
+            };
+            match joined.map_err(io::Error::other)? {
+                (key, Ok((digest, size))) => hashed.push((key, digest, size)),
+                (_, Err(StorageError::NotFound(_))) => {}
+                (_, Err(err)) => return Err(err),
+            }
+        }
+        hashed.sort();
+        Ok(hashed)
+    }
+
+    /// Streams `key` through SHA-256, returning the hex digest and the number
+    /// of bytes hashed.
+    async fn hash_object(&self, key: &str) -> Result<(String, u64), StorageError> {
+        let path = self.path_for(key)?;
+        self.ensure_within_root(key, &path).await?;
+        let stored = self.open_stored(key, &path).await?;
+        Ok(hash_stored(stored).await?)
+    }
+
+    /// Restores objects that fail verification against `manifest` from `replica`.
+    ///
+    /// The replica's copy is hashed while it is streamed into a temp file, and
+    /// only replaces the primary object if it matches the manifest digest.
+    pub async fn repair_from(
+        &self,
+        replica: &FileStorage,
+        manifest: &[ManifestEntry],
+    ) -> Result<RepairReport, StorageError> {
+        let mut report = RepairReport::default();
+        for entry in manifest {
+            match self.indexed_sha256(&entry.key).await {
+                Ok(digest) if digest == entry.sha256 => {
+                    report.skipped.push(entry.key.clone());
+                    continue;
+                }
+                Ok(_) | Err(StorageError::NotFound(_)) => {}
+                Err(err) => return Err(err),
+            }
+
+            let src = replica.path_for(&entry.key)?;
+            let store = self.holder(&entry.key).await?;
+            let dst = store.path_for(&entry.key)?;
+            store.index_insert(&entry.key);
+            let (digest, decode) = (&entry.sha256, self.compression);
+            if copy_verified(&src, &dst, digest, decode, self.rename_strategy).await? {
+                store.record_key(&entry.key);
+                report.healed.push(entry.key.clone());
+            } else {
+                report.unrepairable.push(entry.key.clone());
+            }
+        }
+        for key in &report.healed {
+            self.invalidate_quotas(key).await;
+        }
+        Ok(report)
+    }
+
+    /// Returns the digest of `key`, preferring a checksum index entry that is
+    /// at least as new as the object.
+    async fn indexed_sha256(&self, key: &str) -> Result<String, StorageError> {
+        if let Some(tier) = self.tier_holding(key).await? {
+            return Box::pin(tier.indexed_sha256(key)).await;
+        }
+        if self.checksums.is_some() {
+            let path = self.path_for(key)?;
+            let metadata = fs::metadata(&path)
+                .await
+                .map_err(|err| io_error(key, err))?;
+            let modified = unix_nanos(metadata.modified()?);
+            let (dir, name) = index_location(&path);
+            let recorded = read_index(&dir).await?.remove(&name);
+            if let Some(entry) = recorded.filter(|entry| entry.modified >= modified) {
+                return Ok(entry.sha256);
+            }
+        }
+        self.sha256(key).await
+    }
+
+    /// Records the digest of `data`, just written to `path`, in its directory's index.
+    pub(crate) async fn record_checksum(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        if self.checksums.is_none() {
+            return Ok(());
+        }
+        self.record_digest(path, hex::encode(Sha256::digest(data)))
+            .await
+    }
+
+    /// Records `sha256`, the digest of the object just written to `path`.
+    pub(crate) async fn record_digest(&self, path: &Path, sha256: String) -> io::Result<()> {
+        let Some(lock) = &self.checksums else {
+            return Ok(());
+        };
+        let modified = unix_nanos(fs::metadata(path).await?.modified()?);
+        let (dir, name) = index_location(path);
+        let mut appends = lock.lock().await;
+        let entry = ChecksumEntry {
+            name,
+            sha256,
+            modified,
+            removed: false,
+        };
+        self.append_index_entry(&mut appends, &dir, &entry).await
+    }
+
+    /// Appends `entry` to the checksum index in `dir`, rewriting the index
+    /// compacted once appends outnumber its live entries.
+    async fn append_index_entry(
+        &self,
+        appends: &mut IndexAppends,
+        dir: &Path,
+        entry: &ChecksumEntry,
+    ) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry).map_err(io::Error::other)?;
+        line.push(b'\n');
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(index_path(dir))
+            .await?
+            .write_all(&line)
+            .await?;
+        let counts = appends.dirs.entry(dir.to_path_buf()).or_default();
+        counts.lines += 1;
+        if counts.lines > counts.live.max(MIN_APPENDS_BEFORE_COMPACTION) {
+            let entries = read_index(dir).await?;
+            write_index(dir, &entries, self.rename_strategy).await?;
+            *counts = Appends {
+                lines: 0,
+                live: entries.len(),
+            };
+        }
+        Ok(())
+    }
+
+    /// Drops entries of the checksum index at `index` whose objects no longer
+    /// exist, returning how many were dropped.
+    pub(crate) async fn prune_checksums(&self, index: &Path) -> io::Result<usize> {
+        let Some(dir) = index.parent() else {
+            return Ok(0);
+        };
+        let mut appends = match &self.checksums {
+            Some(lock) => Some(lock.lock().await),
+            None => None,
+        };
+        let entries = read_index(dir).await?;
+        let before = entries.len();
+        let mut live = BTreeMap::new();
+        for (name, entry) in entries {
+            if fs::symlink_metadata(dir.join(&name)).await.is_ok() {
+                live.insert(name, entry);
+            }
+        }
+        let pruned = before - live.len();
+        if pruned > 0 {
+            write_index(dir, &live, self.rename_strategy).await?;
+            if let Some(appends) = &mut appends {
+                appends.dirs.remove(dir);
+            }
+        }
+        Ok(pruned)
+    }
+
+    /// Moves the index entry of the object just renamed from `src` to `dst`,
+    /// dropping any entry left for an object `dst` replaced.
+    pub(crate) async fn move_checksum(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        let Some(lock) = &self.checksums else {
+            return Ok(());
+        };
+        let (src_dir, src_name) = index_location(src);
+        let (dst_dir, dst_name) = index_location(dst);
+        let mut appends = lock.lock().await;
+        let Some(entry) = read_index(&src_dir).await?.remove(&src_name) else {
+            drop(appends);
+            return self.forget_checksum(dst).await;
+        };
+        let tombstone = ChecksumEntry {
+            name: src_name,
+            sha256: String::new(),
+            modified: 0,
+            removed: true,
+        };
+        self.append_index_entry(&mut appends, &src_dir, &tombstone)
+            .await?;
+        // A rename keeps the modification time, so the entry stays current.
+        let moved = ChecksumEntry {
+            name: dst_name,
+            ..entry
+        };
+        self.append_index_entry(&mut appends, &dst_dir, &moved)
+            .await
+    }
+
+    /// Drops the index entry for the object that was stored at `path`.
+    pub(crate) async fn forget_checksum(&self, path: &Path) -> io::Result<()> {
+        let Some(lock) = &self.checksums else {
+            return Ok(());
+        };
+        let (dir, name) = index_location(path);
+        let mut appends = lock.lock().await;
+        if fs::symlink_metadata(index_path(&dir)).await.is_err() {
+            return Ok(());
+        }
+        let tombstone = ChecksumEntry {
+            name,
+            sha256: String::new(),
+            modified: 0,
+            removed: true,
+        };
+        self.append_index_entry(&mut appends, &dir, &tombstone)
+            .await
+    }
+}
+
+/// Splits an object path into its directory and file name.
+fn index_location(path: &Path) -> (PathBuf, String) {
+    let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    (dir, name.into_owned())
+}
+
+/// Returns whether `path` is a directory's checksum index.
+pub(crate) fn is_checksum_index(path: &Path) -> bool {
+    path.file_name() == index_path(Path::new("")).file_name()
+}
+
+fn index_path(dir: &Path) -> PathBuf {
+    dir.join(format!("{}checksums", atomic::RESERVED_PREFIX))
+}
+
+/// Loads a directory's checksum index, replaying its lines in order and
+/// skipping any that do not parse.
+async fn read_index(dir: &Path) -> io::Result<BTreeMap<String, ChecksumEntry>> {
+    let contents = match fs::read_to_string(index_path(dir)).await {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(err) => return Err(err),
+    };
+    let mut entries = BTreeMap::new();
+    for entry in contents
+        .lines()
+        .filter_map(|line| serde_json::from_str::<ChecksumEntry>(line).ok())
+    {
+        if entry.removed {
+            entries.remove(&entry.name);
+        } else {
+            entries.insert(entry.name.clone(), entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Atomically replaces a directory's checksum index, removing it once empty.
+async fn write_index(
+    dir: &Path,
+    entries: &BTreeMap<String, ChecksumEntry>,
+    strategy: RenameStrategy,
+) -> io::Result<()> {
+    let path = index_path(dir);
+    if entries.is_empty() {
+        return match fs::remove_file(&path).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        };
+    }
+    let mut encoded = String::new();
+    for entry in entries.values() {
+        encoded.push_str(&serde_json::to_string(entry).map_err(io::Error::other)?);
+        encoded.push('\n');
+    }
+    atomic::write_atomic(&path, encoded.as_bytes(), strategy).await
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+/// Streams the content of `stored` through SHA-256, returning the hex digest
+/// and the number of bytes hashed.
+async fn hash_stored(stored: Stored) -> io::Result<(String, u64)> {
+    let (mut reader, _) = stored.into_range(0, u64::MAX).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut size = 0;
+    loop {
+        let read = reader.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+        size += read as u64;
+    }
+    Ok((hex::encode(hasher.finalize()), size))
+}
+
+/// Copies `src` over `dst` if the SHA-256 of its content matches `expected`,
+/// decoding a compressed copy first when `decode` is set.
+///
+/// Returns `false`, leaving `dst` untouched, when `src` is missing or differs.
+async fn copy_verified(
+    src: &Path,
+    dst: &Path,
+    expected: &str,
+    decode: bool,
+    strategy: RenameStrategy,
+) -> Result<bool, StorageError> {
+    let mut reader = match fs::File::open(src).await {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(StorageError::from(err)),
+    };
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    let tmp = atomic::temp_path_for(dst);
+    let mut writer = fs::File::create(&tmp).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; CHUNK_SIZE];
+    let copied = async {
+        loop {
+            let read = reader.read(&mut buf).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+            writer.write_all(&buf[..read]).await?;
+        }
+        writer.flush().await?;
+        if !decode {
+            return Ok(hex::encode(hasher.finalize()));
+        }
+        let hashed = match Stored::open(fs::File::open(&tmp).await?, true).await {
+            Ok(stored) => hash_stored(stored).await,
+            Err(err) => Err(err),
+        };
+        match hashed {
+            Ok((digest, _)) => Ok(digest),
+            // A corrupt compressed copy is as unusable as one that differs.
+            Err(err) if err.kind() == ErrorKind::InvalidData => Ok(String::new()),
+            Err(err) => Err(err),
+        }
+    }
+    .await;
+    let digest = match copied {
+        Ok(digest) => digest,
+        Err(err) => {
+            let _ = fs::remove_file(&tmp).await;
+            return Err(StorageError::from(err));
+        }
+    };
+
+    if digest != expected {
+        let _ = fs::remove_file(&tmp).await;
+        return Ok(false);
+    }
+    atomic::rename_or_discard(&tmp, dst, strategy).await?;
+    Ok(true)
+}
@@ -0,0 +1,189 @@
+//! Persisted set of stored keys that answers existence checks without
+//! touching the filesystem.
+//!
+//! Writes made through the store update the set as they complete, and the
+//! changes are appended to a journal in the root about once a second and on
+//! [`FileStorage::flush`](crate::FileStorage::flush). Opening the store replays
+//! the journal, reconciles it with a full scan, since the files are the source
+//! of truth, and rewrites it compacted.
+
+use std::{
+    collections::HashSet,
+    io::{self, ErrorKind},
+    mem,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, RwLock},
+    time::Duration,
+};
+
+use tokio::{
+    fs::{self, OpenOptions},
+    io::AsyncWriteExt,
+    time::MissedTickBehavior,
+};
+
+use crate::{RenameStrategy, atomic};
+
+/// How often journal lines for recent writes are appended to disk.
+const PERSIST_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub(crate) struct KeyIndex {
+    path: PathBuf,
+    keys: RwLock<HashSet<String>>,
+    /// Journal lines not yet appended to the file.
+    pending: Mutex<Vec<u8>>,
+    /// Serializes appends so lines reach the file in the order they were queued.
+    append: tokio::sync::Mutex<()>,
+    /// Keys the journal disagreed with the files about when the store was opened.
+    drift: usize,
+}
+
+impl KeyIndex {
+    /// Loads the journal under `root`, replaces it with `keys` found by a full
+    /// scan, and starts a task that appends later changes once per interval.
+    ///
+    /// The task holds only a weak reference and exits once the index is dropped.
+    pub(crate) async fn start(
+        root: &Path,
+        keys: Vec<String>,
+        strategy: RenameStrategy,
+    ) -> io::Result<Arc<Self>> {
+        let path = root.join(format!("{}keys", atomic::RESERVED_PREFIX));
+        let journaled = load(&path).await?;
+        let keys: HashSet<String> = keys.into_iter().collect();
+        let drift = journaled.symmetric_difference(&keys).count();
+
+        let mut compacted = Vec::new();
+        for key in &keys {
+            push_line(&mut compacted, '+', key);
+        }
+        atomic::write_atomic(&path, &compacted, strategy).await?;
+
+        let index = Arc::new(Self {
+            path,
+            keys: RwLock::new(keys),
+            pending: Mutex::default(),
+            append: tokio::sync::Mutex::new(()),
+            drift,
+        });
+        let weak = Arc::downgrade(&index);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(PERSIST_INTERVAL);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                let Some(index) = weak.upgrade() else {
+                    break;
+                };
+                if let Err(err) = index.persist().await {
+                    eprintln!("key index journal append failed: {err}");
+                }
+            }
+        });
+        Ok(index)
+    }
+
+    pub(crate) fn insert(&self, key: String) {
+        let mut pending = self.lock_pending();
+        push_line(&mut pending, '+', &key);
+        self.keys
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(key);
+    }
+
+    pub(crate) fn remove(&self, key: &str) {
+        let mut pending = self.lock_pending();
+        push_line(&mut pending, '-', key);
+        self.keys
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(key);
+    }
+
+    pub(crate) fn contains(&self, key: &str) -> bool {
+        self.keys
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .contains(key)
+    }
+
+    /// Adds the `scanned` keys the index is missing and returns the indexed
+    /// keys the scan did not find, which the caller removes once confirmed gone.
+    pub(crate) fn reconcile(&self, scanned: &[String]) -> Vec<String> {
+        let scanned: HashSet<&str> = scanned.iter().map(String::as_str).collect();
+        let (missing, unseen) = {
+            let keys = self
+                .keys
+                .read()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let missing: Vec<String> = scanned
+                .iter()
+                .filter(|key| !keys.contains(**key))
+                .map(|key| key.to_string())
+                .collect();
+            let unseen: Vec<String> = keys
+                .iter()
+                .filter(|key| !scanned.contains(key.as_str()))
+                .cloned()
+                .collect();
+            (missing, unseen)
+        };
+        for key in missing {
+            self.insert(key);
+        }
+        unseen
+    }
+
+    /// Returns how many keys the journal had wrong when the store was opened.
+    pub(crate) fn drift(&self) -> usize {
+        self.drift
+    }
+
+    /// Appends the changes made since the last call to the journal.
+    pub(crate) async fn persist(&self) -> io::Result<()> {
+        let _guard = self.append.lock().await;
+        let lines = mem::take(&mut *self.lock_pending());
+        if lines.is_empty() {
+            return Ok(());
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(&lines).await
+    }
+
+    fn lock_pending(&self) -> MutexGuard<'_, Vec<u8>> {
+        self.pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Replays the journal at `path`; a missing journal holds no keys.
+async fn load(path: &Path) -> io::Result<HashSet<String>> {
+    let contents = match fs::read_to_string(path).await {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(err) => return Err(err),
+    };
+    let mut keys = HashSet::new();
+    for line in contents.lines() {
+        match line.split_at_checked(1) {
+            Some(("+", key)) => keys.insert(key.to_string()),
+            Some(("-", key)) => keys.remove(key),
+            _ => continue,
+        };
+    }
+    Ok(keys)
+}
+
+/// Keys cannot contain control characters, so one line per change is unambiguous.
+fn push_line(out: &mut Vec<u8>, op: char, key: &str) {
+    out.push(op as u8);
+    out.extend_from_slice(key.as_bytes());
+    out.push(b'\n');
+}
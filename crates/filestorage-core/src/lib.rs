@@ -1,92 +1,2819 @@
+mod archive;
+mod atomic;
+mod batch;
+mod bloom;
+mod compression;
+mod dirs;
+mod durability;
+mod expiry;
+mod gc;
+mod integrity;
+mod key_index;
+mod listing;
+mod locks;
+mod mapper;
+mod probe;
+mod quota;
+mod sidecar;
+mod streaming;
+mod tiering;
+mod trash;
+mod wal;
+
 use std::{
-    io::ErrorKind,
+    collections::{BTreeMap, HashMap},
+    ffi::OsStr,
+    io::{ErrorKind, SeekFrom},
+    num::NonZeroUsize,
     path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use thiserror::Error;
-use tokio::fs;
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::OwnedMutexGuard,
+    task::JoinSet,
+};
+
+use crate::{
+    atomic::RESERVED_PREFIX,
+    bloom::ExistenceIndex,
+    dirs::DirCache,
+    durability::{Durability, GroupCommit},
+    integrity::IndexAppends,
+    key_index::KeyIndex,
+    listing::ListingCache,
+    locks::KeyLocks,
+    quota::{Quotas, Reservation},
+    sidecar::Sidecar,
+    wal::{Step, Wal},
+};
+
+pub use crate::{
+    archive::ImportReport,
+    expiry::{ExpirySweeper, SweepSchedule},
+    integrity::{DedupReport, DuplicateGroup, ManifestEntry, RepairReport},
+    mapper::{DefaultKeyMapper, KeyMapper},
+    probe::FsCapabilities,
+    streaming::ObjectReader,
+    tiering::Tier,
+};
+
+/// Attributes stored alongside an object by [`FileStorage::put_with`].
+#[derive(Clone, Debug)]
+pub struct PutOptions {
+    /// Media type to report for the object.
+    pub content_type: Option<String>,
+    /// Arbitrary user metadata; names cannot contain `:` and values cannot contain newlines.
+    pub metadata: BTreeMap<String, String>,
+    /// Instant after which the object is treated as missing.
+    pub expires_at: Option<SystemTime>,
+    /// Requirement on the current object, checked while the key is locked so
+    /// no other write can slip in between the check and the put.
+    pub condition: PutCondition,
+    /// Creates the directories leading up to the object as needed; `true` by
+    /// default. When `false`, a put whose key prefix has no directory yet
+    /// fails with [`StorageError::PrefixNotFound`], catching mistyped prefixes.
+    pub create_parents: bool,
+}
+
+impl Default for PutOptions {
+    fn default() -> Self {
+        Self {
+            content_type: None,
+            metadata: BTreeMap::new(),
+            expires_at: None,
+            condition: PutCondition::default(),
+            create_parents: true,
+        }
+    }
+}
+
+/// What [`FileStorage::put_with`] did with the object it was given.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PutOutcome {
+    /// The object was written.
+    Written,
+    /// The stored object already held the same bytes and was left in place,
+    /// keeping its modification time; see [`StorageOptions::skip_identical`].
+    Unchanged,
+}
+
+/// Requirement a put places on the object it would replace.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum PutCondition {
+    /// Store whether or not the key exists.
+    #[default]
+    Always,
+    /// Fail with [`StorageError::Conflict`] if the key exists.
+    IfAbsent,
+    /// Fail with [`StorageError::VersionMismatch`] unless the key exists with
+    /// this entity tag, as reported by [`FileStorage::head`]; `*` matches any.
+    IfMatch(String),
+}
+
+/// Filesystem attributes of a stored object.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub size: u64,
+    pub modified: SystemTime,
+    /// Quoted entity tag derived from the modification time and size.
+    pub etag: String,
+}
+
+/// Cost of a single operation, as reported by [`FileStorage::put_timed`] and
+/// [`FileStorage::get_timed`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OperationResult {
+    /// Object bytes written or read.
+    pub bytes: u64,
+    /// Wall-clock time the operation took.
+    pub elapsed: Duration,
+}
+
+/// One level of keys below a prefix, as returned by [`FileStorage::list_delimited`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DelimitedListing {
+    /// Keys under the prefix with no further delimiter.
+    pub keys: Vec<String>,
+    /// Distinct key beginnings up to and including the next delimiter.
+    pub common_prefixes: Vec<String>,
+}
+
+/// How mirrored writes react when applying a change to the replica root fails.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReplicaPolicy {
+    /// Return the replica's error. The primary keeps the change already applied.
+    #[default]
+    Fail,
+    /// Report the failure on stderr and treat the operation as successful.
+    LogAndContinue,
+}
+
+/// How a finished temp file replaces the file it is written over.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RenameStrategy {
+    /// Rename the temp file over the target, so readers always see either the
+    /// old or the new content.
+    #[default]
+    Atomic,
+    /// Remove the target, then rename the temp file into place, for network
+    /// filesystems where renaming over an existing file fails or is not atomic.
+    ///
+    /// Readers can find the object missing between the two steps, and a crash
+    /// or failed rename in between loses the previous content.
+    Fallback,
+}
+
+/// How reads treat an object that is itself a symlink.
+///
+/// Links that resolve outside the root are rejected under every policy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Fail with [`InvalidKeyReason::Symlink`].
+    #[default]
+    Reject,
+    /// Read the file the link points to.
+    Follow,
+    /// Treat the link as an object whose content is the path it points to.
+    ReturnTarget,
+}
+
+/// Settings for [`FileStorage::with_options`].
+#[derive(Clone, Debug, Default)]
+pub struct StorageOptions {
+    /// Directory that receives a synchronous copy of every put, delete, and
+    /// prefix rename. Reads are always served from the primary root.
+    pub replica_root: Option<PathBuf>,
+    pub replica_policy: ReplicaPolicy,
+    /// Keeps an in-memory bloom filter of stored keys, built by scanning the
+    /// root on open, so [`FileStorage::exists`] and [`FileStorage::get`] can
+    /// answer for absent keys without touching the filesystem.
+    ///
+    /// Objects written to the root by anything other than this store after it
+    /// was opened may be reported as missing until [`FileStorage::refresh`].
+    pub existence_index: bool,
+    /// Flushes object contents to disk, and syncs the directory holding each
+    /// put or deleted object, before the operation returns.
+    pub sync_writes: bool,
+    /// Syncs the directory that held each deleted object before
+    /// [`FileStorage::delete`] returns, so a crash cannot bring the object
+    /// back. Applies whether or not [`sync_writes`](Self::sync_writes) is set,
+    /// and bypasses [`group_commit_interval`](Self::group_commit_interval).
+    pub sync_deletes: bool,
+    /// Makes [`FileStorage::put`] and [`FileStorage::put_with`] compare the
+    /// incoming bytes with the stored object and leave its file untouched
+    /// when they match, reporting [`PutOutcome::Unchanged`]. The object keeps
+    /// its modification time and entity tag, while attributes given with the
+    /// put are still applied.
+    ///
+    /// Each put over an object of the same size reads that object first.
+    pub skip_identical: bool,
+    /// With [`sync_writes`](Self::sync_writes), defers the directory syncs and
+    /// issues them together once per interval so bursts of writes share them.
+    ///
+    /// Writes are still applied in order and visible immediately, and object
+    /// contents are still flushed before each put returns, but a put or
+    /// delete can be lost in a crash up to one interval after it returned.
+    /// [`FileStorage::flush`] closes that window on demand.
+    pub group_commit_interval: Option<Duration>,
+    /// Longest a single [`put_with`](FileStorage::put_with),
+    /// [`get`](FileStorage::get), or [`delete`](FileStorage::delete) may take
+    /// before it fails with [`StorageError::Timeout`], so a hung mount cannot
+    /// stall callers forever.
+    ///
+    /// A timed-out operation may still complete in the background.
+    pub op_timeout: Option<Duration>,
+    /// Records each put object's SHA-256 in a per-directory index so
+    /// [`FileStorage::verify`] and [`FileStorage::repair_from`] can skip
+    /// rehashing objects that have not changed since.
+    pub checksum_index: bool,
+    /// On-disk layout for keys; [`DefaultKeyMapper`] when unset.
+    pub key_mapper: Option<Arc<dyn KeyMapper>>,
+    /// How written temp files replace existing objects; use
+    /// [`RenameStrategy::Fallback`] on network filesystems whose renames
+    /// cannot replace a file atomically.
+    pub rename_strategy: RenameStrategy,
+    /// Keeps every key in memory so [`FileStorage::list`] answers without
+    /// walking the tree, rescanning the root once per interval.
+    ///
+    /// Writes made through the store are reflected immediately. Objects added
+    /// or removed by anything else appear in or drop out of listings only at
+    /// the next rescan or [`FileStorage::refresh`], so listings may be stale
+    /// by up to one interval.
+    pub listing_cache: Option<Duration>,
+    pub symlink_policy: SymlinkPolicy,
+    /// Logs the renames of [`FileStorage::put_many`] and
+    /// [`FileStorage::rename_prefix`] before applying them, so a batch cut
+    /// short by a crash is completed the next time the store is opened.
+    ///
+    /// Opening a store always replays a log left behind, even with this off.
+    pub write_ahead_log: bool,
+    /// Directories read at the same time while walking the tree for listings,
+    /// scans, and [`FileStorage::verify_all`]; 8 when unset.
+    ///
+    /// Each concurrent read holds one file descriptor open.
+    pub walk_parallelism: Option<NonZeroUsize>,
+    /// Byte limits for the objects under each top-level prefix, such as
+    /// `tenant-a` for keys like `tenant-a/report.pdf`; keys without a `/` are
+    /// never limited.
+    ///
+    /// Writes that would take a prefix over its limit fail with
+    /// [`StorageError::QuotaExceeded`]. Usage is counted by a scan on open and
+    /// then tracked by every write and delete made through the store.
+    pub prefix_quotas: HashMap<String, u64>,
+    /// Keeps the set of stored keys in memory and in a journal under the root,
+    /// so [`FileStorage::exists`] and [`FileStorage::head`] answer for absent
+    /// keys, and `exists` for present ones, without a filesystem lookup.
+    ///
+    /// Opening the store reconciles the journal with a full scan, repairing
+    /// any drift left by a crash or by changes made behind the store's back.
+    /// Objects written to the root by anything else while the store is open
+    /// may be reported as missing until it is reopened or
+    /// [`FileStorage::refresh`] is called.
+    pub key_index: bool,
+    /// Objects written, read, or deleted at the same time by
+    /// [`FileStorage::put_many`], [`FileStorage::get_many`], and
+    /// [`FileStorage::delete_many`]; the number of CPUs when unset.
+    pub batch_concurrency: Option<NonZeroUsize>,
+    /// Roots for objects by size. A put stores its object under the root of
+    /// the tier with the highest `min_size` it reaches, or under the main
+    /// root when it reaches none, and removes any copy left in another tier.
+    /// Reads and deletes look in the main root first and then in each tier.
+    ///
+    /// Writes in place, such as [`FileStorage::append_record`], and attribute
+    /// reads go to whichever root holds the object, which stays there.
+    /// [`FileStorage::put_stream`] and [`FileStorage::copy_many`] route their
+    /// objects by size like puts, while [`FileStorage::put_many`] writes under
+    /// the main root. Sweeping operations such as [`FileStorage::clear`],
+    /// [`FileStorage::retain`], and [`FileStorage::verify_all`] walk every
+    /// root. Backups, the trash, claims, and [`FileStorage::rename_prefix`]
+    /// see the main root alone, as do the existence and key indexes and
+    /// prefix quotas.
+    pub tiering: Vec<Tier>,
+    /// Most keys [`FileStorage::list`] returns before failing with
+    /// [`StorageError::ListTooLarge`] instead, so a listing of a huge prefix
+    /// cannot exhaust the caller. [`FileStorage::list_delimited`] and
+    /// [`FileStorage::export_tar`] are not limited.
+    pub max_list_entries: Option<usize>,
+    /// Compresses the objects [`FileStorage::put`] and
+    /// [`FileStorage::put_with`] write, unless a sample of their first 64 KiB
+    /// shrinks by less than a tenth, in which case they are stored as given
+    /// with a header flagging them uncompressed. Reads decode both kinds, and
+    /// objects written by other means are stored as given.
+    ///
+    /// Reads only decode objects while this is on, so keep it on once objects
+    /// were stored with it. Range reads of a compressed object decompress all
+    /// of it, and writes in place store it uncompressed first.
+    pub compression: bool,
+}
 
 #[derive(Clone, Debug)]
 pub struct FileStorage {
     root: PathBuf,
+    /// `root` with symlinks resolved, which every object path must stay under.
+    canonical_root: PathBuf,
+    replica: Option<Arc<FileStorage>>,
+    replica_policy: ReplicaPolicy,
+    index: Option<Arc<ExistenceIndex>>,
+    listing: Option<Arc<ListingCache>>,
+    keys: Option<Arc<KeyIndex>>,
+    /// Serializes writes to each key; shared by namespaces of the same store.
+    locks: Arc<KeyLocks>,
+    /// Parent directories puts have already created.
+    dirs: Arc<DirCache>,
+    durability: Durability,
+    sync_deletes: bool,
+    skip_identical: bool,
+    op_timeout: Option<Duration>,
+    /// Serializes checksum index updates and counts their appends; `None`
+    /// when the index is disabled.
+    checksums: Option<Arc<tokio::sync::Mutex<IndexAppends>>>,
+    mapper: Arc<dyn KeyMapper>,
+    rename_strategy: RenameStrategy,
+    symlink_policy: SymlinkPolicy,
+    /// Shared by namespaces, which log paths relative to the top-level root.
+    wal: Option<Arc<Wal>>,
+    walk_parallelism: usize,
+    batch_concurrency: usize,
+    /// Shared by namespaces, which charge keys qualified by their prefix.
+    quotas: Option<Arc<Quotas>>,
+    /// Stores for [`StorageOptions::tiering`] with their size thresholds, in
+    /// ascending order.
+    tiers: Vec<(u64, Arc<FileStorage>)>,
+    max_list_entries: Option<usize>,
+    compression: bool,
+    /// Key prefix, ending in `/`, of a handle created by
+    /// [`namespace`](Self::namespace); empty for the top-level store.
+    namespace: String,
 }
 
 impl FileStorage {
     pub async fn new<P: AsRef<Path>>(root: P) -> Result<Self, StorageError> {
+        Self::with_options(root, StorageOptions::default()).await
+    }
+
+    /// Opens a store at an existing `root` directory.
+    ///
+    /// Unlike [`new`](Self::new), a missing root is reported as
+    /// [`StorageError::RootMissing`] instead of being created, which catches
+    /// misconfigured paths early.
+    pub async fn open<P: AsRef<Path>>(root: P) -> Result<Self, StorageError> {
+        let root = root.as_ref();
+        match fs::metadata(root).await {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => return Err(StorageError::RootNotDirectory(root.to_path_buf())),
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Err(StorageError::RootMissing(root.to_path_buf()));
+            }
+            Err(err) => return Err(StorageError::from(err)),
+        }
+        Self::new(root).await
+    }
+
+    /// Opens a store at `root`, creating it if needed, configured by `options`.
+    pub async fn with_options<P: AsRef<Path>>(
+        root: P,
+        options: StorageOptions,
+    ) -> Result<Self, StorageError> {
         let root = root.as_ref().to_path_buf();
-        fs::create_dir_all(&root).await?;
-        Ok(Self { root })
+        create_root(&root).await?;
+        wal::replay(&root, options.rename_strategy).await?;
+        let walk_parallelism = options
+            .walk_parallelism
+            .map_or(DEFAULT_WALK_PARALLELISM, NonZeroUsize::get);
+        let batch_concurrency = options.batch_concurrency.map_or_else(
+            || std::thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            NonZeroUsize::get,
+        );
+        let durability = match (options.sync_writes, options.group_commit_interval) {
+            (false, _) => Durability::None,
+            (true, Some(interval)) if !interval.is_zero() => {
+                Durability::Grouped(GroupCommit::start(interval))
+            }
+            (true, _) => Durability::Immediate,
+        };
+        let mapper = options
+            .key_mapper
+            .unwrap_or_else(|| Arc::new(DefaultKeyMapper));
+        let replica = match options.replica_root {
+            Some(replica_root) => {
+                create_root(&replica_root).await?;
+                Some(Arc::new(Self {
+                    canonical_root: fs::canonicalize(&replica_root).await?,
+                    root: replica_root,
+                    replica: None,
+                    replica_policy: ReplicaPolicy::default(),
+                    index: None,
+                    listing: None,
+                    keys: None,
+                    locks: Arc::default(),
+                    dirs: Arc::default(),
+                    durability: durability.clone(),
+                    sync_deletes: options.sync_deletes,
+                    skip_identical: options.skip_identical,
+                    op_timeout: None,
+                    checksums: None,
+                    mapper: mapper.clone(),
+                    rename_strategy: options.rename_strategy,
+                    symlink_policy: options.symlink_policy,
+                    wal: None,
+                    walk_parallelism,
+                    batch_concurrency,
+                    quotas: None,
+                    tiers: Vec::new(),
+                    max_list_entries: None,
+                    compression: options.compression,
+                    namespace: String::new(),
+                }))
+            }
+            None => None,
+        };
+        let wal = options.write_ahead_log.then(|| Arc::new(Wal::new(&root)));
+        let mut storage = Self {
+            canonical_root: fs::canonicalize(&root).await?,
+            root,
+            replica,
+            replica_policy: options.replica_policy,
+            index: None,
+            listing: None,
+            keys: None,
+            locks: Arc::default(),
+            dirs: Arc::default(),
+            durability,
+            sync_deletes: options.sync_deletes,
+            skip_identical: options.skip_identical,
+            op_timeout: options.op_timeout,
+            checksums: options.checksum_index.then(Arc::default),
+            mapper,
+            rename_strategy: options.rename_strategy,
+            symlink_policy: options.symlink_policy,
+            wal,
+            walk_parallelism,
+            batch_concurrency,
+            quotas: None,
+            tiers: Vec::new(),
+            max_list_entries: options.max_list_entries,
+            compression: options.compression,
+            namespace: String::new(),
+        };
+        let mut tiering = options.tiering;
+        tiering.sort_by_key(|tier| tier.min_size);
+        for tier in tiering {
+            create_root(&tier.root).await?;
+            let store = Self {
+                canonical_root: fs::canonicalize(&tier.root).await?,
+                root: tier.root,
+                replica: None,
+                locks: Arc::default(),
+                wal: None,
+                tiers: Vec::new(),
+                ..storage.clone()
+            };
+            storage.tiers.push((tier.min_size, Arc::new(store)));
+        }
+        if options.existence_index {
+            let keys = storage.scan().await?.keys();
+            let index = ExistenceIndex::with_capacity(keys.len() * 2);
+            for key in &keys {
+                index.insert(key);
+            }
+            storage.index = Some(Arc::new(index));
+        }
+        if !options.prefix_quotas.is_empty() {
+            storage.quotas = Some(Quotas::start(&options.prefix_quotas, storage.clone()).await?);
+        }
+        if let Some(interval) = options.listing_cache {
+            let keys = storage.scan().await?.keys();
+            storage.listing = Some(ListingCache::start(keys, storage.clone(), interval));
+        }
+        if options.key_index {
+            let keys = storage.scan().await?.keys();
+            let index = KeyIndex::start(&storage.root, keys, storage.rename_strategy).await?;
+            storage.keys = Some(index);
+        }
+        Ok(storage)
+    }
+
+    /// Returns the directory objects are stored under.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns a handle whose keys are relative to `prefix`.
+    ///
+    /// The handle is rooted at `<root>/<prefix>`, so it can neither see nor
+    /// modify keys outside the prefix, and shares this store's configuration
+    /// (including the replica, which is scoped the same way). `prefix` must be
+    /// a valid key; a trailing `/` is ignored.
+    pub fn namespace(&self, prefix: &str) -> Result<FileStorage, StorageError> {
+        let prefix = prefix.trim_end_matches('/');
+        validate_key(prefix)?;
+        let replica = self
+            .replica
+            .as_ref()
+            .map(|replica| replica.namespace(prefix).map(Arc::new))
+            .transpose()?;
+        let tiers = self
+            .tiers
+            .iter()
+            .map(|(min_size, tier)| Ok((*min_size, Arc::new(tier.namespace(prefix)?))))
+            .collect::<Result<_, StorageError>>()?;
+        Ok(Self {
+            root: self.root.join(prefix),
+            canonical_root: self.canonical_root.join(prefix),
+            replica,
+            tiers,
+            namespace: format!("{}{prefix}/", self.namespace),
+            ..self.clone()
+        })
+    }
+
+    /// Returns the filesystem path `key` is stored at, after validating it.
+    ///
+    /// Useful for handing an object to tools that need a real path. The path
+    /// is not required to exist.
+    pub fn resolve(&self, key: &str) -> Result<PathBuf, StorageError> {
+        self.path_for(key)
+    }
+
+    pub async fn put(&self, key: &str, data: &[u8]) -> Result<PutOutcome, StorageError> {
+        self.put_with(key, data, &PutOptions::default()).await
+    }
+
+    /// Like [`put`](Self::put), but also reports the bytes written and how long it took.
+    pub async fn put_timed(&self, key: &str, data: &[u8]) -> Result<OperationResult, StorageError> {
+        let started = Instant::now();
+        self.put(key, data).await?;
+        Ok(OperationResult {
+            bytes: data.len() as u64,
+            elapsed: started.elapsed(),
+        })
+    }
+
+    /// Stores several objects, replacing any previous versions and their attributes.
+    ///
+    /// Every object is written to a temp file, up to
+    /// [`StorageOptions::batch_concurrency`] at once, before any of them is
+    /// renamed into place, so a failed write leaves the store untouched. With
+    /// [`StorageOptions::write_ahead_log`], a crash during the renames is
+    /// completed the next time the store is opened; without it, only some of
+    /// the objects may have been replaced. With [`StorageOptions::tiering`],
+    /// the objects go under the main root whatever their size, where the log
+    /// covers them, and copies left in the tiers are removed.
+    pub async fn put_many(&self, objects: &[(&str, &[u8])]) -> Result<(), StorageError> {
+        let mut paths = Vec::with_capacity(objects.len());
+        for (key, _) in objects {
+            let path = self.path_for(key)?;
+            self.ensure_within_root(key, &path).await?;
+            paths.push(path);
+        }
+        // Taking the locks in key order keeps concurrent batches from deadlocking.
+        let mut keys: Vec<&str> = objects.iter().map(|(key, _)| *key).collect();
+        keys.sort_unstable();
+        keys.dedup();
+        let mut _guards = Vec::with_capacity(keys.len());
+        for key in &keys {
+            _guards.push(self.lock_key(key).await);
+        }
+        let mut charges = match &self.quotas {
+            Some(quotas) => {
+                let keys: Vec<String> = keys.iter().map(|key| self.qualified(key)).collect();
+                quotas.charge_all(keys.iter().map(String::as_str)).await?
+            }
+            None => Vec::new(),
+        };
+        // Per charged prefix, the bytes the batch replaces and writes. A key
+        // written twice only counts with its last contents.
+        let mut changes = vec![(0, 0); charges.len()];
+        for (i, ((key, data), path)) in objects.iter().zip(&paths).enumerate() {
+            let key = self.qualified(key);
+            let Some(at) = quota::tenant(&key)
+                .and_then(|prefix| charges.iter().position(|charge| charge.prefix() == prefix))
+            else {
+                continue;
+            };
+            if objects[i + 1..]
+                .iter()
+                .any(|(later, _)| self.qualified(later) == key)
+            {
+                continue;
+            }
+            changes[at].0 += quota::file_len(path).await?;
+            changes[at].1 += data.len() as u64;
+        }
+        for (charge, &(old, new)) in charges.iter().zip(&changes) {
+            charge.check(old, new)?;
+        }
+
+        let sync = self.durability.syncs_files() || self.wal.is_some();
+        let temps: Vec<PathBuf> = paths
+            .iter()
+            .map(|path| atomic::temp_path_for(path))
+            .collect();
+        let writes = objects
+            .iter()
+            .zip(&paths)
+            .zip(&temps)
+            .map(|(((key, data), path), tmp)| {
+                let (key, path, tmp) = (key.to_string(), path.clone(), tmp.clone());
+                let data = bytes::Bytes::copy_from_slice(data);
+                async move {
+                    create_parent(&key, &path).await?;
+                    let mut file = fs::File::create(&tmp).await?;
+                    file.write_all(&data).await?;
+                    if sync {
+                        file.sync_all().await?;
+                    } else {
+                        file.flush().await?;
+                    }
+                    Ok(())
+                }
+            });
+        if let Err(err) = self.run_bounded(writes).await {
+            for tmp in &temps {
+                let _ = fs::remove_file(tmp).await;
+            }
+            return Err(err);
+        }
+
+        for (key, _) in objects {
+            self.index_insert(key);
+        }
+        let batch = match &self.wal {
+            Some(wal) => {
+                let steps = temps.iter().zip(&paths).map(|(tmp, path)| Step::Replace {
+                    src: tmp.clone(),
+                    dst: path.clone(),
+                });
+                Some(wal.begin(steps).await?)
+            }
+            None => None,
+        };
+        for (i, ((key, data), path)) in objects.iter().zip(&paths).enumerate() {
+            Sidecar::remove_all(path).await?;
+            if let Err(err) = atomic::rename(&temps[i], path, self.rename_strategy).await {
+                // Without a log nothing would finish the batch, so drop the rest.
+                if batch.is_none() {
+                    for tmp in &temps[i..] {
+                        let _ = fs::remove_file(tmp).await;
+                    }
+                }
+                for charge in &mut charges {
+                    charge.forget();
+                }
+                return Err(io_error(key, err));
+            }
+            self.record_key(key);
+            self.record_checksum(path, data).await?;
+            self.durability.sync_parent(path).await?;
+            self.evict_from_other_tiers(key, None).await?;
+        }
+        if let Some(batch) = batch {
+            batch.finish().await?;
+        }
+        for (charge, (old, new)) in charges.iter_mut().zip(changes) {
+            charge.apply(old, new);
+        }
+
+        if let Some(replica) = &self.replica {
+            for ((key, _), path) in objects.iter().zip(&paths) {
+                let result = replica.copy_in(key, path).await;
+                self.apply_replica_policy(key, result)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stores `data` under `key` along with its content type and user metadata.
+    ///
+    /// Attributes from any previous version of the object are replaced.
+    pub async fn put_with(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &PutOptions,
+    ) -> Result<PutOutcome, StorageError> {
+        self.timed(key, async {
+            let _guard = self.lock_key(key).await;
+            self.check_condition(key, &options.condition).await?;
+            if !options.create_parents {
+                self.ensure_prefix(key).await?;
+            }
+            let outcome = self.put_routed(key, data, options).await?;
+            if let Some(replica) = &self.replica {
+                let result = replica.put_local(key, data, options).await.map(drop);
+                self.apply_replica_policy(key, result)?;
+            }
+            Ok(outcome)
+        })
+        .await
+    }
+
+    async fn put_local(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &PutOptions,
+    ) -> Result<PutOutcome, StorageError> {
+        let path = self.path_for(key)?;
+        self.ensure_within_root(key, &path).await?;
+        let metadata = sidecar::encode_metadata(&options.metadata)?;
+        let encoded = self.compression.then(|| compression::encode(data));
+        let stored = encoded.as_deref().unwrap_or(data);
+        if self.skip_identical && same_content(&path, stored).await {
+            self.index_insert(key);
+            self.write_attributes(&path, options, metadata.as_deref())
+                .await?;
+            self.record_key(key);
+            self.durability.sync_parent(&path).await?;
+            return Ok(PutOutcome::Unchanged);
+        }
+        let reservation = self
+            .reserve_quota(key, &path, |_| stored.len() as u64)
+            .await?;
+        self.index_insert(key);
+        let cached = self.create_parent_cached(key, &path).await?;
+        let write = || async {
+            if self.durability.syncs_files() {
+                atomic::write_atomic_synced(&path, stored, self.rename_strategy).await
+            } else {
+                atomic::write_atomic(&path, stored, self.rename_strategy).await
+            }
+        };
+        let mut written = write().await;
+        if cached && matches!(&written, Err(err) if err.kind() == ErrorKind::NotFound) {
+            // The cached parent was removed behind the store's back.
+            self.dirs.clear();
+            self.create_parent_cached(key, &path).await?;
+            written = write().await;
+        }
+        written.map_err(|err| io_error(key, err))?;
+        if let Some(reservation) = reservation {
+            reservation.settle();
+        }
+        self.write_attributes(&path, options, metadata.as_deref())
+            .await?;
+        self.record_key(key);
+        self.record_checksum(&path, data).await?;
+        self.durability.sync_parent(&path).await?;
+        Ok(PutOutcome::Written)
+    }
+
+    /// Replaces the content type, metadata, and expiry of the object at `path`.
+    async fn write_attributes(
+        &self,
+        path: &Path,
+        options: &PutOptions,
+        metadata: Option<&str>,
+    ) -> Result<(), StorageError> {
+        Sidecar::ContentType
+            .write(path, options.content_type.as_deref(), self.rename_strategy)
+            .await?;
+        Sidecar::Metadata
+            .write(path, metadata, self.rename_strategy)
+            .await?;
+        let expiry = options.expires_at.map(sidecar::encode_expiry);
+        Sidecar::Expiry
+            .write(path, expiry.as_deref(), self.rename_strategy)
+            .await?;
+        Ok(())
+    }
+
+    /// Fails with [`StorageError::PrefixNotFound`] unless the directory that
+    /// would hold `key` exists under the main root or a tier.
+    async fn ensure_prefix(&self, key: &str) -> Result<(), StorageError> {
+        let Some((prefix, _)) = key.rsplit_once('/') else {
+            return Ok(());
+        };
+        let tiers = self.tiers.iter().map(|(_, tier)| tier.as_ref());
+        for store in std::iter::once(self).chain(tiers) {
+            let path = store.path_for(key)?;
+            let Some(parent) = path.parent() else {
+                return Ok(());
+            };
+            match fs::metadata(parent).await {
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                // Anything else is left for the write to report.
+                _ => return Ok(()),
+            }
+        }
+        Err(StorageError::PrefixNotFound(prefix.to_string()))
+    }
+
+    async fn check_condition(
+        &self,
+        key: &str,
+        condition: &PutCondition,
+    ) -> Result<(), StorageError> {
+        if *condition == PutCondition::Always {
+            return Ok(());
+        }
+        let current = match self.head(key).await {
+            Ok(metadata) => Some(metadata),
+            Err(StorageError::NotFound(_)) => None,
+            Err(err) => return Err(err),
+        };
+        match (condition, current) {
+            (PutCondition::IfAbsent, Some(_)) => {
+                Err(StorageError::Conflict(format!("`{key}` already exists")))
+            }
+            (PutCondition::IfMatch(_), None) => Err(StorageError::VersionMismatch(key.to_string())),
+            (PutCondition::IfMatch(etag), Some(metadata))
+                if etag != "*" && *etag != metadata.etag =>
+            {
+                Err(StorageError::VersionMismatch(key.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Stores `data` under `key` so that it reads as missing once `ttl` has elapsed.
+    ///
+    /// Expired objects are reported as [`StorageError::NotFound`] by reads but
+    /// keep occupying disk space until they are deleted.
+    pub async fn put_with_ttl(
+        &self,
+        key: &str,
+        data: &[u8],
+        ttl: Duration,
+    ) -> Result<PutOutcome, StorageError> {
+        let options = PutOptions {
+            expires_at: Some(SystemTime::now() + ttl),
+            ..PutOptions::default()
+        };
+        self.put_with(key, data, &options).await
+    }
+
+    /// Returns when `key` expires, if it was stored with a TTL.
+    pub async fn expires_at(&self, key: &str) -> Result<Option<SystemTime>, StorageError> {
+        let path = self.path_for(key)?;
+        if let Some(tier) = self.tier_holding(key).await? {
+            return Box::pin(tier.expires_at(key)).await;
+        }
+        self.expires_at_here(key, &path).await
+    }
+
+    /// Like [`expires_at`](Self::expires_at), without looking in other tiers.
+    async fn expires_at_here(
+        &self,
+        key: &str,
+        path: &Path,
+    ) -> Result<Option<SystemTime>, StorageError> {
+        self.ensure_within_root(key, path).await?;
+        let encoded = Sidecar::Expiry.read(path).await?;
+        Ok(encoded.as_deref().and_then(sidecar::decode_expiry))
+    }
+
+    /// Replaces `key` while keeping its previous content, and its attributes,
+    /// under `<key>.bak`.
+    ///
+    /// The old object is copied into place before the new content is stored
+    /// the way [`put`](Self::put) stores it, so readers of `key` always see
+    /// either the old or new bytes.
+    pub async fn put_with_backup(&self, key: &str, data: &[u8]) -> Result<(), StorageError> {
+        let backup_key = backup_key(key);
+        self.timed(key, async {
+            let _guards = self.lock_keys(&[key, &backup_key]).await;
+            self.back_up(key, &backup_key).await?;
+            self.put_routed(key, data, &PutOptions::default()).await?;
+            if let Some(replica) = &self.replica {
+                let result = async {
+                    replica.back_up(key, &backup_key).await?;
+                    replica.put_local(key, data, &PutOptions::default()).await
+                }
+                .await;
+                self.apply_replica_policy(key, result.map(drop))?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Copies `key` over `backup_key`, doing nothing when `key` does not
+    /// exist; the caller holds both keys' locks.
+    async fn back_up(&self, key: &str, backup_key: &str) -> Result<(), StorageError> {
+        match self.copy_routed(key, backup_key).await {
+            Err(StorageError::NotFound(missing)) if missing == key => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Swaps `key` with its `<key>.bak` copy created by [`put_with_backup`](Self::put_with_backup).
+    ///
+    /// Afterwards `key` holds the backed-up bytes and `<key>.bak` holds what
+    /// `key` contained before the restore, each with its attributes. When
+    /// `key` does not exist, the backup is moved to it.
+    pub async fn restore_backup(&self, key: &str) -> Result<(), StorageError> {
+        let backup_key = backup_key(key);
+        self.timed(key, async {
+            let _guards = self.lock_keys(&[key, &backup_key]).await;
+            self.restore_local(key, &backup_key).await?;
+            if let Some(replica) = &self.replica {
+                let result = replica.restore_local(key, &backup_key).await;
+                self.apply_replica_policy(key, result)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Swaps `key` with `backup_key`, or moves the backup to `key` when only
+    /// the backup exists; the caller holds both keys' locks.
+    async fn restore_local(&self, key: &str, backup_key: &str) -> Result<(), StorageError> {
+        let backup_store = self.holder(backup_key).await?;
+        if !backup_store.exists_here(backup_key).await? {
+            return Err(StorageError::NotFound(backup_key.to_string()));
+        }
+        let store = self.holder(key).await?;
+        if !store.exists_here(key).await? {
+            backup_store.move_local(backup_key, key).await?;
+            self.evict_from_other_tiers(key, self.tier_index(backup_store))
+                .await?;
+        } else if std::ptr::eq(store, backup_store) {
+            store.swap_local(key, backup_key).await?;
+        } else {
+            self.swap_across_tiers((store, key), (backup_store, backup_key))
+                .await?;
+        }
+        self.invalidate_quotas(key).await;
+        Ok(())
+    }
+
+    /// Exchanges the objects stored under `a` and `b`, along with their
+    /// content types, metadata, and expiry times.
+    ///
+    /// Both keys must exist. The content of `a` is first linked aside under a
+    /// temporary name, and each key is then replaced by a single rename, so
+    /// readers of either key always find one of the two objects. The
+    /// attributes are exchanged right after the content.
+    pub async fn swap(&self, a: &str, b: &str) -> Result<(), StorageError> {
+        self.timed(a, async {
+            let _guards = self.lock_keys(&[a, b]).await;
+            let (store_a, store_b) = (self.holder(a).await?, self.holder(b).await?);
+            if std::ptr::eq(store_a, store_b) {
+                store_a.swap_local(a, b).await?;
+            } else {
+                self.swap_across_tiers((store_a, a), (store_b, b)).await?;
+            }
+            if let Some(replica) = &self.replica {
+                let result = replica.swap_local(a, b).await;
+                self.apply_replica_policy(a, result)?;
+            }
+            Ok(())
+        })
+        .await
     }
 
-    pub async fn put(&self, key: &str, data: &[u8]) -> Result<(), StorageError> {
+    async fn swap_local(&self, a: &str, b: &str) -> Result<(), StorageError> {
+        let path_a = self.path_for(a)?;
+        let path_b = self.path_for(b)?;
+        for (key, path) in [(a, &path_a), (b, &path_b)] {
+            if !self.may_exist(key) {
+                return Err(StorageError::NotFound(key.to_string()));
+            }
+            self.ensure_within_root(key, path).await?;
+            self.ensure_live(key).await?;
+            match fs::symlink_metadata(path).await {
+                Ok(metadata) if !metadata.is_dir() => {}
+                Ok(_) => return Err(StorageError::NotFound(key.to_string())),
+                Err(err) => return Err(io_error(key, err)),
+            }
+        }
+        if path_a == path_b {
+            return Ok(());
+        }
+
+        let keys = [self.qualified(a), self.qualified(b)];
+        let mut charges = match &self.quotas {
+            Some(quotas) => quotas.charge_all(keys.iter().map(String::as_str)).await?,
+            None => Vec::new(),
+        };
+        let lens = [
+            quota::file_len(&path_a).await?,
+            quota::file_len(&path_b).await?,
+        ];
+        // Per charged prefix, the bytes its keys hold before and after the swap.
+        let mut changes = Vec::with_capacity(charges.len());
+        for charge in &charges {
+            let (mut old, mut new) = (0, 0);
+            for (i, key) in keys.iter().enumerate() {
+                if quota::tenant(key) == Some(charge.prefix()) {
+                    old += lens[i];
+                    new += lens[1 - i];
+                }
+            }
+            charge.check(old, new)?;
+            changes.push((old, new));
+        }
+
+        let stash = atomic::temp_path_for(&path_a);
+        atomic::link_or_copy(&path_a, &stash)
+            .await
+            .map_err(|err| io_error(a, err))?;
+        if let Err(err) = atomic::rename(&path_b, &path_a, self.rename_strategy).await {
+            if self.rename_strategy == RenameStrategy::Fallback {
+                // The fallback already removed `a`; put its content back.
+                let _ = fs::rename(&stash, &path_a).await;
+            }
+            let _ = fs::remove_file(&stash).await;
+            return Err(io_error(b, err));
+        }
+        if let Err(err) = atomic::rename(&stash, &path_b, self.rename_strategy).await {
+            // Move both objects back where they were.
+            let _ = fs::rename(&path_a, &path_b).await;
+            let _ = fs::rename(&stash, &path_a).await;
+            return Err(io_error(a, err));
+        }
+        for (charge, (old, new)) in charges.iter_mut().zip(changes) {
+            charge.apply(old, new);
+        }
+        Sidecar::swap_all(&path_a, &path_b).await?;
+        self.forget_checksum(&path_a).await?;
+        self.forget_checksum(&path_b).await?;
+        self.durability.sync_parent(&path_a).await?;
+        self.durability.sync_parent(&path_b).await?;
+        Ok(())
+    }
+
+    /// Writes `data` into `key` starting at byte `offset`, creating the object if needed.
+    ///
+    /// Bytes between the previous end of the object and `offset` read as
+    /// zeros. The write is ordered against puts, deletes, and other range
+    /// writes to `key`, but it happens in place, so unlike [`put`](Self::put)
+    /// a concurrent reader can observe it partially applied.
+    pub async fn write_range(
+        &self,
+        key: &str,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), StorageError> {
+        self.timed(key, async {
+            let _guard = self.lock_key(key).await;
+            match self.tier_holding(key).await? {
+                Some(tier) => tier.write_range_local(key, offset, data).await?,
+                None => self.write_range_local(key, offset, data).await?,
+            }
+            if let Some(replica) = &self.replica {
+                let result = replica.write_range_local(key, offset, data).await;
+                self.apply_replica_policy(key, result)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn write_range_local(
+        &self,
+        key: &str,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), StorageError> {
         let path = self.path_for(key)?;
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent).await?;
+        self.ensure_within_root(key, &path).await?;
+        self.decompress_in_place(key, &path).await?;
+        let end = offset.saturating_add(data.len() as u64);
+        let reservation = self.reserve_quota(key, &path, |old| old.max(end)).await?;
+        self.index_insert(key);
+        create_parent(key, &path).await?;
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .await
+            .map_err(|err| io_error(key, err))?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        file.write_all(data).await?;
+        if self.durability.syncs_files() {
+            file.sync_all().await?;
+        } else {
+            file.flush().await?;
+        }
+        if let Some(reservation) = reservation {
+            reservation.settle();
         }
-        fs::write(path, data).await.map_err(StorageError::from)?;
+        self.record_key(key);
+        self.forget_checksum(&path).await?;
+        self.durability.sync_parent(&path).await?;
+        Ok(())
+    }
+
+    /// Appends `record` and a trailing newline to `key`, creating the object
+    /// if needed.
+    ///
+    /// The line is written in one call under the key's lock, so records
+    /// appended concurrently never interleave. `record` should not contain
+    /// a newline itself.
+    pub async fn append_record(&self, key: &str, record: &[u8]) -> Result<(), StorageError> {
+        let mut line = Vec::with_capacity(record.len() + 1);
+        line.extend_from_slice(record);
+        line.push(b'\n');
+        self.timed(key, async {
+            let _guard = self.lock_key(key).await;
+            match self.tier_holding(key).await? {
+                Some(tier) => tier.append_local(key, &line).await?,
+                None => self.append_local(key, &line).await?,
+            }
+            if let Some(replica) = &self.replica {
+                let result = replica.append_local(key, &line).await;
+                self.apply_replica_policy(key, result)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn append_local(&self, key: &str, data: &[u8]) -> Result<(), StorageError> {
+        let path = self.path_for(key)?;
+        self.ensure_within_root(key, &path).await?;
+        self.decompress_in_place(key, &path).await?;
+        let reservation = self
+            .reserve_quota(key, &path, |old| old.saturating_add(data.len() as u64))
+            .await?;
+        self.index_insert(key);
+        create_parent(key, &path).await?;
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .await
+            .map_err(|err| io_error(key, err))?;
+        file.write_all(data).await?;
+        if self.durability.syncs_files() {
+            file.sync_all().await?;
+        } else {
+            file.flush().await?;
+        }
+        if let Some(reservation) = reservation {
+            reservation.settle();
+        }
+        self.record_key(key);
+        self.forget_checksum(&path).await?;
+        self.durability.sync_parent(&path).await?;
         Ok(())
     }
 
     pub async fn get(&self, key: &str) -> Result<Vec<u8>, StorageError> {
         let path = self.path_for(key)?;
-        match fs::read(path).await {
-            Ok(bytes) => Ok(bytes),
-            Err(err) if err.kind() == ErrorKind::NotFound => {
-                Err(StorageError::NotFound(key.to_string()))
+        if let Some(tier) = self.tier_holding(key).await? {
+            return Box::pin(tier.get(key)).await;
+        }
+        if !self.may_exist(key) {
+            return Err(StorageError::NotFound(key.to_string()));
+        }
+        self.timed(key, async {
+            self.ensure_within_root(key, &path).await?;
+            self.ensure_live(key).await?;
+            if let Some(target) = self.link_target(key, &path).await? {
+                return Ok(target);
+            }
+            if self.compression {
+                return Ok(self.open_stored(key, &path).await?.into_bytes().await?);
+            }
+            fs::read(path).await.map_err(|err| io_error(key, err))
+        })
+        .await
+    }
+
+    /// Like [`get`](Self::get), but also reports the bytes read and how long it took.
+    pub async fn get_timed(&self, key: &str) -> Result<(Vec<u8>, OperationResult), StorageError> {
+        let started = Instant::now();
+        let bytes = self.get(key).await?;
+        let result = OperationResult {
+            bytes: bytes.len() as u64,
+            elapsed: started.elapsed(),
+        };
+        Ok((bytes, result))
+    }
+
+    /// Reads the object stored under `key` as UTF-8 text.
+    pub async fn get_text(&self, key: &str) -> Result<String, StorageError> {
+        let bytes = self.get(key).await?;
+        String::from_utf8(bytes).map_err(|err| StorageError::Encoding {
+            key: key.to_string(),
+            source: err.utf8_error(),
+        })
+    }
+
+    /// Stores `value` under `key` serialized as JSON.
+    #[cfg(feature = "json")]
+    pub async fn put_json<T: serde::Serialize>(
+        &self,
+        key: &str,
+        value: &T,
+    ) -> Result<PutOutcome, StorageError> {
+        let data = serde_json::to_vec(value).map_err(|source| StorageError::Serialization {
+            key: key.to_string(),
+            source,
+        })?;
+        self.put(key, &data).await
+    }
+
+    /// Reads the object stored under `key` and deserializes it from JSON.
+    #[cfg(feature = "json")]
+    pub async fn get_json<T: serde::de::DeserializeOwned>(
+        &self,
+        key: &str,
+    ) -> Result<T, StorageError> {
+        let bytes = self.get(key).await?;
+        serde_json::from_slice(&bytes).map_err(|source| StorageError::Serialization {
+            key: key.to_string(),
+            source,
+        })
+    }
+
+    /// Applies `f` to the JSON value stored under `key`, or to `default` when
+    /// there is none, stores the result, and returns it.
+    ///
+    /// The key stays locked from the read to the write, so concurrent updates
+    /// through this store are applied one after another and none is lost.
+    /// The object keeps its content type, metadata, and expiry time.
+    #[cfg(feature = "json")]
+    pub async fn update_json<T, F>(&self, key: &str, default: T, f: F) -> Result<T, StorageError>
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+        F: FnOnce(&mut T),
+    {
+        let serialization = |source| StorageError::Serialization {
+            key: key.to_string(),
+            source,
+        };
+        self.timed(key, async {
+            let _guard = self.lock_key(key).await;
+            let (mut value, options) = match self.get(key).await {
+                Ok(bytes) => {
+                    let value = serde_json::from_slice(&bytes).map_err(serialization)?;
+                    let options = PutOptions {
+                        content_type: self.content_type(key).await?,
+                        metadata: self.user_metadata(key).await?,
+                        expires_at: self.expires_at(key).await?,
+                        ..PutOptions::default()
+                    };
+                    (value, options)
+                }
+                Err(StorageError::NotFound(_)) => (default, PutOptions::default()),
+                Err(err) => return Err(err),
+            };
+            f(&mut value);
+            let data = serde_json::to_vec(&value).map_err(serialization)?;
+            self.put_routed(key, &data, &options).await?;
+            if let Some(replica) = &self.replica {
+                let result = replica.put_local(key, &data, &options).await.map(drop);
+                self.apply_replica_policy(key, result)?;
+            }
+            Ok(value)
+        })
+        .await
+    }
+
+    /// Returns whether `key` holds a live object.
+    ///
+    /// With [`StorageOptions::existence_index`] enabled, keys the index rules
+    /// out are answered without a filesystem call.
+    pub async fn exists(&self, key: &str) -> Result<bool, StorageError> {
+        if self.exists_here(key).await? {
+            return Ok(true);
+        }
+        for (_, tier) in &self.tiers {
+            if tier.exists_here(key).await? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Like [`exists`](Self::exists), without looking in other tiers.
+    pub(crate) async fn exists_here(&self, key: &str) -> Result<bool, StorageError> {
+        let path = self.path_for(key)?;
+        if !self.may_exist(key) {
+            return Ok(false);
+        }
+        self.ensure_within_root(key, &path).await?;
+        // The key index already vouched for the file.
+        if self.keys.is_none() {
+            match fs::metadata(&path).await {
+                Ok(metadata) if metadata.is_file() => {}
+                Ok(_) => return Ok(false),
+                Err(err)
+                    if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) =>
+                {
+                    return Ok(false);
+                }
+                Err(err) => return Err(StorageError::from(err)),
             }
-            Err(err) => Err(StorageError::from(err)),
         }
+        match self.ensure_live(key).await {
+            Ok(()) => Ok(true),
+            Err(StorageError::NotFound(_)) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Reads up to `len` bytes of `key` starting at `offset`.
+    ///
+    /// Fewer bytes are returned when the range extends past the end of the
+    /// object, and none when `offset` is beyond it.
+    pub async fn get_range(
+        &self,
+        key: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, StorageError> {
+        let path = self.path_for(key)?;
+        if let Some(tier) = self.tier_holding(key).await? {
+            return Box::pin(tier.get_range(key, offset, len)).await;
+        }
+        self.ensure_within_root(key, &path).await?;
+        self.ensure_live(key).await?;
+        if let Some(target) = self.link_target(key, &path).await? {
+            let start = offset.min(target.len() as u64) as usize;
+            let end = offset.saturating_add(len).min(target.len() as u64) as usize;
+            return Ok(target[start..end].to_vec());
+        }
+        if self.compression {
+            let stored = self.open_stored(key, &path).await?;
+            let (mut reader, len) = stored.into_range(offset, len).await?;
+            let mut bytes = Vec::with_capacity(len as usize);
+            reader.read_to_end(&mut bytes).await?;
+            return Ok(bytes);
+        }
+        let mut file = fs::File::open(&path)
+            .await
+            .map_err(|err| io_error(key, err))?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut bytes = Vec::new();
+        file.take(len).read_to_end(&mut bytes).await?;
+        Ok(bytes)
+    }
+
+    /// Reads at most the first `n` bytes of `key`, fewer if the object is shorter.
+    pub async fn peek(&self, key: &str, n: usize) -> Result<Vec<u8>, StorageError> {
+        self.get_range(key, 0, n as u64).await
+    }
+
+    /// Reads `key` only if it is at most `max` bytes long.
+    ///
+    /// The size is checked before reading, and the read itself is capped so an
+    /// object growing concurrently cannot exceed the limit either.
+    pub async fn get_limited(&self, key: &str, max: u64) -> Result<Vec<u8>, StorageError> {
+        let path = self.path_for(key)?;
+        if let Some(tier) = self.tier_holding(key).await? {
+            return Box::pin(tier.get_limited(key, max)).await;
+        }
+        self.ensure_within_root(key, &path).await?;
+        self.ensure_live(key).await?;
+        if let Some(target) = self.link_target(key, &path).await? {
+            let size = target.len() as u64;
+            return if size > max {
+                Err(too_large(key, size, max))
+            } else {
+                Ok(target)
+            };
+        }
+        if self.compression {
+            let stored = self.open_stored(key, &path).await?;
+            let size = stored.len();
+            if size > max {
+                return Err(too_large(key, size, max));
+            }
+            return Ok(stored.into_bytes().await?);
+        }
+        let file = fs::File::open(&path)
+            .await
+            .map_err(|err| io_error(key, err))?;
+        let size = file.metadata().await?.len();
+        if size > max {
+            return Err(too_large(key, size, max));
+        }
+
+        let mut bytes = Vec::with_capacity(size as usize);
+        file.take(max.saturating_add(1))
+            .read_to_end(&mut bytes)
+            .await?;
+        let read = bytes.len() as u64;
+        if read > max {
+            return Err(too_large(key, read, max));
+        }
+        Ok(bytes)
+    }
+
+    /// Returns the size, modification time, and entity tag of `key`.
+    pub async fn head(&self, key: &str) -> Result<Metadata, StorageError> {
+        let path = self.path_for(key)?;
+        if let Some(tier) = self.tier_holding(key).await? {
+            return Box::pin(tier.head(key)).await;
+        }
+        if !self.may_exist(key) {
+            return Err(StorageError::NotFound(key.to_string()));
+        }
+        self.ensure_within_root(key, &path).await?;
+        self.ensure_live(key).await?;
+        let link = self.link_target(key, &path).await?;
+        let metadata = match link {
+            Some(_) => fs::symlink_metadata(&path).await,
+            None => fs::metadata(&path).await,
+        }
+        .map_err(|err| io_error(key, err))?;
+        if !metadata.is_file() && !metadata.is_symlink() {
+            return Err(StorageError::NotFound(key.to_string()));
+        }
+        let modified = metadata.modified()?;
+        let size = match link {
+            None if self.compression => self.open_stored(key, &path).await?.len(),
+            _ => metadata.len(),
+        };
+        Ok(Metadata {
+            size,
+            modified,
+            etag: etag_for(modified, size),
+        })
+    }
+
+    /// Reads `key` only if it was modified after `since`.
+    ///
+    /// Returns `Ok(None)` when the object's modification time is at or before
+    /// `since`, letting pollers skip unchanged objects.
+    pub async fn get_if_modified_since(
+        &self,
+        key: &str,
+        since: SystemTime,
+    ) -> Result<Option<Vec<u8>>, StorageError> {
+        if self.head(key).await?.modified <= since {
+            return Ok(None);
+        }
+        self.get(key).await.map(Some)
+    }
+
+    /// Returns the length of `key` in bytes without reading its content.
+    pub async fn size(&self, key: &str) -> Result<u64, StorageError> {
+        Ok(self.head(key).await?.size)
+    }
+
+    /// Returns the content type recorded for `key`, if one was provided on upload.
+    pub async fn content_type(&self, key: &str) -> Result<Option<String>, StorageError> {
+        let path = self.path_for(key)?;
+        if let Some(tier) = self.tier_holding(key).await? {
+            return Box::pin(tier.content_type(key)).await;
+        }
+        self.ensure_within_root(key, &path).await?;
+        Ok(Sidecar::ContentType.read(&path).await?)
+    }
+
+    /// Returns the user metadata recorded for `key`.
+    pub async fn user_metadata(&self, key: &str) -> Result<BTreeMap<String, String>, StorageError> {
+        let path = self.path_for(key)?;
+        if let Some(tier) = self.tier_holding(key).await? {
+            return Box::pin(tier.user_metadata(key)).await;
+        }
+        self.ensure_within_root(key, &path).await?;
+        let encoded = Sidecar::Metadata.read(&path).await?;
+        Ok(encoded
+            .map(|encoded| sidecar::decode_metadata(&encoded))
+            .unwrap_or_default())
     }
 
     pub async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        self.timed(key, async {
+            let _guard = self.lock_key(key).await;
+            self.delete_locked(key).await
+        })
+        .await
+    }
+
+    /// Deletes `key` only if its entity tag, as reported by
+    /// [`FileStorage::head`], is still `expected_etag`; `*` matches any.
+    ///
+    /// Returns `Ok(false)` and leaves the object in place when the tag has
+    /// changed, so a client never removes a version it has not seen.
+    pub async fn delete_if_etag(
+        &self,
+        key: &str,
+        expected_etag: &str,
+    ) -> Result<bool, StorageError> {
+        self.timed(key, async {
+            let _guard = self.lock_key(key).await;
+            let current = self.head(key).await?;
+            if expected_etag != "*" && expected_etag != current.etag {
+                return Ok(false);
+            }
+            self.delete_locked(key).await?;
+            Ok(true)
+        })
+        .await
+    }
+
+    /// Deletes `key` from whichever tier holds it and from the replica; the
+    /// caller holds the key's lock.
+    async fn delete_locked(&self, key: &str) -> Result<(), StorageError> {
+        match self.tier_holding(key).await? {
+            Some(tier) => tier.delete_local(key).await?,
+            None => self.delete_local(key).await?,
+        }
+        if let Some(replica) = &self.replica {
+            let result = match replica.delete_local(key).await {
+                Err(StorageError::NotFound(_)) => Ok(()),
+                result => result,
+            };
+            self.apply_replica_policy(key, result)?;
+        }
+        Ok(())
+    }
+
+    async fn delete_local(&self, key: &str) -> Result<(), StorageError> {
         let path = self.path_for(key)?;
-        match fs::remove_file(path).await {
-            Ok(_) => Ok(()),
-            Err(err) if err.kind() == ErrorKind::NotFound => {
+        self.ensure_within_root(key, &path).await?;
+        let reservation = self.reserve_quota(key, &path, |_| 0).await?;
+        fs::remove_file(&path)
+            .await
+            .map_err(|err| io_error(key, err))?;
+        if let Some(reservation) = reservation {
+            reservation.settle();
+        }
+        self.index_remove(key);
+        self.forget_key(key);
+        Sidecar::remove_all(&path).await?;
+        self.forget_checksum(&path).await?;
+        match path.parent() {
+            Some(dir) if self.sync_deletes => durability::sync_dir(dir).await?,
+            _ => self.durability.sync_parent(&path).await?,
+        }
+        Ok(())
+    }
+
+    /// Completes deferred work so every write that has returned is durable.
+    ///
+    /// Issues the directory syncs queued by group commit, here and on the
+    /// replica, and appends pending changes to the key index journal. Meant
+    /// for graceful shutdown; returns immediately unless
+    /// [`StorageOptions::group_commit_interval`] or
+    /// [`StorageOptions::key_index`] is set.
+    pub async fn flush(&self) -> Result<(), StorageError> {
+        self.durability.flush().await?;
+        if let Some(keys) = &self.keys {
+            keys.persist().await?;
+        }
+        Ok(())
+    }
+
+    /// Flushes `key`'s contents and its directory entry to disk, whatever
+    /// [`StorageOptions::sync_writes`] is set to.
+    ///
+    /// Once this returns, the object as it is now survives a crash.
+    pub async fn sync(&self, key: &str) -> Result<(), StorageError> {
+        let path = self.path_for(key)?;
+        if let Some(tier) = self.tier_holding(key).await? {
+            return Box::pin(tier.sync(key)).await;
+        }
+        if !self.may_exist(key) {
+            return Err(StorageError::NotFound(key.to_string()));
+        }
+        self.timed(key, async {
+            self.ensure_within_root(key, &path).await?;
+            self.ensure_live(key).await?;
+            let file = fs::File::open(&path)
+                .await
+                .map_err(|err| io_error(key, err))?;
+            if !file.metadata().await?.is_file() {
+                return Err(StorageError::NotFound(key.to_string()));
+            }
+            file.sync_all().await?;
+            if let Some(dir) = path.parent() {
+                durability::sync_dir(dir).await?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Rescans the tree and brings the in-memory listing cache, existence
+    /// index, key index, and quota usage up to date with it, picking up
+    /// objects added or removed by other processes.
+    ///
+    /// The whole store is rescanned even from a [`namespace`](Self::namespace)
+    /// handle, since those share the top-level store's caches. Without any of
+    /// them configured this only forgets which directories puts have created.
+    pub async fn refresh(&self) -> Result<(), StorageError> {
+        self.dirs.clear();
+        if self.index.is_none()
+            && self.listing.is_none()
+            && self.keys.is_none()
+            && self.quotas.is_none()
+        {
+            return Ok(());
+        }
+        let top = self.top_level();
+        let rescan = self.listing.as_ref().map(|listing| listing.rescan());
+        let keys = top.scan().await?.keys();
+        if let Some(index) = &self.index {
+            // The filter only learns keys it rules out: resetting it could
+            // hide a key written while the scan ran.
+            for key in keys.iter().filter(|key| !index.may_contain(key)) {
+                index.insert(key);
+            }
+        }
+        if let Some(index) = &self.keys {
+            // A key missing from the scan may have been stored since, so it
+            // is only dropped once its file is confirmed gone.
+            for key in index.reconcile(&keys) {
+                let path = top.path_for(&key)?;
+                match fs::symlink_metadata(&path).await {
+                    Err(err) if err.kind() == ErrorKind::NotFound => index.remove(&key),
+                    Err(err) => return Err(io_error(&key, err)),
+                    Ok(_) => {}
+                }
+            }
+        }
+        if let Some(rescan) = rescan {
+            rescan.finish(keys);
+        }
+        if let Some(quotas) = &self.quotas {
+            quotas.invalidate_all().await;
+        }
+        Ok(())
+    }
+
+    /// Returns how many keys the persisted key index had wrong when the store
+    /// was opened and reconciled it, or `None` without
+    /// [`StorageOptions::key_index`].
+    pub fn key_index_drift(&self) -> Option<usize> {
+        self.keys.as_ref().map(|keys| keys.drift())
+    }
+
+    /// Returns every stored key starting with `prefix`, sorted lexicographically.
+    ///
+    /// Served from memory when [`StorageOptions::listing_cache`] is set.
+    /// Fails with [`StorageError::ListTooLarge`] when there are more keys than
+    /// [`StorageOptions::max_list_entries`].
+    pub async fn list(&self, prefix: &str) -> Result<Vec<String>, StorageError> {
+        let Some(max) = self.max_list_entries else {
+            return self.list_all(prefix).await;
+        };
+        // One key past the maximum is enough to know the listing is too large.
+        let keys = self.list_up_to(prefix, max.saturating_add(1)).await?;
+        if keys.len() > max {
+            return Err(StorageError::ListTooLarge {
+                found_at_least: keys.len(),
+            });
+        }
+        Ok(keys)
+    }
+
+    /// Like [`list`](Self::list), ignoring [`StorageOptions::max_list_entries`].
+    pub(crate) async fn list_all(&self, prefix: &str) -> Result<Vec<String>, StorageError> {
+        self.list_up_to(prefix, usize::MAX).await
+    }
+
+    /// Returns the first `limit` keys starting with `prefix`, in sorted order.
+    ///
+    /// Without a listing cache, the walk stops as soon as it has found
+    /// `limit` keys, which are then not necessarily the first ones.
+    async fn list_up_to(&self, prefix: &str, limit: usize) -> Result<Vec<String>, StorageError> {
+        let mut keys = match &self.listing {
+            Some(listing) => {
+                let keys = listing.list(&format!("{}{prefix}", self.namespace), limit);
+                let skip = self.namespace.len();
+                keys.into_iter()
+                    .map(|key| key[skip..].to_string())
+                    .collect()
+            }
+            None => self.walk(prefix, limit).await?.keys(),
+        };
+        if !self.tiers.is_empty() {
+            for (_, tier) in &self.tiers {
+                keys.extend(Box::pin(tier.list_up_to(prefix, limit)).await?);
+            }
+            keys.sort();
+            keys.dedup();
+            keys.truncate(limit);
+        }
+        Ok(keys)
+    }
+
+    /// Returns whether the store holds no objects, stopping at the first one
+    /// found rather than walking the whole tree.
+    ///
+    /// Directories holding only sidecars or other internal files count as
+    /// empty, while expired objects count until they are removed.
+    pub async fn is_empty(&self) -> Result<bool, StorageError> {
+        let mut pending = vec![self.root.clone()];
+        while let Some(dir) = pending.pop() {
+            for (path, is_dir) in read_entries(dir).await? {
+                if path.file_name().is_some_and(is_reserved) {
+                    continue;
+                } else if is_dir {
+                    pending.push(path);
+                } else if self.key_for(&path).is_some() {
+                    return Ok(false);
+                }
+            }
+        }
+        for (_, tier) in &self.tiers {
+            if !Box::pin(tier.is_empty()).await? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Lists one level below `prefix`, grouping deeper keys by the next `delimiter`.
+    ///
+    /// With a `/` delimiter this reads like a directory listing: `keys` holds
+    /// the objects directly under the prefix and `common_prefixes` the
+    /// sub-prefixes, such as `photos/2024/`, each ending in the delimiter.
+    /// Both are sorted. Built on [`list`](Self::list), so it is served from
+    /// memory when the listing cache is enabled.
+    pub async fn list_delimited(
+        &self,
+        prefix: &str,
+        delimiter: char,
+    ) -> Result<DelimitedListing, StorageError> {
+        let mut listing = DelimitedListing::default();
+        for key in self.list_all(prefix).await? {
+            match key[prefix.len()..].find(delimiter) {
+                Some(at) => {
+                    let common = &key[..prefix.len() + at + delimiter.len_utf8()];
+                    if listing
+                        .common_prefixes
+                        .last()
+                        .is_none_or(|last| last != common)
+                    {
+                        listing.common_prefixes.push(common.to_string());
+                    }
+                }
+                None => listing.keys.push(key),
+            }
+        }
+        Ok(listing)
+    }
+
+    /// Removes every object whose key starts with `prefix`, here and on the
+    /// replica.
+    ///
+    /// Returns the number of objects removed and prunes directories left empty.
+    pub async fn delete_prefix(&self, prefix: &str) -> Result<usize, StorageError> {
+        let mut removed = 0;
+        for (store, tree) in self.select_everywhere(prefix).await? {
+            removed += self.delete_walked(store, &tree.files).await?;
+        }
+        Ok(removed)
+    }
+
+    /// Removes every object under `prefix` last modified more than `age` ago.
+    ///
+    /// Each object's modification time is checked again under its key lock
+    /// just before removal, so objects rewritten during the walk are kept.
+    /// Returns the number of objects removed and prunes directories left empty.
+    pub async fn delete_older_than(
+        &self,
+        prefix: &str,
+        age: Duration,
+    ) -> Result<usize, StorageError> {
+        let Some(cutoff) = SystemTime::now().checked_sub(age) else {
+            return Ok(0);
+        };
+        let mut removed = 0;
+        for (store, tree) in self.select_everywhere(prefix).await? {
+            for (key, path) in &tree.files {
+                let _guard = self.lock_key(key).await;
+                let modified = match fs::symlink_metadata(path).await {
+                    Ok(metadata) => metadata.modified()?,
+                    Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                    Err(err) => return Err(StorageError::from(err)),
+                };
+                if modified >= cutoff {
+                    continue;
+                }
+                match self.delete_locked(key).await {
+                    Ok(()) => removed += 1,
+                    Err(StorageError::NotFound(_)) => continue,
+                    Err(err) => return Err(err),
+                }
+                store.prune_empty_parents(path).await;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Removes every object whose key `keep` returns `false` for.
+    ///
+    /// Returns the number of objects removed and prunes directories left
+    /// empty. Objects that disappear during the walk are skipped.
+    pub async fn retain<F: Fn(&str) -> bool>(&self, keep: F) -> Result<usize, StorageError> {
+        let mut removed = 0;
+        for (store, tree) in self.select_everywhere("").await? {
+            let doomed: Vec<_> = tree
+                .files
+                .into_iter()
+                .filter(|(key, _)| !keep(key))
+                .collect();
+            removed += self.delete_walked(store, &doomed).await?;
+        }
+        Ok(removed)
+    }
+
+    /// Deletes each of `files`, found by a walk of `store`, under its key lock
+    /// the way [`delete`](Self::delete) does, and prunes directories left
+    /// empty.
+    ///
+    /// Returns how many were deleted, skipping those already gone. A symlink
+    /// resolving outside the root is unlinked rather than refused.
+    async fn delete_walked(
+        &self,
+        store: &FileStorage,
+        files: &[(String, PathBuf)],
+    ) -> Result<usize, StorageError> {
+        let mut removed = 0;
+        for (key, path) in files {
+            let _guard = self.lock_key(key).await;
+            match self.delete_locked(key).await {
+                Ok(()) => removed += 1,
+                Err(StorageError::NotFound(_)) => continue,
+                Err(StorageError::InvalidKey {
+                    reason: InvalidKeyReason::OutsideRoot,
+                    ..
+                }) if fs::symlink_metadata(path)
+                    .await
+                    .is_ok_and(|metadata| metadata.is_symlink()) =>
+                {
+                    removed += remove_files(&[(key.clone(), path.clone())]).await?;
+                    store.index_remove(key);
+                    store.forget_key(key);
+                }
+                Err(err) => return Err(err),
+            }
+            store.prune_empty_parents(path).await;
+        }
+        Ok(removed)
+    }
+
+    /// Lists the keys [`delete_prefix`](Self::delete_prefix) would remove, without deleting.
+    pub async fn delete_prefix_preview(&self, prefix: &str) -> Result<Vec<String>, StorageError> {
+        self.keys_everywhere(prefix).await
+    }
+
+    /// Removes every object under the root and each tier's root, here and on
+    /// the replica, keeping the root directories themselves.
+    ///
+    /// Returns the number of objects removed. Symlinks are unlinked rather than
+    /// followed, and entries that disappear concurrently are skipped.
+    pub async fn clear(&self) -> Result<usize, StorageError> {
+        let mut removed = 0;
+        for (store, tree) in self.select_everywhere("").await? {
+            removed += self.delete_walked(store, &tree.files).await?;
+            for path in &tree.reserved {
+                match fs::remove_file(path).await {
+                    Ok(()) => {}
+                    Err(err) if err.kind() == ErrorKind::NotFound => {}
+                    Err(err) => return Err(StorageError::from(err)),
+                }
+            }
+            for dir in &tree.dirs {
+                store.dirs.forget_under(dir);
+            }
+            remove_empty_dirs(tree.dirs).await?;
+        }
+        self.invalidate_quotas("").await;
+        Ok(removed)
+    }
+
+    /// Removes every empty directory below the root, keeping the root itself,
+    /// and returns how many were removed.
+    ///
+    /// Directories are removed deepest-first, so one holding nothing but empty
+    /// directories goes as well. Directories that gain an entry or disappear
+    /// while the sweep runs are skipped.
+    pub async fn purge_empty_dirs(&self) -> Result<usize, StorageError> {
+        let tree = self.scan().await?;
+        for dir in &tree.dirs {
+            self.dirs.forget_under(dir);
+        }
+        remove_empty_dirs(tree.dirs).await
+    }
+
+    /// Lists the keys [`clear`](Self::clear) would remove, without deleting.
+    pub async fn clear_preview(&self) -> Result<Vec<String>, StorageError> {
+        self.keys_everywhere("").await
+    }
+
+    /// Moves every object under the `src_prefix/` directory to `dst_prefix/`.
+    ///
+    /// When the destination does not exist yet the whole subtree is moved with
+    /// a single rename; otherwise, or if that rename fails (for example across
+    /// filesystems), objects are moved one at a time. Moves that would
+    /// overwrite an existing destination object are refused up front, as are
+    /// prefixes nested inside each other. Returns the number of objects moved.
+    pub async fn rename_prefix(
+        &self,
+        src_prefix: &str,
+        dst_prefix: &str,
+    ) -> Result<usize, StorageError> {
+        let moved = self.rename_prefix_local(src_prefix, dst_prefix).await;
+        self.invalidate_quotas(src_prefix).await;
+        self.invalidate_quotas(dst_prefix).await;
+        let moved = moved?;
+        if let Some(replica) = &self.replica {
+            let result = replica.rename_prefix_local(src_prefix, dst_prefix).await;
+            self.apply_replica_policy(src_prefix, result.map(|_| ()))?;
+        }
+        Ok(moved)
+    }
+
+    async fn rename_prefix_local(
+        &self,
+        src_prefix: &str,
+        dst_prefix: &str,
+    ) -> Result<usize, StorageError> {
+        let src = src_prefix.trim_end_matches('/');
+        let dst = dst_prefix.trim_end_matches('/');
+        validate_key(src)?;
+        validate_key(dst)?;
+        let nested =
+            |outer: &str, inner: &str| inner == outer || inner.starts_with(&format!("{outer}/"));
+        if nested(src, dst) || nested(dst, src) {
+            return Err(invalid_key(
+                InvalidKeyReason::Other,
+                format!("cannot move `{src_prefix}` to overlapping prefix `{dst_prefix}`"),
+            ));
+        }
+
+        let tree = self.select_prefix(&format!("{src}/")).await?;
+        if tree.files.is_empty() {
+            return Ok(0);
+        }
+        let dst_key_for = |key: &str| format!("{dst}/{}", &key[src.len() + 1..]);
+        for (key, _) in &tree.files {
+            self.index_insert(&dst_key_for(key));
+        }
+
+        if self.mapper.nests_prefixes() {
+            let src_dir = self.path_for(src)?;
+            let dst_dir = self.path_for(dst)?;
+            if fs::symlink_metadata(&dst_dir).await.is_err() {
+                if let Some(parent) = dst_dir.parent() {
+                    fs::create_dir_all(parent).await?;
+                }
+                self.dirs.forget_under(&src_dir);
+                if fs::rename(&src_dir, &dst_dir).await.is_ok() {
+                    for (key, _) in &tree.files {
+                        self.forget_key(key);
+                        self.record_key(&dst_key_for(key));
+                    }
+                    return Ok(tree.files.len());
+                }
+            }
+        }
+
+        let mut moves = Vec::with_capacity(tree.files.len());
+        for (key, path) in &tree.files {
+            let dst_key = dst_key_for(key);
+            let dst_path = self.path_for(&dst_key)?;
+            if fs::symlink_metadata(&dst_path).await.is_ok() {
+                return Err(invalid_key(
+                    InvalidKeyReason::Other,
+                    format!("moving `{key}` would overwrite existing object `{dst_key}`"),
+                ));
+            }
+            moves.push((key, path, dst_key, dst_path));
+        }
+        let batch = match &self.wal {
+            Some(wal) => {
+                let steps = moves.iter().map(|(_, src, _, dst)| Step::Move {
+                    src: src.to_path_buf(),
+                    dst: dst.clone(),
+                });
+                Some(wal.begin(steps).await?)
+            }
+            None => None,
+        };
+        for (key, src_path, dst_key, dst_path) in moves {
+            if let Some(parent) = dst_path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            move_object(src_path, &dst_path, self.rename_strategy).await?;
+            self.forget_key(key);
+            self.record_key(&dst_key);
+            self.prune_empty_parents(src_path).await;
+        }
+        if let Some(batch) = batch {
+            batch.finish().await?;
+        }
+        Ok(tree.files.len())
+    }
+
+    /// Moves `key` to `<worker_prefix>/<key>` and returns the new key with its contents.
+    ///
+    /// The move is a single rename made holding both keys' locks, so when
+    /// several workers race to claim the same key exactly one succeeds and
+    /// the rest get [`StorageError::NotFound`], and no write to either key is
+    /// lost or claimed half-done.
+    pub async fn claim(
+        &self,
+        key: &str,
+        worker_prefix: &str,
+    ) -> Result<(String, Vec<u8>), StorageError> {
+        let claimed = format!("{}/{key}", worker_prefix.trim_end_matches('/'));
+        let _guards = self.lock_keys(&[key, &claimed]).await;
+        let data = self.claim_local(key, &claimed).await;
+        self.invalidate_quotas(key).await;
+        self.invalidate_quotas(&claimed).await;
+        let data = data?;
+        if let Some(replica) = &self.replica {
+            let result = replica.claim_local(key, &claimed).await;
+            self.apply_replica_policy(key, result.map(|_| ()))?;
+        }
+        Ok((claimed, data))
+    }
+
+    async fn claim_local(&self, key: &str, claimed: &str) -> Result<Vec<u8>, StorageError> {
+        let src = self.path_for(key)?;
+        let dst = self.path_for(claimed)?;
+        if !self.may_exist(key) {
+            return Err(StorageError::NotFound(key.to_string()));
+        }
+        self.ensure_within_root(key, &src).await?;
+        self.ensure_within_root(claimed, &dst).await?;
+        self.ensure_live(key).await?;
+        if let Some(parent) = dst.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .map_err(|err| io_error(claimed, err))?;
+        }
+        self.index_insert(claimed);
+        fs::rename(&src, &dst)
+            .await
+            .map_err(|err| io_error(key, err))?;
+        self.index_remove(key);
+        self.forget_key(key);
+        self.record_key(claimed);
+        for sidecar in Sidecar::ALL {
+            match fs::rename(sidecar.path_for(&src), sidecar.path_for(&dst)).await {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(StorageError::from(err)),
+            }
+        }
+        self.move_checksum(&src, &dst).await?;
+        self.durability.sync_parent(&src).await?;
+        self.durability.sync_parent(&dst).await?;
+        self.prune_empty_parents(&src).await;
+        if self.compression {
+            return Ok(self.open_stored(claimed, &dst).await?.into_bytes().await?);
+        }
+        fs::read(&dst).await.map_err(|err| io_error(claimed, err))
+    }
+
+    /// Decides whether a failed replica update for `key` fails the whole operation.
+    fn apply_replica_policy(
+        &self,
+        key: &str,
+        result: Result<(), StorageError>,
+    ) -> Result<(), StorageError> {
+        match (result, self.replica_policy) {
+            (Ok(()), _) => Ok(()),
+            (Err(err), ReplicaPolicy::Fail) => Err(err),
+            (Err(err), ReplicaPolicy::LogAndContinue) => {
+                eprintln!("replica update for `{key}` failed: {err}");
+                Ok(())
+            }
+        }
+    }
+
+    /// Runs `op` on `key`, failing with [`StorageError::Timeout`] once the
+    /// configured operation timeout elapses.
+    async fn timed<T>(
+        &self,
+        key: &str,
+        op: impl Future<Output = Result<T, StorageError>>,
+    ) -> Result<T, StorageError> {
+        let Some(limit) = self.op_timeout else {
+            return op.await;
+        };
+        tokio::time::timeout(limit, op).await.unwrap_or_else(|_| {
+            Err(StorageError::Timeout {
+                key: key.to_string(),
+                after: limit,
+            })
+        })
+    }
+
+    /// Waits for exclusive write access to `key`.
+    async fn lock_key(&self, key: &str) -> OwnedMutexGuard<()> {
+        self.locks.lock(&format!("{}{key}", self.namespace)).await
+    }
+
+    /// Waits for exclusive write access to each of `keys`, taking the locks in
+    /// key order so that callers locking overlapping keys cannot deadlock.
+    async fn lock_keys(&self, keys: &[&str]) -> Vec<OwnedMutexGuard<()>> {
+        let mut keys = keys.to_vec();
+        keys.sort_unstable();
+        keys.dedup();
+        let mut guards = Vec::with_capacity(keys.len());
+        for key in keys {
+            guards.push(self.lock_key(key).await);
+        }
+        guards
+    }
+
+    /// Locks the usage of the quota-limited prefix `key` falls under, if any.
+    async fn quota_charge(&self, key: &str) -> Result<Option<quota::Charge<'_>>, StorageError> {
+        match &self.quotas {
+            Some(quotas) => quotas.charge(&self.qualified(key)).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Checks that `key`, stored at `path`, may grow from its current length
+    /// to `new_len` of it under its prefix's quota.
+    async fn reserve_quota(
+        &self,
+        key: &str,
+        path: &Path,
+        new_len: impl FnOnce(u64) -> u64,
+    ) -> Result<Option<Reservation<'_>>, StorageError> {
+        let Some(charge) = self.quota_charge(key).await? else {
+            return Ok(None);
+        };
+        let old = quota::file_len(path).await?;
+        charge.reserve(old, new_len(old)).map(Some)
+    }
+
+    /// Marks the quota usage of every limited prefix `prefix` can reach for
+    /// a recount, after objects there changed in bulk.
+    async fn invalidate_quotas(&self, prefix: &str) {
+        let Some(quotas) = &self.quotas else {
+            return;
+        };
+        let prefix = self.qualified(prefix);
+        if quota::tenant(&prefix).is_some() {
+            quotas.invalidate(&prefix).await;
+        } else {
+            quotas.invalidate_all().await;
+        }
+    }
+
+    /// Returns a handle on the top-level store this one belongs to, for scans.
+    fn top_level(&self) -> FileStorage {
+        if self.namespace.is_empty() {
+            return self.clone();
+        }
+        let depth = self.namespace.matches('/').count();
+        let canonical_root = self.canonical_root.ancestors().nth(depth);
+        Self {
+            root: self.top_root().to_path_buf(),
+            canonical_root: canonical_root.unwrap_or(&self.canonical_root).to_path_buf(),
+            replica: None,
+            namespace: String::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Returns `key` relative to the top-level store rather than this namespace.
+    fn qualified(&self, key: &str) -> String {
+        format!("{}{key}", self.namespace)
+    }
+
+    /// Records `key` in the existence index ahead of creating its file.
+    fn index_insert(&self, key: &str) {
+        if let Some(index) = &self.index {
+            index.insert(&format!("{}{key}", self.namespace));
+        }
+    }
+
+    /// Forgets one insert of `key` after its file is gone.
+    fn index_remove(&self, key: &str) {
+        if let Some(index) = &self.index {
+            index.remove(&format!("{}{key}", self.namespace));
+        }
+    }
+
+    /// Adds `key` to the listing cache and key index once its file exists.
+    fn record_key(&self, key: &str) {
+        if let Some(listing) = &self.listing {
+            listing.insert(self.qualified(key));
+        }
+        if let Some(keys) = &self.keys {
+            keys.insert(self.qualified(key));
+        }
+    }
+
+    /// Drops `key` from the listing cache and key index after its file is gone.
+    fn forget_key(&self, key: &str) {
+        if let Some(listing) = &self.listing {
+            listing.remove(&self.qualified(key));
+        }
+        if let Some(keys) = &self.keys {
+            keys.remove(&self.qualified(key));
+        }
+    }
+
+    /// Returns `false` when the existence index or key index rules `key` out.
+    fn may_exist(&self, key: &str) -> bool {
+        let key = self.qualified(key);
+        self.index
+            .as_ref()
+            .is_none_or(|index| index.may_contain(&key))
+            && self.keys.as_ref().is_none_or(|keys| keys.contains(&key))
+    }
+
+    /// Fails with [`StorageError::NotFound`] if `key` has passed its expiry.
+    async fn ensure_live(&self, key: &str) -> Result<(), StorageError> {
+        match self.expires_at_here(key, &self.path_for(key)?).await? {
+            Some(expires_at) if expires_at <= SystemTime::now() => {
                 Err(StorageError::NotFound(key.to_string()))
             }
-            Err(err) => Err(StorageError::from(err)),
+            _ => Ok(()),
         }
     }
 
     fn path_for(&self, key: &str) -> Result<PathBuf, StorageError> {
         validate_key(key)?;
-        Ok(self.root.join(key))
+        let relative = self.mapper.to_path(key)?;
+        let plain = relative.components().all(
+            |component| matches!(component, Component::Normal(segment) if !is_reserved(segment)),
+        );
+        if !plain || relative.as_os_str().is_empty() {
+            return Err(invalid_key(
+                InvalidKeyReason::Other,
+                format!(
+                    "key mapper produced unsupported path `{}` for `{key}`",
+                    relative.display()
+                ),
+            ));
+        }
+        Ok(self.root.join(relative))
     }
+
+    /// Fails with [`InvalidKeyReason::OutsideRoot`] if `path`, or the nearest
+    /// ancestor of it that exists, resolves through symlinks to a location
+    /// outside the root.
+    async fn ensure_within_root(&self, key: &str, path: &Path) -> Result<(), StorageError> {
+        for ancestor in path.ancestors() {
+            match fs::canonicalize(ancestor).await {
+                Ok(resolved) => {
+                    // Segments below the ancestor do not exist yet, so they
+                    // cannot be symlinks.
+                    let rest = path.strip_prefix(ancestor).unwrap_or(Path::new(""));
+                    if resolved.join(rest).starts_with(&self.canonical_root) {
+                        return Ok(());
+                    }
+                    break;
+                }
+                Err(err)
+                    if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) =>
+                {
+                    // A dangling symlink cannot be resolved, but writing
+                    // through it would create its target wherever that is.
+                    if fs::symlink_metadata(ancestor).await.is_ok() {
+                        return Err(invalid_key(
+                            InvalidKeyReason::OutsideRoot,
+                            format!("`{key}` resolves through a dangling symlink"),
+                        ));
+                    }
+                }
+                Err(err) => return Err(io_error(key, err)),
+            }
+        }
+        Err(invalid_key(
+            InvalidKeyReason::OutsideRoot,
+            format!("`{key}` resolves through a symlink to outside the storage root"),
+        ))
+    }
+
+    /// Applies the [`SymlinkPolicy`] when the object at `path` is a symlink.
+    ///
+    /// Returns the link's target path under [`SymlinkPolicy::ReturnTarget`],
+    /// and `None` when the object should be read normally.
+    async fn link_target(&self, key: &str, path: &Path) -> Result<Option<Vec<u8>>, StorageError> {
+        match fs::symlink_metadata(path).await {
+            Ok(metadata) if metadata.is_symlink() => {}
+            _ => return Ok(None),
+        }
+        match self.symlink_policy {
+            SymlinkPolicy::Reject => Err(invalid_key(
+                InvalidKeyReason::Symlink,
+                format!("`{key}` is a symlink"),
+            )),
+            SymlinkPolicy::Follow => Ok(None),
+            SymlinkPolicy::ReturnTarget => {
+                let target = fs::read_link(path)
+                    .await
+                    .map_err(|err| io_error(key, err))?;
+                Ok(Some(target.into_os_string().into_encoded_bytes()))
+            }
+        }
+    }
+
+    /// Maps a path below the root back to its object key.
+    fn key_for(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        self.mapper.to_key(relative)
+    }
+
+    /// Scans the root and keeps only the objects whose key starts with `prefix`.
+    async fn select_prefix(&self, prefix: &str) -> Result<Tree, StorageError> {
+        self.walk(prefix, usize::MAX).await
+    }
+
+    /// Creates the parent directory of `path` unless a previous put already
+    /// did, returning whether it was skipped because of that.
+    async fn create_parent_cached(&self, key: &str, path: &Path) -> Result<bool, StorageError> {
+        let Some(parent) = path.parent() else {
+            return Ok(false);
+        };
+        if self.dirs.contains(parent) {
+            return Ok(true);
+        }
+        create_parent(key, path).await?;
+        self.dirs.insert(parent);
+        Ok(false)
+    }
+
+    /// Creates the directories for keys under each of `prefixes` ahead of
+    /// time, so the first puts there do not have to.
+    ///
+    /// Puts remember the directories they create, so this only saves work
+    /// for prefixes not yet written to. Does nothing when the
+    /// [`KeyMapper`] does not store prefixes as directories.
+    pub async fn ensure_prefixes(&self, prefixes: &[&str]) -> Result<(), StorageError> {
+        if !self.mapper.nests_prefixes() {
+            return Ok(());
+        }
+        for prefix in prefixes {
+            let prefix = prefix.trim_end_matches('/');
+            let dir = self.path_for(prefix)?;
+            self.ensure_within_root(prefix, &dir).await?;
+            // Creating a placeholder child keeps `create_parent` in charge of
+            // reporting objects in the way.
+            create_parent(prefix, &dir.join("_")).await?;
+            self.dirs.insert(&dir);
+        }
+        if let Some(replica) = &self.replica {
+            let result = Box::pin(replica.ensure_prefixes(prefixes)).await;
+            self.apply_replica_policy("", result)?;
+        }
+        Ok(())
+    }
+
+    /// Removes now-empty directories between `path` and the root.
+    ///
+    /// Cleanup is best-effort: it stops at the first directory that is still in
+    /// use or cannot be removed.
+    async fn prune_empty_parents(&self, path: &Path) {
+        let mut current = path.parent();
+        while let Some(dir) = current {
+            if dir == self.root || !dir.starts_with(&self.root) {
+                break;
+            }
+            if fs::remove_dir(dir).await.is_err() {
+                break;
+            }
+            self.dirs.forget_under(dir);
+            current = dir.parent();
+        }
+    }
+
+    /// Walks the tree below the root without following symlinks or entering
+    /// directories with reserved names.
+    ///
+    /// Up to [`StorageOptions::walk_parallelism`] directories are read at
+    /// once. Files are returned sorted by key whatever order they were found in.
+    async fn scan(&self) -> Result<Tree, StorageError> {
+        self.walk("", usize::MAX).await
+    }
+
+    /// Like [`scan`](Self::scan), keeping only the objects whose key starts
+    /// with `prefix` and stopping once `limit` of them are found.
+    async fn walk(&self, prefix: &str, limit: usize) -> Result<Tree, StorageError> {
+        let mut tree = Tree::default();
+        let mut pending = vec![self.root.clone()];
+        let mut reads = JoinSet::new();
+        loop {
+            while reads.len() < self.walk_parallelism {
+                let Some(dir) = pending.pop() else { break };
+                reads.spawn(read_entries(dir));
+            }
+            let Some(read) = reads.join_next().await else {
+                break;
+            };
+            for (path, is_dir) in read.map_err(std::io::Error::other)?? {
+                if is_dir && path.file_name().is_some_and(is_reserved) {
+                    continue;
+                } else if is_dir {
+                    tree.dirs.push(path.clone());
+                    pending.push(path);
+                } else if path.file_name().is_some_and(is_reserved) {
+                    tree.reserved.push(path);
+                } else if let Some(key) = self.key_for(&path)
+                    && key.starts_with(prefix)
+                {
+                    tree.files.push((key, path));
+                }
+            }
+            if tree.files.len() >= limit {
+                tree.files.truncate(limit);
+                break;
+            }
+        }
+        tree.files.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(tree)
+    }
+}
+
+/// Reads the entries of `dir`, flagging subdirectories, and skips entries
+/// that vanish while being read. A missing `dir` has no entries.
+async fn read_entries(dir: PathBuf) -> std::io::Result<Vec<(PathBuf, bool)>> {
+    let mut entries = match fs::read_dir(&dir).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut found = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        match entry.file_type().await {
+            Ok(file_type) => found.push((entry.path(), file_type.is_dir())),
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(found)
 }
 
+/// Maps an I/O error on `key`'s file, turning a missing file into [`StorageError::NotFound`].
+///
+/// Errors caused by `key` colliding with a directory, or by one of its parent
+/// segments being an object, become [`StorageError::Conflict`].
+fn io_error(key: &str, err: std::io::Error) -> StorageError {
+    match err.kind() {
+        ErrorKind::NotFound => StorageError::NotFound(key.to_string()),
+        ErrorKind::IsADirectory => StorageError::Conflict(format!(
+            "`{key}` collides with an existing directory of keys"
+        )),
+        ErrorKind::NotADirectory => {
+            StorageError::Conflict(format!("a parent segment of `{key}` is an existing object"))
+        }
+        _ => StorageError::from(err),
+    }
+}
+
+/// Returns whether `path` is a regular file holding exactly `data`, comparing
+/// lengths before contents. Failing to read it counts as a difference, leaving
+/// the write that follows to report the problem.
+async fn same_content(path: &Path, data: &[u8]) -> bool {
+    match fs::symlink_metadata(path).await {
+        Ok(metadata) if metadata.is_file() && metadata.len() == data.len() as u64 => {}
+        _ => return false,
+    }
+    let Ok(mut file) = fs::File::open(path).await else {
+        return false;
+    };
+    let mut buf = vec![0; data.len().min(64 * 1024)];
+    let mut rest = data;
+    while !rest.is_empty() {
+        let want = rest.len().min(buf.len());
+        let read = match file.read(&mut buf[..want]).await {
+            Ok(0) | Err(_) => return false,
+            Ok(read) => read,
+        };
+        if buf[..read] != rest[..read] {
+            return false;
+        }
+        rest = &rest[read..];
+    }
+    true
+}
+
+/// Creates the directories leading up to `key`'s file at `path`.
+async fn create_parent(key: &str, path: &Path) -> Result<(), StorageError> {
+    let Some(parent) = path.parent() else {
+        return Ok(());
+    };
+    fs::create_dir_all(parent)
+        .await
+        .map_err(|err| match err.kind() {
+            // An object in place of a parent directory surfaces as
+            // `AlreadyExists` rather than `NotADirectory`.
+            ErrorKind::AlreadyExists => io_error(key, ErrorKind::NotADirectory.into()),
+            _ => io_error(key, err),
+        })
+}
+
+/// Creates a store root, naming it in the error when that is impossible.
+async fn create_root(root: &Path) -> Result<(), StorageError> {
+    fs::create_dir_all(root)
+        .await
+        .map_err(|err| StorageError::RootUnavailable(root.to_path_buf(), err))
+}
+
+fn too_large(key: &str, size: u64, max: u64) -> StorageError {
+    StorageError::TooLarge {
+        key: key.to_string(),
+        size,
+        max,
+    }
+}
+
+/// Moves the object at `src` and its sidecars to `dst`.
+///
+/// Sidecars go first, so an interrupted move can be finished by moving the
+/// object again while it is still at `src`.
+pub(crate) async fn move_object(
+    src: &Path,
+    dst: &Path,
+    strategy: RenameStrategy,
+) -> std::io::Result<()> {
+    for sidecar in Sidecar::ALL {
+        match move_file(&sidecar.path_for(src), &sidecar.path_for(dst), strategy).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+    }
+    move_file(src, dst, strategy).await
+}
+
+/// Renames `src` to `dst`, copying and unlinking when a rename is not possible.
+async fn move_file(src: &Path, dst: &Path, strategy: RenameStrategy) -> std::io::Result<()> {
+    match fs::rename(src, dst).await {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == ErrorKind::NotFound => Err(err),
+        Err(_) => {
+            atomic::copy_atomic(src, dst, strategy).await?;
+            fs::remove_file(src).await
+        }
+    }
+}
+
+/// Builds a quoted entity tag from an object's modification time and size.
+fn etag_for(modified: SystemTime, size: u64) -> String {
+    let nanos = modified
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    format!("\"{nanos:x}-{size:x}\"")
+}
+
+/// Returns the key under which [`FileStorage::put_with_backup`] keeps the previous copy.
+fn backup_key(key: &str) -> String {
+    format!("{key}.bak")
+}
+
+/// Files and directories found below a storage root.
+#[derive(Debug, Default)]
+struct Tree {
+    files: Vec<(String, PathBuf)>,
+    dirs: Vec<PathBuf>,
+    /// Sidecars and other internal files, which are never reported as objects.
+    reserved: Vec<PathBuf>,
+}
+
+impl Tree {
+    /// Returns the object keys in sorted order.
+    fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.files.iter().map(|(key, _)| key.clone()).collect();
+        keys.sort();
+        keys
+    }
+}
+
+/// Removes the given object files and their sidecars, skipping any that are already gone.
+async fn remove_files(files: &[(String, PathBuf)]) -> Result<usize, StorageError> {
+    let mut removed = 0;
+    for (_, path) in files {
+        match fs::remove_file(path).await {
+            Ok(()) => removed += 1,
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(StorageError::from(err)),
+        }
+        Sidecar::remove_all(path).await?;
+    }
+    Ok(removed)
+}
+
+/// Removes directories deepest-first, leaving any that gained new entries,
+/// and returns how many were removed.
+async fn remove_empty_dirs(mut dirs: Vec<PathBuf>) -> Result<usize, StorageError> {
+    dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+    let mut removed = 0;
+    for dir in dirs {
+        match fs::remove_dir(&dir).await {
+            Ok(()) => removed += 1,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::NotFound | ErrorKind::DirectoryNotEmpty
+                ) => {}
+            Err(err) => return Err(StorageError::from(err)),
+        }
+    }
+    Ok(removed)
+}
+
+/// Directories read at once while walking the tree, unless configured otherwise.
+const DEFAULT_WALK_PARALLELISM: usize = 8;
+
+/// Longest accepted key, in bytes.
+const MAX_KEY_LEN: usize = 1024;
+
+/// Longest accepted `/`-separated key segment, in bytes: the usual file name
+/// limit, less the prefix that names the segment's sidecars.
+const MAX_SEGMENT_LEN: usize = 255 - Sidecar::MAX_NAME_PREFIX_LEN;
+
 fn validate_key(key: &str) -> Result<(), StorageError> {
+    use InvalidKeyReason::*;
+
     if key.is_empty() {
-        return Err(StorageError::InvalidKey("key cannot be empty".to_string()));
+        return Err(invalid_key(Empty, "key cannot be empty"));
+    }
+    if key.len() > MAX_KEY_LEN {
+        return Err(invalid_key(
+            TooLong,
+            format!(
+                "key is {} bytes, over the {MAX_KEY_LEN}-byte limit",
+                key.len()
+            ),
+        ));
+    }
+    if key.chars().any(char::is_control) {
+        return Err(invalid_key(
+            DisallowedChar,
+            format!("`{}` contains control characters", key.escape_debug()),
+        ));
     }
 
     let path = Path::new(key);
     if path.is_absolute() {
-        return Err(StorageError::InvalidKey(format!(
-            "absolute paths are not allowed (got `{key}`)"
-        )));
+        return Err(invalid_key(
+            Absolute,
+            format!("absolute paths are not allowed (got `{key}`)"),
+        ));
+    }
+    if key.ends_with('/') {
+        return Err(invalid_key(
+            TrailingSlash,
+            format!("`{key}` ends in `/`, which names a prefix rather than an object"),
+        ));
     }
 
     for component in path.components() {
-        match component {
+        let (reason, message) = match component {
+            Component::Normal(segment) if is_reserved(segment) => (
+                Reserved,
+                format!("`{key}` uses the reserved `{RESERVED_PREFIX}` prefix"),
+            ),
+            Component::Normal(segment) if segment.len() > MAX_SEGMENT_LEN => (
+                TooLong,
+                format!("`{key}` has a segment over the {MAX_SEGMENT_LEN}-byte limit"),
+            ),
             Component::Normal(_) => continue,
-            _ => {
-                return Err(StorageError::InvalidKey(format!(
-                    "`{key}` contains unsupported segments"
-                )));
-            }
-        }
+            Component::ParentDir => (ParentTraversal, format!("`{key}` contains `..` segments")),
+            Component::RootDir | Component::Prefix(_) => (
+                Absolute,
+                format!("absolute paths are not allowed (got `{key}`)"),
+            ),
+            Component::CurDir => (Other, format!("`{key}` contains unsupported segments")),
+        };
+        return Err(invalid_key(reason, message));
     }
 
     Ok(())
 }
 
+fn invalid_key(reason: InvalidKeyReason, message: impl Into<String>) -> StorageError {
+    StorageError::InvalidKey {
+        reason,
+        message: message.into(),
+    }
+}
+
+/// Returns whether a file name belongs to the store's internal bookkeeping.
+fn is_reserved(name: &OsStr) -> bool {
+    name.to_str()
+        .is_some_and(|name| name.starts_with(RESERVED_PREFIX))
+}
+
+/// Why a key was rejected, for callers that branch on it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvalidKeyReason {
+    /// The key is empty.
+    Empty,
+    /// The key starts at the filesystem root.
+    Absolute,
+    /// The key has a `..` segment.
+    ParentTraversal,
+    /// The key ends in `/`, naming a prefix rather than an object.
+    TrailingSlash,
+    /// The key, or one of its segments, exceeds the length limit.
+    TooLong,
+    /// The key contains control characters.
+    DisallowedChar,
+    /// A segment uses the prefix reserved for the store's bookkeeping files.
+    Reserved,
+    /// The key resolves through a symlink to a location outside the root.
+    OutsideRoot,
+    /// The object is a symlink and [`SymlinkPolicy::Reject`] is in effect.
+    Symlink,
+    /// Any other rejection, such as by a [`KeyMapper`] or a prefix operation.
+    Other,
+}
+
+impl InvalidKeyReason {
+    /// Returns a stable, machine-readable name for the reason.
+    pub fn code(self) -> &'static str {
+        match self {
+            InvalidKeyReason::Empty => "key_empty",
+            InvalidKeyReason::Absolute => "key_absolute",
+            InvalidKeyReason::ParentTraversal => "key_parent_traversal",
+            InvalidKeyReason::TrailingSlash => "key_trailing_slash",
+            InvalidKeyReason::TooLong => "key_too_long",
+            InvalidKeyReason::DisallowedChar => "key_disallowed_char",
+            InvalidKeyReason::Reserved => "key_reserved",
+            InvalidKeyReason::OutsideRoot => "key_outside_root",
+            InvalidKeyReason::Symlink => "key_symlink",
+            InvalidKeyReason::Other => "key_invalid",
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum StorageError {
-    #[error("invalid object key: {0}")]
-    InvalidKey(String),
+    #[error("invalid object key: {message}")]
+    InvalidKey {
+        reason: InvalidKeyReason,
+        message: String,
+    },
     #[error("object not found: {0}")]
     NotFound(String),
+    #[error("invalid object metadata: {0}")]
+    InvalidMetadata(String),
+    #[error("key conflict: {0}")]
+    Conflict(String),
+    /// A put with [`PutOptions::create_parents`] off named a key prefix that
+    /// has no directory yet.
+    #[error("key prefix {0} does not exist")]
+    PrefixNotFound(String),
+    /// The object no longer matches the version a conditional operation expected.
+    #[error("object {0} does not match the expected version")]
+    VersionMismatch(String),
+    /// The object is held by another writer.
+    #[error("object {0} is locked")]
+    Locked(String),
+    /// A write would take a prefix over its [`StorageOptions::prefix_quotas`] limit.
+    #[error("storage quota exceeded: {0}")]
+    QuotaExceeded(String),
+    /// A listing matched more keys than [`StorageOptions::max_list_entries`]
+    /// allows; list a narrower prefix or use [`FileStorage::list_delimited`].
+    #[error("listing matched {found_at_least} keys or more, over the configured maximum")]
+    ListTooLarge { found_at_least: usize },
+    #[error("object {key} is not valid UTF-8: {source}")]
+    Encoding {
+        key: String,
+        source: std::str::Utf8Error,
+    },
+    #[error("object {key} could not be converted to or from JSON: {source}")]
+    Serialization {
+        key: String,
+        source: serde_json::Error,
+    },
+    /// A tar archive given to [`FileStorage::import_tar`] is damaged or cut short.
+    #[error("invalid tar archive: {0}")]
+    InvalidArchive(String),
+    #[error("object {key} is {size} bytes, exceeding the {max}-byte limit")]
+    TooLarge { key: String, size: u64, max: u64 },
+    #[error("operation on {key} timed out after {after:?}")]
+    Timeout { key: String, after: Duration },
+    #[error("storage root {} does not exist", .0.display())]
+    RootMissing(PathBuf),
+    #[error("storage root {} is not a directory", .0.display())]
+    RootNotDirectory(PathBuf),
+    /// The storage root could not be created, usually because of a wrong
+    /// path or missing permissions rather than a transient fault.
+    #[error("storage root {} could not be created: {1}", .0.display())]
+    RootUnavailable(PathBuf, #[source] std::io::Error),
+    /// The process or system ran out of file descriptors; retrying later may succeed.
+    #[error("storage resources exhausted: {0}")]
+    ResourceExhausted(String),
     #[error("storage I/O error: {0}")]
-    Io(#[from] std::io::Error),
+    Io(std::io::Error),
+}
+
+/// `EMFILE` and `ENFILE`, which share these values on Linux, macOS, and the BSDs.
+#[cfg(unix)]
+const OUT_OF_FILE_DESCRIPTORS: [i32; 2] = [24, 23];
+/// `ERROR_TOO_MANY_OPEN_FILES`.
+#[cfg(not(unix))]
+const OUT_OF_FILE_DESCRIPTORS: [i32; 1] = [4];
+
+impl From<std::io::Error> for StorageError {
+    fn from(err: std::io::Error) -> Self {
+        match err.raw_os_error() {
+            Some(code) if OUT_OF_FILE_DESCRIPTORS.contains(&code) => {
+                StorageError::ResourceExhausted(err.to_string())
+            }
+            _ => StorageError::Io(err),
+        }
+    }
 }
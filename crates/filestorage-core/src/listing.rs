@@ -0,0 +1,146 @@
+//! In-memory copy of the stored keys that lets listings skip the tree walk.
+//!
+//! Writes made through the store update the cache as they complete. Files
+//! added or removed behind the store's back are only picked up by the periodic
+//! full scan, so listings can miss them for up to one reconcile interval.
+//! Writes that complete while a scan runs are remembered and replayed over
+//! its result, since the scan may have read their directory before or after
+//! they landed.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::Bound,
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::Duration,
+};
+
+use tokio::time::MissedTickBehavior;
+
+use crate::FileStorage;
+
+#[derive(Debug, Default)]
+pub(crate) struct ListingCache {
+    keys: RwLock<Keys>,
+}
+
+#[derive(Debug, Default)]
+struct Keys {
+    stored: BTreeSet<String>,
+    /// Scans started with [`ListingCache::rescan`] and not yet finished.
+    rescans: usize,
+    /// Whether each key written while a scan ran was last stored or removed.
+    pending: HashMap<String, bool>,
+}
+
+/// A full scan in progress, whose result is swapped in by [`finish`](Self::finish).
+pub(crate) struct Rescan<'a> {
+    cache: &'a ListingCache,
+}
+
+impl ListingCache {
+    /// Creates a cache holding `keys` and starts a task that replaces them with
+    /// a fresh scan of `storage` every `interval`.
+    ///
+    /// The task holds only a weak reference and exits once the cache is dropped.
+    pub(crate) fn start(keys: Vec<String>, storage: FileStorage, interval: Duration) -> Arc<Self> {
+        let cache = Arc::new(Self {
+            keys: RwLock::new(Keys {
+                stored: keys.into_iter().collect(),
+                ..Keys::default()
+            }),
+        });
+        let weak = Arc::downgrade(&cache);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // The first tick completes immediately, right after the initial scan.
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(cache) = weak.upgrade() else {
+                    break;
+                };
+                let rescan = cache.rescan();
+                match storage.scan().await {
+                    Ok(tree) => rescan.finish(tree.keys()),
+                    Err(err) => eprintln!("listing cache reconcile failed: {err}"),
+                }
+            }
+        });
+        cache
+    }
+
+    pub(crate) fn insert(&self, key: String) {
+        let mut keys = self.write();
+        if keys.rescans > 0 {
+            keys.pending.insert(key.clone(), true);
+        }
+        keys.stored.insert(key);
+    }
+
+    pub(crate) fn remove(&self, key: &str) {
+        let mut keys = self.write();
+        if keys.rescans > 0 {
+            keys.pending.insert(key.to_string(), false);
+        }
+        keys.stored.remove(key);
+    }
+
+    /// Starts remembering writes, to be replayed over the result of a full
+    /// scan that starts now.
+    pub(crate) fn rescan(&self) -> Rescan<'_> {
+        self.write().rescans += 1;
+        Rescan { cache: self }
+    }
+
+    /// Returns the first `limit` cached keys starting with `prefix`, in
+    /// sorted order.
+    pub(crate) fn list(&self, prefix: &str, limit: usize) -> Vec<String> {
+        self.read()
+            .stored
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|key| key.starts_with(prefix))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Keys> {
+        self.keys
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Keys> {
+        self.keys
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Rescan<'_> {
+    /// Swaps in the keys found by the scan, with the writes made since it
+    /// started applied on top.
+    pub(crate) fn finish(self, keys: Vec<String>) {
+        let mut state = self.cache.write();
+        let mut stored: BTreeSet<String> = keys.into_iter().collect();
+        for (key, &present) in &state.pending {
+            if present {
+                stored.insert(key.clone());
+            } else {
+                stored.remove(key);
+            }
+        }
+        state.stored = stored;
+    }
+}
+
+impl Drop for Rescan<'_> {
+    fn drop(&mut self) {
+        let mut state = self.cache.write();
+        state.rescans -= 1;
+        if state.rescans == 0 {
+            state.pending.clear();
+        }
+    }
+}
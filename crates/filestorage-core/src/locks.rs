@@ -0,0 +1,62 @@
+//! Per-key locks that serialize writes to the same object.
+//!
+//! Only writers that take the lock are ordered against each other; reads never
+//! wait for it. Locks are created on first use and dropped from the table once
+//! nobody holds or waits for them.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, Weak},
+};
+
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+/// Table size below which finished locks are left in place rather than pruned.
+const MIN_PRUNE_LEN: usize = 64;
+
+#[derive(Debug)]
+pub(crate) struct KeyLocks {
+    table: Mutex<Table>,
+}
+
+#[derive(Debug)]
+struct Table {
+    locks: HashMap<String, Weak<AsyncMutex<()>>>,
+    /// Length at which entries for released locks are swept out next.
+    prune_at: usize,
+}
+
+impl Default for KeyLocks {
+    fn default() -> Self {
+        Self {
+            table: Mutex::new(Table {
+                locks: HashMap::new(),
+                prune_at: MIN_PRUNE_LEN,
+            }),
+        }
+    }
+}
+
+impl KeyLocks {
+    /// Waits until no other holder has `key` locked and returns the guard.
+    pub(crate) async fn lock(&self, key: &str) -> OwnedMutexGuard<()> {
+        self.handle(key).lock_owned().await
+    }
+
+    fn handle(&self, key: &str) -> Arc<AsyncMutex<()>> {
+        let mut table = self
+            .table
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(lock) = table.locks.get(key).and_then(Weak::upgrade) {
+            return lock;
+        }
+        let lock = Arc::new(AsyncMutex::new(()));
+        table.locks.insert(key.to_string(), Arc::downgrade(&lock));
+        if table.locks.len() >= table.prune_at {
+            table.locks.retain(|_, lock| lock.strong_count() > 0);
+            table.prune_at = (table.locks.len() * 2).max(MIN_PRUNE_LEN);
+        }
+        lock
+    }
+}
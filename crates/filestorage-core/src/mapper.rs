@@ -0,0 +1,57 @@
+//! Translation between logical object keys and their on-disk layout.
+
+use std::{
+    fmt,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::StorageError;
+
+/// Decides where each object lives below the storage root.
+///
+/// Keys handed to [`to_path`](Self::to_path) have already passed the store's
+/// key validation. The returned path is relative to the root and may only
+/// contain plain segments; anything else is rejected as an invalid key.
+/// [`to_key`](Self::to_key) must invert `to_path` so listings report the
+/// original keys.
+pub trait KeyMapper: fmt::Debug + Send + Sync {
+    /// Maps `key` to a path relative to the storage root.
+    fn to_path(&self, key: &str) -> Result<PathBuf, StorageError>;
+
+    /// Maps a file found below the root back to its key, or `None` for files
+    /// that do not belong to this layout.
+    fn to_key(&self, path: &Path) -> Option<String>;
+
+    /// Returns whether every key starting with `prefix/` is stored below
+    /// `to_path(prefix)`, which lets a whole prefix move with one directory
+    /// rename.
+    fn nests_prefixes(&self) -> bool {
+        false
+    }
+}
+
+/// Stores each key at the matching path below the root, with `/`-separated
+/// segments becoming directories.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultKeyMapper;
+
+impl KeyMapper for DefaultKeyMapper {
+    fn to_path(&self, key: &str) -> Result<PathBuf, StorageError> {
+        Ok(PathBuf::from(key))
+    }
+
+    fn to_key(&self, path: &Path) -> Option<String> {
+        let segments: Option<Vec<&str>> = path
+            .components()
+            .map(|component| match component {
+                Component::Normal(segment) => segment.to_str(),
+                _ => None,
+            })
+            .collect();
+        Some(segments?.join("/"))
+    }
+
+    fn nests_prefixes(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,97 @@
+//! Experiments that check the filesystem under the root behaves as the store
+//! assumes.
+
+use std::{
+    io::{self, ErrorKind},
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use tokio::fs;
+
+use crate::{FileStorage, StorageError, atomic::RESERVED_PREFIX, durability};
+
+static PROBE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// What [`FileStorage::probe_filesystem`] found the filesystem under the root can do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FsCapabilities {
+    /// Renaming a file over an existing one replaces it in a single step, as
+    /// [`RenameStrategy::Atomic`](crate::RenameStrategy::Atomic) requires.
+    pub atomic_rename: bool,
+    /// Files and directories can be flushed to disk, as
+    /// [`StorageOptions::sync_writes`](crate::StorageOptions::sync_writes) requires.
+    pub supports_fsync: bool,
+    /// The filesystem reports its size and free space.
+    pub reports_free_space: bool,
+    /// Names differing only in case are different files, so keys that differ
+    /// only in case do not collide.
+    pub case_sensitive: bool,
+}
+
+impl FileStorage {
+    /// Runs small write, rename, fsync, and stat experiments in a scratch
+    /// directory under the root and reports which assumptions held.
+    ///
+    /// Meant to be run once before trusting a new volume. Failures of the
+    /// experiments themselves are reported as missing capabilities; only
+    /// failing to set up or clean up the scratch directory is an error.
+    pub async fn probe_filesystem(&self) -> Result<FsCapabilities, StorageError> {
+        let n = PROBE_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let scratch = self
+            .root
+            .join(format!("{RESERVED_PREFIX}probe-{}-{n}", std::process::id()));
+        fs::create_dir(&scratch).await?;
+        let capabilities = FsCapabilities {
+            atomic_rename: probe_rename(&scratch).await.unwrap_or(false),
+            supports_fsync: probe_fsync(&scratch).await.is_ok(),
+            reports_free_space: probe_free_space(&scratch).await,
+            case_sensitive: probe_case(&scratch).await.unwrap_or(true),
+        };
+        fs::remove_dir_all(&scratch).await?;
+        Ok(capabilities)
+    }
+}
+
+/// Renames a file over another and checks only the new content remains.
+async fn probe_rename(dir: &Path) -> io::Result<bool> {
+    let target = dir.join("target");
+    let replacement = dir.join("replacement");
+    fs::write(&target, b"old").await?;
+    fs::write(&replacement, b"new").await?;
+    fs::rename(&replacement, &target).await?;
+    let replaced = fs::read(&target).await? == b"new";
+    let source_gone = matches!(
+        fs::symlink_metadata(&replacement).await,
+        Err(err) if err.kind() == ErrorKind::NotFound
+    );
+    Ok(replaced && source_gone)
+}
+
+async fn probe_fsync(dir: &Path) -> io::Result<()> {
+    let file = fs::File::create(dir.join("synced")).await?;
+    file.sync_all().await?;
+    durability::sync_dir(dir).await
+}
+
+#[cfg(unix)]
+async fn probe_free_space(dir: &Path) -> bool {
+    let dir = dir.to_path_buf();
+    let stats = tokio::task::spawn_blocking(move || rustix::fs::statvfs(&dir)).await;
+    matches!(stats, Ok(Ok(stats)) if stats.f_blocks > 0 && stats.f_bavail <= stats.f_blocks)
+}
+
+#[cfg(not(unix))]
+async fn probe_free_space(_dir: &Path) -> bool {
+    false
+}
+
+/// Creates a lowercase name and looks it up in uppercase.
+async fn probe_case(dir: &Path) -> io::Result<bool> {
+    fs::write(dir.join("case"), b"").await?;
+    match fs::symlink_metadata(dir.join("CASE")).await {
+        Ok(_) => Ok(false),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(true),
+        Err(err) => Err(err),
+    }
+}
@@ -0,0 +1,207 @@
+//! Storage limits per tenant, where a key's tenant is its first `/`-separated segment.
+//!
+//! Usage of each limited prefix is counted by a scan when the store opens and
+//! then adjusted by every put, range write, and delete. Operations that touch
+//! many objects at once, such as prefix deletes and moves, mark the prefixes
+//! involved as stale instead, and the next write there recounts them.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+};
+
+use tokio::{
+    fs,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::{FileStorage, StorageError};
+
+#[derive(Debug)]
+pub(crate) struct Quotas {
+    /// Handle on the top-level store, without quotas, used for recounts.
+    scanner: FileStorage,
+    prefixes: HashMap<String, Usage>,
+}
+
+#[derive(Debug)]
+struct Usage {
+    limit: u64,
+    /// Bytes stored under the prefix, or `None` until the next recount.
+    ///
+    /// Writers hold the lock from their check until their write lands, so
+    /// writes within one limited prefix are serialized.
+    used: Mutex<Option<u64>>,
+}
+
+/// Locked usage of one limited prefix.
+pub(crate) struct Charge<'a> {
+    prefix: &'a str,
+    limit: u64,
+    used: MutexGuard<'a, Option<u64>>,
+}
+
+/// A checked change in usage, recorded by [`settle`](Self::settle) once the
+/// write has landed. Dropping it leaves usage as it was.
+pub(crate) struct Reservation<'a> {
+    charge: Charge<'a>,
+    old: u64,
+    new: u64,
+}
+
+impl Quotas {
+    /// Sets up `limits`, keyed by prefix with or without a trailing `/`, and
+    /// counts current usage with one scan of `scanner`.
+    pub(crate) async fn start(
+        limits: &HashMap<String, u64>,
+        scanner: FileStorage,
+    ) -> Result<Arc<Self>, StorageError> {
+        let limits: HashMap<String, u64> = limits
+            .iter()
+            .map(|(prefix, &limit)| (prefix.trim_end_matches('/').to_string(), limit))
+            .collect();
+        let mut used: HashMap<String, u64> = HashMap::new();
+        for (key, path) in scanner.scan().await?.files {
+            if let Some(prefix) = tenant(&key).filter(|prefix| limits.contains_key(*prefix)) {
+                *used.entry(prefix.to_string()).or_default() += file_len(&path).await?;
+            }
+        }
+        let prefixes = limits
+            .into_iter()
+            .map(|(prefix, limit)| {
+                let usage = Usage {
+                    limit,
+                    used: Mutex::new(Some(used.get(&prefix).copied().unwrap_or_default())),
+                };
+                (prefix, usage)
+            })
+            .collect();
+        Ok(Arc::new(Self { scanner, prefixes }))
+    }
+
+    /// Locks the usage of the prefix `key` falls under, recounting it if
+    /// stale, or returns `None` when that prefix has no limit.
+    pub(crate) async fn charge(&self, key: &str) -> Result<Option<Charge<'_>>, StorageError> {
+        match tenant(key) {
+            Some(prefix) => self.charge_prefix(prefix).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Like [`charge`](Self::charge) for every distinct limited prefix among
+    /// `keys`, locked in a fixed order so concurrent batches cannot deadlock.
+    pub(crate) async fn charge_all<'k>(
+        &self,
+        keys: impl IntoIterator<Item = &'k str>,
+    ) -> Result<Vec<Charge<'_>>, StorageError> {
+        let prefixes: BTreeSet<&str> = keys.into_iter().filter_map(tenant).collect();
+        let mut charges = Vec::with_capacity(prefixes.len());
+        for prefix in prefixes {
+            charges.extend(self.charge_prefix(prefix).await?);
+        }
+        Ok(charges)
+    }
+
+    async fn charge_prefix(&self, prefix: &str) -> Result<Option<Charge<'_>>, StorageError> {
+        let Some((prefix, usage)) = self.prefixes.get_key_value(prefix) else {
+            return Ok(None);
+        };
+        let mut used = usage.used.lock().await;
+        if used.is_none() {
+            *used = Some(self.recount(prefix).await?);
+        }
+        Ok(Some(Charge {
+            prefix,
+            limit: usage.limit,
+            used,
+        }))
+    }
+
+    /// Marks the usage of the prefix `key` falls under for a recount.
+    pub(crate) async fn invalidate(&self, key: &str) {
+        if let Some(usage) = tenant(key).and_then(|prefix| self.prefixes.get(prefix)) {
+            *usage.used.lock().await = None;
+        }
+    }
+
+    /// Marks the usage of every limited prefix for a recount.
+    pub(crate) async fn invalidate_all(&self) {
+        for usage in self.prefixes.values() {
+            *usage.used.lock().await = None;
+        }
+    }
+
+    async fn recount(&self, prefix: &str) -> Result<u64, StorageError> {
+        let mut used = 0;
+        for (key, path) in self.scanner.scan().await?.files {
+            if tenant(&key) == Some(prefix) {
+                used += file_len(&path).await?;
+            }
+        }
+        Ok(used)
+    }
+}
+
+impl<'a> Charge<'a> {
+    /// Returns the prefix this charge applies to.
+    pub(crate) fn prefix(&self) -> &str {
+        self.prefix
+    }
+
+    /// Fails with [`StorageError::QuotaExceeded`] if replacing `old` bytes
+    /// with `new` would take the prefix over its limit. Shrinking always passes.
+    pub(crate) fn check(&self, old: u64, new: u64) -> Result<(), StorageError> {
+        let used = self.used.unwrap_or_default();
+        let after = used.saturating_sub(old).saturating_add(new);
+        if new > old && after > self.limit {
+            return Err(StorageError::QuotaExceeded(format!(
+                "`{}` would use {after} bytes, over its {}-byte quota",
+                self.prefix, self.limit
+            )));
+        }
+        Ok(())
+    }
+
+    /// Records that `old` bytes under the prefix were replaced by `new`.
+    pub(crate) fn apply(&mut self, old: u64, new: u64) {
+        if let Some(used) = self.used.as_mut() {
+            *used = used.saturating_sub(old).saturating_add(new);
+        }
+    }
+
+    /// Marks the prefix for a recount, for when a change may have partly landed.
+    pub(crate) fn forget(&mut self) {
+        *self.used = None;
+    }
+
+    /// [`check`](Self::check)s the change, keeping the prefix locked until it is settled.
+    pub(crate) fn reserve(self, old: u64, new: u64) -> Result<Reservation<'a>, StorageError> {
+        self.check(old, new)?;
+        Ok(Reservation {
+            charge: self,
+            old,
+            new,
+        })
+    }
+}
+
+impl Reservation<'_> {
+    /// Records the reserved change in the prefix's usage.
+    pub(crate) fn settle(mut self) {
+        self.charge.apply(self.old, self.new);
+    }
+}
+
+/// Returns the first segment of `key`, or `None` for keys without a `/`.
+pub(crate) fn tenant(key: &str) -> Option<&str> {
+    key.split_once('/').map(|(prefix, _)| prefix)
+}
+
+/// Returns the length of the file at `path`, or 0 if it is already gone.
+pub(crate) async fn file_len(path: &std::path::Path) -> Result<u64, StorageError> {
+    match fs::symlink_metadata(path).await {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(StorageError::from(err)),
+    }
+}
@@ -0,0 +1,181 @@
+//! Per-object sidecar files holding attributes that are not part of the content.
+//!
+//! A sidecar lives next to its object and is named
+//! `.filestorage-<kind>.<object file name>`, so directory scans skip it along
+//! with every other reserved file.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::fs;
+
+use crate::{RenameStrategy, StorageError, atomic};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Sidecar {
+    ContentType,
+    Metadata,
+    Expiry,
+}
+
+impl Sidecar {
+    pub(crate) const ALL: [Sidecar; 3] = [Sidecar::ContentType, Sidecar::Metadata, Sidecar::Expiry];
+
+    /// Longest prefix a sidecar adds to its object's file name.
+    pub(crate) const MAX_NAME_PREFIX_LEN: usize = {
+        let mut longest = 0;
+        let mut i = 0;
+        while i < Sidecar::ALL.len() {
+            let len = Sidecar::ALL[i].kind().len();
+            if len > longest {
+                longest = len;
+            }
+            i += 1;
+        }
+        atomic::RESERVED_PREFIX.len() + longest + 1
+    };
+
+    const fn kind(self) -> &'static str {
+        match self {
+            Sidecar::ContentType => "type",
+            Sidecar::Metadata => "meta",
+            Sidecar::Expiry => "expires",
+        }
+    }
+
+    /// Returns the sidecar path for the object stored at `object`.
+    pub(crate) fn path_for(self, object: &Path) -> PathBuf {
+        let name = object.file_name().unwrap_or_default().to_string_lossy();
+        object.with_file_name(format!("{}{}.{name}", atomic::RESERVED_PREFIX, self.kind()))
+    }
+
+    /// Returns the object a sidecar file at `path` belongs to, or `None` if
+    /// `path` is not a sidecar.
+    pub(crate) fn object_for(path: &Path) -> Option<PathBuf> {
+        let name = path.file_name()?.to_str()?;
+        let rest = name.strip_prefix(atomic::RESERVED_PREFIX)?;
+        Sidecar::ALL.iter().find_map(|sidecar| {
+            let object = rest.strip_prefix(sidecar.kind())?.strip_prefix('.')?;
+            (!object.is_empty()).then(|| path.with_file_name(object))
+        })
+    }
+
+    /// Reads the sidecar for `object`, returning `None` when it does not exist.
+    pub(crate) async fn read(self, object: &Path) -> io::Result<Option<String>> {
+        match fs::read_to_string(self.path_for(object)).await {
+            Ok(contents) => Ok(Some(contents)),
+            Err(err) if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => {
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Replaces the sidecar for `object`, or removes it when `contents` is `None`.
+    pub(crate) async fn write(
+        self,
+        object: &Path,
+        contents: Option<&str>,
+        strategy: RenameStrategy,
+    ) -> io::Result<()> {
+        let path = self.path_for(object);
+        match contents {
+            Some(contents) => atomic::write_atomic(&path, contents.as_bytes(), strategy).await,
+            None => remove_if_present(&path).await,
+        }
+    }
+
+    /// Removes every sidecar belonging to `object`.
+    pub(crate) async fn remove_all(object: &Path) -> io::Result<()> {
+        for sidecar in Sidecar::ALL {
+            remove_if_present(&sidecar.path_for(object)).await?;
+        }
+        Ok(())
+    }
+
+    /// Exchanges the sidecars of `a` and `b`, including ones only one of them has.
+    pub(crate) async fn swap_all(a: &Path, b: &Path) -> io::Result<()> {
+        for sidecar in Sidecar::ALL {
+            let (a, b) = (sidecar.path_for(a), sidecar.path_for(b));
+            let stash = atomic::temp_path_for(&a);
+            let had_a = rename_if_present(&a, &stash).await?;
+            rename_if_present(&b, &a).await?;
+            if had_a {
+                fs::rename(&stash, &b).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Renames `src` to `dst`, returning whether `src` existed.
+async fn rename_if_present(src: &Path, dst: &Path) -> io::Result<bool> {
+    match fs::rename(src, dst).await {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+async fn remove_if_present(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path).await {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Serializes user metadata as `name: value` lines.
+pub(crate) fn encode_metadata(
+    metadata: &BTreeMap<String, String>,
+) -> Result<Option<String>, StorageError> {
+    if metadata.is_empty() {
+        return Ok(None);
+    }
+    let mut encoded = String::new();
+    for (name, value) in metadata {
+        if name.is_empty() || name.contains([':', '\n', '\r']) {
+            return Err(StorageError::InvalidMetadata(format!(
+                "metadata name `{name}` must be non-empty and cannot contain `:` or newlines"
+            )));
+        }
+        if value.contains(['\n', '\r']) {
+            return Err(StorageError::InvalidMetadata(format!(
+                "metadata value for `{name}` cannot contain newlines"
+            )));
+        }
+        encoded.push_str(name);
+        encoded.push_str(": ");
+        encoded.push_str(value);
+        encoded.push('\n');
+    }
+    Ok(Some(encoded))
+}
+
+/// Parses the `name: value` lines written by [`encode_metadata`].
+pub(crate) fn decode_metadata(encoded: &str) -> BTreeMap<String, String> {
+    encoded
+        .lines()
+        .filter_map(|line| line.split_once(": "))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Serializes an expiry instant as milliseconds since the Unix epoch.
+pub(crate) fn encode_expiry(expires_at: SystemTime) -> String {
+    let millis = expires_at
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis())
+        .unwrap_or_default();
+    millis.to_string()
+}
+
+/// Parses the value written by [`encode_expiry`], ignoring malformed contents.
+pub(crate) fn decode_expiry(encoded: &str) -> Option<SystemTime> {
+    let millis = encoded.trim().parse().ok()?;
+    UNIX_EPOCH.checked_add(Duration::from_millis(millis))
+}
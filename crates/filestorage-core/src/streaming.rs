@@ -0,0 +1,447 @@
+//! Object content read or written as a stream of chunks rather than one buffer.
+
+use std::{
+    future::{Future, poll_fn},
+    io::{self, SeekFrom},
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures_core::Stream;
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs,
+    io::{
+        AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, ReadBuf,
+    },
+};
+
+use crate::{
+    FileStorage, PutCondition, StorageError, atomic, create_parent, io_error, quota,
+    sidecar::Sidecar,
+};
+
+/// Size of the chunks readers are consumed in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Reader over part of an object, returned by [`FileStorage::read_range`].
+pub type ObjectReader = Box<dyn AsyncRead + Send + Unpin>;
+
+impl FileStorage {
+    /// Stores the chunks of `stream` under `key` and returns the number of bytes written.
+    ///
+    /// Chunks are written to a temp file that is renamed into place once the
+    /// stream ends, so an error from the stream or the disk leaves any
+    /// previous object untouched and no partial one behind. Each chunk is
+    /// checked against the key's prefix quota before it is written, so an
+    /// oversized stream fails with [`StorageError::QuotaExceeded`] as soon as
+    /// it crosses the limit. Once the stream ends, the object is moved to the
+    /// tier its size calls for. Like [`put`](Self::put), the new object has no
+    /// content type or metadata.
+    pub async fn put_stream<S>(&self, key: &str, stream: S) -> Result<u64, StorageError>
+    where
+        S: Stream<Item = Result<Bytes, io::Error>> + Unpin,
+    {
+        self.put_stream_if(key, stream, &PutCondition::Always).await
+    }
+
+    /// Like [`put_stream`](Self::put_stream), failing before `stream` is
+    /// read unless `condition` holds once the key is locked.
+    pub(crate) async fn put_stream_if<S>(
+        &self,
+        key: &str,
+        mut stream: S,
+        condition: &PutCondition,
+    ) -> Result<u64, StorageError>
+    where
+        S: Stream<Item = Result<Bytes, io::Error>> + Unpin,
+    {
+        let path = self.path_for(key)?;
+        self.ensure_within_root(key, &path).await?;
+        let _guard = self.lock_key(key).await;
+        self.check_condition(key, condition).await?;
+        self.index_insert(key);
+        create_parent(key, &path).await?;
+
+        // The prefix stays locked from the first chunk until the object lands.
+        let charge = self.quota_charge(key).await?;
+        let old = match charge {
+            Some(_) => quota::file_len(&path).await?,
+            None => 0,
+        };
+        let tmp = atomic::temp_path_for(&path);
+        let mut hasher = self.checksums.as_ref().map(|_| Sha256::new());
+        let written = async {
+            let mut file = fs::File::create(&tmp).await?;
+            let mut total = 0u64;
+            while let Some(chunk) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+                let chunk = chunk?;
+                total += chunk.len() as u64;
+                if let Some(charge) = &charge {
+                    charge.check(old, total)?;
+                }
+                file.write_all(&chunk).await?;
+                if let Some(hasher) = &mut hasher {
+                    hasher.update(&chunk);
+                }
+            }
+            if self.durability.syncs_files() {
+                file.sync_all().await?;
+            } else {
+                file.flush().await?;
+            }
+            Ok::<_, StorageError>(total)
+        }
+        .await;
+        let reserved = written.and_then(|total| {
+            let reservation = charge
+                .map(|charge| charge.reserve(old, total))
+                .transpose()?;
+            Ok((total, reservation))
+        });
+        let (total, reservation) = match reserved {
+            Ok(reserved) => reserved,
+            Err(err) => {
+                let _ = fs::remove_file(&tmp).await;
+                return Err(err);
+            }
+        };
+        let tier = self.tier_for_size(total);
+        let stored = match tier {
+            // The tier's root may be on another device, so the object is copied.
+            Some(i) => {
+                drop(reservation);
+                let store = &self.tiers[i].1;
+                let copied = async {
+                    store.ensure_within_root(key, &store.path_for(key)?).await?;
+                    store.copy_in(key, &tmp).await
+                }
+                .await;
+                let _ = fs::remove_file(&tmp).await;
+                copied?;
+                store.path_for(key)?
+            }
+            None => {
+                atomic::rename_or_discard(&tmp, &path, self.rename_strategy)
+                    .await
+                    .map_err(|err| io_error(key, err))?;
+                if let Some(reservation) = reservation {
+                    reservation.settle();
+                }
+                Sidecar::remove_all(&path).await?;
+                self.record_key(key);
+                if let Some(hasher) = hasher {
+                    self.record_digest(&path, hex::encode(hasher.finalize()))
+                        .await?;
+                }
+                self.durability.sync_parent(&path).await?;
+                path
+            }
+        };
+        self.evict_from_other_tiers(key, tier).await?;
+
+        if let Some(replica) = &self.replica {
+            let result = replica.copy_in(key, &stored).await;
+            self.apply_replica_policy(key, result)?;
+        }
+        Ok(total)
+    }
+
+    /// Stores everything `reader` yields under `key` and returns the number of bytes written.
+    ///
+    /// The reader is consumed in fixed-size chunks with the same guarantees as
+    /// [`put_stream`](Self::put_stream).
+    pub async fn put_reader<R>(&self, key: &str, reader: R) -> Result<u64, StorageError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        self.put_stream(key, ReaderStream::new(reader)).await
+    }
+
+    /// Appends everything `reader` yields to `key` without buffering it,
+    /// creating the object if needed, and returns the object's new length.
+    ///
+    /// The reader is consumed under the key's lock, so appends to the same key
+    /// never interleave. Each chunk is checked against the key's prefix quota
+    /// before it is written, as with [`put_stream`](Self::put_stream). If the
+    /// reader or the disk fails part way, the appended bytes would exceed the
+    /// quota, or [`StorageOptions::op_timeout`](crate::StorageOptions::op_timeout)
+    /// runs out, the object is cut back to its previous length. Attributes
+    /// such as the content type are kept.
+    pub async fn append_reader<R>(&self, key: &str, reader: R) -> Result<u64, StorageError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        self.timed(key, async {
+            let _guard = self.lock_key(key).await;
+            let holder = self.holder(key).await?;
+            let (old, len) = holder.append_reader_local(key, reader).await?;
+            if let Some(replica) = &self.replica {
+                // The replica is sent the bytes just appended, read back from disk.
+                let result = async {
+                    let mut appended = fs::File::open(holder.path_for(key)?).await?;
+                    appended.seek(SeekFrom::Start(old)).await?;
+                    replica
+                        .append_reader_local(key, appended.take(len - old))
+                        .await
+                        .map(drop)
+                }
+                .await;
+                self.apply_replica_policy(key, result)?;
+            }
+            Ok(len)
+        })
+        .await
+    }
+
+    /// Appends `reader` to `key` in this root alone, returning the object's
+    /// previous and new lengths.
+    async fn append_reader_local<R>(
+        &self,
+        key: &str,
+        mut reader: R,
+    ) -> Result<(u64, u64), StorageError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let path = self.path_for(key)?;
+        self.ensure_within_root(key, &path).await?;
+        self.decompress_in_place(key, &path).await?;
+        self.index_insert(key);
+        create_parent(key, &path).await?;
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .await
+            .map_err(|err| io_error(key, err))?;
+        let old = file.metadata().await?.len();
+        // The prefix stays locked from the first chunk until the append lands
+        // or is cut back.
+        let charge = self.quota_charge(key).await?;
+        let cut_back = CutBack {
+            path: &path,
+            len: old,
+        };
+        let mut buf = vec![0; CHUNK_SIZE];
+        let mut len = old;
+        loop {
+            let read = reader.read(&mut buf).await?;
+            if read == 0 {
+                break;
+            }
+            len += read as u64;
+            if let Some(charge) = &charge {
+                charge.check(old, len)?;
+            }
+            file.write_all(&buf[..read]).await?;
+        }
+        if self.durability.syncs_files() {
+            file.sync_all().await?;
+        } else {
+            file.flush().await?;
+        }
+        let reservation = charge.map(|charge| charge.reserve(old, len)).transpose()?;
+        // The append landed, so there is nothing to cut back.
+        std::mem::forget(cut_back);
+        if let Some(reservation) = reservation {
+            reservation.settle();
+        }
+        self.record_key(key);
+        self.forget_checksum(&path).await?;
+        self.durability.sync_parent(&path).await?;
+        Ok((old, len))
+    }
+
+    /// Opens up to `len` bytes of `key` starting at `offset` for reading
+    /// without buffering them, returning the reader and the number of bytes
+    /// it will yield.
+    ///
+    /// The length is taken from the opened file, so it stays accurate even
+    /// if the object is replaced while the reader is in use. Only opening the
+    /// object counts towards [`StorageOptions::op_timeout`](crate::StorageOptions::op_timeout).
+    pub async fn read_range(
+        &self,
+        key: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<(ObjectReader, u64), StorageError> {
+        let path = self.path_for(key)?;
+        if let Some(tier) = self.tier_holding(key).await? {
+            return Box::pin(tier.read_range(key, offset, len)).await;
+        }
+        if !self.may_exist(key) {
+            return Err(StorageError::NotFound(key.to_string()));
+        }
+        self.timed(key, async {
+            self.ensure_within_root(key, &path).await?;
+            self.ensure_live(key).await?;
+            if let Some(target) = self.link_target(key, &path).await? {
+                let start = offset.min(target.len() as u64) as usize;
+                let end = offset.saturating_add(len).min(target.len() as u64) as usize;
+                let part = target[start..end].to_vec();
+                let len = part.len() as u64;
+                return Ok((Box::new(io::Cursor::new(part)) as ObjectReader, len));
+            }
+            let stored = self.open_stored(key, &path).await?;
+            Ok(stored.into_range(offset, len).await?)
+        })
+        .await
+    }
+
+    /// Like [`read_range`](Self::read_range), but reads to the end of the
+    /// object when `len` is `None` and returns only the reader.
+    pub async fn get_range_reader(
+        &self,
+        key: &str,
+        offset: u64,
+        len: Option<u64>,
+    ) -> Result<ObjectReader, StorageError> {
+        let (reader, _) = self
+            .read_range(key, offset, len.unwrap_or(u64::MAX))
+            .await?;
+        Ok(reader)
+    }
+
+    /// Streams the lines of `key` as UTF-8 text, reading the object lazily
+    /// through a buffer rather than loading it whole.
+    ///
+    /// Lines are yielded without their `\n` or `\r\n` ending, including a
+    /// final line with no ending. Nothing is opened until the stream is first
+    /// polled, so a missing key shows up as a `NotFound` first item. The
+    /// stream ends after the first error, such as a line that is not UTF-8.
+    pub fn read_lines(
+        &self,
+        key: &str,
+    ) -> impl Stream<Item = Result<String, StorageError>> + Send + use<> {
+        let storage = self.clone();
+        let key = key.to_string();
+        ObjectLines::Opening(Box::pin(async move {
+            storage.get_range_reader(&key, 0, None).await
+        }))
+    }
+
+    /// Copies `key` from this store to `dst_key` in `dst` without buffering the
+    /// whole object. Like [`put_reader`](Self::put_reader), the copy has no
+    /// content type or metadata.
+    pub async fn copy_to(
+        &self,
+        key: &str,
+        dst: &FileStorage,
+        dst_key: &str,
+    ) -> Result<(), StorageError> {
+        let path = self.path_for(key)?;
+        if !self.may_exist(key) {
+            return Err(StorageError::NotFound(key.to_string()));
+        }
+        if let Some(tier) = self.tier_holding(key).await? {
+            return Box::pin(tier.copy_to(key, dst, dst_key)).await;
+        }
+        self.ensure_within_root(key, &path).await?;
+        self.ensure_live(key).await?;
+        let (reader, _) = self
+            .open_stored(key, &path)
+            .await?
+            .into_range(0, u64::MAX)
+            .await?;
+        dst.put_reader(dst_key, reader).await?;
+        Ok(())
+    }
+
+    /// Replaces `key` with a copy of the file at `src`, dropping its attributes.
+    pub(crate) async fn copy_in(&self, key: &str, src: &Path) -> Result<(), StorageError> {
+        let path = self.path_for(key)?;
+        create_parent(key, &path).await?;
+        atomic::copy_atomic(src, &path, self.rename_strategy)
+            .await
+            .map_err(|err| io_error(key, err))?;
+        Sidecar::remove_all(&path).await?;
+        self.durability.sync_parent(&path).await?;
+        Ok(())
+    }
+}
+
+/// Stream returned by [`FileStorage::read_lines`].
+enum ObjectLines {
+    Opening(Pin<Box<dyn Future<Output = Result<ObjectReader, StorageError>> + Send>>),
+    Reading(tokio::io::Lines<BufReader<ObjectReader>>),
+    Done,
+}
+
+impl Stream for ObjectLines {
+    type Item = Result<String, StorageError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let next = match this {
+                Self::Opening(open) => match open.as_mut().poll(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Ok(reader)) => {
+                        *this = Self::Reading(BufReader::new(reader).lines());
+                        continue;
+                    }
+                    Poll::Ready(Err(err)) => Some(Err(err)),
+                },
+                Self::Reading(lines) => match Pin::new(lines).poll_next_line(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Ok(Some(line))) => return Poll::Ready(Some(Ok(line))),
+                    Poll::Ready(Ok(None)) => None,
+                    Poll::Ready(Err(err)) => Some(Err(err.into())),
+                },
+                Self::Done => None,
+            };
+            *this = Self::Done;
+            return Poll::Ready(next);
+        }
+    }
+}
+
+/// Adapts an [`AsyncRead`] into a stream of chunks.
+struct ReaderStream<R> {
+    reader: R,
+    buf: Box<[u8]>,
+}
+
+impl<R> ReaderStream<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: vec![0; CHUNK_SIZE].into_boxed_slice(),
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for ReaderStream<R> {
+    type Item = Result<Bytes, io::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let mut buf = ReadBuf::new(&mut this.buf);
+        match Pin::new(&mut this.reader).poll_read(cx, &mut buf) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Err(err)) => Poll::Ready(Some(Err(err))),
+            Poll::Ready(Ok(())) if buf.filled().is_empty() => Poll::Ready(None),
+            Poll::Ready(Ok(())) => Poll::Ready(Some(Ok(Bytes::copy_from_slice(buf.filled())))),
+        }
+    }
+}
+
+/// Cuts the file at `path` back to `len` bytes when dropped, undoing an
+/// append that failed or was abandoned part way.
+struct CutBack<'a> {
+    path: &'a Path,
+    len: u64,
+}
+
+impl Drop for CutBack<'_> {
+    fn drop(&mut self) {
+        // `drop` cannot await, so the file is truncated with a blocking call.
+        let _ = std::fs::OpenOptions::new()
+            .write(true)
+            .open(self.path)
+            .and_then(|file| file.set_len(self.len));
+    }
+}
@@ -0,0 +1,185 @@
+//! Routing of objects to separate roots by size, such as small objects on
+//! SSD and large ones on HDD.
+//!
+//! Each tier is a plain store rooted at its own directory. Objects smaller
+//! than every tier's threshold stay under the main root.
+
+use std::{path::PathBuf, sync::Arc};
+
+use crate::{
+    FileStorage, PutOptions, PutOutcome, StorageError, Tree, create_parent, io_error, move_object,
+    sidecar::Sidecar,
+};
+
+/// A root that receives objects of at least `min_size` bytes, configured
+/// through [`StorageOptions::tiering`](crate::StorageOptions::tiering).
+#[derive(Clone, Debug)]
+pub struct Tier {
+    pub min_size: u64,
+    pub root: PathBuf,
+}
+
+impl FileStorage {
+    /// Returns the position in `tiers` of the tier a `len`-byte object
+    /// belongs in, or `None` for the main root.
+    pub(crate) fn tier_for_size(&self, len: u64) -> Option<usize> {
+        self.tiers
+            .iter()
+            .rposition(|(min_size, _)| *min_size <= len)
+    }
+
+    /// Returns the tier holding `key` when the main root does not, checking
+    /// the tiers in order.
+    ///
+    /// Every operation on an existing key resolves it through this, so that a
+    /// copy left in the main root never shadows the tiered object.
+    pub(crate) async fn tier_holding(
+        &self,
+        key: &str,
+    ) -> Result<Option<&Arc<FileStorage>>, StorageError> {
+        if self.tiers.is_empty() || self.exists_here(key).await? {
+            return Ok(None);
+        }
+        for (_, tier) in &self.tiers {
+            if tier.exists_here(key).await? {
+                return Ok(Some(tier));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns this store followed by each tier, in order.
+    pub(crate) fn stores(&self) -> impl Iterator<Item = &FileStorage> {
+        std::iter::once(self).chain(self.tiers.iter().map(|(_, tier)| tier.as_ref()))
+    }
+
+    /// Walks the main root and then each tier's, returning every store with
+    /// the objects under it whose key starts with `prefix`.
+    pub(crate) async fn select_everywhere(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<(&FileStorage, Tree)>, StorageError> {
+        let mut selected = Vec::with_capacity(self.tiers.len() + 1);
+        for store in self.stores() {
+            selected.push((store, store.select_prefix(prefix).await?));
+        }
+        Ok(selected)
+    }
+
+    /// Returns the keys [`select_everywhere`](Self::select_everywhere)
+    /// finds, sorted and without duplicates.
+    pub(crate) async fn keys_everywhere(&self, prefix: &str) -> Result<Vec<String>, StorageError> {
+        let mut keys = Vec::new();
+        for (_, tree) in self.select_everywhere(prefix).await? {
+            keys.extend(tree.files.into_iter().map(|(key, _)| key));
+        }
+        keys.sort();
+        keys.dedup();
+        Ok(keys)
+    }
+
+    /// Returns the store holding `key`: its tier, or this store when the main
+    /// root holds it or nothing does.
+    pub(crate) async fn holder(&self, key: &str) -> Result<&FileStorage, StorageError> {
+        Ok(self
+            .tier_holding(key)
+            .await?
+            .map_or(self, |tier| tier.as_ref()))
+    }
+
+    /// Stores `data` under `key` in the tier its size calls for and removes
+    /// any copy left in another; the caller holds the key's lock.
+    pub(crate) async fn put_routed(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &PutOptions,
+    ) -> Result<PutOutcome, StorageError> {
+        let tier = self.tier_for_size(data.len() as u64);
+        let outcome = match tier {
+            Some(i) => self.tiers[i].1.put_local(key, data, options).await?,
+            None => self.put_local(key, data, options).await?,
+        };
+        self.evict_from_other_tiers(key, tier).await?;
+        Ok(outcome)
+    }
+
+    /// Exchanges `a`, held by `store_a`, with `b`, held by another store, by
+    /// moving each object to the other key within the store holding it, so
+    /// both stay in the tier their size put them in.
+    ///
+    /// Unlike a swap within one root, a reader may find `a` missing, or `b`
+    /// in both roots, between the two moves.
+    pub(crate) async fn swap_across_tiers(
+        &self,
+        (store_a, a): (&FileStorage, &str),
+        (store_b, b): (&FileStorage, &str),
+    ) -> Result<(), StorageError> {
+        for (store, key) in [(store_a, a), (store_b, b)] {
+            if !store.exists_here(key).await? {
+                return Err(StorageError::NotFound(key.to_string()));
+            }
+        }
+        for (store, from, to) in [(store_a, a, b), (store_b, b, a)] {
+            store.move_local(from, to).await?;
+        }
+        self.invalidate_quotas(a).await;
+        self.invalidate_quotas(b).await;
+        Ok(())
+    }
+
+    /// Moves the object `from` and its attributes to `to` in this root alone,
+    /// replacing any object there; the caller holds both keys' locks.
+    pub(crate) async fn move_local(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let src = self.path_for(from)?;
+        let dst = self.path_for(to)?;
+        self.ensure_within_root(to, &dst).await?;
+        self.index_insert(to);
+        create_parent(to, &dst).await?;
+        Sidecar::remove_all(&dst).await?;
+        move_object(&src, &dst, self.rename_strategy)
+            .await
+            .map_err(|err| io_error(from, err))?;
+        self.forget_key(from);
+        self.record_key(to);
+        self.forget_checksum(&src).await?;
+        self.forget_checksum(&dst).await?;
+        self.prune_empty_parents(&src).await;
+        self.durability.sync_parent(&dst).await?;
+        Ok(())
+    }
+
+    /// Returns the position in `tiers` of `store`, or `None` when it is this
+    /// store.
+    pub(crate) fn tier_index(&self, store: &FileStorage) -> Option<usize> {
+        self.tiers
+            .iter()
+            .position(|(_, tier)| std::ptr::eq(tier.as_ref(), store))
+    }
+
+    /// Removes `key` from the main root and every tier except `kept`, after
+    /// it was stored in `kept` (`None` for the main root).
+    pub(crate) async fn evict_from_other_tiers(
+        &self,
+        key: &str,
+        kept: Option<usize>,
+    ) -> Result<(), StorageError> {
+        if self.tiers.is_empty() {
+            return Ok(());
+        }
+        let others = self
+            .tiers
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| Some(*i) != kept)
+            .map(|(_, (_, tier))| tier.as_ref());
+        let main = kept.is_some().then_some(self);
+        for store in main.into_iter().chain(others) {
+            match store.delete_local(key).await {
+                Ok(()) | Err(StorageError::NotFound(_)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+//! Soft deletes, which move objects aside until the trash is purged.
+//!
+//! A soft-deleted object is moved, with its sidecars, to
+//! `<root>/.filestorage-trash/<millis>/<path>`, where `<millis>` is when it
+//! was deleted and `<path>` is where it was stored relative to the top-level
+//! root. The trash sits under a reserved name, so trashed objects are
+//! invisible to reads, listings, and scans.
+
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::fs;
+
+use crate::{
+    FileStorage, StorageError, atomic::RESERVED_PREFIX, create_parent, io_error, is_reserved,
+    move_object, read_entries,
+};
+
+/// Most recent deletion time handed out, in milliseconds since the epoch.
+static LAST_STAMP: AtomicU64 = AtomicU64::new(0);
+
+impl FileStorage {
+    /// Moves `key` into the trash, from which
+    /// [`restore_from_trash`](Self::restore_from_trash) can bring it back
+    /// until [`purge_trash`](Self::purge_trash) removes it.
+    pub async fn soft_delete(&self, key: &str) -> Result<(), StorageError> {
+        let stamp = next_stamp();
+        let _guard = self.lock_key(key).await;
+        self.soft_delete_local(key, stamp).await?;
+        if let Some(replica) = &self.replica {
+            let result = match replica.soft_delete_local(key, stamp).await {
+                Err(StorageError::NotFound(_)) => Ok(()),
+                result => result,
+            };
+            self.apply_replica_policy(key, result)?;
+        }
+        Ok(())
+    }
+
+    async fn soft_delete_local(&self, key: &str, stamp: u128) -> Result<(), StorageError> {
+        let path = self.path_for(key)?;
+        if !self.may_exist(key) {
+            return Err(StorageError::NotFound(key.to_string()));
+        }
+        self.ensure_within_root(key, &path).await?;
+        // Checked up front so the sidecars of a missing object are not moved.
+        match fs::symlink_metadata(&path).await {
+            Ok(metadata) if !metadata.is_dir() => {}
+            Ok(_) => return Err(StorageError::NotFound(key.to_string())),
+            Err(err) => return Err(io_error(key, err)),
+        }
+        let reservation = self.reserve_quota(key, &path, |_| 0).await?;
+        let trashed = self
+            .trash_dir()
+            .join(stamp.to_string())
+            .join(self.relative(&path));
+        if let Some(parent) = trashed.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        move_object(&path, &trashed, self.rename_strategy)
+            .await
+            .map_err(|err| io_error(key, err))?;
+        if let Some(reservation) = reservation {
+            reservation.settle();
+        }
+        self.index_remove(key);
+        self.forget_key(key);
+        self.forget_checksum(&path).await?;
+        self.durability.sync_parent(&path).await?;
+        self.prune_empty_parents(&path).await;
+        Ok(())
+    }
+
+    /// Moves the most recently soft-deleted copy of `key` back into place.
+    ///
+    /// Fails with [`StorageError::NotFound`] when the trash holds no copy, and
+    /// with [`StorageError::Conflict`] when `key` has been stored again since.
+    pub async fn restore_from_trash(&self, key: &str) -> Result<(), StorageError> {
+        let _guard = self.lock_key(key).await;
+        self.restore_from_trash_local(key).await?;
+        if let Some(replica) = &self.replica {
+            let result = replica.restore_from_trash_local(key).await;
+            self.apply_replica_policy(key, result)?;
+        }
+        Ok(())
+    }
+
+    async fn restore_from_trash_local(&self, key: &str) -> Result<(), StorageError> {
+        let path = self.path_for(key)?;
+        self.ensure_within_root(key, &path).await?;
+        let relative = self.relative(&path);
+        let mut stamps = self.trash_stamps().await?;
+        stamps.sort_unstable_by(|a, b| b.cmp(a));
+        let mut trashed = None;
+        for stamp in stamps {
+            let candidate = self.trash_dir().join(stamp.to_string()).join(&relative);
+            if fs::symlink_metadata(&candidate).await.is_ok() {
+                trashed = Some(candidate);
+                break;
+            }
+        }
+        let Some(trashed) = trashed else {
+            return Err(StorageError::NotFound(key.to_string()));
+        };
+        if fs::symlink_metadata(&path).await.is_ok() {
+            return Err(StorageError::Conflict(format!(
+                "`{key}` was stored again since it was deleted"
+            )));
+        }
+
+        let len = fs::symlink_metadata(&trashed).await?.len();
+        let reservation = self.reserve_quota(key, &path, |_| len).await?;
+        self.index_insert(key);
+        create_parent(key, &path).await?;
+        move_object(&trashed, &path, self.rename_strategy)
+            .await
+            .map_err(|err| io_error(key, err))?;
+        if let Some(reservation) = reservation {
+            reservation.settle();
+        }
+        self.record_key(key);
+        self.durability.sync_parent(&path).await?;
+        self.prune_trash(&trashed).await;
+        Ok(())
+    }
+
+    /// Permanently removes objects soft-deleted more than `older_than` ago,
+    /// returning how many were removed. A zero duration empties the trash.
+    ///
+    /// A handle from [`namespace`](Self::namespace) only purges objects
+    /// deleted under its prefix.
+    pub async fn purge_trash(&self, older_than: Duration) -> Result<usize, StorageError> {
+        let cutoff = SystemTime::now()
+            .checked_sub(older_than)
+            .map_or(0, millis_since_epoch);
+        let purged = self.purge_trash_local(cutoff).await?;
+        if let Some(replica) = &self.replica {
+            let result = replica.purge_trash_local(cutoff).await;
+            self.apply_replica_policy("", result.map(|_| ()))?;
+        }
+        Ok(purged)
+    }
+
+    async fn purge_trash_local(&self, cutoff: u128) -> Result<usize, StorageError> {
+        let scope = self.relative(&self.root);
+        let mut purged = 0;
+        for stamp in self.trash_stamps().await? {
+            if stamp > cutoff {
+                continue;
+            }
+            let target = self.trash_dir().join(stamp.to_string()).join(&scope);
+            purged += count_objects(&target).await?;
+            match fs::remove_dir_all(&target).await {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(StorageError::from(err)),
+            }
+            self.prune_trash(&target).await;
+        }
+        Ok(purged)
+    }
+
+    /// Returns the deletion times of the batches in the trash.
+    async fn trash_stamps(&self) -> Result<Vec<u128>, StorageError> {
+        let entries = read_entries(self.trash_dir()).await?;
+        Ok(entries
+            .into_iter()
+            .filter(|(_, is_dir)| *is_dir)
+            .filter_map(|(path, _)| path.file_name()?.to_str()?.parse().ok())
+            .collect())
+    }
+
+    /// Removes now-empty trash directories above `path`.
+    async fn prune_trash(&self, path: &Path) {
+        let trash = self.trash_dir();
+        let mut current = path.parent();
+        while let Some(dir) = current {
+            if dir == trash || !dir.starts_with(&trash) || fs::remove_dir(dir).await.is_err() {
+                break;
+            }
+            current = dir.parent();
+        }
+    }
+
+    fn trash_dir(&self) -> PathBuf {
+        self.top_root().join(format!("{RESERVED_PREFIX}trash"))
+    }
+
+    /// Returns `path` relative to the top-level root, so namespaces share one trash.
+    fn relative(&self, path: &Path) -> PathBuf {
+        path.strip_prefix(self.top_root())
+            .unwrap_or(path)
+            .to_path_buf()
+    }
+
+    /// Returns the root of the top-level store this handle belongs to.
+    pub(crate) fn top_root(&self) -> &Path {
+        let depth = self.namespace.matches('/').count();
+        self.root.ancestors().nth(depth).unwrap_or(&self.root)
+    }
+}
+
+/// Counts the objects, but not sidecars, in the tree at `dir`.
+async fn count_objects(dir: &Path) -> Result<usize, StorageError> {
+    let mut count = 0;
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for (path, is_dir) in read_entries(dir).await? {
+            if is_dir {
+                pending.push(path);
+            } else if !path.file_name().is_some_and(is_reserved) {
+                count += 1;
+            }
+        }
+    }
+    Ok(count)
+}
+
+/// Returns the current time as a deletion stamp, moved past the previous
+/// one if needed so two deletes of the same key never share a batch.
+fn next_stamp() -> u128 {
+    let now = millis_since_epoch(SystemTime::now()) as u64;
+    let previous = LAST_STAMP
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |last| {
+            Some(now.max(last + 1))
+        })
+        .unwrap_or_default();
+    u128::from(now.max(previous + 1))
+}
+
+fn millis_since_epoch(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis())
+        .unwrap_or_default()
+}
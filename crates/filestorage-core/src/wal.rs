@@ -0,0 +1,303 @@
+//! Write-ahead log that makes multi-object batches crash consistent.
+//!
+//! With [`StorageOptions::write_ahead_log`](crate::StorageOptions::write_ahead_log)
+//! enabled, [`FileStorage::put_many`](crate::FileStorage::put_many) and the
+//! object-by-object path of [`FileStorage::rename_prefix`](crate::FileStorage::rename_prefix)
+//! append the renames they are about to make to a reserved JSON-lines file in
+//! the root, ending the list with a commit marker written in the same append,
+//! followed by a completion marker once every rename is applied. Opening the
+//! store rolls any committed batch without a completion marker forward,
+//! discards batches whose list was cut short, then removes the log.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{self, OpenOptions},
+    io::AsyncWriteExt,
+};
+
+use crate::{RenameStrategy, atomic, move_object, sidecar::Sidecar};
+
+/// Log size above which it is truncated once no batch is in flight.
+const COMPACT_LEN: u64 = 1024 * 1024;
+
+/// One rename a batch is about to make, with paths relative to the log's root.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub(crate) enum Step {
+    /// Moves an object and its sidecars to a new key.
+    Move { src: PathBuf, dst: PathBuf },
+    /// Renames a finished temp file over an object, dropping its old sidecars.
+    Replace { src: PathBuf, dst: PathBuf },
+    /// Ends the list of steps. A batch without it was cut short while being
+    /// logged, before any of its steps was applied.
+    Commit,
+    /// Marks the batch as fully applied.
+    Done,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    batch: u64,
+    #[serde(flatten)]
+    step: Step,
+}
+
+#[derive(Debug)]
+pub(crate) struct Wal {
+    root: PathBuf,
+    /// Serializes appends so records from concurrent batches never interleave.
+    append: tokio::sync::Mutex<()>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    next_batch: u64,
+    in_flight: usize,
+    /// Set once a batch fails part-way; its records must survive until the
+    /// next open replays them, so the log is no longer truncated.
+    incomplete: bool,
+}
+
+/// A batch whose steps are logged; dropping it without
+/// [`finish`](Self::finish) leaves it to be replayed on the next open.
+pub(crate) struct Batch<'a> {
+    wal: &'a Wal,
+    id: u64,
+    finished: bool,
+}
+
+impl Wal {
+    pub(crate) fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            append: tokio::sync::Mutex::new(()),
+            state: Mutex::default(),
+        }
+    }
+
+    /// Durably records `steps`, whose paths are absolute, before any of them
+    /// is applied.
+    pub(crate) async fn begin(
+        &self,
+        steps: impl IntoIterator<Item = Step>,
+    ) -> io::Result<Batch<'_>> {
+        let id = {
+            let mut state = self.lock_state();
+            state.next_batch += 1;
+            state.in_flight += 1;
+            state.next_batch
+        };
+        let batch = Batch {
+            wal: self,
+            id,
+            finished: false,
+        };
+        let mut lines = Vec::new();
+        for step in steps {
+            let step = match step {
+                Step::Move { src, dst } => Step::Move {
+                    src: self.relative(&src)?,
+                    dst: self.relative(&dst)?,
+                },
+                Step::Replace { src, dst } => Step::Replace {
+                    src: self.relative(&src)?,
+                    dst: self.relative(&dst)?,
+                },
+                step @ (Step::Commit | Step::Done) => step,
+            };
+            encode(&Record { batch: id, step }, &mut lines)?;
+        }
+        encode(
+            &Record {
+                batch: id,
+                step: Step::Commit,
+            },
+            &mut lines,
+        )?;
+        self.append(&lines, true).await?;
+        Ok(batch)
+    }
+
+    async fn append(&self, lines: &[u8], sync: bool) -> io::Result<()> {
+        let _guard = self.append.lock().await;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_path(&self.root))
+            .await?;
+        file.write_all(lines).await?;
+        if sync {
+            file.sync_data().await?;
+        }
+        Ok(())
+    }
+
+    fn relative(&self, path: &Path) -> io::Result<PathBuf> {
+        path.strip_prefix(&self.root)
+            .map(Path::to_path_buf)
+            .map_err(|_| io::Error::other(format!("{} is outside the log root", path.display())))
+    }
+
+    fn is_idle(&self) -> bool {
+        let state = self.lock_state();
+        state.in_flight == 0 && !state.incomplete
+    }
+
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Batch<'_> {
+    /// Appends the completion marker, truncating the log when it has grown
+    /// large and holds no batch that still needs replaying.
+    pub(crate) async fn finish(mut self) -> io::Result<()> {
+        let mut line = Vec::new();
+        encode(
+            &Record {
+                batch: self.id,
+                step: Step::Done,
+            },
+            &mut line,
+        )?;
+        self.wal.append(&line, false).await?;
+        self.finished = true;
+
+        let compact = {
+            let mut state = self.wal.lock_state();
+            state.in_flight -= 1;
+            state.in_flight == 0 && !state.incomplete
+        };
+        if compact {
+            let _guard = self.wal.append.lock().await;
+            let path = log_path(&self.wal.root);
+            if fs::metadata(&path).await?.len() > COMPACT_LEN && self.wal.is_idle() {
+                fs::OpenOptions::new()
+                    .write(true)
+                    .open(&path)
+                    .await?
+                    .set_len(0)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Batch<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let mut state = self.wal.lock_state();
+            state.in_flight -= 1;
+            state.incomplete = true;
+        }
+    }
+}
+
+/// Completes every committed batch in the log under `root` that lacks a
+/// completion marker, then removes the log. Returns the number of batches
+/// replayed.
+///
+/// Steps whose source is already gone were applied before the crash and are
+/// skipped, so replaying the same log twice is harmless. A batch without its
+/// commit marker, torn while it was being logged, is dropped along with the
+/// temp files it would have renamed.
+pub(crate) async fn replay(root: &Path, strategy: RenameStrategy) -> io::Result<usize> {
+    let path = log_path(root);
+    let contents = match fs::read_to_string(&path).await {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    let mut batches: BTreeMap<u64, Vec<Step>> = BTreeMap::new();
+    let mut committed = BTreeSet::new();
+    let mut done = BTreeSet::new();
+    for line in contents.lines() {
+        let Ok(record) = serde_json::from_str::<Record>(line) else {
+            continue;
+        };
+        match record.step {
+            Step::Commit => {
+                committed.insert(record.batch);
+            }
+            Step::Done => {
+                done.insert(record.batch);
+            }
+            step => batches.entry(record.batch).or_default().push(step),
+        }
+    }
+
+    let mut replayed = 0;
+    for (id, steps) in batches {
+        if done.contains(&id) {
+            continue;
+        }
+        if !committed.contains(&id) {
+            discard(root, steps).await?;
+            continue;
+        }
+        for step in steps {
+            apply(root, step, strategy).await?;
+        }
+        replayed += 1;
+    }
+    fs::remove_file(&path).await?;
+    Ok(replayed)
+}
+
+async fn apply(root: &Path, step: Step, strategy: RenameStrategy) -> io::Result<()> {
+    match step {
+        Step::Move { src, dst } => {
+            let (src, dst) = (root.join(src), root.join(dst));
+            if fs::symlink_metadata(&src).await.is_err() {
+                return Ok(());
+            }
+            if let Some(parent) = dst.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            move_object(&src, &dst, strategy).await
+        }
+        Step::Replace { src, dst } => {
+            let (src, dst) = (root.join(src), root.join(dst));
+            if fs::symlink_metadata(&src).await.is_err() {
+                return Ok(());
+            }
+            Sidecar::remove_all(&dst).await?;
+            atomic::rename(&src, &dst, strategy).await
+        }
+        Step::Commit | Step::Done => Ok(()),
+    }
+}
+
+/// Removes the temp files an uncommitted batch wrote for its replacements.
+async fn discard(root: &Path, steps: Vec<Step>) -> io::Result<()> {
+    for step in steps {
+        if let Step::Replace { src, .. } = step {
+            match fs::remove_file(root.join(src)).await {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+fn encode(record: &Record, out: &mut Vec<u8>) -> io::Result<()> {
+    serde_json::to_writer(&mut *out, record)?;
+    out.push(b'\n');
+    Ok(())
+}
+
+fn log_path(root: &Path) -> PathBuf {
+    root.join(format!("{}wal", atomic::RESERVED_PREFIX))
+}
@@ -1,5 +1,19 @@
-use filestorage_core::{FileStorage, StorageError};
+use std::{
+    collections::{BTreeMap, HashMap},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use filestorage_core::{
+    DedupReport, DefaultKeyMapper, DuplicateGroup, FileStorage, ImportReport, InvalidKeyReason,
+    KeyMapper, ManifestEntry, OperationResult, PutCondition, PutOptions, PutOutcome,
+    RenameStrategy, RepairReport, ReplicaPolicy, StorageError, StorageOptions, SweepSchedule,
+    SymlinkPolicy, Tier,
+};
 use tempfile::tempdir;
+use tokio::io::AsyncReadExt;
 
 #[tokio::test]
 async fn put_get_delete_round_trip() {
@@ -20,5 +34,3319 @@ async fn rejects_keys_with_parent_dirs() {
     let tmp = tempdir().unwrap();
     let storage = FileStorage::new(tmp.path()).await.unwrap();
     let err = storage.put("../bad", b"nope").await.unwrap_err();
-    assert!(matches!(err, StorageError::InvalidKey(_)));
+    assert!(matches!(err, StorageError::InvalidKey { .. }));
+}
+
+#[tokio::test]
+async fn clear_removes_objects_but_keeps_root() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    for key in ["a.txt", "nested/b.txt", "nested/deeper/c.txt"] {
+        storage.put(key, b"data").await.unwrap();
+    }
+
+    let removed = storage.clear().await.unwrap();
+    assert_eq!(removed, 3);
+    assert!(storage.list("").await.unwrap().is_empty());
+    assert!(tmp.path().is_dir());
+}
+
+#[tokio::test]
+async fn purge_empty_dirs_collapses_empty_trees_only() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    storage.put("kept/a.txt", b"data").await.unwrap();
+    for dir in ["empty/nested/deeper", "lone", "kept/hollow"] {
+        std::fs::create_dir_all(tmp.path().join(dir)).unwrap();
+    }
+
+    assert_eq!(storage.purge_empty_dirs().await.unwrap(), 5);
+    for dir in ["empty", "lone", "kept/hollow"] {
+        assert!(!tmp.path().join(dir).exists(), "{dir}");
+    }
+    assert_eq!(storage.get("kept/a.txt").await.unwrap(), b"data");
+    assert!(tmp.path().is_dir());
+    assert_eq!(storage.purge_empty_dirs().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn delete_prefix_preview_matches_delete_prefix() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    for key in [
+        "logs/a.txt",
+        "logs/old/b.txt",
+        "logsheet.txt",
+        "other/c.txt",
+    ] {
+        storage.put(key, b"data").await.unwrap();
+    }
+
+    let preview = storage.delete_prefix_preview("logs/").await.unwrap();
+    assert_eq!(preview, ["logs/a.txt", "logs/old/b.txt"]);
+    assert_eq!(storage.list("").await.unwrap().len(), 4);
+
+    let removed = storage.delete_prefix("logs/").await.unwrap();
+    assert_eq!(removed, preview.len());
+    assert_eq!(
+        storage.list("").await.unwrap(),
+        ["logsheet.txt", "other/c.txt"]
+    );
+    assert!(!tmp.path().join("logs").exists());
+}
+
+#[tokio::test]
+async fn delete_prefix_and_clear_reach_the_replica_and_checksum_index() {
+    let tmp = tempdir().unwrap();
+    let replica_dir = tempdir().unwrap();
+    let options = StorageOptions {
+        replica_root: Some(replica_dir.path().to_path_buf()),
+        checksum_index: true,
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+    for key in ["logs/a.txt", "logs/b.txt", "logsheet.txt", "other/c.txt"] {
+        storage.put(key, b"data").await.unwrap();
+    }
+    let replica = FileStorage::new(replica_dir.path()).await.unwrap();
+
+    assert_eq!(storage.delete_prefix("logs/").await.unwrap(), 2);
+    assert_eq!(
+        replica.list("").await.unwrap(),
+        ["logsheet.txt", "other/c.txt"]
+    );
+    // Nothing is left in the checksum index for the deleted objects.
+    assert_eq!(storage.gc_sidecars().await.unwrap(), 0);
+
+    assert_eq!(storage.clear().await.unwrap(), 2);
+    assert!(replica.list("").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn clear_preview_leaves_objects_intact() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    for key in ["b.txt", "a/c.txt"] {
+        storage.put(key, b"data").await.unwrap();
+    }
+
+    let preview = storage.clear_preview().await.unwrap();
+    assert_eq!(preview, ["a/c.txt", "b.txt"]);
+    assert_eq!(storage.get("a/c.txt").await.unwrap(), b"data");
+    assert_eq!(storage.clear().await.unwrap(), preview.len());
+}
+
+#[tokio::test]
+async fn put_with_backup_keeps_previous_content() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+
+    storage.put_with_backup("app.toml", b"v1").await.unwrap();
+    assert!(matches!(
+        storage.get("app.toml.bak").await.unwrap_err(),
+        StorageError::NotFound(_)
+    ));
+
+    storage.put_with_backup("app.toml", b"v2").await.unwrap();
+    assert_eq!(storage.get("app.toml").await.unwrap(), b"v2");
+    assert_eq!(storage.get("app.toml.bak").await.unwrap(), b"v1");
+}
+
+#[tokio::test]
+async fn restore_backup_swaps_back_previous_content() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    storage.put_with_backup("app.toml", b"v1").await.unwrap();
+    storage.put_with_backup("app.toml", b"v2").await.unwrap();
+
+    storage.restore_backup("app.toml").await.unwrap();
+    assert_eq!(storage.get("app.toml").await.unwrap(), b"v1");
+    assert_eq!(storage.get("app.toml.bak").await.unwrap(), b"v2");
+    assert_eq!(
+        storage.list("").await.unwrap(),
+        ["app.toml", "app.toml.bak"]
+    );
+
+    let err = storage.restore_backup("missing").await.unwrap_err();
+    assert!(matches!(err, StorageError::NotFound(key) if key == "missing.bak"));
+}
+
+#[tokio::test]
+async fn backups_are_routed_mirrored_and_keep_attributes() {
+    let (ssd, hdd, replica_dir) = (tempdir().unwrap(), tempdir().unwrap(), tempdir().unwrap());
+    let options = StorageOptions {
+        tiering: vec![Tier {
+            min_size: 1024,
+            root: hdd.path().to_path_buf(),
+        }],
+        replica_root: Some(replica_dir.path().to_path_buf()),
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(ssd.path(), options)
+        .await
+        .unwrap();
+    let replica = FileStorage::new(replica_dir.path()).await.unwrap();
+    let large = vec![7u8; 2000];
+    let typed = PutOptions {
+        content_type: Some("application/json".to_string()),
+        ..PutOptions::default()
+    };
+    storage.put_with("cfg", &large, &typed).await.unwrap();
+
+    storage.put_with_backup("cfg", b"small").await.unwrap();
+    assert!(ssd.path().join("cfg").exists());
+    assert!(hdd.path().join("cfg.bak").exists());
+    assert!(!hdd.path().join("cfg").exists());
+    assert_eq!(storage.content_type("cfg").await.unwrap(), None);
+    assert_eq!(
+        storage.content_type("cfg.bak").await.unwrap().as_deref(),
+        Some("application/json")
+    );
+    for store in [&storage, &replica] {
+        assert_eq!(store.get("cfg").await.unwrap(), b"small");
+        assert_eq!(store.get("cfg.bak").await.unwrap(), large);
+    }
+
+    storage.restore_backup("cfg").await.unwrap();
+    assert!(hdd.path().join("cfg").exists());
+    assert!(ssd.path().join("cfg.bak").exists());
+    assert_eq!(
+        storage.content_type("cfg").await.unwrap().as_deref(),
+        Some("application/json")
+    );
+    for store in [&storage, &replica] {
+        assert_eq!(store.get("cfg").await.unwrap(), large);
+        assert_eq!(store.get("cfg.bak").await.unwrap(), b"small");
+    }
+
+    storage.delete("cfg").await.unwrap();
+    storage.restore_backup("cfg").await.unwrap();
+    assert_eq!(storage.get("cfg").await.unwrap(), b"small");
+    assert!(matches!(
+        storage.get("cfg.bak").await.unwrap_err(),
+        StorageError::NotFound(_)
+    ));
+}
+
+#[tokio::test]
+async fn swap_exchanges_two_objects() {
+    let tmp = tempdir().unwrap();
+    let storage = Arc::new(FileStorage::new(tmp.path()).await.unwrap());
+    let options = PutOptions {
+        content_type: Some("application/toml".to_string()),
+        ..PutOptions::default()
+    };
+    storage
+        .put_with("config/blue.toml", b"color = 'blue'", &options)
+        .await
+        .unwrap();
+    storage
+        .put("staging/green.toml", b"color = 'green'")
+        .await
+        .unwrap();
+
+    storage
+        .swap("config/blue.toml", "staging/green.toml")
+        .await
+        .unwrap();
+    assert_eq!(
+        storage.get("config/blue.toml").await.unwrap(),
+        b"color = 'green'"
+    );
+    assert_eq!(
+        storage.get("staging/green.toml").await.unwrap(),
+        b"color = 'blue'"
+    );
+    assert_eq!(
+        storage.content_type("config/blue.toml").await.unwrap(),
+        None
+    );
+    assert_eq!(
+        storage
+            .content_type("staging/green.toml")
+            .await
+            .unwrap()
+            .as_deref(),
+        Some("application/toml")
+    );
+    assert_eq!(
+        storage.list("").await.unwrap(),
+        ["config/blue.toml", "staging/green.toml"]
+    );
+
+    // Opposite swaps running together must not deadlock.
+    let swaps: Vec<_> = (0..16)
+        .map(|n| {
+            let storage = storage.clone();
+            tokio::spawn(async move {
+                let (a, b) = if n % 2 == 0 {
+                    ("config/blue.toml", "staging/green.toml")
+                } else {
+                    ("staging/green.toml", "config/blue.toml")
+                };
+                storage.swap(a, b).await.unwrap();
+            })
+        })
+        .collect();
+    for swap in swaps {
+        swap.await.unwrap();
+    }
+    assert_eq!(
+        storage.get("config/blue.toml").await.unwrap(),
+        b"color = 'green'"
+    );
+
+    let err = storage
+        .swap("config/blue.toml", "missing.toml")
+        .await
+        .unwrap_err();
+    assert!(matches!(err, StorageError::NotFound(key) if key == "missing.toml"));
+    assert_eq!(
+        storage.get("config/blue.toml").await.unwrap(),
+        b"color = 'green'"
+    );
+}
+
+#[tokio::test]
+async fn get_limited_rejects_objects_over_the_limit() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    storage.put("small.bin", &[1; 8]).await.unwrap();
+    storage.put("large.bin", &[2; 64]).await.unwrap();
+
+    assert_eq!(storage.get_limited("small.bin", 16).await.unwrap(), [1; 8]);
+    let err = storage.get_limited("large.bin", 16).await.unwrap_err();
+    assert!(matches!(
+        err,
+        StorageError::TooLarge { key, size: 64, max: 16 } if key == "large.bin"
+    ));
+}
+
+#[tokio::test]
+async fn put_with_records_attributes_until_replaced() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    let options = PutOptions {
+        content_type: Some("text/plain".to_string()),
+        metadata: [("owner".to_string(), "alice".to_string())].into(),
+        ..PutOptions::default()
+    };
+
+    storage
+        .put_with("notes.txt", b"hi", &options)
+        .await
+        .unwrap();
+    assert_eq!(storage.head("notes.txt").await.unwrap().size, 2);
+    assert_eq!(
+        storage.content_type("notes.txt").await.unwrap().as_deref(),
+        Some("text/plain")
+    );
+    assert_eq!(
+        storage.user_metadata("notes.txt").await.unwrap(),
+        options.metadata
+    );
+    assert_eq!(storage.list("").await.unwrap(), ["notes.txt"]);
+
+    storage.put("notes.txt", b"bye").await.unwrap();
+    assert_eq!(storage.content_type("notes.txt").await.unwrap(), None);
+    assert!(storage.user_metadata("notes.txt").await.unwrap().is_empty());
+
+    storage
+        .put_with("notes.txt", b"hi", &options)
+        .await
+        .unwrap();
+    storage.delete("notes.txt").await.unwrap();
+    let leftovers = std::fs::read_dir(tmp.path()).unwrap().count();
+    assert_eq!(leftovers, 0);
+}
+
+#[tokio::test]
+async fn put_conditions_are_checked_against_the_current_object() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    let with = |condition| PutOptions {
+        condition,
+        ..PutOptions::default()
+    };
+
+    let if_match = with(PutCondition::IfMatch("*".to_string()));
+    let err = storage
+        .put_with("a.txt", b"1", &if_match)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, StorageError::VersionMismatch(_)));
+    let create = with(PutCondition::IfAbsent);
+    storage.put_with("a.txt", b"1", &create).await.unwrap();
+    let err = storage.put_with("a.txt", b"2", &create).await.unwrap_err();
+    assert!(matches!(err, StorageError::Conflict(_)));
+
+    let stale = with(PutCondition::IfMatch("\"0-0\"".to_string()));
+    let err = storage.put_with("a.txt", b"2", &stale).await.unwrap_err();
+    assert!(matches!(err, StorageError::VersionMismatch(_)));
+    let etag = storage.head("a.txt").await.unwrap().etag;
+    let current = with(PutCondition::IfMatch(etag));
+    storage.put_with("a.txt", b"2", &current).await.unwrap();
+    storage.put_with("a.txt", b"3", &if_match).await.unwrap();
+    assert_eq!(storage.get("a.txt").await.unwrap(), b"3");
+}
+
+#[tokio::test]
+async fn delete_if_etag_only_removes_the_expected_version() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    storage.put("a.txt", b"1").await.unwrap();
+
+    let deleted = storage.delete_if_etag("a.txt", "\"0-0\"").await.unwrap();
+    assert!(!deleted);
+    assert_eq!(storage.get("a.txt").await.unwrap(), b"1");
+
+    let etag = storage.head("a.txt").await.unwrap().etag;
+    assert!(storage.delete_if_etag("a.txt", &etag).await.unwrap());
+    assert!(!storage.exists("a.txt").await.unwrap());
+    let err = storage.delete_if_etag("a.txt", "*").await.unwrap_err();
+    assert!(matches!(err, StorageError::NotFound(_)));
+}
+
+#[tokio::test]
+async fn rename_prefix_moves_whole_namespace() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    for key in ["old-tenant/a.txt", "old-tenant/nested/b.txt", "other/c.txt"] {
+        storage.put(key, key.as_bytes()).await.unwrap();
+    }
+
+    let moved = storage
+        .rename_prefix("old-tenant/", "new-tenant/")
+        .await
+        .unwrap();
+    assert_eq!(moved, 2);
+    assert_eq!(
+        storage.list("").await.unwrap(),
+        ["new-tenant/a.txt", "new-tenant/nested/b.txt", "other/c.txt"]
+    );
+    assert_eq!(
+        storage.get("new-tenant/nested/b.txt").await.unwrap(),
+        b"old-tenant/nested/b.txt"
+    );
+    assert!(storage.list("old-tenant/").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn rename_prefix_merges_without_clobbering() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    storage.put("src/a.txt", b"a").await.unwrap();
+    storage.put("dst/b.txt", b"b").await.unwrap();
+
+    assert_eq!(storage.rename_prefix("src/", "dst/").await.unwrap(), 1);
+    assert_eq!(storage.list("").await.unwrap(), ["dst/a.txt", "dst/b.txt"]);
+
+    storage.put("src/a.txt", b"again").await.unwrap();
+    let err = storage.rename_prefix("src/", "dst/").await.unwrap_err();
+    assert!(matches!(err, StorageError::InvalidKey { .. }));
+    assert_eq!(storage.get("dst/a.txt").await.unwrap(), b"a");
+
+    let err = storage.rename_prefix("dst/", "dst/sub/").await.unwrap_err();
+    assert!(matches!(err, StorageError::InvalidKey { .. }));
+}
+
+#[tokio::test]
+async fn objects_read_as_missing_after_their_ttl() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+
+    storage
+        .put_with_ttl("short", b"soon gone", Duration::ZERO)
+        .await
+        .unwrap();
+    storage
+        .put_with_ttl("long", b"still here", Duration::from_secs(3600))
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        storage.get("short").await.unwrap_err(),
+        StorageError::NotFound(key) if key == "short"
+    ));
+    assert!(matches!(
+        storage.head("short").await.unwrap_err(),
+        StorageError::NotFound(_)
+    ));
+    assert_eq!(storage.get("long").await.unwrap(), b"still here");
+    assert!(storage.expires_at("long").await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn repair_from_restores_corrupted_objects() {
+    let primary_dir = tempdir().unwrap();
+    let replica_dir = tempdir().unwrap();
+    let primary = FileStorage::new(primary_dir.path()).await.unwrap();
+    let replica = FileStorage::new(replica_dir.path()).await.unwrap();
+    for store in [&primary, &replica] {
+        store.put("good.txt", b"good").await.unwrap();
+        store.put("docs/report.txt", b"quarterly").await.unwrap();
+    }
+    let manifest = vec![
+        ManifestEntry {
+            key: "good.txt".to_string(),
+            sha256: primary.sha256("good.txt").await.unwrap(),
+        },
+        ManifestEntry {
+            key: "docs/report.txt".to_string(),
+            sha256: primary.sha256("docs/report.txt").await.unwrap(),
+        },
+        ManifestEntry {
+            key: "lost.txt".to_string(),
+            sha256: "00".repeat(32),
+        },
+    ];
+    std::fs::write(primary_dir.path().join("docs/report.txt"), b"bitrot").unwrap();
+
+    let report = primary.repair_from(&replica, &manifest).await.unwrap();
+    assert_eq!(
+        report,
+        RepairReport {
+            healed: vec!["docs/report.txt".to_string()],
+            unrepairable: vec!["lost.txt".to_string()],
+            skipped: vec!["good.txt".to_string()],
+        }
+    );
+    assert_eq!(primary.get("docs/report.txt").await.unwrap(), b"quarterly");
+}
+
+#[tokio::test]
+async fn replica_root_mirrors_puts_and_deletes() {
+    let primary_dir = tempdir().unwrap();
+    let replica_dir = tempdir().unwrap();
+    let options = StorageOptions {
+        replica_root: Some(replica_dir.path().to_path_buf()),
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(primary_dir.path(), options)
+        .await
+        .unwrap();
+    let replica = FileStorage::new(replica_dir.path()).await.unwrap();
+
+    storage.put("docs/a.txt", b"mirrored").await.unwrap();
+    assert_eq!(replica.get("docs/a.txt").await.unwrap(), b"mirrored");
+
+    storage.rename_prefix("docs/", "archive/").await.unwrap();
+    assert_eq!(replica.list("").await.unwrap(), ["archive/a.txt"]);
+
+    storage.delete("archive/a.txt").await.unwrap();
+    assert!(storage.list("").await.unwrap().is_empty());
+    assert!(replica.list("").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn replica_policy_decides_whether_replica_failures_fail_writes() {
+    for policy in [ReplicaPolicy::Fail, ReplicaPolicy::LogAndContinue] {
+        let primary_dir = tempdir().unwrap();
+        let replica_dir = tempdir().unwrap();
+        // A non-empty directory where the replica copy should go makes its write fail.
+        std::fs::create_dir_all(replica_dir.path().join("blocked.txt/child")).unwrap();
+        let options = StorageOptions {
+            replica_root: Some(replica_dir.path().to_path_buf()),
+            replica_policy: policy,
+            ..StorageOptions::default()
+        };
+        let storage = FileStorage::with_options(primary_dir.path(), options)
+            .await
+            .unwrap();
+
+        let result = storage.put("blocked.txt", b"data").await;
+        match policy {
+            ReplicaPolicy::Fail => assert!(matches!(result, Err(StorageError::Conflict(_)))),
+            ReplicaPolicy::LogAndContinue => assert_eq!(result.unwrap(), PutOutcome::Written),
+        }
+        assert_eq!(storage.get("blocked.txt").await.unwrap(), b"data");
+    }
+}
+
+#[tokio::test]
+async fn open_requires_an_existing_directory() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::open(tmp.path()).await.unwrap();
+    storage.put("a.txt", b"a").await.unwrap();
+
+    let missing = tmp.path().join("typo");
+    let err = FileStorage::open(&missing).await.unwrap_err();
+    assert!(matches!(err, StorageError::RootMissing(path) if path == missing));
+    assert!(!missing.exists());
+
+    let file = tmp.path().join("a.txt");
+    let err = FileStorage::open(&file).await.unwrap_err();
+    assert!(matches!(err, StorageError::RootNotDirectory(path) if path == file));
+}
+
+#[tokio::test]
+async fn probe_filesystem_reports_a_capable_tempdir() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    storage.put("a.txt", b"a").await.unwrap();
+
+    let capabilities = storage.probe_filesystem().await.unwrap();
+    assert!(capabilities.atomic_rename);
+    assert!(capabilities.supports_fsync);
+    if cfg!(unix) {
+        assert!(capabilities.reports_free_space);
+    }
+    if cfg!(target_os = "linux") {
+        assert!(capabilities.case_sensitive);
+    }
+    assert_eq!(storage.list("").await.unwrap(), ["a.txt"]);
+    assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 1);
+}
+
+#[tokio::test]
+async fn new_reports_an_uncreatable_root() {
+    let tmp = tempdir().unwrap();
+    let file = tmp.path().join("not-a-dir");
+    std::fs::write(&file, b"x").unwrap();
+
+    let root = file.join("store");
+    let err = FileStorage::new(&root).await.unwrap_err();
+    assert!(matches!(&err, StorageError::RootUnavailable(path, _) if *path == root));
+    assert!(err.to_string().contains("not-a-dir/store"));
+}
+
+#[tokio::test]
+async fn size_reports_stored_length() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    let data = vec![7; 1234];
+    storage.put("blob.bin", &data).await.unwrap();
+
+    assert_eq!(storage.size("blob.bin").await.unwrap(), data.len() as u64);
+    let err = storage.size("missing.bin").await.unwrap_err();
+    assert!(matches!(err, StorageError::NotFound(key) if key == "missing.bin"));
+}
+
+#[tokio::test]
+async fn get_range_reads_a_slice() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    storage.put("digits.txt", b"0123456789").await.unwrap();
+
+    assert_eq!(storage.get_range("digits.txt", 2, 3).await.unwrap(), b"234");
+    assert_eq!(storage.get_range("digits.txt", 8, 10).await.unwrap(), b"89");
+    assert!(
+        storage
+            .get_range("digits.txt", 20, 1)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn get_range_reader_streams_the_same_slice_as_get_range() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    let data: Vec<u8> = (0..100_000u32).map(|n| (n % 251) as u8).collect();
+    storage.put("video.bin", &data).await.unwrap();
+
+    let mut reader = storage
+        .get_range_reader("video.bin", 40_000, Some(20_000))
+        .await
+        .unwrap();
+    let mut streamed = Vec::new();
+    reader.read_to_end(&mut streamed).await.unwrap();
+    let expected = storage
+        .get_range("video.bin", 40_000, 20_000)
+        .await
+        .unwrap();
+    assert_eq!(streamed, expected);
+
+    let mut reader = storage
+        .get_range_reader("video.bin", 99_990, None)
+        .await
+        .unwrap();
+    let mut tail = Vec::new();
+    reader.read_to_end(&mut tail).await.unwrap();
+    assert_eq!(tail, &data[99_990..]);
+
+    let err = storage
+        .get_range_reader("missing.bin", 0, None)
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, StorageError::NotFound(_)));
+}
+
+#[tokio::test]
+async fn append_reader_streams_onto_existing_objects() {
+    let tmp = tempdir().unwrap();
+    let replica_dir = tempdir().unwrap();
+    let options = StorageOptions {
+        replica_root: Some(replica_dir.path().to_path_buf()),
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+    storage.put("capture.bin", b"header:").await.unwrap();
+    let tail: Vec<u8> = (0..2 * 1024 * 1024u32).map(|n| (n % 251) as u8).collect();
+
+    let len = storage
+        .append_reader("capture.bin", &tail[..])
+        .await
+        .unwrap();
+    assert_eq!(len, 7 + tail.len() as u64);
+    let mut expected = b"header:".to_vec();
+    expected.extend_from_slice(&tail);
+    assert_eq!(storage.get("capture.bin").await.unwrap(), expected);
+    let replica = FileStorage::new(replica_dir.path()).await.unwrap();
+    assert_eq!(replica.get("capture.bin").await.unwrap(), expected);
+
+    let len = storage
+        .append_reader("new/capture.bin", &b"first"[..])
+        .await
+        .unwrap();
+    assert_eq!(len, 5);
+    assert_eq!(storage.get("new/capture.bin").await.unwrap(), b"first");
+}
+
+#[tokio::test]
+async fn read_lines_streams_each_record() {
+    use futures_util::TryStreamExt;
+
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    let records: Vec<String> = (0..5_000).map(|n| format!(r#"{{"id":{n}}}"#)).collect();
+    // The last record has no trailing newline and one uses a CRLF ending.
+    let text = records.join("\n").replacen("\n", "\r\n", 1);
+    storage.put("events.jsonl", text.as_bytes()).await.unwrap();
+
+    let lines: Vec<String> = storage
+        .read_lines("events.jsonl")
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(lines, records);
+
+    let mut missing = Box::pin(storage.read_lines("missing.jsonl"));
+    let err = missing.try_next().await.unwrap_err();
+    assert!(matches!(err, StorageError::NotFound(key) if key == "missing.jsonl"));
+    assert!(missing.try_next().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn keys_with_a_trailing_slash_are_rejected() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+
+    let err = storage.put("foo/", b"data").await.unwrap_err();
+    assert!(matches!(
+        err,
+        StorageError::InvalidKey {
+            reason: InvalidKeyReason::TrailingSlash,
+            ..
+        }
+    ));
+    assert!(!tmp.path().join("foo").exists());
+
+    storage.put("foo/bar", b"data").await.unwrap();
+    assert_eq!(storage.get("foo/bar").await.unwrap(), b"data");
+}
+
+#[tokio::test]
+async fn directory_collisions_are_conflicts() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    storage.put("a/b/c", b"leaf").await.unwrap();
+
+    for err in [
+        storage.put("a/b", b"clash").await.unwrap_err(),
+        storage.get("a/b").await.unwrap_err(),
+        storage.delete("a/b").await.unwrap_err(),
+        storage.put("a/b/c/d", b"under a file").await.unwrap_err(),
+        storage.get("a/b/c/d").await.unwrap_err(),
+    ] {
+        assert!(matches!(err, StorageError::Conflict(_)), "{err:?}");
+    }
+}
+
+#[tokio::test]
+async fn existence_index_survives_deleting_objects_it_never_saw() {
+    let tmp = tempdir().unwrap();
+    let options = StorageOptions {
+        existence_index: true,
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+    let keys: Vec<String> = (0..50).map(|i| format!("kept-{i}")).collect();
+    for key in &keys {
+        storage.put(key, b"data").await.unwrap();
+    }
+
+    // Written behind the store's back, so never inserted into the filter.
+    for i in 0..200 {
+        let key = format!("external-{i}");
+        std::fs::write(tmp.path().join(&key), b"data").unwrap();
+        storage.delete(&key).await.unwrap();
+    }
+    for key in &keys {
+        assert!(storage.exists(key).await.unwrap(), "{key} not found");
+    }
+}
+
+#[tokio::test]
+async fn existence_index_answers_for_present_and_absent_keys() {
+    let tmp = tempdir().unwrap();
+    std::fs::create_dir_all(tmp.path().join("docs")).unwrap();
+    std::fs::write(tmp.path().join("docs/existing.txt"), b"before open").unwrap();
+    let options = StorageOptions {
+        existence_index: true,
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+
+    assert!(storage.exists("docs/existing.txt").await.unwrap());
+    assert!(!storage.exists("docs/missing.txt").await.unwrap());
+    assert!(matches!(
+        storage.get("docs/missing.txt").await,
+        Err(StorageError::NotFound(_))
+    ));
+
+    let keys: Vec<String> = (0..500).map(|i| format!("bulk/{i}")).collect();
+    for key in &keys {
+        storage.put(key, key.as_bytes()).await.unwrap();
+    }
+    for key in &keys {
+        assert!(storage.exists(key).await.unwrap(), "{key} not found");
+    }
+    assert_eq!(storage.get("bulk/42").await.unwrap(), b"bulk/42");
+
+    storage.rename_prefix("bulk", "moved").await.unwrap();
+    assert!(storage.exists("moved/7").await.unwrap());
+    assert!(!storage.exists("bulk/7").await.unwrap());
+
+    storage.delete("docs/existing.txt").await.unwrap();
+    assert!(!storage.exists("docs/existing.txt").await.unwrap());
+    storage.put("docs/existing.txt", b"again").await.unwrap();
+    assert!(storage.exists("docs/existing.txt").await.unwrap());
+}
+
+#[tokio::test]
+async fn puts_recreate_cached_directories_that_were_removed() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+
+    storage
+        .ensure_prefixes(&["logs/2024/06", "uploads/"])
+        .await
+        .unwrap();
+    assert!(tmp.path().join("logs/2024/06").is_dir());
+    assert!(tmp.path().join("uploads").is_dir());
+    assert!(storage.list("").await.unwrap().is_empty());
+
+    storage.put("logs/2024/06/a.log", b"1").await.unwrap();
+    storage.put("logs/2024/06/b.log", b"2").await.unwrap();
+
+    // Removed behind the store's back while cached.
+    std::fs::remove_dir_all(tmp.path().join("logs")).unwrap();
+    storage.put("logs/2024/06/c.log", b"3").await.unwrap();
+    assert_eq!(storage.get("logs/2024/06/c.log").await.unwrap(), b"3");
+
+    // Removed by the store itself.
+    storage.delete_prefix("logs/").await.unwrap();
+    assert!(!tmp.path().join("logs").exists());
+    storage.put("logs/2024/06/d.log", b"4").await.unwrap();
+    assert_eq!(storage.list("logs/").await.unwrap(), ["logs/2024/06/d.log"]);
+
+    storage.put("taken", b"object").await.unwrap();
+    let err = storage.ensure_prefixes(&["taken/sub"]).await.unwrap_err();
+    assert!(matches!(err, StorageError::Conflict(_)), "{err:?}");
+}
+
+#[tokio::test]
+async fn tiering_routes_objects_by_size() {
+    let ssd = tempdir().unwrap();
+    let hdd = tempdir().unwrap();
+    let options = StorageOptions {
+        tiering: vec![Tier {
+            min_size: 1024,
+            root: hdd.path().to_path_buf(),
+        }],
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(ssd.path(), options)
+        .await
+        .unwrap();
+    let large = vec![7u8; 4096];
+
+    storage.put("thumbs/small.jpg", b"tiny").await.unwrap();
+    storage.put("videos/large.mp4", &large).await.unwrap();
+    assert!(ssd.path().join("thumbs/small.jpg").exists());
+    assert!(!hdd.path().join("thumbs/small.jpg").exists());
+    assert!(hdd.path().join("videos/large.mp4").exists());
+    assert!(!ssd.path().join("videos/large.mp4").exists());
+
+    assert_eq!(storage.get("thumbs/small.jpg").await.unwrap(), b"tiny");
+    assert_eq!(storage.get("videos/large.mp4").await.unwrap(), large);
+    assert_eq!(storage.head("videos/large.mp4").await.unwrap().size, 4096);
+    assert_eq!(
+        storage.get_range("videos/large.mp4", 100, 3).await.unwrap(),
+        [7, 7, 7]
+    );
+    assert!(storage.exists("videos/large.mp4").await.unwrap());
+    assert_eq!(
+        storage.list("").await.unwrap(),
+        ["thumbs/small.jpg", "videos/large.mp4"]
+    );
+
+    // Shrinking an object moves it back to the main root.
+    storage.put("videos/large.mp4", b"trimmed").await.unwrap();
+    assert!(ssd.path().join("videos/large.mp4").exists());
+    assert!(!hdd.path().join("videos/large.mp4").exists());
+    assert_eq!(storage.get("videos/large.mp4").await.unwrap(), b"trimmed");
+
+    storage.put("videos/other.mp4", &large).await.unwrap();
+    storage.delete("videos/other.mp4").await.unwrap();
+    assert!(!hdd.path().join("videos/other.mp4").exists());
+    assert!(!storage.exists("videos/other.mp4").await.unwrap());
+}
+
+/// Opens a store under `ssd` that sends objects of 1 KiB or more to `hdd`.
+async fn tiered(ssd: &Path, hdd: &Path) -> FileStorage {
+    let options = StorageOptions {
+        tiering: vec![Tier {
+            min_size: 1024,
+            root: hdd.to_path_buf(),
+        }],
+        ..StorageOptions::default()
+    };
+    FileStorage::with_options(ssd, options).await.unwrap()
+}
+
+#[tokio::test]
+async fn tiering_writes_in_place_to_the_tier_holding_the_object() {
+    let (ssd, hdd) = (tempdir().unwrap(), tempdir().unwrap());
+    let storage = tiered(ssd.path(), hdd.path()).await;
+    let large = vec![7u8; 4096];
+    for key in ["ranged", "records", "streamed"] {
+        storage.put(key, &large).await.unwrap();
+    }
+
+    storage.write_range("ranged", 0, b"ab").await.unwrap();
+    storage.append_record("records", b"tail").await.unwrap();
+    let len = storage
+        .append_reader("streamed", &b"more"[..])
+        .await
+        .unwrap();
+    assert_eq!(len, 4100);
+
+    let mut ranged = large.clone();
+    ranged[..2].copy_from_slice(b"ab");
+    let records = [&large[..], b"tail\n"].concat();
+    let streamed = [&large[..], b"more"].concat();
+    for (key, expected) in [
+        ("ranged", ranged),
+        ("records", records),
+        ("streamed", streamed),
+    ] {
+        assert!(!ssd.path().join(key).exists(), "{key}");
+        assert_eq!(
+            std::fs::read(hdd.path().join(key)).unwrap(),
+            expected,
+            "{key}"
+        );
+        assert_eq!(storage.get(key).await.unwrap(), expected, "{key}");
+        assert_eq!(storage.sha256(key).await.unwrap().len(), 64);
+    }
+}
+
+#[tokio::test]
+async fn tiering_reads_and_keeps_attributes_of_tiered_objects() {
+    let (ssd, hdd) = (tempdir().unwrap(), tempdir().unwrap());
+    let storage = tiered(ssd.path(), hdd.path()).await;
+    let expires_at = SystemTime::now() + Duration::from_secs(3600);
+    let options = PutOptions {
+        content_type: Some("application/json".to_string()),
+        metadata: BTreeMap::from([("owner".to_string(), "ops".to_string())]),
+        expires_at: Some(expires_at),
+        ..PutOptions::default()
+    };
+    let padded = format!("[{}]", vec!["0"; 1024].join(","));
+    storage
+        .put_with("config.json", padded.as_bytes(), &options)
+        .await
+        .unwrap();
+    assert!(hdd.path().join("config.json").exists());
+
+    let check = |storage: FileStorage| async move {
+        assert_eq!(
+            storage
+                .content_type("config.json")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("application/json")
+        );
+        assert_eq!(
+            storage.user_metadata("config.json").await.unwrap()["owner"],
+            "ops"
+        );
+        let recorded = storage.expires_at("config.json").await.unwrap().unwrap();
+        let drift = recorded
+            .duration_since(expires_at)
+            .unwrap_or_else(|err| err.duration());
+        assert!(drift < Duration::from_secs(1));
+    };
+    check(storage.clone()).await;
+
+    // Shrinking the value moves it to the main root with its attributes.
+    let value: Vec<u32> = storage
+        .update_json("config.json", Vec::new(), |value: &mut Vec<u32>| {
+            value.truncate(1)
+        })
+        .await
+        .unwrap();
+    assert_eq!(value, [0]);
+    assert!(ssd.path().join("config.json").exists());
+    assert!(!hdd.path().join("config.json").exists());
+    check(storage.clone()).await;
+    assert_eq!(storage.get("config.json").await.unwrap(), b"[0]");
+}
+
+#[tokio::test]
+async fn tiering_routes_streamed_batched_and_copied_objects() {
+    use bytes::Bytes;
+    use futures_util::stream;
+
+    let (ssd, hdd) = (tempdir().unwrap(), tempdir().unwrap());
+    let storage = tiered(ssd.path(), hdd.path()).await;
+    let large = vec![7u8; 4096];
+
+    let chunks = large
+        .chunks(1000)
+        .map(|chunk| Ok(Bytes::copy_from_slice(chunk)));
+    storage
+        .put_stream("streamed", stream::iter(chunks))
+        .await
+        .unwrap();
+    assert!(hdd.path().join("streamed").exists());
+    assert!(!ssd.path().join("streamed").exists());
+    assert_eq!(storage.get("streamed").await.unwrap(), large);
+    // A small stream replaces the tiered object rather than shadowing it.
+    let chunks = [Ok(Bytes::from_static(b"small"))];
+    storage
+        .put_stream("streamed", stream::iter(chunks))
+        .await
+        .unwrap();
+    assert!(!hdd.path().join("streamed").exists());
+    assert_eq!(storage.get("streamed").await.unwrap(), b"small");
+
+    storage.put("batched", &large).await.unwrap();
+    storage
+        .put_many(&[("batched", b"replaced"), ("other", b"new")])
+        .await
+        .unwrap();
+    assert!(!hdd.path().join("batched").exists());
+    assert_eq!(storage.get("batched").await.unwrap(), b"replaced");
+
+    storage.put("source", &large).await.unwrap();
+    storage.put("small-copy", &large).await.unwrap();
+    storage
+        .copy_many(&[("source", "large-copy"), ("other", "small-copy")])
+        .await
+        .unwrap();
+    assert!(hdd.path().join("large-copy").exists());
+    assert_eq!(storage.get("large-copy").await.unwrap(), large);
+    assert!(!hdd.path().join("small-copy").exists());
+    assert_eq!(storage.get("small-copy").await.unwrap(), b"new");
+}
+
+#[tokio::test]
+async fn sweeping_operations_walk_every_tier() {
+    let (ssd, warm, cold) = (tempdir().unwrap(), tempdir().unwrap(), tempdir().unwrap());
+    let options = StorageOptions {
+        tiering: vec![
+            Tier {
+                min_size: 1024,
+                root: warm.path().to_path_buf(),
+            },
+            Tier {
+                min_size: 4096,
+                root: cold.path().to_path_buf(),
+            },
+        ],
+        checksum_index: true,
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(ssd.path(), options)
+        .await
+        .unwrap();
+    let objects = [
+        ("a/small", vec![1u8; 10]),
+        ("b/warm", vec![2u8; 2000]),
+        ("c/cold", vec![3u8; 8000]),
+        ("c/colder", vec![4u8; 9000]),
+    ];
+    for (key, data) in &objects {
+        storage.put(key, data).await.unwrap();
+    }
+    assert!(warm.path().join("b/warm").exists());
+    assert!(cold.path().join("c/colder").exists());
+
+    let mut manifest = Vec::new();
+    for (key, _) in &objects {
+        let sha256 = storage.sha256(key).await.unwrap();
+        manifest.push(ManifestEntry {
+            key: key.to_string(),
+            sha256,
+        });
+    }
+    let verified = storage.verify_all().await.unwrap();
+    let expected: Vec<_> = manifest
+        .iter()
+        .map(|entry| (entry.key.clone(), entry.sha256.clone()))
+        .collect();
+    assert_eq!(verified, expected);
+    assert!(storage.verify(&manifest).await.unwrap().is_empty());
+
+    assert_eq!(
+        storage.retain(|key| !key.starts_with("c/")).await.unwrap(),
+        2
+    );
+    assert!(!cold.path().join("c/cold").exists());
+    assert_eq!(storage.list("").await.unwrap(), ["a/small", "b/warm"]);
+    assert_eq!(storage.gc_sidecars().await.unwrap(), 0);
+
+    assert_eq!(
+        storage.clear_preview().await.unwrap(),
+        ["a/small", "b/warm"]
+    );
+    assert_eq!(storage.clear().await.unwrap(), 2);
+    for root in [ssd.path(), warm.path(), cold.path()] {
+        assert_eq!(std::fs::read_dir(root).unwrap().count(), 0, "{root:?}");
+    }
+}
+
+#[tokio::test]
+async fn tiering_swaps_objects_held_by_different_tiers() {
+    let (ssd, hdd) = (tempdir().unwrap(), tempdir().unwrap());
+    let storage = tiered(ssd.path(), hdd.path()).await;
+    let large = vec![7u8; 4096];
+    let options = PutOptions {
+        content_type: Some("video/mp4".to_string()),
+        ..PutOptions::default()
+    };
+    storage.put_with("a/large", &large, &options).await.unwrap();
+    storage.put("b/small", b"small").await.unwrap();
+
+    storage.swap("a/large", "b/small").await.unwrap();
+    assert_eq!(storage.get("a/large").await.unwrap(), b"small");
+    assert_eq!(storage.get("b/small").await.unwrap(), large);
+    assert!(ssd.path().join("a/large").exists());
+    assert!(hdd.path().join("b/small").exists());
+    assert!(!hdd.path().join("a/large").exists());
+    assert!(!ssd.path().join("b/small").exists());
+    assert_eq!(storage.content_type("a/large").await.unwrap(), None);
+    assert_eq!(
+        storage.content_type("b/small").await.unwrap().as_deref(),
+        Some("video/mp4")
+    );
+    assert_eq!(storage.list("").await.unwrap(), ["a/large", "b/small"]);
+
+    storage.put("c/large", &large).await.unwrap();
+    storage.swap("b/small", "c/large").await.unwrap();
+    assert_eq!(
+        storage.content_type("c/large").await.unwrap().as_deref(),
+        Some("video/mp4")
+    );
+    let err = storage.swap("a/large", "missing").await.unwrap_err();
+    assert!(matches!(err, StorageError::NotFound(_)), "{err:?}");
+}
+
+#[tokio::test]
+async fn sync_deletes_removes_objects_without_sync_writes() {
+    let tmp = tempdir().unwrap();
+    let options = StorageOptions {
+        sync_deletes: true,
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+
+    storage.put("logs/a.txt", b"a").await.unwrap();
+    storage.put("logs/b.txt", b"b").await.unwrap();
+    storage.delete("logs/a.txt").await.unwrap();
+
+    assert!(!storage.exists("logs/a.txt").await.unwrap());
+    assert!(!tmp.path().join("logs/a.txt").exists());
+    assert_eq!(storage.get("logs/b.txt").await.unwrap(), b"b");
+    let err = storage.delete("logs/a.txt").await.unwrap_err();
+    assert!(matches!(err, StorageError::NotFound(_)));
+}
+
+async fn skipping_store(root: &Path) -> FileStorage {
+    let options = StorageOptions {
+        skip_identical: true,
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(root, options).await.unwrap();
+    assert_eq!(
+        storage.put("feed.xml", b"<feed/>").await.unwrap(),
+        PutOutcome::Written
+    );
+    // Backdated so a rewrite would visibly move the modification time.
+    let backdated = filetime::FileTime::from_unix_time(1_000_000_000, 0);
+    filetime::set_file_mtime(root.join("feed.xml"), backdated).unwrap();
+    storage
+}
+
+#[tokio::test]
+async fn skip_identical_leaves_identical_objects_untouched() {
+    let tmp = tempdir().unwrap();
+    let storage = skipping_store(tmp.path()).await;
+    let before = storage.head("feed.xml").await.unwrap();
+
+    let outcome = storage.put("feed.xml", b"<feed/>").await.unwrap();
+    assert_eq!(outcome, PutOutcome::Unchanged);
+    assert_eq!(storage.head("feed.xml").await.unwrap(), before);
+
+    // Attributes still follow the put even though the bytes stay in place.
+    let options = PutOptions {
+        content_type: Some("application/atom+xml".to_string()),
+        ..PutOptions::default()
+    };
+    let outcome = storage
+        .put_with("feed.xml", b"<feed/>", &options)
+        .await
+        .unwrap();
+    assert_eq!(outcome, PutOutcome::Unchanged);
+    assert_eq!(storage.head("feed.xml").await.unwrap(), before);
+    assert_eq!(
+        storage.content_type("feed.xml").await.unwrap().as_deref(),
+        Some("application/atom+xml")
+    );
+}
+
+#[tokio::test]
+async fn skip_identical_still_writes_changed_objects() {
+    let tmp = tempdir().unwrap();
+    let storage = skipping_store(tmp.path()).await;
+    let before = storage.head("feed.xml").await.unwrap();
+
+    // Same length, different bytes.
+    let outcome = storage.put("feed.xml", b"<atom/>").await.unwrap();
+    assert_eq!(outcome, PutOutcome::Written);
+    assert_eq!(storage.get("feed.xml").await.unwrap(), b"<atom/>");
+    let after = storage.head("feed.xml").await.unwrap();
+    assert!(after.modified > before.modified);
+    assert_ne!(after.etag, before.etag);
+
+    let plain = FileStorage::new(tmp.path()).await.unwrap();
+    let outcome = plain.put("feed.xml", b"<atom/>").await.unwrap();
+    assert_eq!(outcome, PutOutcome::Written);
+}
+
+#[tokio::test]
+async fn puts_create_missing_parents_by_default() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+
+    storage
+        .put_with("a/b/c/deep.txt", b"deep", &PutOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(storage.get("a/b/c/deep.txt").await.unwrap(), b"deep");
+    assert!(tmp.path().join("a/b/c").is_dir());
+}
+
+#[tokio::test]
+async fn puts_without_create_parents_need_an_existing_prefix() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    storage.put("reports/2024.csv", b"old").await.unwrap();
+    let strict = PutOptions {
+        create_parents: false,
+        ..PutOptions::default()
+    };
+
+    let err = storage
+        .put_with("reprots/2025.csv", b"new", &strict)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, StorageError::PrefixNotFound(prefix) if prefix == "reprots"));
+    assert!(!tmp.path().join("reprots").exists());
+
+    storage
+        .put_with("reports/2025.csv", b"new", &strict)
+        .await
+        .unwrap();
+    assert_eq!(storage.get("reports/2025.csv").await.unwrap(), b"new");
+    // Keys at the top level have the root as their parent.
+    storage.put_with("top.txt", b"top", &strict).await.unwrap();
+}
+
+#[tokio::test]
+async fn group_commit_keeps_writes_ordered_and_intact() {
+    let primary_dir = tempdir().unwrap();
+    let replica_dir = tempdir().unwrap();
+    let options = StorageOptions {
+        replica_root: Some(replica_dir.path().to_path_buf()),
+        sync_writes: true,
+        group_commit_interval: Some(Duration::from_millis(5)),
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(primary_dir.path(), options)
+        .await
+        .unwrap();
+    let replica = FileStorage::new(replica_dir.path()).await.unwrap();
+
+    for round in 0..3 {
+        for i in 0..50 {
+            let key = format!("dir{}/object{i}", i % 4);
+            let data = format!("round {round} object {i}");
+            storage.put(&key, data.as_bytes()).await.unwrap();
+            assert_eq!(storage.get(&key).await.unwrap(), data.as_bytes());
+        }
+    }
+    for i in (0..50).step_by(5) {
+        storage
+            .delete(&format!("dir{}/object{i}", i % 4))
+            .await
+            .unwrap();
+    }
+    storage.flush().await.unwrap();
+
+    for i in 0..50 {
+        let key = format!("dir{}/object{i}", i % 4);
+        if i % 5 == 0 {
+            assert!(!storage.exists(&key).await.unwrap());
+            assert!(!replica.exists(&key).await.unwrap());
+        } else {
+            let expected = format!("round 2 object {i}");
+            assert_eq!(storage.get(&key).await.unwrap(), expected.as_bytes());
+            assert_eq!(replica.get(&key).await.unwrap(), expected.as_bytes());
+        }
+    }
+}
+
+#[tokio::test]
+async fn root_and_resolve_expose_validated_paths() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    assert_eq!(storage.root(), tmp.path());
+
+    storage.put("docs/readme.txt", b"hello").await.unwrap();
+    let path = storage.resolve("docs/readme.txt").unwrap();
+    assert_eq!(path, tmp.path().join("docs/readme.txt"));
+    assert_eq!(std::fs::read(path).unwrap(), b"hello");
+
+    for key in ["../escape", "docs/../../escape", "/etc/passwd", ""] {
+        assert!(matches!(
+            storage.resolve(key),
+            Err(StorageError::InvalidKey { .. })
+        ));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn concurrent_claims_hand_a_job_to_exactly_one_worker() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    storage.put("jobs/42", b"resize image").await.unwrap();
+
+    let (first, second) = tokio::join!(
+        storage.claim("jobs/42", "worker-a"),
+        storage.claim("jobs/42", "worker-b"),
+    );
+    let (claimed, data) = match (first, second) {
+        (Ok(claim), Err(StorageError::NotFound(_))) => claim,
+        (Err(StorageError::NotFound(_)), Ok(claim)) => claim,
+        other => panic!("expected exactly one claim to succeed, got {other:?}"),
+    };
+    assert!(claimed == "worker-a/jobs/42" || claimed == "worker-b/jobs/42");
+    assert_eq!(data, b"resize image");
+    assert_eq!(storage.list("").await.unwrap(), vec![claimed]);
+}
+
+#[tokio::test]
+async fn claims_carry_their_checksum_index_entry() {
+    let tmp = tempdir().unwrap();
+    let options = StorageOptions {
+        checksum_index: true,
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+    storage.put("jobs/42", b"resize image").await.unwrap();
+
+    let (claimed, _) = storage.claim("jobs/42", "worker-a").await.unwrap();
+    let index = tmp.path().join("worker-a/jobs/.filestorage-checksums");
+    let contents = std::fs::read_to_string(&index).unwrap();
+    assert!(contents.contains(&sha256_hex(b"resize image")));
+    assert_eq!(storage.gc_sidecars().await.unwrap(), 0);
+    let manifest = vec![ManifestEntry {
+        key: claimed,
+        sha256: sha256_hex(b"resize image"),
+    }];
+    assert!(storage.verify(&manifest).await.unwrap().is_empty());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn op_timeout_fails_hung_reads_but_not_fast_ones() {
+    let tmp = tempdir().unwrap();
+    let options = StorageOptions {
+        op_timeout: Some(Duration::from_millis(200)),
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+    // Reading a FIFO without a writer blocks, standing in for a hung mount.
+    let fifo = tmp.path().join("stuck");
+    let status = std::process::Command::new("mkfifo")
+        .arg(&fifo)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let err = storage.get("stuck").await.unwrap_err();
+    assert!(matches!(err, StorageError::Timeout { key, .. } if key == "stuck"));
+    // Release the abandoned read so the runtime can shut down.
+    drop(std::fs::OpenOptions::new().write(true).open(&fifo).unwrap());
+
+    storage.put("fast.txt", b"quick").await.unwrap();
+    assert_eq!(storage.get("fast.txt").await.unwrap(), b"quick");
+    storage.delete("fast.txt").await.unwrap();
+}
+
+#[tokio::test]
+async fn verify_uses_the_checksum_index_until_objects_change() {
+    let tmp = tempdir().unwrap();
+    let options = StorageOptions {
+        checksum_index: true,
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+    storage.put("docs/a.txt", b"alpha").await.unwrap();
+    storage.put("docs/b.txt", b"beta").await.unwrap();
+    storage.put("docs/c.txt", b"gamma").await.unwrap();
+    let manifest = vec![
+        ManifestEntry {
+            key: "docs/a.txt".to_string(),
+            sha256: sha256_hex(b"alpha"),
+        },
+        ManifestEntry {
+            key: "docs/b.txt".to_string(),
+            sha256: sha256_hex(b"beta"),
+        },
+    ];
+    assert!(storage.verify(&manifest).await.unwrap().is_empty());
+    assert!(
+        storage
+            .list("")
+            .await
+            .unwrap()
+            .iter()
+            .all(|key| key.ends_with(".txt"))
+    );
+
+    // A tampered entry is trusted while the object is unchanged, so it surfaces as a mismatch.
+    let index = tmp.path().join("docs/.filestorage-checksums");
+    let contents = std::fs::read_to_string(&index).unwrap();
+    std::fs::write(
+        &index,
+        contents.replace(&sha256_hex(b"alpha"), &"0".repeat(64)),
+    )
+    .unwrap();
+    assert_eq!(storage.verify(&manifest).await.unwrap(), vec!["docs/a.txt"]);
+
+    // Modifying an object outside the store makes its entry stale and forces a rehash.
+    std::thread::sleep(Duration::from_millis(10));
+    std::fs::write(tmp.path().join("docs/b.txt"), b"corrupted").unwrap();
+    assert_eq!(
+        storage.verify(&manifest).await.unwrap(),
+        vec!["docs/a.txt", "docs/b.txt"]
+    );
+
+    // Deletes and overwrites are appended, and the index is compacted once
+    // appends outnumber its entries.
+    storage.delete("docs/c.txt").await.unwrap();
+    for _ in 0..300 {
+        storage.put("docs/a.txt", b"alpha").await.unwrap();
+    }
+    let contents = std::fs::read_to_string(&index).unwrap();
+    assert!(contents.lines().count() < 300);
+    assert!(!contents.contains("c.txt"));
+    assert!(contents.contains("b.txt"));
+    assert!(storage.verify(&manifest[..1]).await.unwrap().is_empty());
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    hex::encode(Sha256::digest(data))
+}
+
+#[tokio::test]
+async fn get_if_modified_since_skips_unchanged_objects() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    storage.put("feed.json", b"v1").await.unwrap();
+    let modified = storage.head("feed.json").await.unwrap().modified;
+
+    let unchanged = storage
+        .get_if_modified_since("feed.json", modified)
+        .await
+        .unwrap();
+    assert_eq!(unchanged, None);
+
+    let earlier = modified - Duration::from_secs(1);
+    let changed = storage
+        .get_if_modified_since("feed.json", earlier)
+        .await
+        .unwrap();
+    assert_eq!(changed.as_deref(), Some(&b"v1"[..]));
+
+    let err = storage
+        .get_if_modified_since("missing.json", earlier)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, StorageError::NotFound(_)));
+}
+
+/// Stores `YYYY-MM-DD/<name>` keys under `YYYY/MM/DD/<name>`.
+#[derive(Debug)]
+struct DatePartitionMapper;
+
+impl KeyMapper for DatePartitionMapper {
+    fn to_path(&self, key: &str) -> Result<PathBuf, StorageError> {
+        let invalid = || StorageError::InvalidKey {
+            reason: InvalidKeyReason::Other,
+            message: format!("`{key}` is not `YYYY-MM-DD/<name>`"),
+        };
+        let (date, name) = key.split_once('/').ok_or_else(invalid)?;
+        let parts: Vec<&str> = date.split('-').collect();
+        if parts.len() != 3 || name.contains('/') {
+            return Err(invalid());
+        }
+        Ok([parts[0], parts[1], parts[2], name].iter().collect())
+    }
+
+    fn to_key(&self, path: &Path) -> Option<String> {
+        let parts: Vec<&str> = path
+            .iter()
+            .map(|segment| segment.to_str())
+            .collect::<Option<_>>()?;
+        match parts.as_slice() {
+            [year, month, day, name] => Some(format!("{year}-{month}-{day}/{name}")),
+            _ => None,
+        }
+    }
+}
+
+#[tokio::test]
+async fn key_mappers_round_trip_keys_through_their_layout() {
+    let default_dir = tempdir().unwrap();
+    let options = StorageOptions {
+        key_mapper: Some(Arc::new(DefaultKeyMapper)),
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(default_dir.path(), options)
+        .await
+        .unwrap();
+    storage.put("docs/readme.txt", b"hello").await.unwrap();
+    assert!(default_dir.path().join("docs/readme.txt").is_file());
+    assert_eq!(storage.list("").await.unwrap(), vec!["docs/readme.txt"]);
+
+    let dated_dir = tempdir().unwrap();
+    let options = StorageOptions {
+        key_mapper: Some(Arc::new(DatePartitionMapper)),
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(dated_dir.path(), options)
+        .await
+        .unwrap();
+    storage.put("2024-05-17/app.log", b"boot").await.unwrap();
+    storage.put("2024-05-18/app.log", b"ready").await.unwrap();
+    assert!(dated_dir.path().join("2024/05/17/app.log").is_file());
+
+    assert_eq!(storage.get("2024-05-17/app.log").await.unwrap(), b"boot");
+    assert_eq!(
+        storage.list("2024-05").await.unwrap(),
+        vec!["2024-05-17/app.log", "2024-05-18/app.log"]
+    );
+    assert!(matches!(
+        storage.put("undated.log", b"nope").await,
+        Err(StorageError::InvalidKey { .. })
+    ));
+
+    storage.delete("2024-05-17/app.log").await.unwrap();
+    assert_eq!(storage.list("").await.unwrap(), vec!["2024-05-18/app.log"]);
+}
+
+#[tokio::test]
+async fn namespaces_scope_keys_below_a_prefix() {
+    let tmp = tempdir().unwrap();
+    let options = StorageOptions {
+        existence_index: true,
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+    let tenant = storage.namespace("tenants/acme/").unwrap();
+    assert_eq!(tenant.root(), tmp.path().join("tenants/acme"));
+
+    tenant.put("invoice.pdf", b"acme invoice").await.unwrap();
+    assert!(tmp.path().join("tenants/acme/invoice.pdf").is_file());
+    assert!(!storage.exists("invoice.pdf").await.unwrap());
+    assert_eq!(
+        storage.get("tenants/acme/invoice.pdf").await.unwrap(),
+        b"acme invoice"
+    );
+
+    storage.put("invoice.pdf", b"parent invoice").await.unwrap();
+    assert_eq!(tenant.get("invoice.pdf").await.unwrap(), b"acme invoice");
+    assert_eq!(tenant.list("").await.unwrap(), vec!["invoice.pdf"]);
+
+    for prefix in ["../outside", "/abs", ""] {
+        assert!(matches!(
+            storage.namespace(prefix),
+            Err(StorageError::InvalidKey { .. })
+        ));
+    }
+    assert!(matches!(
+        tenant.put("../other/invoice.pdf", b"escape").await,
+        Err(StorageError::InvalidKey { .. })
+    ));
+}
+
+#[tokio::test]
+async fn flush_completes_deferred_syncs() {
+    let tmp = tempdir().unwrap();
+    let plain = FileStorage::new(tmp.path()).await.unwrap();
+    plain.flush().await.unwrap();
+
+    let options = StorageOptions {
+        sync_writes: true,
+        group_commit_interval: Some(Duration::from_secs(3600)),
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+    storage.put("queue/a", b"first").await.unwrap();
+    storage.put("queue/b", b"second").await.unwrap();
+    storage.flush().await.unwrap();
+    drop(storage);
+
+    let reopened = FileStorage::open(tmp.path()).await.unwrap();
+    assert_eq!(
+        reopened.list("queue/").await.unwrap(),
+        vec!["queue/a", "queue/b"]
+    );
+    assert_eq!(reopened.get("queue/b").await.unwrap(), b"second");
+}
+
+#[test]
+fn descriptor_exhaustion_is_resource_exhausted() {
+    let codes: &[i32] = if cfg!(unix) { &[24, 23] } else { &[4] };
+    for &code in codes {
+        let err = StorageError::from(std::io::Error::from_raw_os_error(code));
+        assert!(matches!(err, StorageError::ResourceExhausted(_)), "{err:?}");
+    }
+    let err = StorageError::from(std::io::Error::other("disk on fire"));
+    assert!(matches!(err, StorageError::Io(_)));
+}
+
+#[tokio::test]
+async fn put_stream_writes_chunks_and_discards_failed_streams() {
+    use bytes::Bytes;
+    use futures_util::stream;
+
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    let chunks =
+        ["hello", ", ", "streamed ", "world"].map(|chunk| Ok(Bytes::from_static(chunk.as_bytes())));
+    let written = storage
+        .put_stream("logs/out.txt", stream::iter(chunks))
+        .await
+        .unwrap();
+    assert_eq!(written, 21);
+    assert_eq!(
+        storage.get("logs/out.txt").await.unwrap(),
+        b"hello, streamed world"
+    );
+
+    let failing = vec![
+        Ok(Bytes::from_static(b"partial")),
+        Err(std::io::Error::other("upstream hung up")),
+    ];
+    let err = storage
+        .put_stream("logs/broken.txt", stream::iter(failing))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, StorageError::Io(_)));
+    assert!(!storage.exists("logs/broken.txt").await.unwrap());
+
+    let failing = vec![Err(std::io::Error::other("upstream hung up"))];
+    storage
+        .put_stream("logs/out.txt", stream::iter(failing))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        storage.get("logs/out.txt").await.unwrap(),
+        b"hello, streamed world"
+    );
+    let leftovers: Vec<_> = std::fs::read_dir(tmp.path().join("logs"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(leftovers, vec!["out.txt"]);
+}
+
+#[tokio::test]
+async fn put_stream_stops_reading_once_a_quota_is_exceeded() {
+    use bytes::Bytes;
+    use futures_util::stream;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let tmp = tempdir().unwrap();
+    let options = StorageOptions {
+        prefix_quotas: HashMap::from([("tenant".to_string(), 10)]),
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+    storage.put("tenant/a", b"old").await.unwrap();
+
+    let pulled = AtomicUsize::new(0);
+    let chunks = std::iter::repeat_with(|| {
+        pulled.fetch_add(1, Ordering::Relaxed);
+        Ok(Bytes::from_static(b"four"))
+    })
+    .take(1000);
+    let err = storage
+        .put_stream("tenant/a", stream::iter(chunks))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, StorageError::QuotaExceeded(_)), "{err:?}");
+    // Replacing the 3 old bytes leaves room for two chunks but not a third.
+    assert_eq!(pulled.load(Ordering::Relaxed), 3);
+    assert_eq!(storage.get("tenant/a").await.unwrap(), b"old");
+    let leftovers: Vec<_> = std::fs::read_dir(tmp.path().join("tenant"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(leftovers, vec!["a"]);
+
+    let chunks = ["four", "four"].map(|chunk| Ok(Bytes::from_static(chunk.as_bytes())));
+    storage
+        .put_stream("tenant/a", stream::iter(chunks))
+        .await
+        .unwrap();
+    storage.put("tenant/b", b"xxx").await.unwrap_err();
+}
+
+#[tokio::test]
+async fn append_reader_stops_reading_once_a_quota_is_exceeded() {
+    let tmp = tempdir().unwrap();
+    let options = StorageOptions {
+        prefix_quotas: HashMap::from([("tenant".to_string(), 10)]),
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+    storage.put("tenant/a", b"old").await.unwrap();
+
+    // The reader never ends, so only a check per chunk can stop the append.
+    let err = storage
+        .append_reader("tenant/a", tokio::io::repeat(b'x'))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, StorageError::QuotaExceeded(_)), "{err:?}");
+    assert_eq!(storage.get("tenant/a").await.unwrap(), b"old");
+
+    let len = storage
+        .append_reader("tenant/a", &b"1234567"[..])
+        .await
+        .unwrap();
+    assert_eq!(len, 10);
+    storage.put("tenant/b", b"x").await.unwrap_err();
+}
+
+#[tokio::test]
+async fn append_reader_cuts_back_an_append_that_times_out() {
+    use tokio::io::AsyncWriteExt;
+
+    let tmp = tempdir().unwrap();
+    let options = StorageOptions {
+        op_timeout: Some(Duration::from_millis(200)),
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+    storage.put("capture.bin", b"header:").await.unwrap();
+
+    // The writer sends a few bytes and then hangs without closing.
+    let (mut writer, reader) = tokio::io::duplex(64);
+    writer.write_all(b"partial").await.unwrap();
+    let err = storage
+        .append_reader("capture.bin", reader)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, StorageError::Timeout { key, .. } if key == "capture.bin"));
+    assert_eq!(storage.get("capture.bin").await.unwrap(), b"header:");
+    drop(writer);
+}
+
+#[tokio::test(start_paused = true)]
+async fn expiry_sweeper_removes_expired_objects_and_backs_off_when_idle() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    let expired = PutOptions {
+        expires_at: Some(SystemTime::now() - Duration::from_secs(1)),
+        ..PutOptions::default()
+    };
+    for n in 0..5 {
+        let key = format!("sessions/{n}");
+        storage.put_with(&key, b"stale", &expired).await.unwrap();
+    }
+    storage.put("sessions/keep", b"fresh").await.unwrap();
+
+    let schedule = SweepSchedule {
+        min_interval: Duration::from_secs(1),
+        max_interval: Duration::from_secs(60),
+        max_per_cycle: 2,
+    };
+    let sweeper = storage.spawn_expiry_sweeper(schedule);
+    for _ in 0..30 {
+        if sweeper.swept() == 5 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    assert_eq!(sweeper.swept(), 5);
+    assert!(sweeper.cycles() >= 3, "cycles remove at most two objects");
+    assert_eq!(storage.list("").await.unwrap(), ["sessions/keep"]);
+    for n in 0..5 {
+        assert!(!tmp.path().join(format!("sessions/{n}")).exists());
+    }
+
+    // An hour of idling, with the delay backing off towards a minute and
+    // never jittered below half of it.
+    let idle_start = sweeper.cycles();
+    tokio::time::sleep(Duration::from_secs(3600)).await;
+    let idle_cycles = sweeper.cycles() - idle_start;
+    assert!(idle_cycles > 0);
+    assert!(idle_cycles <= 3600 / 30 + 6, "{idle_cycles} idle cycles");
+    assert_eq!(sweeper.swept(), 5);
+    assert_eq!(storage.get("sessions/keep").await.unwrap(), b"fresh");
+}
+
+#[tokio::test]
+async fn gc_sidecars_removes_only_orphans() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    let options = PutOptions {
+        content_type: Some("text/plain".to_string()),
+        ..PutOptions::default()
+    };
+    storage.put_with("kept.txt", b"hi", &options).await.unwrap();
+
+    let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+    for name in [".filestorage-type.kept.txt", ".filestorage-type.gone.txt"] {
+        let path = tmp.path().join(name);
+        std::fs::write(&path, "text/plain").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(an_hour_ago)
+            .unwrap();
+    }
+    std::fs::write(tmp.path().join(".filestorage-meta.fresh.txt"), "{}").unwrap();
+
+    assert_eq!(storage.gc_sidecars().await.unwrap(), 1);
+    assert!(!tmp.path().join(".filestorage-type.gone.txt").exists());
+    assert!(tmp.path().join(".filestorage-meta.fresh.txt").exists());
+    assert_eq!(
+        storage.content_type("kept.txt").await.unwrap().as_deref(),
+        Some("text/plain")
+    );
+    assert_eq!(storage.gc_sidecars().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn get_text_decodes_utf8_objects() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+
+    storage
+        .put("greeting.txt", "grüße".as_bytes())
+        .await
+        .unwrap();
+    assert_eq!(storage.get_text("greeting.txt").await.unwrap(), "grüße");
+
+    storage.put("blob.bin", &[0x66, 0x6f, 0xff]).await.unwrap();
+    let err = storage.get_text("blob.bin").await.unwrap_err();
+    assert!(matches!(err, StorageError::Encoding { ref key, .. } if key == "blob.bin"));
+}
+
+#[tokio::test]
+async fn both_rename_strategies_replace_objects() {
+    for rename_strategy in [RenameStrategy::Atomic, RenameStrategy::Fallback] {
+        let tmp = tempdir().unwrap();
+        let options = StorageOptions {
+            rename_strategy,
+            ..StorageOptions::default()
+        };
+        let storage = FileStorage::with_options(tmp.path(), options)
+            .await
+            .unwrap();
+        let typed = PutOptions {
+            content_type: Some("text/plain".to_string()),
+            ..PutOptions::default()
+        };
+
+        storage.put("doc.txt", b"one").await.unwrap();
+        storage.put_with("doc.txt", b"two", &typed).await.unwrap();
+        storage.put_with("doc.txt", b"three", &typed).await.unwrap();
+        assert_eq!(storage.get("doc.txt").await.unwrap(), b"three");
+        assert_eq!(
+            storage.content_type("doc.txt").await.unwrap().as_deref(),
+            Some("text/plain")
+        );
+
+        storage.put_with_backup("doc.txt", b"four").await.unwrap();
+        storage.restore_backup("doc.txt").await.unwrap();
+        assert_eq!(storage.get("doc.txt").await.unwrap(), b"three");
+        assert_eq!(storage.get("doc.txt.bak").await.unwrap(), b"four");
+
+        let err = storage.restore_backup("missing.txt").await.unwrap_err();
+        assert!(matches!(err, StorageError::NotFound(_)));
+        storage.put("solo.txt", b"kept").await.unwrap();
+        storage.restore_backup("solo.txt").await.unwrap_err();
+        assert_eq!(storage.get("solo.txt").await.unwrap(), b"kept");
+
+        let mut names: Vec<_> = std::fs::read_dir(tmp.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                ".filestorage-type.doc.txt",
+                "doc.txt",
+                "doc.txt.bak",
+                "solo.txt"
+            ]
+        );
+    }
+}
+
+#[tokio::test]
+async fn keys_at_the_segment_limit_keep_their_attributes() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    // 255 bytes, less the `.filestorage-expires.` prefix of sidecar names.
+    let key = format!("dir/{}", "a".repeat(234));
+    let options = PutOptions {
+        content_type: Some("text/plain".to_string()),
+        metadata: BTreeMap::from([("owner".to_string(), "ops".to_string())]),
+        expires_at: Some(SystemTime::now() + Duration::from_secs(3600)),
+        ..PutOptions::default()
+    };
+    storage.put_with(&key, b"data", &options).await.unwrap();
+    assert_eq!(
+        storage.content_type(&key).await.unwrap().as_deref(),
+        Some("text/plain")
+    );
+    assert_eq!(storage.user_metadata(&key).await.unwrap()["owner"], "ops");
+    assert!(storage.expires_at(&key).await.unwrap().is_some());
+
+    let over = format!("dir/{}", "a".repeat(235));
+    match storage.put_with(&over, b"data", &options).await {
+        Err(StorageError::InvalidKey { reason, .. }) => {
+            assert_eq!(reason, InvalidKeyReason::TooLong)
+        }
+        other => panic!("over-long segment was accepted: {other:?}"),
+    }
+    assert_eq!(storage.list("").await.unwrap(), [key]);
+}
+
+#[tokio::test]
+async fn rejected_keys_report_their_reason() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    let long_segment = "a".repeat(256);
+    let long_key = ["abcdefgh"; 130].join("/");
+    let cases = [
+        ("", InvalidKeyReason::Empty),
+        ("/etc/passwd", InvalidKeyReason::Absolute),
+        ("a/../../b", InvalidKeyReason::ParentTraversal),
+        (long_segment.as_str(), InvalidKeyReason::TooLong),
+        (long_key.as_str(), InvalidKeyReason::TooLong),
+        ("bad\0name", InvalidKeyReason::DisallowedChar),
+        ("line\nbreak", InvalidKeyReason::DisallowedChar),
+        ("dir/.filestorage-meta.x", InvalidKeyReason::Reserved),
+    ];
+    for (key, expected) in cases {
+        match storage.put(key, b"x").await {
+            Err(StorageError::InvalidKey { reason, .. }) => assert_eq!(reason, expected, "{key}"),
+            other => panic!("`{key}` was not rejected as invalid: {other:?}"),
+        }
+    }
+    assert_eq!(
+        InvalidKeyReason::ParentTraversal.code(),
+        "key_parent_traversal"
+    );
+    storage.put("ok/key.txt", b"x").await.unwrap();
+}
+
+#[tokio::test]
+async fn listing_cache_tracks_writes_and_reconciles_with_disk() {
+    let tmp = tempdir().unwrap();
+    std::fs::write(tmp.path().join("before.txt"), b"x").unwrap();
+    let options = StorageOptions {
+        listing_cache: Some(Duration::from_millis(50)),
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+    assert_eq!(storage.list("").await.unwrap(), ["before.txt"]);
+
+    storage.put("logs/a.txt", b"a").await.unwrap();
+    storage.put("logs/b.txt", b"b").await.unwrap();
+    storage.delete("before.txt").await.unwrap();
+    assert_eq!(
+        storage.list("logs/").await.unwrap(),
+        ["logs/a.txt", "logs/b.txt"]
+    );
+    storage.rename_prefix("logs", "archive").await.unwrap();
+    let scoped = storage.namespace("archive").unwrap();
+    scoped.delete("b.txt").await.unwrap();
+    assert_eq!(scoped.list("").await.unwrap(), ["a.txt"]);
+    assert_eq!(storage.list("").await.unwrap(), ["archive/a.txt"]);
+
+    std::fs::write(tmp.path().join("dropped-in.txt"), b"x").unwrap();
+    let uncached = FileStorage::new(tmp.path()).await.unwrap();
+    let fresh = uncached.list("").await.unwrap();
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while storage.list("").await.unwrap() != fresh {
+        assert!(
+            std::time::Instant::now() < deadline,
+            "cache never reconciled"
+        );
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(fresh, ["archive/a.txt", "dropped-in.txt"]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn listing_cache_keeps_writes_made_while_it_rescans() {
+    let tmp = tempdir().unwrap();
+    let options = StorageOptions {
+        listing_cache: Some(Duration::from_millis(1)),
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+    for i in 0..300 {
+        let key = format!("k{i:03}");
+        storage.put(&key, b"x").await.unwrap();
+        let listed = storage.list("").await.unwrap();
+        assert!(listed.contains(&key), "{key} missing after its put");
+        if i > 0 {
+            let previous = format!("k{:03}", i - 1);
+            storage.delete(&previous).await.unwrap();
+            let listed = storage.list("").await.unwrap();
+            assert!(
+                !listed.contains(&previous),
+                "{previous} listed after its delete"
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn copy_to_streams_objects_between_stores() {
+    let staging_dir = tempdir().unwrap();
+    let production_dir = tempdir().unwrap();
+    let staging = FileStorage::new(staging_dir.path()).await.unwrap();
+    let production = FileStorage::new(production_dir.path()).await.unwrap();
+    let data: Vec<u8> = (0..300_000u32).map(|n| (n % 251) as u8).collect();
+    staging.put("build/app.bin", &data).await.unwrap();
+
+    staging
+        .copy_to("build/app.bin", &production, "releases/app.bin")
+        .await
+        .unwrap();
+    assert_eq!(production.get("releases/app.bin").await.unwrap(), data);
+    assert_eq!(staging.get("build/app.bin").await.unwrap(), data);
+
+    let err = staging
+        .copy_to("build/missing.bin", &production, "releases/missing.bin")
+        .await
+        .unwrap_err();
+    assert!(matches!(err, StorageError::NotFound(_)));
+    assert!(!production.exists("releases/missing.bin").await.unwrap());
+}
+
+#[tokio::test]
+async fn write_range_updates_objects_in_place() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+
+    storage.put("doc.bin", b"0123456789").await.unwrap();
+    storage.write_range("doc.bin", 4, b"ab").await.unwrap();
+    assert_eq!(storage.get("doc.bin").await.unwrap(), b"0123ab6789");
+
+    storage.write_range("doc.bin", 13, b"xyz").await.unwrap();
+    assert_eq!(
+        storage.get("doc.bin").await.unwrap(),
+        b"0123ab6789\0\0\0xyz"
+    );
+
+    storage
+        .write_range("fresh/new.bin", 2, b"hi")
+        .await
+        .unwrap();
+    assert_eq!(storage.get("fresh/new.bin").await.unwrap(), b"\0\0hi");
+
+    let storage = Arc::new(storage);
+    let writers: Vec<_> = (0..32u8)
+        .map(|n| {
+            let storage = storage.clone();
+            tokio::spawn(async move {
+                storage
+                    .write_range("blocks.bin", u64::from(n) * 4, &[n; 4])
+                    .await
+                    .unwrap();
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.await.unwrap();
+    }
+    let expected: Vec<u8> = (0..32u8).flat_map(|n| [n; 4]).collect();
+    assert_eq!(storage.get("blocks.bin").await.unwrap(), expected);
+}
+
+#[tokio::test]
+async fn append_record_keeps_concurrent_records_whole() {
+    let tmp = tempdir().unwrap();
+    let storage = Arc::new(FileStorage::new(tmp.path()).await.unwrap());
+
+    let appenders: Vec<_> = (0..64)
+        .map(|n| {
+            let storage = storage.clone();
+            tokio::spawn(async move {
+                let record = format!(r#"{{"event":{n},"pad":"{}"}}"#, "x".repeat(512));
+                storage
+                    .append_record("logs/events.jsonl", record.as_bytes())
+                    .await
+                    .unwrap();
+            })
+        })
+        .collect();
+    for appender in appenders {
+        appender.await.unwrap();
+    }
+
+    let text = storage.get_text("logs/events.jsonl").await.unwrap();
+    assert!(text.ends_with('\n'));
+    let mut seen: Vec<usize> = text
+        .lines()
+        .map(|line| {
+            let n = line
+                .strip_prefix(r#"{"event":"#)
+                .and_then(|rest| rest.split_once(','))
+                .map(|(n, _)| n.parse().unwrap())
+                .unwrap();
+            assert_eq!(
+                line,
+                format!(r#"{{"event":{n},"pad":"{}"}}"#, "x".repeat(512))
+            );
+            n
+        })
+        .collect();
+    seen.sort_unstable();
+    assert_eq!(seen, (0..64).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn list_fails_past_max_list_entries() {
+    let tmp = tempdir().unwrap();
+    let options = StorageOptions {
+        max_list_entries: Some(10),
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+    for n in 0..25 {
+        storage
+            .put(&format!("logs/{n:02}.log"), b"line")
+            .await
+            .unwrap();
+    }
+    storage.put("other/a.txt", b"a").await.unwrap();
+
+    // The listing stops one key past the maximum rather than collecting all.
+    let err = storage.list("").await.unwrap_err();
+    assert!(matches!(
+        err,
+        StorageError::ListTooLarge { found_at_least: 11 }
+    ));
+    let err = storage.list("logs/").await.unwrap_err();
+    assert!(matches!(
+        err,
+        StorageError::ListTooLarge { found_at_least: 11 }
+    ));
+    assert_eq!(storage.list("other/").await.unwrap(), ["other/a.txt"]);
+    assert_eq!(storage.list("logs/0").await.unwrap().len(), 10);
+
+    let listing = storage.list_delimited("", '/').await.unwrap();
+    assert_eq!(listing.common_prefixes, ["logs/", "other/"]);
+
+    let options = StorageOptions {
+        max_list_entries: Some(10),
+        listing_cache: Some(Duration::from_secs(60)),
+        ..StorageOptions::default()
+    };
+    let cached = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+    let err = cached.list("logs/").await.unwrap_err();
+    assert!(matches!(
+        err,
+        StorageError::ListTooLarge { found_at_least: 11 }
+    ));
+    let keys = cached.list("logs/0").await.unwrap();
+    assert_eq!(keys.first().map(String::as_str), Some("logs/00.log"));
+    assert_eq!(keys.len(), 10);
+}
+
+#[tokio::test]
+async fn is_empty_tracks_whether_any_object_exists() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    assert!(storage.is_empty().await.unwrap());
+
+    let options = PutOptions {
+        content_type: Some("text/plain".to_string()),
+        ..PutOptions::default()
+    };
+    storage
+        .put_with("notes/today.txt", b"hello", &options)
+        .await
+        .unwrap();
+    assert!(!storage.is_empty().await.unwrap());
+
+    storage.delete("notes/today.txt").await.unwrap();
+    assert!(storage.is_empty().await.unwrap());
+
+    // A directory left with only a sidecar holds no objects.
+    std::fs::create_dir_all(tmp.path().join("orphans")).unwrap();
+    std::fs::write(
+        tmp.path().join("orphans/.filestorage-type.gone"),
+        "text/plain",
+    )
+    .unwrap();
+    assert!(storage.is_empty().await.unwrap());
+}
+
+#[tokio::test]
+async fn list_delimited_groups_keys_by_the_next_segment() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    for key in [
+        "readme.txt",
+        "photos/cover.jpg",
+        "photos/2023/a.jpg",
+        "photos/2024/b.jpg",
+        "photos/2024/trip/c.jpg",
+        "photos-old/d.jpg",
+    ] {
+        storage.put(key, b"x").await.unwrap();
+    }
+
+    let root = storage.list_delimited("", '/').await.unwrap();
+    assert_eq!(root.keys, ["readme.txt"]);
+    assert_eq!(root.common_prefixes, ["photos-old/", "photos/"]);
+
+    let photos = storage.list_delimited("photos/", '/').await.unwrap();
+    assert_eq!(photos.keys, ["photos/cover.jpg"]);
+    assert_eq!(photos.common_prefixes, ["photos/2023/", "photos/2024/"]);
+
+    let partial = storage.list_delimited("photos/202", '/').await.unwrap();
+    assert!(partial.keys.is_empty());
+    assert_eq!(partial.common_prefixes, ["photos/2023/", "photos/2024/"]);
+
+    let trip = storage
+        .list_delimited("photos/2024/trip/", '/')
+        .await
+        .unwrap();
+    assert_eq!(trip.keys, ["photos/2024/trip/c.jpg"]);
+    assert!(trip.common_prefixes.is_empty());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn symlinks_pointing_outside_the_root_are_rejected() {
+    use std::os::unix::fs::symlink;
+
+    let tmp = tempdir().unwrap();
+    let outside = tempdir().unwrap();
+    std::fs::write(outside.path().join("secret.txt"), b"secret").unwrap();
+    symlink(outside.path(), tmp.path().join("escape")).unwrap();
+    symlink(
+        outside.path().join("secret.txt"),
+        tmp.path().join("linked.txt"),
+    )
+    .unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+
+    for result in [
+        storage
+            .put("escape/new.txt", b"x")
+            .await
+            .map(|_| Vec::new()),
+        storage.get("escape/secret.txt").await,
+        storage.get("linked.txt").await,
+    ] {
+        match result {
+            Err(StorageError::InvalidKey { reason, .. }) => {
+                assert_eq!(reason, InvalidKeyReason::OutsideRoot)
+            }
+            other => panic!("symlinked key was not rejected: {other:?}"),
+        }
+    }
+    assert!(!outside.path().join("new.txt").exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn in_place_writes_do_not_follow_dangling_symlinks_out_of_the_root() {
+    use std::os::unix::fs::symlink;
+
+    let tmp = tempdir().unwrap();
+    let outside = tempdir().unwrap();
+    for name in ["appended.txt", "ranged.txt", "streamed.txt"] {
+        symlink(outside.path().join(name), tmp.path().join(name)).unwrap();
+    }
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+
+    let results = [
+        storage.append_record("appended.txt", b"x").await.map(drop),
+        storage.write_range("ranged.txt", 0, b"x").await,
+        storage
+            .append_reader("streamed.txt", &b"x"[..])
+            .await
+            .map(drop),
+    ];
+    for result in results {
+        match result {
+            Err(StorageError::InvalidKey { reason, .. }) => {
+                assert_eq!(reason, InvalidKeyReason::OutsideRoot)
+            }
+            other => panic!("dangling symlink was written through: {other:?}"),
+        }
+    }
+    assert_eq!(std::fs::read_dir(outside.path()).unwrap().count(), 0);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn symlinked_root_is_resolved_once() {
+    let tmp = tempdir().unwrap();
+    let real = tmp.path().join("real");
+    std::fs::create_dir(&real).unwrap();
+    std::os::unix::fs::symlink(&real, tmp.path().join("link")).unwrap();
+    let storage = FileStorage::new(tmp.path().join("link")).await.unwrap();
+
+    storage.put("nested/a.txt", b"data").await.unwrap();
+    assert_eq!(storage.get("nested/a.txt").await.unwrap(), b"data");
+    assert!(real.join("nested/a.txt").exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn symlink_policy_decides_how_linked_objects_read() {
+    let tmp = tempdir().unwrap();
+    std::fs::write(tmp.path().join("target.txt"), b"target content").unwrap();
+    std::os::unix::fs::symlink("target.txt", tmp.path().join("link.txt")).unwrap();
+    let open = |symlink_policy| {
+        let options = StorageOptions {
+            symlink_policy,
+            ..StorageOptions::default()
+        };
+        FileStorage::with_options(tmp.path(), options)
+    };
+
+    let storage = open(SymlinkPolicy::default()).await.unwrap();
+    for err in [
+        storage.get("link.txt").await.unwrap_err(),
+        storage.head("link.txt").await.unwrap_err(),
+    ] {
+        assert!(matches!(
+            err,
+            StorageError::InvalidKey {
+                reason: InvalidKeyReason::Symlink,
+                ..
+            }
+        ));
+    }
+    assert_eq!(storage.get("target.txt").await.unwrap(), b"target content");
+
+    let storage = open(SymlinkPolicy::Follow).await.unwrap();
+    assert_eq!(storage.get("link.txt").await.unwrap(), b"target content");
+    assert_eq!(storage.head("link.txt").await.unwrap().size, 14);
+
+    let storage = open(SymlinkPolicy::ReturnTarget).await.unwrap();
+    assert_eq!(storage.get("link.txt").await.unwrap(), b"target.txt");
+    assert_eq!(storage.get_range("link.txt", 7, 10).await.unwrap(), b"txt");
+    assert_eq!(storage.head("link.txt").await.unwrap().size, 10);
+}
+
+#[tokio::test]
+async fn timed_operations_report_bytes_and_elapsed() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    let payload = vec![7u8; 4096];
+
+    let OperationResult { bytes, elapsed } = storage.put_timed("a.bin", &payload).await.unwrap();
+    assert_eq!(bytes, 4096);
+    assert!(elapsed > Duration::ZERO);
+
+    let (data, result) = storage.get_timed("a.bin").await.unwrap();
+    assert_eq!(data, payload);
+    assert_eq!(result.bytes, 4096);
+    assert!(result.elapsed > Duration::ZERO);
+}
+
+#[tokio::test]
+async fn verify_all_hashes_every_object() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    let objects: Vec<(String, Vec<u8>)> = (0..20)
+        .map(|i| (format!("dir{}/obj{i:02}", i % 3), vec![i as u8; i * 1000]))
+        .collect();
+    for (key, data) in &objects {
+        storage.put(key, data).await.unwrap();
+    }
+
+    let mut expected: Vec<(String, String)> = objects
+        .iter()
+        .map(|(key, data)| (key.clone(), sha256_hex(data)))
+        .collect();
+    expected.sort();
+    assert_eq!(storage.verify_all().await.unwrap(), expected);
+}
+
+#[tokio::test]
+async fn dedup_report_groups_identical_objects() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    let shared = vec![7; 1000];
+    for key in ["b/copy", "a/copy", "c"] {
+        storage.put(key, &shared).await.unwrap();
+    }
+    storage.put("x/one", b"ab").await.unwrap();
+    storage.put("x/two", b"ab").await.unwrap();
+    storage.put("unique", b"only once").await.unwrap();
+
+    let report = storage.dedup_report().await.unwrap();
+    let expected = DedupReport {
+        total_bytes: 3000 + 4 + 9,
+        unique_bytes: 1000 + 2 + 9,
+        duplicates: vec![
+            DuplicateGroup {
+                sha256: sha256_hex(&shared),
+                size: 1000,
+                keys: vec!["a/copy".into(), "b/copy".into(), "c".into()],
+            },
+            DuplicateGroup {
+                sha256: sha256_hex(b"ab"),
+                size: 2,
+                keys: vec!["x/one".into(), "x/two".into()],
+            },
+        ],
+    };
+    assert_eq!(report, expected);
+    assert_eq!(report.saved_bytes(), 2002);
+    assert_eq!(storage.list("").await.unwrap().len(), 6);
+}
+
+#[tokio::test]
+async fn put_many_replaces_objects_and_their_attributes() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    let options = PutOptions {
+        content_type: Some("text/plain".to_string()),
+        ..PutOptions::default()
+    };
+    storage.put_with("a.txt", b"old", &options).await.unwrap();
+
+    storage
+        .put_many(&[("a.txt", b"new a"), ("nested/b.txt", b"new b")])
+        .await
+        .unwrap();
+    assert_eq!(storage.get("a.txt").await.unwrap(), b"new a");
+    assert_eq!(storage.get("nested/b.txt").await.unwrap(), b"new b");
+    assert_eq!(storage.content_type("a.txt").await.unwrap(), None);
+
+    let err = storage
+        .put_many(&[("c.txt", b"c"), ("../bad", b"x")])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, StorageError::InvalidKey { .. }));
+    assert!(!storage.exists("c.txt").await.unwrap());
+}
+
+#[tokio::test]
+async fn batches_give_the_same_results_at_any_concurrency() {
+    let data: Vec<Vec<u8>> = (0..50)
+        .map(|i| format!("object {i}").into_bytes())
+        .collect();
+    let keys: Vec<String> = (0..50).map(|i| format!("dir{}/obj{i}", i % 4)).collect();
+    let objects: Vec<(&str, &[u8])> = keys
+        .iter()
+        .zip(&data)
+        .map(|(key, data)| (key.as_str(), data.as_slice()))
+        .collect();
+
+    for concurrency in [1, 3, 64] {
+        let tmp = tempdir().unwrap();
+        let options = StorageOptions {
+            batch_concurrency: NonZeroUsize::new(concurrency),
+            ..StorageOptions::default()
+        };
+        let storage = FileStorage::with_options(tmp.path(), options)
+            .await
+            .unwrap();
+        storage.put_many(&objects).await.unwrap();
+
+        let mut wanted: Vec<&str> = keys.iter().map(String::as_str).collect();
+        wanted.push("missing");
+        let found = storage.get_many(&wanted).await.unwrap();
+        assert_eq!(found.len(), 51);
+        for (found, data) in found.iter().zip(&data) {
+            assert_eq!(found.as_deref(), Some(data.as_slice()));
+        }
+        assert_eq!(found[50], None);
+
+        assert_eq!(storage.delete_many(&wanted).await.unwrap(), 50);
+        assert!(storage.list("").await.unwrap().is_empty());
+
+        let err = storage
+            .get_many(&["dir0/obj0", "../bad"])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, StorageError::InvalidKey { .. }));
+    }
+}
+
+#[tokio::test]
+async fn copy_many_copies_objects_and_their_attributes() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    let sources: Vec<String> = (0..8).map(|n| format!("live/{n}.txt")).collect();
+    for (n, key) in sources.iter().enumerate() {
+        storage
+            .put(key, format!("object {n}").as_bytes())
+            .await
+            .unwrap();
+    }
+    let options = PutOptions {
+        content_type: Some("text/csv".to_string()),
+        ..PutOptions::default()
+    };
+    storage
+        .put_with("live/table.csv", b"a,b", &options)
+        .await
+        .unwrap();
+
+    let targets: Vec<String> = sources
+        .iter()
+        .map(|key| key.replace("live/", "snap/"))
+        .collect();
+    let mut pairs: Vec<(&str, &str)> = sources
+        .iter()
+        .zip(&targets)
+        .map(|(src, dst)| (src.as_str(), dst.as_str()))
+        .collect();
+    pairs.push(("live/table.csv", "snap/table.csv"));
+    storage.copy_many(&pairs).await.unwrap();
+
+    for (n, key) in targets.iter().enumerate() {
+        assert_eq!(
+            storage.get(key).await.unwrap(),
+            format!("object {n}").as_bytes()
+        );
+    }
+    assert_eq!(
+        storage
+            .content_type("snap/table.csv")
+            .await
+            .unwrap()
+            .as_deref(),
+        Some("text/csv")
+    );
+    // Copies are independent files, so appending to one leaves the other alone.
+    storage.append_record("live/0.txt", b"more").await.unwrap();
+    assert_eq!(storage.get("snap/0.txt").await.unwrap(), b"object 0");
+
+    let err = storage
+        .copy_many(&[
+            ("live/1.txt", "snap/again.txt"),
+            ("missing.txt", "snap/x.txt"),
+        ])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, StorageError::NotFound(key) if key == "missing.txt"));
+    let err = storage
+        .copy_many(&[("live/1.txt", "../escape")])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, StorageError::InvalidKey { .. }));
+}
+
+#[tokio::test]
+async fn head_many_reports_each_key_separately() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    storage.put("photos/a.jpg", b"aaaa").await.unwrap();
+    storage.put("photos/b.jpg", b"bb").await.unwrap();
+
+    let heads = storage
+        .head_many(&[
+            "photos/b.jpg",
+            "photos/missing.jpg",
+            "photos/a.jpg",
+            "../bad",
+        ])
+        .await
+        .unwrap();
+    let keys: Vec<&str> = heads.iter().map(|(key, _)| key.as_str()).collect();
+    assert_eq!(
+        keys,
+        [
+            "photos/b.jpg",
+            "photos/missing.jpg",
+            "photos/a.jpg",
+            "../bad"
+        ]
+    );
+    assert_eq!(
+        heads[0].1.as_ref().unwrap(),
+        &storage.head("photos/b.jpg").await.unwrap()
+    );
+    assert_eq!(heads[0].1.as_ref().unwrap().size, 2);
+    assert!(matches!(heads[1].1, Err(StorageError::NotFound(_))));
+    assert_eq!(heads[2].1.as_ref().unwrap().size, 4);
+    assert!(matches!(heads[3].1, Err(StorageError::InvalidKey { .. })));
+}
+
+#[tokio::test]
+async fn write_ahead_log_completes_interrupted_batches_on_open() {
+    let tmp = tempdir().unwrap();
+    let options = || StorageOptions {
+        write_ahead_log: true,
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options())
+        .await
+        .unwrap();
+    storage.put("src/a.txt", b"a").await.unwrap();
+    storage.put("src/z/c.txt", b"c").await.unwrap();
+    // An object where the batch needs a directory makes it fail after `a.txt`
+    // has already moved, which leaves the log as a crash would.
+    storage.put("dst/z", b"in the way").await.unwrap();
+
+    storage.rename_prefix("src/", "dst/").await.unwrap_err();
+    assert_eq!(
+        storage.list("").await.unwrap(),
+        ["dst/a.txt", "dst/z", "src/z/c.txt"]
+    );
+    storage.delete("dst/z").await.unwrap();
+    drop(storage);
+
+    let storage = FileStorage::with_options(tmp.path(), options())
+        .await
+        .unwrap();
+    assert_eq!(
+        storage.list("").await.unwrap(),
+        ["dst/a.txt", "dst/z/c.txt"]
+    );
+    assert_eq!(storage.get("dst/z/c.txt").await.unwrap(), b"c");
+    assert!(!tmp.path().join(".filestorage-wal").exists());
+}
+
+#[tokio::test]
+async fn write_ahead_log_drops_batches_torn_while_being_logged() {
+    let tmp = tempdir().unwrap();
+    let options = || StorageOptions {
+        write_ahead_log: true,
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options())
+        .await
+        .unwrap();
+    storage.put("src/a.txt", b"a").await.unwrap();
+    storage.put("src/z/c.txt", b"c").await.unwrap();
+    storage.put("dst/z", b"in the way").await.unwrap();
+    storage.rename_prefix("src/", "dst/").await.unwrap_err();
+    storage.delete("dst/z").await.unwrap();
+    drop(storage);
+
+    // Keep every step but cut the commit marker short, as a crash while the
+    // batch was still being logged would.
+    let wal = tmp.path().join(".filestorage-wal");
+    let logged = std::fs::read_to_string(&wal).unwrap();
+    let mut torn: String = logged
+        .lines()
+        .filter(|line| line.contains("\"move\""))
+        .map(|line| format!("{line}\n"))
+        .collect();
+    assert_eq!(torn.lines().count(), 2);
+    torn.push_str(r#"{"batch":1,"op":"com"#);
+    std::fs::write(&wal, torn).unwrap();
+
+    let storage = FileStorage::with_options(tmp.path(), options())
+        .await
+        .unwrap();
+    assert_eq!(
+        storage.list("").await.unwrap(),
+        ["dst/a.txt", "src/z/c.txt"]
+    );
+    assert!(!wal.exists());
+}
+
+#[tokio::test]
+async fn peek_reads_only_the_head_of_an_object() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    let large: Vec<u8> = (0..1_000_000u32).map(|i| i as u8).collect();
+    storage.put("large.bin", &large).await.unwrap();
+    storage.put("short.txt", b"abc").await.unwrap();
+
+    assert_eq!(storage.peek("large.bin", 16).await.unwrap(), &large[..16]);
+    assert_eq!(storage.peek("short.txt", 16).await.unwrap(), b"abc");
+    assert!(matches!(
+        storage.peek("missing", 16).await,
+        Err(StorageError::NotFound(_))
+    ));
+}
+
+#[tokio::test]
+async fn delete_older_than_removes_only_stale_objects() {
+    let tmp = tempdir().unwrap();
+    let replica_dir = tempdir().unwrap();
+    let options = StorageOptions {
+        replica_root: Some(replica_dir.path().to_path_buf()),
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+    for key in [
+        "logs/old.log",
+        "logs/2020/older.log",
+        "logs/new.log",
+        "other/old.log",
+    ] {
+        storage.put(key, b"line").await.unwrap();
+    }
+    let backdated = filetime::FileTime::from_system_time(
+        SystemTime::now() - Duration::from_secs(10 * 24 * 60 * 60),
+    );
+    for key in ["logs/old.log", "logs/2020/older.log", "other/old.log"] {
+        filetime::set_file_mtime(tmp.path().join(key), backdated).unwrap();
+    }
+
+    let week = Duration::from_secs(7 * 24 * 60 * 60);
+    assert_eq!(storage.delete_older_than("logs/", week).await.unwrap(), 2);
+    assert_eq!(
+        storage.list("").await.unwrap(),
+        ["logs/new.log", "other/old.log"]
+    );
+    assert!(!tmp.path().join("logs/2020").exists());
+    let replica = FileStorage::new(replica_dir.path()).await.unwrap();
+    assert_eq!(
+        replica.list("").await.unwrap(),
+        ["logs/new.log", "other/old.log"]
+    );
+}
+
+#[tokio::test]
+async fn walk_parallelism_does_not_change_results() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    for i in 0..40 {
+        let key = format!("d{}/e{}/f{}/obj{i}", i % 2, i % 3, i % 5);
+        storage.put(&key, key.as_bytes()).await.unwrap();
+    }
+
+    let mut results = Vec::new();
+    for parallelism in [1, 3, 16] {
+        let options = StorageOptions {
+            walk_parallelism: NonZeroUsize::new(parallelism),
+            ..StorageOptions::default()
+        };
+        let storage = FileStorage::with_options(tmp.path(), options)
+            .await
+            .unwrap();
+        let listed = storage.list("").await.unwrap();
+        assert_eq!(listed.len(), 40);
+        results.push((listed, storage.verify_all().await.unwrap()));
+    }
+    assert!(results.windows(2).all(|pair| pair[0] == pair[1]));
+}
+
+#[tokio::test]
+async fn get_range_matches_reference_slices() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    let data: Vec<u8> = (0..300_000u32).map(|i| (i * 31 % 251) as u8).collect();
+    storage.put("object", &data).await.unwrap();
+
+    let len = data.len() as u64;
+    for (offset, count) in [
+        (0, 1),
+        (0, 4096),
+        (65_535, 2),
+        (100_000, 150_000),
+        (len - 10, 10),
+        (len - 10, 100),
+        (len, 10),
+    ] {
+        let start = offset as usize;
+        let end = (offset + count).min(len) as usize;
+        assert_eq!(
+            storage.get_range("object", offset, count).await.unwrap(),
+            &data[start..end],
+            "{offset}+{count}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn prefix_quotas_limit_each_tenant_separately() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    storage.put("tenant-a/existing", b"1234").await.unwrap();
+    let options = StorageOptions {
+        prefix_quotas: HashMap::from([("tenant-a".to_string(), 10)]),
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+
+    storage.put("tenant-a/more", b"123456").await.unwrap();
+    let err = storage.put("tenant-a/extra", b"1").await.unwrap_err();
+    assert!(matches!(err, StorageError::QuotaExceeded(_)), "{err:?}");
+    assert!(!storage.exists("tenant-a/extra").await.unwrap());
+
+    // Overwrites are charged for the difference, and deletes free space.
+    storage.put("tenant-a/more", b"12").await.unwrap();
+    storage.put("tenant-a/extra", b"1234").await.unwrap();
+    storage.delete("tenant-a/existing").await.unwrap();
+    storage
+        .write_range("tenant-a/more", 0, b"123456")
+        .await
+        .unwrap();
+    let err = storage
+        .write_range("tenant-a/more", 6, b"1")
+        .await
+        .unwrap_err();
+    assert!(matches!(err, StorageError::QuotaExceeded(_)), "{err:?}");
+
+    // Other tenants, and keys outside any tenant, are unaffected.
+    storage.put("tenant-b/big", &[0; 100]).await.unwrap();
+    storage.put("top-level", &[0; 100]).await.unwrap();
+
+    // Namespaces share the quota, and bulk deletes are recounted.
+    let tenant = storage.namespace("tenant-a").unwrap();
+    let err = tenant.put("nested", b"1").await.unwrap_err();
+    assert!(matches!(err, StorageError::QuotaExceeded(_)), "{err:?}");
+    assert_eq!(storage.delete_prefix("tenant-a/").await.unwrap(), 2);
+    tenant.put("nested", &[0; 10]).await.unwrap();
+    let err = storage
+        .put_many(&[("tenant-b/x", b"1"), ("tenant-a/y", b"1")])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, StorageError::QuotaExceeded(_)), "{err:?}");
+    assert!(!storage.exists("tenant-b/x").await.unwrap());
+}
+
+#[tokio::test]
+async fn retain_removes_only_unkept_keys() {
+    let tmp = tempdir().unwrap();
+    let replica_dir = tempdir().unwrap();
+    let options = StorageOptions {
+        replica_root: Some(replica_dir.path().to_path_buf()),
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+    for key in [
+        "keep/a",
+        "keep/b",
+        "drop/a",
+        "drop/deep/b",
+        "mixed/keep",
+        "mixed/drop",
+    ] {
+        storage.put(key, b"data").await.unwrap();
+    }
+
+    let removed = storage
+        .retain(|key| key.starts_with("keep/") || key.ends_with("/keep"))
+        .await
+        .unwrap();
+    assert_eq!(removed, 3);
+    assert_eq!(
+        storage.list("").await.unwrap(),
+        ["keep/a", "keep/b", "mixed/keep"]
+    );
+    assert!(!tmp.path().join("drop").exists());
+    let replica = FileStorage::new(replica_dir.path()).await.unwrap();
+    assert_eq!(
+        replica.list("").await.unwrap(),
+        ["keep/a", "keep/b", "mixed/keep"]
+    );
+}
+
+#[tokio::test]
+async fn key_index_tracks_writes_and_is_reconciled_on_open() {
+    let tmp = tempdir().unwrap();
+    let open = || {
+        let options = StorageOptions {
+            key_index: true,
+            ..StorageOptions::default()
+        };
+        FileStorage::with_options(tmp.path(), options)
+    };
+    let storage = open().await.unwrap();
+    assert_eq!(storage.key_index_drift(), Some(0));
+    storage.put("a", b"1").await.unwrap();
+    storage.put("dir/b", b"2").await.unwrap();
+    storage.put("dir/c", b"3").await.unwrap();
+    storage.delete("dir/c").await.unwrap();
+    assert!(storage.exists("dir/b").await.unwrap());
+    assert!(!storage.exists("dir/c").await.unwrap());
+    assert!(matches!(
+        storage.head("dir/c").await,
+        Err(StorageError::NotFound(_))
+    ));
+    storage.flush().await.unwrap();
+    drop(storage);
+
+    let storage = open().await.unwrap();
+    assert_eq!(storage.key_index_drift(), Some(0));
+    assert!(storage.exists("a").await.unwrap());
+    drop(storage);
+
+    // Changes made behind the store's back are picked up on the next open.
+    std::fs::remove_file(tmp.path().join("a")).unwrap();
+    std::fs::write(tmp.path().join("dir/d"), b"4").unwrap();
+    let storage = open().await.unwrap();
+    assert_eq!(storage.key_index_drift(), Some(2));
+    assert!(!storage.exists("a").await.unwrap());
+    assert!(storage.exists("dir/d").await.unwrap());
+    assert_eq!(storage.list("").await.unwrap(), ["dir/b", "dir/d"]);
+}
+
+#[tokio::test]
+async fn refresh_picks_up_changes_made_behind_the_stores_back() {
+    let tmp = tempdir().unwrap();
+    let options = StorageOptions {
+        existence_index: true,
+        listing_cache: Some(Duration::from_secs(3600)),
+        key_index: true,
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+    storage.put("a", b"1").await.unwrap();
+    storage.put("dir/b", b"2").await.unwrap();
+
+    std::fs::remove_file(tmp.path().join("a")).unwrap();
+    std::fs::write(tmp.path().join("dir/c"), b"3").unwrap();
+    assert!(!storage.exists("dir/c").await.unwrap());
+    assert_eq!(storage.list("").await.unwrap(), ["a", "dir/b"]);
+
+    storage.namespace("dir").unwrap().refresh().await.unwrap();
+    assert!(storage.exists("dir/c").await.unwrap());
+    assert_eq!(storage.get("dir/c").await.unwrap(), b"3");
+    assert!(!storage.exists("a").await.unwrap());
+    assert_eq!(storage.list("").await.unwrap(), ["dir/b", "dir/c"]);
+
+    let plain = FileStorage::new(tmp.path()).await.unwrap();
+    plain.refresh().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn refresh_keeps_writes_made_while_it_scans() {
+    let tmp = tempdir().unwrap();
+    let options = StorageOptions {
+        listing_cache: Some(Duration::from_secs(3600)),
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+    let refresher = storage.clone();
+    let refreshing = tokio::spawn(async move {
+        // Runs until aborted, which can fail a scan cut short at shutdown.
+        loop {
+            let _ = refresher.refresh().await;
+        }
+    });
+    for i in 0..300 {
+        let key = format!("k{i:03}");
+        storage.put(&key, b"x").await.unwrap();
+        let listed = storage.list("").await.unwrap();
+        assert!(listed.contains(&key), "{key} missing after its put");
+        if i > 0 {
+            let previous = format!("k{:03}", i - 1);
+            storage.delete(&previous).await.unwrap();
+            let listed = storage.list("").await.unwrap();
+            assert!(
+                !listed.contains(&previous),
+                "{previous} listed after its delete"
+            );
+        }
+    }
+    refreshing.abort();
+}
+
+#[cfg(feature = "json")]
+#[tokio::test]
+async fn json_objects_round_trip() {
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Settings {
+        name: String,
+        retries: u32,
+        tags: Vec<String>,
+    }
+
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    let settings = Settings {
+        name: "primary".to_string(),
+        retries: 3,
+        tags: vec!["a".to_string(), "b".to_string()],
+    };
+    storage.put_json("settings.json", &settings).await.unwrap();
+    assert_eq!(
+        storage.get_json::<Settings>("settings.json").await.unwrap(),
+        settings
+    );
+
+    storage.put("broken.json", b"{\"name\": 7}").await.unwrap();
+    let err = storage
+        .get_json::<Settings>("broken.json")
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&err, StorageError::Serialization { key, .. } if key == "broken.json"),
+        "{err:?}"
+    );
+}
+
+#[cfg(feature = "json")]
+#[tokio::test]
+async fn update_json_applies_concurrent_updates_one_at_a_time() {
+    let tmp = tempdir().unwrap();
+    let storage = Arc::new(FileStorage::new(tmp.path()).await.unwrap());
+
+    let updates: Vec<_> = (0..50)
+        .map(|_| {
+            let storage = storage.clone();
+            tokio::spawn(async move {
+                storage
+                    .update_json("counters/hits.json", 0u64, |hits| *hits += 1)
+                    .await
+                    .unwrap()
+            })
+        })
+        .collect();
+    let mut returned = Vec::new();
+    for update in updates {
+        returned.push(update.await.unwrap());
+    }
+    returned.sort_unstable();
+    assert_eq!(returned, (1..=50).collect::<Vec<u64>>());
+    assert_eq!(
+        storage.get_json::<u64>("counters/hits.json").await.unwrap(),
+        50
+    );
+
+    let options = PutOptions {
+        content_type: Some("application/json".to_string()),
+        ..PutOptions::default()
+    };
+    storage
+        .put_with("config.json", br#"{"replicas":1}"#, &options)
+        .await
+        .unwrap();
+    let merged = storage
+        .update_json("config.json", HashMap::new(), |config| {
+            config.insert("zone".to_string(), serde_json::json!("eu"));
+        })
+        .await
+        .unwrap();
+    assert_eq!(merged["replicas"], 1);
+    assert_eq!(merged["zone"], "eu");
+    assert_eq!(
+        storage
+            .content_type("config.json")
+            .await
+            .unwrap()
+            .as_deref(),
+        Some("application/json")
+    );
+
+    storage.put("broken.json", b"not json").await.unwrap();
+    let err = storage
+        .update_json("broken.json", 0u64, |n| *n += 1)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, StorageError::Serialization { .. }));
+    assert_eq!(storage.get("broken.json").await.unwrap(), b"not json");
+}
+
+#[tokio::test]
+async fn export_tar_archives_the_objects_under_a_prefix() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    let long_key = format!("docs/{}/deep.txt", "nested".repeat(20));
+    storage.put("docs/a.txt", b"alpha").await.unwrap();
+    storage.put(&long_key, &[7; 1500]).await.unwrap();
+    storage.put("other.txt", b"skipped").await.unwrap();
+
+    let mut archive = Vec::new();
+    let exported = storage.export_tar("docs/", &mut archive).await.unwrap();
+    assert_eq!(exported, 2);
+    assert_eq!(archive.len() % 512, 0);
+
+    let mut entries = Vec::new();
+    for entry in tar::Archive::new(archive.as_slice()).entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let name = entry.path().unwrap().to_str().unwrap().to_string();
+        let mut contents = Vec::new();
+        std::io::Read::read_to_end(&mut entry, &mut contents).unwrap();
+        entries.push((name, contents));
+    }
+    assert_eq!(
+        entries,
+        [
+            ("docs/a.txt".to_string(), b"alpha".to_vec()),
+            (long_key, vec![7; 1500]),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn import_tar_restores_an_export() {
+    let source_dir = tempdir().unwrap();
+    let source = FileStorage::new(source_dir.path()).await.unwrap();
+    let long_key = format!("docs/{}/deep.txt", "nested".repeat(20));
+    source.put("docs/a.txt", b"alpha").await.unwrap();
+    source.put(&long_key, &[7; 1500]).await.unwrap();
+    let mut archive = Vec::new();
+    source.export_tar("", &mut archive).await.unwrap();
+
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    storage.put("docs/a.txt", b"kept").await.unwrap();
+    let report = storage.import_tar(archive.as_slice(), false).await.unwrap();
+    assert_eq!(
+        report,
+        ImportReport {
+            imported: vec![long_key.clone()],
+            skipped: 1
+        }
+    );
+    assert_eq!(storage.get("docs/a.txt").await.unwrap(), b"kept");
+    assert_eq!(storage.get(&long_key).await.unwrap(), vec![7; 1500]);
+
+    let report = storage.import_tar(archive.as_slice(), true).await.unwrap();
+    assert_eq!(
+        report.imported,
+        ["docs/a.txt".to_string(), long_key.clone()]
+    );
+    assert_eq!(storage.get("docs/a.txt").await.unwrap(), b"alpha");
+
+    let truncated = &archive[..archive.len() - 2048];
+    let err = storage.import_tar(truncated, true).await.unwrap_err();
+    assert!(matches!(err, StorageError::InvalidArchive(_)), "{err:?}");
+}
+
+#[tokio::test]
+async fn import_tar_skips_existing_keys_but_not_colliding_ones() {
+    let source_dir = tempdir().unwrap();
+    let source = FileStorage::new(source_dir.path()).await.unwrap();
+    source.put("docs/a.txt", b"alpha").await.unwrap();
+    let mut archive = Vec::new();
+    source.export_tar("", &mut archive).await.unwrap();
+
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    storage
+        .put("docs/a.txt/inner", b"in the way")
+        .await
+        .unwrap();
+    let err = storage
+        .import_tar(archive.as_slice(), false)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, StorageError::Conflict(_)), "{err:?}");
+    assert_eq!(storage.list("").await.unwrap(), ["docs/a.txt/inner"]);
+}
+
+#[tokio::test]
+async fn import_tar_rejects_unsafe_entry_paths() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path().join("root")).await.unwrap();
+    let mut header = tar::Header::new_gnu();
+    header.as_old_mut().name[..10].copy_from_slice(b"../outside");
+    header.set_size(4);
+    header.set_cksum();
+    let mut archive = tar::Builder::new(Vec::new());
+    archive.append(&header, &b"evil"[..]).unwrap();
+    let archive = archive.into_inner().unwrap();
+
+    let err = storage
+        .import_tar(archive.as_slice(), true)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, StorageError::InvalidKey { .. }), "{err:?}");
+    assert!(!tmp.path().join("outside").exists());
+}
+
+#[tokio::test]
+async fn soft_deletes_go_to_a_shared_trash() {
+    let tmp = tempdir().unwrap();
+    let storage = FileStorage::new(tmp.path()).await.unwrap();
+    let tenant = storage.namespace("tenant").unwrap();
+    storage.put("top.txt", b"top").await.unwrap();
+    tenant.put("nested/a.txt", b"first").await.unwrap();
+
+    storage.soft_delete("top.txt").await.unwrap();
+    tenant.soft_delete("nested/a.txt").await.unwrap();
+    assert!(storage.list("").await.unwrap().is_empty());
+    assert!(matches!(
+        storage.soft_delete("top.txt").await,
+        Err(StorageError::NotFound(_))
+    ));
+
+    // Restoring brings back the latest copy, but never over a newer object.
+    tenant.put("nested/a.txt", b"second").await.unwrap();
+    tenant.soft_delete("nested/a.txt").await.unwrap();
+    storage.put("tenant/nested/a.txt", b"third").await.unwrap();
+    assert!(matches!(
+        storage.restore_from_trash("tenant/nested/a.txt").await,
+        Err(StorageError::Conflict(_))
+    ));
+    storage.delete("tenant/nested/a.txt").await.unwrap();
+    storage
+        .restore_from_trash("tenant/nested/a.txt")
+        .await
+        .unwrap();
+    assert_eq!(tenant.get("nested/a.txt").await.unwrap(), b"second");
+
+    // A namespace only purges its own part of the trash.
+    assert_eq!(tenant.purge_trash(Duration::ZERO).await.unwrap(), 1);
+    assert_eq!(
+        storage
+            .purge_trash(Duration::from_secs(3600))
+            .await
+            .unwrap(),
+        0
+    );
+    storage.restore_from_trash("top.txt").await.unwrap();
+    assert_eq!(storage.get("top.txt").await.unwrap(), b"top");
+    assert!(matches!(
+        tenant.restore_from_trash("nested/a.txt").await,
+        Err(StorageError::NotFound(_))
+    ));
+    assert_eq!(storage.purge_trash(Duration::ZERO).await.unwrap(), 0);
+    let trash = tmp.path().join(".filestorage-trash");
+    assert_eq!(std::fs::read_dir(trash).unwrap().count(), 0);
+}
+
+#[tokio::test]
+async fn compression_round_trips_compressible_and_incompressible_objects() {
+    let tmp = tempdir().unwrap();
+    let options = StorageOptions {
+        compression: true,
+        ..StorageOptions::default()
+    };
+    let storage = FileStorage::with_options(tmp.path(), options)
+        .await
+        .unwrap();
+    let text = "the quick brown fox jumps over the lazy dog\n".repeat(5_000);
+    // Xorshift output, which deflate cannot shrink.
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let noise: Vec<u8> = (0..200_000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    storage.put("text.txt", text.as_bytes()).await.unwrap();
+    storage.put("noise.bin", &noise).await.unwrap();
+
+    let on_disk = |key: &str| std::fs::metadata(tmp.path().join(key)).unwrap().len();
+    assert!(on_disk("text.txt") < text.len() as u64 / 10);
+    // Stored as given, behind a short header.
+    assert!(on_disk("noise.bin") > noise.len() as u64);
+    assert!(on_disk("noise.bin") < noise.len() as u64 + 64);
+
+    for (key, content) in [("text.txt", text.as_bytes()), ("noise.bin", &noise[..])] {
+        assert_eq!(storage.get(key).await.unwrap(), content);
+        assert_eq!(storage.head(key).await.unwrap().size, content.len() as u64);
+        assert_eq!(
+            storage.get_range(key, 1_000, 50).await.unwrap(),
+            &content[1_000..1_050]
+        );
+        let (mut reader, len) = storage.read_range(key, 10, u64::MAX).await.unwrap();
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).await.unwrap();
+        assert_eq!(len, content.len() as u64 - 10);
+        assert_eq!(read, &content[10..]);
+    }
+
+    // Appending stores the object uncompressed before writing to it.
+    storage.append_record("text.txt", b"tail").await.unwrap();
+    let mut appended = text.clone().into_bytes();
+    appended.extend_from_slice(b"tail\n");
+    assert_eq!(storage.get("text.txt").await.unwrap(), appended);
+    assert_eq!(on_disk("text.txt"), appended.len() as u64);
+
+    // Without the option, the stored bytes are read as they are.
+    let plain = FileStorage::new(tmp.path()).await.unwrap();
+    assert_ne!(plain.get("noise.bin").await.unwrap(), noise);
 }
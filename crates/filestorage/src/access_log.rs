@@ -0,0 +1,198 @@
+//! Request log appended to a file by a background task, rotated by size.
+
+use std::{
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use tokio::{
+    fs::{self, File},
+    io::AsyncWriteExt,
+    sync::mpsc,
+};
+
+/// Lines waiting for the writer before new ones are dropped.
+const QUEUE_LEN: usize = 1024;
+
+/// Rotated files kept next to the live one, named `<path>.1` (newest) to `<path>.<n>`.
+const ROTATED_FILES: usize = 2;
+
+/// Longest tenant recorded; longer prefixes are cut so a client cannot bloat
+/// every line.
+const MAX_TENANT_LEN: usize = 64;
+
+/// Returns the tenant an object request belongs to: the first segment of a
+/// nested key under `/objects/`, or `-` for anything else.
+///
+/// The node emits no tracing spans or metrics, so this tag on access log
+/// lines is the only per-tenant view of requests; slice latency from there.
+pub fn tenant_of(path: &str) -> &str {
+    let tenant = path
+        .strip_prefix("/objects/")
+        .and_then(|key| key.split_once('/'))
+        .map(|(tenant, _)| tenant)
+        .filter(|tenant| !tenant.is_empty());
+    match tenant {
+        Some(tenant) => match tenant.char_indices().nth(MAX_TENANT_LEN) {
+            Some((end, _)) => &tenant[..end],
+            None => tenant,
+        },
+        None => "-",
+    }
+}
+
+/// Handle for queueing lines to the writer task; cheap to share between requests.
+#[derive(Debug)]
+pub struct AccessLog {
+    lines: mpsc::Sender<String>,
+}
+
+impl AccessLog {
+    /// Starts a task appending lines to `path`, which is moved to `<path>.1`
+    /// once the next line would take it past `max_bytes`.
+    pub fn start(path: PathBuf, max_bytes: u64) -> Self {
+        let (lines, mut queued) = mpsc::channel::<String>(QUEUE_LEN);
+        tokio::spawn(async move {
+            let mut writer = Writer {
+                path,
+                max_bytes,
+                file: None,
+                len: 0,
+            };
+            while let Some(line) = queued.recv().await {
+                let result = writer.write(&line).await;
+                // Flushing once the queue drains lets bursts share a write.
+                let result = match result {
+                    Ok(()) if queued.is_empty() => writer.flush().await,
+                    result => result,
+                };
+                if let Err(err) = result {
+                    eprintln!("access log `{}` failed: {err}", writer.path.display());
+                    writer.file = None;
+                }
+            }
+        });
+        Self { lines }
+    }
+
+    /// Queues `line` without waiting; it is dropped if the writer has
+    /// fallen `QUEUE_LEN` lines behind, so a slow disk never holds up requests.
+    pub fn record(&self, line: String) {
+        let _ = self.lines.try_send(line);
+    }
+}
+
+struct Writer {
+    path: PathBuf,
+    max_bytes: u64,
+    file: Option<File>,
+    /// Bytes in the live file.
+    len: u64,
+}
+
+impl Writer {
+    async fn write(&mut self, line: &str) -> io::Result<()> {
+        let added = line.len() as u64 + 1;
+        if self.file.is_none() {
+            self.open().await?;
+        }
+        if self.len > 0 && self.len + added > self.max_bytes {
+            self.rotate().await?;
+        }
+        let file = self.file.as_mut().expect("access log was just opened");
+        file.write_all(format!("{line}\n").as_bytes()).await?;
+        self.len += added;
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush().await,
+            None => Ok(()),
+        }
+    }
+
+    async fn open(&mut self) -> io::Result<()> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        self.len = file.metadata().await?.len();
+        self.file = Some(file);
+        Ok(())
+    }
+
+    /// Shifts each rotated file up by one, dropping the oldest, and starts a
+    /// new live file.
+    async fn rotate(&mut self) -> io::Result<()> {
+        self.flush().await?;
+        self.file = None;
+        for n in (1..ROTATED_FILES).rev() {
+            rename_if_present(&rotated(&self.path, n), &rotated(&self.path, n + 1)).await?;
+        }
+        rename_if_present(&self.path, &rotated(&self.path, 1)).await?;
+        self.open().await
+    }
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+async fn rename_if_present(src: &Path, dst: &Path) -> io::Result<()> {
+    match fs::rename(src, dst).await {
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Waits for the writer task until the file at `path` satisfies `done`,
+/// returning its contents.
+#[cfg(test)]
+pub async fn read_when(path: &Path, done: impl Fn(&str) -> bool) -> String {
+    for _ in 0..200 {
+        if let Ok(text) = fs::read_to_string(path).await
+            && done(&text)
+        {
+            return text;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    panic!("`{}` never reached the expected content", path.display());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tenant_is_the_first_segment_of_a_nested_key() {
+        assert_eq!(tenant_of("/objects/acme/reports/q1.csv"), "acme");
+        assert_eq!(tenant_of("/objects/flat.txt"), "-");
+        assert_eq!(tenant_of("/objects//odd"), "-");
+        assert_eq!(tenant_of("/info"), "-");
+        let long = format!("/objects/{}/a", "t".repeat(100));
+        assert_eq!(tenant_of(&long).len(), MAX_TENANT_LEN);
+    }
+
+    #[tokio::test]
+    async fn full_files_are_rotated_keeping_the_newest() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("access.log");
+        let log = AccessLog::start(path.clone(), 20);
+        for n in 0..4 {
+            log.record(format!("request {n:02}"));
+        }
+
+        // Each 11-byte line fills a file, so every line after the first rotates.
+        read_when(&path, |text| text == "request 03\n").await;
+        let newest = fs::read_to_string(rotated(&path, 1)).await.unwrap();
+        assert_eq!(newest, "request 02\n");
+        let oldest = fs::read_to_string(rotated(&path, 2)).await.unwrap();
+        assert_eq!(oldest, "request 01\n");
+        assert!(!rotated(&path, 3).exists());
+    }
+}
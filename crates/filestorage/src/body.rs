@@ -0,0 +1,76 @@
+//! Response bodies streamed from stored objects.
+
+use async_compression::tokio::bufread::GzipEncoder;
+use axum::{
+    body::Body,
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use filestorage_core::ObjectReader;
+use tokio::io::BufReader;
+use tokio_util::io::ReaderStream;
+
+/// Object content streamed into a response without buffering it in memory.
+///
+/// Sets `Content-Length`, or `Content-Encoding` when compressed, and for
+/// partial content `Content-Range` with a `206` status; the handler adds any
+/// other headers.
+pub struct ObjectBody {
+    reader: ObjectReader,
+    len: u64,
+    content_range: Option<HeaderValue>,
+    gzip: bool,
+}
+
+impl ObjectBody {
+    /// A whole object of `len` bytes.
+    pub fn new(reader: ObjectReader, len: u64) -> Self {
+        Self {
+            reader,
+            len,
+            content_range: None,
+            gzip: false,
+        }
+    }
+
+    /// Part of an object, described by a `Content-Range` value such as
+    /// `bytes 0-99/1000`.
+    pub fn partial(reader: ObjectReader, len: u64, content_range: HeaderValue) -> Self {
+        Self {
+            reader,
+            len,
+            content_range: Some(content_range),
+            gzip: false,
+        }
+    }
+
+    /// Compresses a whole object with gzip as it streams, leaving the
+    /// response length unknown.
+    pub fn gzip(self) -> Self {
+        Self { gzip: true, ..self }
+    }
+}
+
+impl IntoResponse for ObjectBody {
+    fn into_response(self) -> Response {
+        if self.gzip {
+            let encoder = GzipEncoder::new(BufReader::new(self.reader));
+            let mut response = Response::new(Body::from_stream(ReaderStream::new(encoder)));
+            response
+                .headers_mut()
+                .insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+            return response;
+        }
+        let mut response = Response::new(Body::from_stream(ReaderStream::new(self.reader)));
+        response
+            .headers_mut()
+            .insert(header::CONTENT_LENGTH, HeaderValue::from(self.len));
+        if let Some(content_range) = self.content_range {
+            *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+            response
+                .headers_mut()
+                .insert(header::CONTENT_RANGE, content_range);
+        }
+        response
+    }
+}
@@ -0,0 +1,246 @@
+//! Verification of upload bodies against `Content-MD5` and `Digest` headers,
+//! and digests of stored objects computed on request.
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+/// RFC 1864 header carrying the base64 MD5 of the body.
+pub const CONTENT_MD5: &str = "content-md5";
+
+/// RFC 3230 instance digest header, e.g. `sha-256=<base64>`.
+pub const DIGEST: &str = "digest";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Algorithm {
+    Md5,
+    Sha256,
+}
+
+impl Algorithm {
+    fn hasher(self) -> Hasher {
+        match self {
+            Algorithm::Md5 => Hasher::Md5(Md5::new()),
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+}
+
+enum Hasher {
+    Md5(Md5),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    fn update(&mut self, chunk: &[u8]) {
+        match self {
+            Hasher::Md5(hasher) => hasher.update(chunk),
+            Hasher::Sha256(hasher) => hasher.update(chunk),
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Md5(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+        }
+    }
+}
+
+/// A digest the client expects the body to have, and the header it came from.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Expected {
+    algorithm: Algorithm,
+    header: &'static str,
+    digest: Vec<u8>,
+}
+
+/// Collects the digests a request asks to be checked.
+///
+/// `Digest` entries using algorithms other than SHA-256 and MD5 are ignored,
+/// as RFC 3230 allows. Malformed values are errors.
+pub fn expected(headers: &HeaderMap) -> Result<Vec<Expected>, String> {
+    let mut expected = Vec::new();
+    if let Some(value) = headers.get(CONTENT_MD5) {
+        expected.push(Expected {
+            algorithm: Algorithm::Md5,
+            header: CONTENT_MD5,
+            digest: decode(CONTENT_MD5, value.to_str().ok())?,
+        });
+    }
+    if let Some(value) = headers.get(DIGEST) {
+        let value = value
+            .to_str()
+            .map_err(|_| format!("header `{DIGEST}` must be visible ASCII"))?;
+        for entry in value.split(',') {
+            let Some((name, encoded)) = entry.trim().split_once('=') else {
+                return Err(format!(
+                    "header `{DIGEST}` must look like `sha-256=<base64>`"
+                ));
+            };
+            let algorithm = match name.to_ascii_lowercase().as_str() {
+                "sha-256" => Algorithm::Sha256,
+                "md5" => Algorithm::Md5,
+                _ => continue,
+            };
+            expected.push(Expected {
+                algorithm,
+                header: DIGEST,
+                digest: decode(DIGEST, Some(encoded))?,
+            });
+        }
+    }
+    Ok(expected)
+}
+
+fn decode(header: &str, value: Option<&str>) -> Result<Vec<u8>, String> {
+    value
+        .and_then(|value| STANDARD.decode(value.trim()).ok())
+        .ok_or_else(|| format!("header `{header}` must hold a base64 digest"))
+}
+
+/// Hashes a body chunk by chunk, as it arrives, for each digest a request expects.
+pub struct Verifier {
+    checks: Vec<(Expected, Hasher)>,
+}
+
+impl Verifier {
+    pub fn new(expected: Vec<Expected>) -> Self {
+        let checks = expected
+            .into_iter()
+            .map(|check| {
+                let hasher = check.algorithm.hasher();
+                (check, hasher)
+            })
+            .collect();
+        Self { checks }
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        for (_, hasher) in &mut self.checks {
+            hasher.update(chunk);
+        }
+    }
+
+    /// Checks the body hashed so far against every expected digest and
+    /// returns the headers echoing the computed digests back to the client.
+    pub fn finish(self) -> Result<Vec<(HeaderName, HeaderValue)>, String> {
+        let mut echoed = Vec::with_capacity(self.checks.len());
+        for (check, hasher) in self.checks {
+            let computed = hasher.finalize();
+            if computed != check.digest {
+                return Err(format!(
+                    "body does not match the digest in header `{}`",
+                    check.header
+                ));
+            }
+            let encoded = STANDARD.encode(&computed);
+            let value = match check.header {
+                DIGEST => match check.algorithm {
+                    Algorithm::Sha256 => format!("sha-256={encoded}"),
+                    Algorithm::Md5 => format!("md5={encoded}"),
+                },
+                _ => encoded,
+            };
+            echoed.push((
+                HeaderName::from_static(check.header),
+                HeaderValue::from_str(&value).expect("digest header"),
+            ));
+        }
+        Ok(echoed)
+    }
+}
+
+/// Hasher behind `GET /objects/{key}:digest`, chosen by its `algo` name.
+pub enum ObjectDigest {
+    Sha256(Sha256),
+    Crc32(crc32fast::Hasher),
+}
+
+impl ObjectDigest {
+    /// Names accepted by [`new`](Self::new), for error messages.
+    pub const SUPPORTED: &'static str = "sha256, crc32";
+
+    /// Returns a fresh hasher for `name`, or `None` if it is not supported.
+    pub fn new(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha256" => Some(Self::Sha256(Sha256::new())),
+            "crc32" => Some(Self::Crc32(crc32fast::Hasher::new())),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Sha256(_) => "sha256",
+            Self::Crc32(_) => "crc32",
+        }
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        match self {
+            Self::Sha256(hasher) => hasher.update(chunk),
+            Self::Crc32(hasher) => hasher.update(chunk),
+        }
+    }
+
+    /// Returns the digest as lowercase hex; CRC-32 is written big-endian.
+    pub fn finalize_hex(self) -> String {
+        match self {
+            Self::Sha256(hasher) => hex::encode(hasher.finalize()),
+            Self::Crc32(hasher) => format!("{:08x}", hasher.finalize()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn verifies_md5_and_sha256_digests() {
+        let checks = expected(&headers(&[
+            (CONTENT_MD5, "XUFAKrxLKna5cZ2REBfFkg=="),
+            (
+                DIGEST,
+                "unixsum=30, SHA-256=LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=",
+            ),
+        ]))
+        .unwrap();
+        assert_eq!(checks.len(), 2);
+        let mut verifier = Verifier::new(checks);
+        verifier.update(b"hel");
+        verifier.update(b"lo");
+        let echoed = verifier.finish().unwrap();
+        assert_eq!(echoed[0].1, "XUFAKrxLKna5cZ2REBfFkg==");
+        assert_eq!(
+            echoed[1].1,
+            "sha-256=LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ="
+        );
+
+        let checks = expected(&headers(&[(CONTENT_MD5, "XUFAKrxLKna5cZ2REBfFkg==")])).unwrap();
+        let mut verifier = Verifier::new(checks);
+        verifier.update(b"hellO");
+        assert!(verifier.finish().is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_digests() {
+        assert!(expected(&headers(&[(CONTENT_MD5, "not base64!")])).is_err());
+        assert!(expected(&headers(&[(DIGEST, "sha-256")])).is_err());
+        assert!(expected(&HeaderMap::new()).unwrap().is_empty());
+    }
+}
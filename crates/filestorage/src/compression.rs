@@ -0,0 +1,75 @@
+//! Negotiation of gzip-compressed `GET` responses and the entity tags that
+//! tell them apart from the stored bytes.
+
+use axum::http::{HeaderMap, header};
+
+/// Returns whether the request's `Accept-Encoding` allows a gzip response.
+///
+/// `gzip` and `*` are accepted unless given a zero quality, as in `gzip;q=0`.
+pub fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let refused = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+        })
+}
+
+/// Returns the weak entity tag of the gzip encoding of an object tagged
+/// `etag`.
+///
+/// The compressed bytes differ from the stored ones, so the tag is weak, and
+/// its opaque part differs too so that even a weak comparison never mistakes
+/// one encoding for the other.
+pub fn gzip_etag(etag: &str) -> String {
+    let opaque = etag.trim_matches('"');
+    format!("W/\"{opaque}-gzip\"")
+}
+
+/// Returns whether an `If-None-Match` value lists `etag`, using the weak
+/// comparison the header calls for.
+pub fn none_match_lists(value: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    value.trim() == "*" || value.split(',').any(|tag| opaque(tag) == opaque(etag))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn accept(value: &'static str) -> HeaderMap {
+        HeaderMap::from_iter([(header::ACCEPT_ENCODING, HeaderValue::from_static(value))])
+    }
+
+    #[test]
+    fn gzip_is_accepted_unless_refused() {
+        assert!(accepts_gzip(&accept("gzip")));
+        assert!(accepts_gzip(&accept("br, GZIP;q=0.5")));
+        assert!(accepts_gzip(&accept("*")));
+        assert!(!accepts_gzip(&accept("gzip;q=0")));
+        assert!(!accepts_gzip(&accept("identity, br")));
+        assert!(!accepts_gzip(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn gzip_tags_are_weak_and_distinct() {
+        let etag = "\"5f3a-10\"";
+        assert_eq!(gzip_etag(etag), "W/\"5f3a-10-gzip\"");
+        assert!(none_match_lists("\"other\", W/\"5f3a-10\"", etag));
+        assert!(none_match_lists(&gzip_etag(etag), &gzip_etag(etag)));
+        assert!(!none_match_lists(etag, &gzip_etag(etag)));
+        assert!(none_match_lists("*", etag));
+    }
+}
@@ -0,0 +1,161 @@
+//! Change events for objects written or deleted through the HTTP API, kept
+//! in a bounded buffer so subscribers can resume where they left off.
+
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, MutexGuard},
+};
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// What happened to an object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// The object was created or its content changed.
+    Put,
+    /// The object was deleted or moved to the trash.
+    Delete,
+}
+
+impl EventKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            EventKind::Put => "put",
+            EventKind::Delete => "delete",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct StorageEvent {
+    /// Position in the node's event sequence, starting at 1.
+    pub seq: u64,
+    pub kind: EventKind,
+    pub key: String,
+}
+
+/// Recent events, numbered in the order they were recorded.
+#[derive(Debug)]
+pub struct EventLog {
+    state: Mutex<State>,
+    /// Publishes events to live subscribers; sent while `state` is locked so
+    /// a subscriber never sees an event both replayed and live.
+    live: broadcast::Sender<StorageEvent>,
+}
+
+#[derive(Debug)]
+struct State {
+    next_seq: u64,
+    recent: VecDeque<StorageEvent>,
+    capacity: usize,
+}
+
+/// Events a subscriber has to catch up on, followed by the live feed.
+pub struct Subscription {
+    /// Sequence numbers, inclusive, that fell out of the buffer before the
+    /// subscriber could see them.
+    pub gap: Option<(u64, u64)>,
+    pub replay: Vec<StorageEvent>,
+    /// Sequence number of the last event recorded before subscribing; the
+    /// live feed starts right after it.
+    pub latest: u64,
+    pub live: broadcast::Receiver<StorageEvent>,
+}
+
+impl EventLog {
+    /// Keeps the last `capacity` events for replay.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            state: Mutex::new(State {
+                next_seq: 1,
+                recent: VecDeque::with_capacity(capacity),
+                capacity,
+            }),
+            live: broadcast::channel(capacity).0,
+        }
+    }
+
+    pub fn record(&self, kind: EventKind, key: &str) {
+        let mut state = self.lock();
+        let event = StorageEvent {
+            seq: state.next_seq,
+            kind,
+            key: key.to_string(),
+        };
+        state.next_seq += 1;
+        if state.recent.len() == state.capacity {
+            state.recent.pop_front();
+        }
+        state.recent.push_back(event.clone());
+        // Sending only fails when nobody is subscribed.
+        let _ = self.live.send(event);
+    }
+
+    /// Subscribes to events after `since`, or only to new ones without it.
+    pub fn subscribe(&self, since: Option<u64>) -> Subscription {
+        let state = self.lock();
+        let live = self.live.subscribe();
+        let latest = state.next_seq - 1;
+        let Some(since) = since else {
+            return Subscription {
+                gap: None,
+                replay: Vec::new(),
+                latest,
+                live,
+            };
+        };
+        let oldest = state
+            .recent
+            .front()
+            .map_or(state.next_seq, |event| event.seq);
+        let gap = (since + 1 < oldest).then(|| (since + 1, oldest - 1));
+        let replay = state
+            .recent
+            .iter()
+            .filter(|event| event.seq > since)
+            .cloned()
+            .collect();
+        Subscription {
+            gap,
+            replay,
+            latest,
+            live,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_older_than_the_buffer_are_reported_as_a_gap() {
+        let log = EventLog::new(2);
+        for key in ["a", "b", "c"] {
+            log.record(EventKind::Put, key);
+        }
+
+        let subscription = log.subscribe(Some(0));
+        assert_eq!(subscription.gap, Some((1, 1)));
+        let keys: Vec<&str> = subscription
+            .replay
+            .iter()
+            .map(|event| event.key.as_str())
+            .collect();
+        assert_eq!(keys, ["b", "c"]);
+
+        let subscription = log.subscribe(Some(2));
+        assert_eq!(subscription.gap, None);
+        assert_eq!(subscription.replay.len(), 1);
+        assert!(log.subscribe(Some(3)).replay.is_empty());
+    }
+}
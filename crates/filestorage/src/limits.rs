@@ -0,0 +1,51 @@
+//! Bounds on request headers that are parsed by hand, so that an abusive
+//! client cannot make the server chew through huge values.
+
+use axum::http::{HeaderMap, header};
+
+use crate::USER_METADATA_PREFIX;
+
+/// Limits applied to every request before it reaches a handler.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeaderLimits {
+    /// Longest accepted value of `Range`, `If-Match`, `If-None-Match`, or an
+    /// `x-meta-*` header, in bytes.
+    pub max_header_bytes: usize,
+    /// Most byte ranges accepted in one `Range` header.
+    pub max_ranges: usize,
+}
+
+impl Default for HeaderLimits {
+    fn default() -> Self {
+        Self {
+            max_header_bytes: 8 * 1024,
+            max_ranges: 100,
+        }
+    }
+}
+
+impl HeaderLimits {
+    /// Returns a message describing the first limit `headers` break, if any.
+    pub fn check(&self, headers: &HeaderMap) -> Result<(), String> {
+        for (name, value) in headers {
+            let limited = [header::RANGE, header::IF_MATCH, header::IF_NONE_MATCH].contains(name)
+                || name.as_str().starts_with(USER_METADATA_PREFIX);
+            if limited && value.len() > self.max_header_bytes {
+                return Err(format!(
+                    "header `{name}` is longer than {} bytes",
+                    self.max_header_bytes
+                ));
+            }
+        }
+        for value in headers.get_all(header::RANGE) {
+            let ranges = value.as_bytes().split(|&byte| byte == b',').count();
+            if ranges > self.max_ranges {
+                return Err(format!(
+                    "header `range` lists more than {} ranges",
+                    self.max_ranges
+                ));
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,20 +1,55 @@
+mod access_log;
+mod body;
+mod checksum;
+mod compression;
+mod events;
+mod limits;
+mod media;
+mod range;
+mod remote;
+mod server;
+
 use std::{
+    collections::BTreeMap,
     env,
     error::Error,
     net::SocketAddr,
     path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
 };
 
 use axum::{
-    body::Bytes,
-    extract::{Path, State},
-    http::{header, HeaderValue, StatusCode},
-    response::{IntoResponse, Response},
-    routing::get,
-    Json, Router,
+    body::{Body, Bytes},
+    extract::{Path, Query, Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        AppendHeaders, IntoResponse, Response,
+    },
+    routing::{delete, get, post},
+    Json, RequestExt, Router,
+};
+use filestorage_core::{
+    FileStorage, InvalidKeyReason, Metadata, PutCondition, PutOptions, RenameStrategy,
+    ReplicaPolicy, StorageError, StorageOptions, SymlinkPolicy,
+};
+use futures_util::{StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncReadExt, sync::broadcast::error::RecvError};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use crate::{
+    access_log::AccessLog,
+    body::ObjectBody,
+    checksum::ObjectDigest,
+    events::{EventKind, EventLog, StorageEvent},
+    limits::HeaderLimits,
+    range::{ByteRange, RangeRequest},
+    remote::RemoteReplica,
+    server::HttpOptions,
 };
-use filestorage_core::{FileStorage, StorageError};
-use serde::Serialize;
 
 type AnyError = Box<dyn Error + Send + Sync>;
 
@@ -28,8 +63,9 @@ async fn main() {
 
 async fn run() -> Result<(), AnyError> {
     let settings = Settings::from_env()?;
-    let storage = FileStorage::new(&settings.storage_root).await?;
-    let state = AppState { storage };
+    let storage =
+        FileStorage::with_options(&settings.storage_root, settings.storage_options()).await?;
+    let state = AppState::new(storage.clone(), &settings);
     let router = build_router(state);
 
     let listener = tokio::net::TcpListener::bind(settings.bind_address).await?;
@@ -38,72 +74,1060 @@ async fn run() -> Result<(), AnyError> {
         settings.bind_address,
         settings.storage_root.display()
     );
-    axum::serve(listener, router).await?;
+    tokio::select! {
+        () = server::serve(listener, router, &settings.http) => {}
+        result = tokio::signal::ctrl_c() => result?,
+    }
+    println!("shutting down");
+    storage.flush().await?;
     Ok(())
 }
 
 #[derive(Clone)]
 struct AppState {
     storage: FileStorage,
+    default_content_type: HeaderValue,
+    not_found_fallback: Option<Arc<str>>,
+    directory_index: Option<Arc<str>>,
+    allowed_content_types: Option<Arc<[String]>>,
+    cache_control: Option<HeaderValue>,
+    remote: Option<RemoteReplica>,
+    overwrite_policy: OverwritePolicy,
+    gzip: bool,
+    events: Arc<EventLog>,
+    access_log: Option<Arc<AccessLog>>,
+    header_limits: HeaderLimits,
+    info: Arc<InfoBody>,
+}
+
+impl AppState {
+    fn new(storage: FileStorage, settings: &Settings) -> Self {
+        Self {
+            info: Arc::new(InfoBody::new(&storage, settings)),
+            storage,
+            default_content_type: settings.default_content_type.clone(),
+            not_found_fallback: settings.not_found_fallback.as_deref().map(Arc::from),
+            directory_index: settings.directory_index.as_deref().map(Arc::from),
+            allowed_content_types: settings.allowed_content_types.as_deref().map(Arc::from),
+            cache_control: settings.cache_control.clone(),
+            remote: settings.replica_url.clone().map(|url| {
+                RemoteReplica::new(url, settings.replica_policy, settings.replica_concurrency)
+            }),
+            overwrite_policy: settings.overwrite_policy,
+            gzip: settings.gzip,
+            events: Arc::new(EventLog::new(EVENT_BUFFER)),
+            access_log: settings
+                .access_log
+                .clone()
+                .map(|path| Arc::new(AccessLog::start(path, settings.access_log_max_bytes))),
+            header_limits: settings.header_limits,
+        }
+    }
 }
 
 fn build_router(state: AppState) -> Router {
-    Router::new()
+    let access_log = state.access_log.clone();
+    let header_limits = state.header_limits;
+    let router = Router::new()
+        .route("/info", get(server_info))
+        .route("/trash", delete(purge_trash))
+        .route("/export.tar", get(export_tar))
+        .route("/import.tar", post(import_tar))
+        .route("/events", get(stream_events))
         .route(
             "/objects/*key",
-            get(get_object).put(put_object).delete(delete_object),
+            get(get_object)
+                .put(put_object)
+                .patch(patch_object)
+                .post(post_object)
+                .delete(delete_object)
+                .options(object_options)
+                .fallback(object_method_not_allowed),
         )
         .with_state(state)
+        .layer(middleware::from_fn_with_state(
+            header_limits,
+            enforce_header_limits,
+        ));
+    match access_log {
+        Some(log) => router.layer(middleware::from_fn_with_state(log, log_access)),
+        None => router,
+    }
+}
+
+/// Records one access log line per request once its response head is ready.
+async fn log_access(State(log): State<Arc<AccessLog>>, request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let method = request.method().clone();
+    let uri = request.uri().clone();
+    let response = next.run(request).await;
+    log.record(format!(
+        "[{}] \"{method} {uri}\" {} {}ms tenant={}",
+        httpdate::fmt_http_date(SystemTime::now()),
+        response.status().as_u16(),
+        started.elapsed().as_millis(),
+        access_log::tenant_of(uri.path()),
+    ));
+    response
+}
+
+/// Rejects requests whose headers break the configured limits with `400`.
+async fn enforce_header_limits(
+    State(limits): State<HeaderLimits>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match limits.check(request.headers()) {
+        Ok(()) => next.run(request).await,
+        Err(msg) => ApiError::bad_request(msg).into_response(),
+    }
 }
 
+/// Node configuration reported by `GET /info`.
+///
+/// Only settings that are safe to show anyone who can reach the node belong here.
+#[derive(Debug, Serialize)]
+struct InfoBody {
+    version: &'static str,
+    storage_root: String,
+    default_content_type: String,
+    directory_index: Option<String>,
+    not_found_fallback: Option<String>,
+    op_timeout_ms: Option<u128>,
+    allowed_content_types: Option<Vec<String>>,
+    cache_control: Option<String>,
+    rename_strategy: &'static str,
+    symlink_policy: &'static str,
+    prefix_quotas: BTreeMap<String, u64>,
+    overwrite_policy: &'static str,
+    gzip: bool,
+    max_header_bytes: usize,
+    max_ranges: usize,
+}
+
+impl InfoBody {
+    fn new(storage: &FileStorage, settings: &Settings) -> Self {
+        let header_string = |value: &HeaderValue| value.to_str().unwrap_or_default().to_string();
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            storage_root: storage.root().display().to_string(),
+            default_content_type: header_string(&settings.default_content_type),
+            directory_index: settings.directory_index.clone(),
+            not_found_fallback: settings.not_found_fallback.clone(),
+            op_timeout_ms: settings.op_timeout.map(|timeout| timeout.as_millis()),
+            allowed_content_types: settings.allowed_content_types.clone(),
+            cache_control: settings.cache_control.as_ref().map(header_string),
+            rename_strategy: match settings.rename_strategy {
+                RenameStrategy::Atomic => "atomic",
+                RenameStrategy::Fallback => "fallback",
+            },
+            symlink_policy: match settings.symlink_policy {
+                SymlinkPolicy::Reject => "reject",
+                SymlinkPolicy::Follow => "follow",
+                SymlinkPolicy::ReturnTarget => "return-target",
+            },
+            prefix_quotas: settings.prefix_quotas.clone(),
+            overwrite_policy: match settings.overwrite_policy {
+                OverwritePolicy::Allow => "allow",
+                OverwritePolicy::Deny => "deny",
+                OverwritePolicy::RequireIfMatch => "require-if-match",
+            },
+            gzip: settings.gzip,
+            max_header_bytes: settings.header_limits.max_header_bytes,
+            max_ranges: settings.header_limits.max_ranges,
+        }
+    }
+}
+
+async fn server_info(State(state): State<AppState>) -> Response {
+    Json(&*state.info).into_response()
+}
+
+/// Request headers carrying user metadata start with this prefix.
+const USER_METADATA_PREFIX: &str = "x-meta-";
+
+/// Request header giving an object's time to live in seconds.
+const EXPIRES_IN_HEADER: &str = "x-expires-in";
+
+/// Seconds from the Unix epoch to the last second HTTP dates can express.
+const MAX_HTTP_DATE_SECS: u64 = 253_402_300_799;
+
+/// Request header giving the milliseconds a request may take before it is abandoned.
+const DEADLINE_HEADER: &str = "x-deadline-ms";
+
+/// Header an `EventSource` reconnects with, naming the last event it received.
+const LAST_EVENT_ID_HEADER: &str = "last-event-id";
+
 async fn put_object(
     State(state): State<AppState>,
     Path(key): Path<String>,
-    body: Bytes,
+    headers: HeaderMap,
+    request: Request,
 ) -> Result<impl IntoResponse, ApiError> {
     ensure_key_present(&key)?;
-    state.storage.put(&key, &body).await?;
-    Ok(StatusCode::CREATED)
+    let key = index_key(&state, key);
+    let digests = checksum::expected(&headers).map_err(ApiError::BadRequest)?;
+    let mut verifier = checksum::Verifier::new(digests);
+    let body = receive_body(request, &mut verifier).await?;
+    let echoed = verifier.finish().map_err(ApiError::BadRequest)?;
+    check_content_type(&state, &headers, &body)?;
+    let mut options = put_options(&headers)?;
+    options.condition = put_condition(&state, &key, &headers).await?;
+    within_deadline(&headers, state.storage.put_with(&key, &body, &options)).await??;
+    state.events.record(EventKind::Put, &key);
+    if let Some(remote) = &state.remote {
+        remote
+            .forward(&key, &headers, body)
+            .await
+            .map_err(ApiError::BadGateway)?;
+    }
+    Ok((StatusCode::CREATED, AppendHeaders(echoed)))
+}
+
+/// Buffers the body of `request`, hashing each chunk for `verifier` as it
+/// arrives rather than in a second pass over the whole body.
+async fn receive_body(
+    request: Request,
+    verifier: &mut checksum::Verifier,
+) -> Result<Bytes, ApiError> {
+    let mut chunks = request.into_limited_body().into_data_stream();
+    let mut body = Vec::new();
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(|err| {
+            let too_large = std::iter::successors(err.source(), |err| (*err).source())
+                .any(|err| err.is::<http_body_util::LengthLimitError>());
+            match too_large {
+                true => ApiError::PayloadTooLarge(err.to_string()),
+                false => ApiError::bad_request(format!("failed to read the request body: {err}")),
+            }
+        })?;
+        verifier.update(&chunk);
+        body.extend_from_slice(&chunk);
+    }
+    Ok(Bytes::from(body))
+}
+
+/// Runs `work`, failing with `504` if it outlasts the request's `X-Deadline-Ms`.
+///
+/// Without the header only the storage's own operation timeout applies.
+async fn within_deadline<T>(
+    headers: &HeaderMap,
+    work: impl Future<Output = T>,
+) -> Result<T, ApiError> {
+    let Some(value) = headers.get(DEADLINE_HEADER) else {
+        return Ok(work.await);
+    };
+    let millis: u64 = header_str(DEADLINE_HEADER, value)?.parse().map_err(|_| {
+        ApiError::bad_request(format!("header `{DEADLINE_HEADER}` must be whole milliseconds"))
+    })?;
+    tokio::time::timeout(Duration::from_millis(millis), work)
+        .await
+        .map_err(|_| ApiError::GatewayTimeout(format!("request exceeded its {millis}ms deadline")))
+}
+
+/// Rejects uploads whose media type is not allowlisted or whose leading bytes
+/// contradict it. Uploads without a `Content-Type` count as
+/// `application/octet-stream`.
+fn check_content_type(state: &AppState, headers: &HeaderMap, body: &[u8]) -> Result<(), ApiError> {
+    let Some(allowed) = state.allowed_content_types.as_deref() else {
+        return Ok(());
+    };
+    let media_type = match headers.get(header::CONTENT_TYPE) {
+        Some(value) => media::essence(header_str(header::CONTENT_TYPE.as_str(), value)?),
+        None => DEFAULT_CONTENT_TYPE.to_string(),
+    };
+    if !allowed.contains(&media_type) {
+        return Err(ApiError::UnsupportedMediaType(format!(
+            "media type `{media_type}` is not accepted"
+        )));
+    }
+    if !media::matches_signature(&media_type, body) {
+        return Err(ApiError::UnsupportedMediaType(format!(
+            "content does not look like `{media_type}`"
+        )));
+    }
+    Ok(())
+}
+
+/// Turns the node's [`OverwritePolicy`] and any `If-Match` header into the
+/// condition the put is made under.
+///
+/// `If-Match` takes a single entity tag or `*`.
+async fn put_condition(
+    state: &AppState,
+    key: &str,
+    headers: &HeaderMap,
+) -> Result<PutCondition, ApiError> {
+    let if_match = match headers.get(header::IF_MATCH) {
+        Some(value) => Some(header_str(header::IF_MATCH.as_str(), value)?.trim()),
+        None => None,
+    };
+    match (state.overwrite_policy, if_match) {
+        (OverwritePolicy::Deny, _) => Ok(PutCondition::IfAbsent),
+        (_, Some(etag)) => Ok(PutCondition::IfMatch(etag.to_string())),
+        (OverwritePolicy::Allow, None) => Ok(PutCondition::Always),
+        (OverwritePolicy::RequireIfMatch, None) => {
+            if state.storage.exists(key).await? {
+                return Err(ApiError::PreconditionRequired(format!(
+                    "overwriting `{key}` requires an `If-Match` header"
+                )));
+            }
+            // A racing create still fails, with `409` rather than `428`.
+            Ok(PutCondition::IfAbsent)
+        }
+    }
+}
+
+/// Collects the content type and `x-meta-*` headers of an upload.
+fn put_options(headers: &HeaderMap) -> Result<PutOptions, ApiError> {
+    let mut options = PutOptions::default();
+    if let Some(value) = headers.get(header::CONTENT_TYPE) {
+        options.content_type = Some(header_str(header::CONTENT_TYPE.as_str(), value)?.to_string());
+    }
+    if let Some(value) = headers.get(EXPIRES_IN_HEADER) {
+        let seconds: u64 = header_str(EXPIRES_IN_HEADER, value)?
+            .parse()
+            .map_err(|_| {
+                ApiError::bad_request(format!("header `{EXPIRES_IN_HEADER}` must be whole seconds"))
+            })?;
+        // `Expires` cannot name a time past the end of year 9999.
+        let expires_at = SystemTime::now()
+            .checked_add(Duration::from_secs(seconds))
+            .filter(|at| *at <= SystemTime::UNIX_EPOCH + Duration::from_secs(MAX_HTTP_DATE_SECS));
+        let Some(expires_at) = expires_at else {
+            return Err(ApiError::bad_request(format!(
+                "header `{EXPIRES_IN_HEADER}` is too far in the future"
+            )));
+        };
+        options.expires_at = Some(expires_at);
+    }
+    for (name, value) in headers {
+        if let Some(meta_name) = name.as_str().strip_prefix(USER_METADATA_PREFIX) {
+            let value = header_str(name.as_str(), value)?;
+            options
+                .metadata
+                .insert(meta_name.to_string(), value.to_string());
+        }
+    }
+    Ok(options)
+}
+
+fn header_str<'a>(name: &str, value: &'a HeaderValue) -> Result<&'a str, ApiError> {
+    value
+        .to_str()
+        .map_err(|_| ApiError::bad_request(format!("header `{name}` must be visible ASCII")))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ObjectQuery {
+    /// When present, return the object's metadata document instead of its content.
+    metadata: Option<String>,
+    /// Hash algorithm of a `:digest` request; SHA-256 when absent.
+    algo: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct DigestBody {
+    algorithm: &'static str,
+    hex: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ObjectMetadataBody {
+    key: String,
+    size: u64,
+    content_type: String,
+    etag: String,
+    last_modified: String,
+    user_metadata: BTreeMap<String, String>,
 }
 
 async fn get_object(
     State(state): State<AppState>,
     Path(key): Path<String>,
+    Query(query): Query<ObjectQuery>,
+    headers: HeaderMap,
 ) -> Result<Response, ApiError> {
+    if let Some(key) = key.strip_suffix(":digest") {
+        ensure_key_present(key)?;
+        let digest = object_digest(&state, key, query.algo.as_deref().unwrap_or("sha256"));
+        return within_deadline(&headers, digest).await?;
+    }
     ensure_key_present(&key)?;
-    let bytes = state.storage.get(&key).await?;
-    let len = bytes.len();
+    let mut response =
+        within_deadline(&headers, read_object(&state, key, &query, &headers)).await??;
+    if let Some(cache_control) = &state.cache_control
+        && (response.status().is_success() || response.status() == StatusCode::NOT_MODIFIED)
+    {
+        response
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, cache_control.clone());
+    }
+    Ok(response)
+}
+
+async fn read_object(
+    state: &AppState,
+    key: String,
+    query: &ObjectQuery,
+    headers: &HeaderMap,
+) -> Result<Response, ApiError> {
+    let key = index_key(state, key);
+    if query.metadata.is_some() {
+        return object_metadata(state, key).await;
+    }
+    if let Some(range) = headers.get(header::RANGE) {
+        let metadata = match state.storage.head(&key).await {
+            Ok(metadata) => metadata,
+            Err(StorageError::NotFound(missing)) => return serve_not_found(state, missing).await,
+            Err(err) => return Err(err.into()),
+        };
+        let unchanged = headers
+            .get(header::IF_RANGE)
+            .is_none_or(|validator| if_range_matches(validator, &metadata));
+        let size = metadata.size;
+        let parsed = range
+            .to_str()
+            .ok()
+            .filter(|_| unchanged)
+            .and_then(|range| range::parse(range, size));
+        match parsed {
+            Some(RangeRequest::Satisfiable(ranges)) => {
+                return ranged_response(state, &key, &ranges, &metadata).await;
+            }
+            Some(RangeRequest::Unsatisfiable) => return Err(ApiError::RangeNotSatisfiable(size)),
+            None => {}
+        }
+    }
+    match state.storage.read_range(&key, 0, u64::MAX).await {
+        Ok((reader, len)) => {
+            let body = ObjectBody::new(reader, len);
+            object_response(state, &key, body, Some(headers)).await
+        }
+        Err(StorageError::NotFound(missing)) => serve_not_found(state, missing).await,
+        Err(err) => Err(err.into()),
+    }
+}
 
-    let mut response = Response::new(bytes.into());
+/// Maps a key ending in `/` to the directory index object below it, when one
+/// is configured. Without an index such keys are left as they are for the
+/// store to reject.
+fn index_key(state: &AppState, key: String) -> String {
+    match state.directory_index.as_deref() {
+        Some(index) if key.ends_with('/') => format!("{key}{index}"),
+        _ => key,
+    }
+}
+
+/// Returns whether an `If-Range` validator still identifies the current object.
+///
+/// Entity tags must match exactly (weak tags never do), and dates must equal
+/// the object's modification time to the second.
+fn if_range_matches(validator: &HeaderValue, metadata: &Metadata) -> bool {
+    let Ok(validator) = validator.to_str() else {
+        return false;
+    };
+    if validator.starts_with('"') {
+        return validator == metadata.etag;
+    }
+    httpdate::parse_http_date(validator).is_ok_and(|date| {
+        httpdate::fmt_http_date(date) == httpdate::fmt_http_date(metadata.modified)
+    })
+}
+
+/// Returns the `ETag` and `Last-Modified` headers for an object.
+fn validator_headers(metadata: &Metadata) -> [(HeaderName, HeaderValue); 2] {
+    [
+        (
+            header::ETAG,
+            HeaderValue::from_str(&metadata.etag).expect("etag header"),
+        ),
+        (
+            header::LAST_MODIFIED,
+            HeaderValue::from_str(&httpdate::fmt_http_date(metadata.modified))
+                .expect("last modified header"),
+        ),
+    ]
+}
+
+/// Builds a `200` response streaming `body` with the headers describing `key`.
+///
+/// Given the `request` headers of a `GET`, text objects are compressed for
+/// clients that accept gzip when that is enabled, and `304` is answered when
+/// `If-None-Match` lists the tag of the representation that would be sent.
+/// Compressed responses carry a weak tag distinct from the stored object's.
+async fn object_response(
+    state: &AppState,
+    key: &str,
+    body: ObjectBody,
+    request: Option<&HeaderMap>,
+) -> Result<Response, ApiError> {
+    let content_type = content_type_for(state, key).await?;
+    let expires_at = state.storage.expires_at(key).await?;
+    let metadata = state.storage.head(key).await?;
+
+    let compressible = state.gzip
+        && content_type
+            .to_str()
+            .is_ok_and(|value| media::is_compressible(&media::essence(value)));
+    let gzip = compressible && request.is_some_and(compression::accepts_gzip);
+    let etag = if gzip {
+        compression::gzip_etag(&metadata.etag)
+    } else {
+        metadata.etag.clone()
+    };
+    let not_modified = request
+        .and_then(|headers| headers.get(header::IF_NONE_MATCH))
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| compression::none_match_lists(value, &etag));
+    let mut response = if not_modified {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else if gzip {
+        body.gzip().into_response()
+    } else {
+        body.into_response()
+    };
     response
         .headers_mut()
-        .insert(header::CONTENT_TYPE, HeaderValue::from_static("application/octet-stream"));
+        .insert(header::CONTENT_TYPE, content_type);
+    if let Some(expires_at) = expires_at {
+        response.headers_mut().insert(
+            header::EXPIRES,
+            HeaderValue::from_str(&httpdate::fmt_http_date(expires_at)).expect("expires header"),
+        );
+    }
+    response
+        .headers_mut()
+        .insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    response.headers_mut().extend(validator_headers(&metadata));
     response.headers_mut().insert(
-        header::CONTENT_LENGTH,
-        HeaderValue::from_str(&len.to_string()).expect("content length header"),
+        header::ETAG,
+        HeaderValue::from_str(&etag).expect("etag header"),
     );
+    if compressible {
+        response
+            .headers_mut()
+            .insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
     Ok(response)
 }
 
+/// Builds a `206` response for `ranges` of `key`, described by `metadata`.
+///
+/// A single range is returned as-is with a `Content-Range` header; several
+/// ranges are combined into a `multipart/byteranges` body.
+async fn ranged_response(
+    state: &AppState,
+    key: &str,
+    ranges: &[ByteRange],
+    metadata: &Metadata,
+) -> Result<Response, ApiError> {
+    let size = metadata.size;
+    let content_type = content_type_for(state, key).await?;
+    if let [range] = ranges {
+        let (reader, len) = state.storage.read_range(key, range.start, range.len()).await?;
+        let content_range =
+            HeaderValue::from_str(&range.content_range(size)).expect("content range");
+        return Ok((
+            [
+                (header::CONTENT_TYPE, content_type),
+                (header::ACCEPT_RANGES, HeaderValue::from_static("bytes")),
+            ],
+            validator_headers(metadata),
+            ObjectBody::partial(reader, len, content_range),
+        )
+            .into_response());
+    }
+
+    let boundary = multipart_boundary();
+    let content_type = header_str(header::CONTENT_TYPE.as_str(), &content_type)?;
+    let mut body = Vec::new();
+    for range in ranges {
+        let bytes = state.storage.get_range(key, range.start, range.len()).await?;
+        let part_headers = format!(
+            "\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: {}\r\n\r\n",
+            range.content_range(size)
+        );
+        body.extend_from_slice(part_headers.as_bytes());
+        body.extend_from_slice(&bytes);
+    }
+    body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+
+    let multipart_type = format!("multipart/byteranges; boundary={boundary}");
+    Ok((
+        StatusCode::PARTIAL_CONTENT,
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_str(&multipart_type).expect("multipart content type"),
+            ),
+            (header::ACCEPT_RANGES, HeaderValue::from_static("bytes")),
+        ],
+        validator_headers(metadata),
+        body,
+    )
+        .into_response())
+}
+
+/// Returns a boundary string unlikely to appear inside object content.
+fn multipart_boundary() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    format!("filestorage-{nanos:032x}")
+}
+
+/// Answers a miss on `missing` with the configured fallback object and a `404` status.
+///
+/// A JSON `404` is returned when no fallback is configured or it is missing too.
+async fn serve_not_found(state: &AppState, missing: String) -> Result<Response, ApiError> {
+    if is_collection(state, &missing).await {
+        return Err(collection_error(&missing));
+    }
+    let Some(fallback) = state.not_found_fallback.as_deref() else {
+        return Err(ApiError::NotFound(missing));
+    };
+    match state.storage.read_range(fallback, 0, u64::MAX).await {
+        Ok((reader, len)) => {
+            let body = ObjectBody::new(reader, len);
+            let mut response = object_response(state, fallback, body, None).await?;
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            Ok(response)
+        }
+        Err(StorageError::NotFound(_)) => Err(ApiError::NotFound(missing)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Returns whether `key` names a directory of other keys rather than an object.
+async fn is_collection(state: &AppState, key: &str) -> bool {
+    let Ok(path) = state.storage.resolve(key) else {
+        return false;
+    };
+    tokio::fs::metadata(path)
+        .await
+        .is_ok_and(|metadata| metadata.is_dir())
+}
+
+fn collection_error(key: &str) -> ApiError {
+    ApiError::Conflict(format!(
+        "`{key}` is a prefix of other keys, not an object; request a key below `{key}/`"
+    ))
+}
+
+async fn object_metadata(state: &AppState, key: String) -> Result<Response, ApiError> {
+    let metadata = match state.storage.head(&key).await {
+        Ok(metadata) => metadata,
+        Err(StorageError::NotFound(missing)) if is_collection(state, &missing).await => {
+            return Err(collection_error(&missing));
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let content_type = content_type_for(state, &key).await?;
+    let body = ObjectMetadataBody {
+        size: metadata.size,
+        content_type: header_str(header::CONTENT_TYPE.as_str(), &content_type)?.to_string(),
+        etag: metadata.etag,
+        last_modified: httpdate::fmt_http_date(metadata.modified),
+        user_metadata: state.storage.user_metadata(&key).await?,
+        key,
+    };
+    Ok(Json(body).into_response())
+}
+
+/// Streams `key` through the hasher named `algo` and returns the digest as JSON.
+async fn object_digest(state: &AppState, key: &str, algo: &str) -> Result<Response, ApiError> {
+    let mut digest = ObjectDigest::new(algo).ok_or_else(|| {
+        ApiError::BadRequest(format!(
+            "unsupported digest algorithm `{algo}`; expected one of {}",
+            ObjectDigest::SUPPORTED
+        ))
+    })?;
+    let (mut reader, _) = state.storage.read_range(key, 0, u64::MAX).await?;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = reader
+            .read(&mut buf)
+            .await
+            .map_err(|err| ApiError::internal(format!("reading `{key}` failed: {err}")))?;
+        if read == 0 {
+            break;
+        }
+        digest.update(&buf[..read]);
+    }
+    let body = DigestBody {
+        algorithm: digest.name(),
+        hex: digest.finalize_hex(),
+    };
+    Ok(Json(body).into_response())
+}
+
+/// Returns the stored content type of `key`, or one sniffed from its first
+/// bytes, falling back to the configured default.
+async fn content_type_for(state: &AppState, key: &str) -> Result<HeaderValue, ApiError> {
+    let stored = state.storage.content_type(key).await?;
+    if let Some(value) = stored.and_then(|value| HeaderValue::from_str(&value).ok()) {
+        return Ok(value);
+    }
+    let prefix = state.storage.peek(key, media::SNIFF_LEN).await?;
+    Ok(media::sniff(&prefix)
+        .map(HeaderValue::from_static)
+        .unwrap_or_else(|| state.default_content_type.clone()))
+}
+
+/// Writes the request body over the span of `key` given by `Content-Range`.
+async fn patch_object(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, ApiError> {
+    ensure_key_present(&key)?;
+    let value = headers.get(header::CONTENT_RANGE).ok_or_else(|| {
+        ApiError::bad_request("PATCH requires a `Content-Range: bytes <start>-<end>/*` header")
+    })?;
+    let range = range::parse_content_range(header_str(header::CONTENT_RANGE.as_str(), value)?)
+        .ok_or_else(|| {
+            ApiError::bad_request("header `content-range` must look like `bytes <start>-<end>/*`")
+        })?;
+    if range.len() != body.len() as u64 {
+        return Err(ApiError::bad_request(format!(
+            "`content-range` covers {} bytes but the body has {}",
+            range.len(),
+            body.len()
+        )));
+    }
+    // Writing past the end would leave a hole of zeros the client never sent.
+    let size = match state.storage.size(&key).await {
+        Ok(size) => size,
+        Err(StorageError::NotFound(_)) => 0,
+        Err(err) => return Err(err.into()),
+    };
+    if range.start > size {
+        return Err(ApiError::RangeNotSatisfiable(size));
+    }
+    within_deadline(&headers, state.storage.write_range(&key, range.start, &body)).await??;
+    state.events.record(EventKind::Put, &key);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DeleteQuery {
+    /// Moves the object to the trash instead of removing it.
+    #[serde(default)]
+    soft: bool,
+}
+
 async fn delete_object(
     State(state): State<AppState>,
     Path(key): Path<String>,
+    Query(query): Query<DeleteQuery>,
+    headers: HeaderMap,
 ) -> Result<impl IntoResponse, ApiError> {
     ensure_key_present(&key)?;
-    state.storage.delete(&key).await?;
+    let key = index_key(&state, key);
+    let if_match = match headers.get(header::IF_MATCH) {
+        Some(value) => Some(header_str(header::IF_MATCH.as_str(), value)?.trim()),
+        None => None,
+    };
+    match (query.soft, if_match) {
+        (true, Some(_)) => {
+            return Err(ApiError::BadRequest(
+                "`If-Match` is not supported with `soft=true`".to_string(),
+            ));
+        }
+        (true, None) => state.storage.soft_delete(&key).await?,
+        (false, Some(etag)) => {
+            if !state.storage.delete_if_etag(&key, etag).await? {
+                return Err(ApiError::PreconditionFailed(format!(
+                    "`{key}` does not match `{etag}`"
+                )));
+            }
+        }
+        (false, None) => state.storage.delete(&key).await?,
+    }
+    state.events.record(EventKind::Delete, &key);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PurgeQuery {
+    /// Only purge objects soft-deleted at least this many seconds ago.
+    older_than: Option<u64>,
+    /// Purges everything in the trash; required when `older_than` is absent.
+    #[serde(default)]
+    all: bool,
+}
+
+async fn purge_trash(
+    State(state): State<AppState>,
+    Query(query): Query<PurgeQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let older_than = match (query.older_than, query.all) {
+        (Some(secs), false) => Duration::from_secs(secs),
+        (None, true) => Duration::ZERO,
+        (Some(_), true) => {
+            return Err(ApiError::BadRequest(
+                "`older_than` cannot be combined with `all=true`".to_string(),
+            ));
+        }
+        (None, false) => {
+            return Err(ApiError::BadRequest(
+                "purging the trash needs `older_than` or `all=true`".to_string(),
+            ));
+        }
+    };
+    state.storage.purge_trash(older_than).await?;
     Ok(StatusCode::NO_CONTENT)
 }
 
+#[derive(Debug, Default, Deserialize)]
+struct ExportQuery {
+    /// Only export objects whose keys start with this prefix.
+    #[serde(default)]
+    prefix: String,
+}
+
+/// Bytes of the archive buffered between the exporter and the response body.
+const EXPORT_BUFFER: usize = 64 * 1024;
+
+/// Streams a tar archive of the objects under `prefix` as it is written.
+///
+/// The status is sent before the first object is read, so a failure part way
+/// is only logged and leaves the client with an archive missing its end marker.
+async fn export_tar(
+    State(state): State<AppState>,
+    Query(query): Query<ExportQuery>,
+) -> impl IntoResponse {
+    let (writer, reader) = tokio::io::duplex(EXPORT_BUFFER);
+    tokio::spawn(async move {
+        if let Err(err) = state.storage.export_tar(&query.prefix, writer).await {
+            eprintln!("export of `{}` failed: {err}", query.prefix);
+        }
+    });
+    (
+        [
+            (header::CONTENT_TYPE, "application/x-tar"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"export.tar\"",
+            ),
+        ],
+        Body::from_stream(ReaderStream::new(reader)),
+    )
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ImportQuery {
+    /// Replaces objects that already exist instead of skipping their entries.
+    /// Refused unless the node's [`OverwritePolicy`] is `Allow`.
+    #[serde(default)]
+    overwrite: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct ImportBody {
+    imported: usize,
+    skipped: usize,
+}
+
+/// Stores the files of a tar archive streamed as the request body, then
+/// announces each imported object and forwards it to the replica like a `PUT`.
+async fn import_tar(
+    State(state): State<AppState>,
+    Query(query): Query<ImportQuery>,
+    body: Body,
+) -> Result<impl IntoResponse, ApiError> {
+    // Entries cannot carry `If-Match`, so overwriting is all or nothing.
+    if query.overwrite {
+        match state.overwrite_policy {
+            OverwritePolicy::Allow => {}
+            OverwritePolicy::Deny => {
+                return Err(ApiError::Conflict(
+                    "this node does not allow overwriting objects".to_string(),
+                ));
+            }
+            OverwritePolicy::RequireIfMatch => {
+                return Err(ApiError::PreconditionRequired(
+                    "overwriting objects requires `If-Match`, which an import lacks".to_string(),
+                ));
+            }
+        }
+    }
+    let chunks = body
+        .into_data_stream()
+        .map(|chunk| chunk.map_err(std::io::Error::other));
+    let report = state
+        .storage
+        .import_tar(StreamReader::new(chunks), query.overwrite)
+        .await?;
+    for key in &report.imported {
+        state.events.record(EventKind::Put, key);
+        if let Some(remote) = &state.remote {
+            let body = state.storage.get(key).await?;
+            remote
+                .forward(key, &HeaderMap::new(), body.into())
+                .await
+                .map_err(ApiError::BadGateway)?;
+        }
+    }
+    Ok(Json(ImportBody {
+        imported: report.imported.len(),
+        skipped: report.skipped,
+    }))
+}
+
+/// Events kept for subscribers resuming with `?since`.
+const EVENT_BUFFER: usize = 1024;
+
+#[derive(Debug, Default, Deserialize)]
+struct EventsQuery {
+    /// Sequence number of the last event the subscriber saw.
+    since: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct GapBody {
+    from: u64,
+    to: u64,
+}
+
+/// Streams object changes as server-sent events named `put` or `delete`,
+/// with the event's sequence number as its ID.
+///
+/// With `?since=<seq>`, or the `Last-Event-ID` header a reconnecting
+/// `EventSource` sends, buffered events after `seq` are replayed before live
+/// ones. Events the subscriber can no longer get, because they left the
+/// buffer or it fell behind, are announced by a `gap` event naming the range.
+async fn stream_events(
+    State(state): State<AppState>,
+    Query(query): Query<EventsQuery>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let since = match (query.since, headers.get(LAST_EVENT_ID_HEADER)) {
+        (Some(since), _) => Some(since),
+        (None, Some(value)) => Some(header_str(LAST_EVENT_ID_HEADER, value)?.parse().map_err(
+            |_| ApiError::bad_request(format!("header `{LAST_EVENT_ID_HEADER}` must be a number")),
+        )?),
+        (None, None) => None,
+    };
+    let subscription = state.events.subscribe(since);
+    let mut catch_up = Vec::new();
+    if let Some((from, to)) = subscription.gap {
+        catch_up.push(gap_event(from, to));
+    }
+    catch_up.extend(subscription.replay.iter().map(change_event));
+
+    // Live events are checked against the last sequence number sent, so one
+    // already replayed is skipped and missed ones become a gap.
+    let live = (subscription.live, subscription.latest, None);
+    let live = stream::unfold(live, |(mut live, mut last, mut pending)| async move {
+        loop {
+            let event = match pending.take() {
+                Some(event) => event,
+                None => match live.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                },
+            };
+            if event.seq <= last {
+                continue;
+            }
+            if event.seq > last + 1 {
+                let gap = gap_event(last + 1, event.seq - 1);
+                last = event.seq - 1;
+                return Some((gap, (live, last, Some(event))));
+            }
+            return Some((change_event(&event), (live, event.seq, None)));
+        }
+    });
+    Ok(Sse::new(stream::iter(catch_up).chain(live)).keep_alive(KeepAlive::default()))
+}
+
+fn change_event(event: &StorageEvent) -> Result<Event, axum::Error> {
+    Event::default()
+        .event(event.kind.name())
+        .id(event.seq.to_string())
+        .json_data(event)
+}
+
+fn gap_event(from: u64, to: u64) -> Result<Event, axum::Error> {
+    Event::default()
+        .event("gap")
+        .json_data(GapBody { from, to })
+}
+
+/// Runs the action named by the path suffix, as in `/objects/a.txt:sync`.
+///
+/// `:sync` flushes the object to disk and `:restore` brings it back from the trash.
+async fn post_object(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Some((key, action)) = key.rsplit_once(':') else {
+        return Err(ApiError::MethodNotAllowed(Method::POST));
+    };
+    ensure_key_present(key)?;
+    match action {
+        "sync" => state.storage.sync(key).await?,
+        "restore" => {
+            state.storage.restore_from_trash(key).await?;
+            state.events.record(EventKind::Put, key);
+        }
+        _ => return Err(ApiError::MethodNotAllowed(Method::POST)),
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Methods supported on `/objects/*key`, as advertised in `Allow` headers.
+const OBJECT_METHODS: &str = "GET, HEAD, PUT, PATCH, POST, DELETE, OPTIONS";
+
+async fn object_options() -> impl IntoResponse {
+    (StatusCode::NO_CONTENT, [(header::ALLOW, OBJECT_METHODS)])
+}
+
+async fn object_method_not_allowed(method: Method) -> ApiError {
+    ApiError::MethodNotAllowed(method)
+}
+
 #[derive(Debug, Serialize)]
 struct ErrorBody {
     error: String,
+    /// Stable identifier of the failure for clients that branch on it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+}
+
+impl ErrorBody {
+    fn new(error: String) -> Self {
+        Self { error, code: None }
+    }
 }
 
 #[derive(Debug)]
 enum ApiError {
     BadRequest(String),
+    InvalidKey(InvalidKeyReason, String),
     NotFound(String),
+    MethodNotAllowed(Method),
+    Conflict(String),
+    PreconditionFailed(String),
+    /// The request must be made conditional, as with `If-Match`.
+    PreconditionRequired(String),
+    Locked(String),
+    PayloadTooLarge(String),
+    UnsupportedMediaType(String),
+    /// No requested range overlaps the object of this many bytes.
+    RangeNotSatisfiable(u64),
     Internal(String),
+    /// A transient shortage the client should retry after backing off.
+    ServiceUnavailable(String),
+    /// The remote replica rejected or never received a forwarded write.
+    BadGateway(String),
+    GatewayTimeout(String),
+    InsufficientStorage(String),
 }
 
 impl ApiError {
@@ -119,8 +1143,24 @@ impl ApiError {
 impl From<StorageError> for ApiError {
     fn from(value: StorageError) -> Self {
         match value {
-            StorageError::InvalidKey(msg) => Self::BadRequest(msg),
+            StorageError::InvalidKey { reason, message } => Self::InvalidKey(reason, message),
+            StorageError::InvalidMetadata(msg) => Self::BadRequest(msg),
             StorageError::NotFound(key) => Self::NotFound(key),
+            StorageError::Conflict(msg) => Self::Conflict(msg),
+            err @ StorageError::PrefixNotFound(_) => Self::Conflict(err.to_string()),
+            err @ StorageError::VersionMismatch(_) => Self::PreconditionFailed(err.to_string()),
+            err @ StorageError::Locked(_) => Self::Locked(err.to_string()),
+            err @ StorageError::QuotaExceeded(_) => Self::InsufficientStorage(err.to_string()),
+            err @ StorageError::ListTooLarge { .. } => Self::BadRequest(err.to_string()),
+            err @ StorageError::InvalidArchive(_) => Self::BadRequest(err.to_string()),
+            err @ StorageError::TooLarge { .. } => Self::PayloadTooLarge(err.to_string()),
+            err @ StorageError::Timeout { .. } => Self::GatewayTimeout(err.to_string()),
+            err @ (StorageError::Encoding { .. }
+            | StorageError::Serialization { .. }
+            | StorageError::RootMissing(_)
+            | StorageError::RootNotDirectory(_)
+            | StorageError::RootUnavailable(..)) => Self::internal(err.to_string()),
+            StorageError::ResourceExhausted(msg) => Self::ServiceUnavailable(msg),
             StorageError::Io(err) => Self::internal(format!("storage I/O error: {err}")),
         }
     }
@@ -130,35 +1170,144 @@ impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
         match self {
             ApiError::BadRequest(msg) => {
-                (StatusCode::BAD_REQUEST, Json(ErrorBody { error: msg })).into_response()
+                (StatusCode::BAD_REQUEST, Json(ErrorBody::new(msg))).into_response()
             }
-            ApiError::NotFound(key) => (
-                StatusCode::NOT_FOUND,
+            ApiError::InvalidKey(reason, msg) => (
+                StatusCode::BAD_REQUEST,
                 Json(ErrorBody {
-                    error: format!("object `{key}` not found"),
+                    error: msg,
+                    code: Some(reason.code()),
                 }),
             )
                 .into_response(),
-            ApiError::Internal(msg) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorBody { error: msg }),
+            ApiError::NotFound(key) => (
+                StatusCode::NOT_FOUND,
+                Json(ErrorBody::new(format!("object `{key}` not found"))),
+            )
+                .into_response(),
+            ApiError::MethodNotAllowed(method) => (
+                StatusCode::METHOD_NOT_ALLOWED,
+                [(header::ALLOW, OBJECT_METHODS)],
+                Json(ErrorBody::new(format!(
+                    "method {method} is not allowed on objects"
+                ))),
+            )
+                .into_response(),
+            ApiError::Conflict(msg) => {
+                (StatusCode::CONFLICT, Json(ErrorBody::new(msg))).into_response()
+            }
+            ApiError::PreconditionFailed(msg) => {
+                (StatusCode::PRECONDITION_FAILED, Json(ErrorBody::new(msg))).into_response()
+            }
+            ApiError::PreconditionRequired(msg) => {
+                (StatusCode::PRECONDITION_REQUIRED, Json(ErrorBody::new(msg))).into_response()
+            }
+            ApiError::Locked(msg) => {
+                (StatusCode::LOCKED, Json(ErrorBody::new(msg))).into_response()
+            }
+            ApiError::PayloadTooLarge(msg) => {
+                (StatusCode::PAYLOAD_TOO_LARGE, Json(ErrorBody::new(msg))).into_response()
+            }
+            ApiError::UnsupportedMediaType(msg) => {
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, Json(ErrorBody::new(msg))).into_response()
+            }
+            ApiError::RangeNotSatisfiable(size) => (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{size}"))],
+                Json(ErrorBody::new(format!(
+                    "requested range lies outside the {size}-byte object"
+                ))),
+            )
+                .into_response(),
+            ApiError::Internal(msg) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorBody::new(msg))).into_response()
+            }
+            ApiError::ServiceUnavailable(msg) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, RETRY_AFTER_SECS)],
+                Json(ErrorBody::new(msg)),
             )
                 .into_response(),
+            ApiError::BadGateway(msg) => {
+                (StatusCode::BAD_GATEWAY, Json(ErrorBody::new(msg))).into_response()
+            }
+            ApiError::GatewayTimeout(msg) => {
+                (StatusCode::GATEWAY_TIMEOUT, Json(ErrorBody::new(msg))).into_response()
+            }
+            ApiError::InsufficientStorage(msg) => {
+                (StatusCode::INSUFFICIENT_STORAGE, Json(ErrorBody::new(msg))).into_response()
+            }
         }
     }
 }
 
+/// `Retry-After` value, in seconds, sent with `503` responses.
+const RETRY_AFTER_SECS: &str = "1";
+
 fn ensure_key_present(key: &str) -> Result<(), ApiError> {
     if key.is_empty() {
-        return Err(ApiError::bad_request("object key cannot be empty"));
+        return Err(ApiError::InvalidKey(
+            InvalidKeyReason::Empty,
+            "object key cannot be empty".to_string(),
+        ));
     }
     Ok(())
 }
 
+/// Content type served when nothing more specific is known about an object.
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// Uploads to the remote replica allowed in flight when not configured.
+const DEFAULT_REPLICA_CONCURRENCY: usize = 16;
+
+/// Size at which the access log is rotated when not configured.
+const DEFAULT_ACCESS_LOG_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
 #[derive(Debug)]
 struct Settings {
     bind_address: SocketAddr,
     storage_root: PathBuf,
+    default_content_type: HeaderValue,
+    /// Object served with a `404` status in place of missing keys.
+    not_found_fallback: Option<String>,
+    /// Object name served for keys ending in `/`, like a web server's index page.
+    directory_index: Option<String>,
+    /// Limit on each storage put, get, and delete.
+    op_timeout: Option<Duration>,
+    http: HttpOptions,
+    /// Media types accepted by `PUT`; any type is accepted when unset.
+    allowed_content_types: Option<Vec<String>>,
+    rename_strategy: RenameStrategy,
+    symlink_policy: SymlinkPolicy,
+    /// `Cache-Control` value attached to successful `GET` and `HEAD` responses.
+    cache_control: Option<HeaderValue>,
+    /// Byte limit for each top-level key prefix.
+    prefix_quotas: BTreeMap<String, u64>,
+    /// Node that receives a copy of every `PUT`.
+    replica_url: Option<reqwest::Url>,
+    replica_policy: ReplicaPolicy,
+    /// Uploads to the replica allowed in flight at once.
+    replica_concurrency: usize,
+    overwrite_policy: OverwritePolicy,
+    /// Compresses text objects sent whole by `GET` for clients accepting gzip.
+    gzip: bool,
+    /// File that receives one line per request.
+    access_log: Option<PathBuf>,
+    access_log_max_bytes: u64,
+    header_limits: HeaderLimits,
+}
+
+/// Whether `PUT` may replace an object that already exists.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum OverwritePolicy {
+    /// Replace existing objects, honoring `If-Match` when it is sent.
+    #[default]
+    Allow,
+    /// Only create objects; `PUT` to an existing key fails with `409`.
+    Deny,
+    /// Replace existing objects only with an `If-Match` header, answering
+    /// `428` without one. New objects need none.
+    RequireIfMatch,
 }
 
 impl Settings {
@@ -169,9 +1318,1825 @@ impl Settings {
         let storage_root = PathBuf::from(
             env::var("FILESTORAGE_DATA_DIR").unwrap_or_else(|_| "data".to_string()),
         );
+        let default_content_type = match env::var("FILESTORAGE_DEFAULT_CONTENT_TYPE") {
+            Ok(value) => HeaderValue::from_str(&value)?,
+            Err(_) => HeaderValue::from_static(DEFAULT_CONTENT_TYPE),
+        };
+        let not_found_fallback = env::var("FILESTORAGE_NOT_FOUND_FALLBACK").ok();
+        let directory_index = env::var("FILESTORAGE_DIRECTORY_INDEX").ok();
+        let op_timeout = match env::var("FILESTORAGE_OP_TIMEOUT_MS") {
+            Ok(value) => Some(Duration::from_millis(value.parse()?)),
+            Err(_) => None,
+        };
+        let mut http = HttpOptions::default();
+        if let Ok(value) = env::var("FILESTORAGE_HTTP_KEEP_ALIVE") {
+            http.keep_alive = value.parse()?;
+        }
+        if let Ok(value) = env::var("FILESTORAGE_HTTP2_MAX_STREAMS") {
+            http.max_concurrent_streams = Some(value.parse()?);
+        }
+        if let Ok(value) = env::var("FILESTORAGE_HTTP2_KEEP_ALIVE_SECS") {
+            http.keep_alive_interval = Some(Duration::from_secs(value.parse()?));
+        }
+        let allowed_content_types = env::var("FILESTORAGE_ALLOWED_CONTENT_TYPES")
+            .ok()
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(media::essence)
+                    .filter(|media_type| !media_type.is_empty())
+                    .collect()
+            });
+        let rename_strategy = match env::var("FILESTORAGE_RENAME_STRATEGY").as_deref() {
+            Ok("atomic") | Err(_) => RenameStrategy::Atomic,
+            Ok("fallback") => RenameStrategy::Fallback,
+            Ok(other) => {
+                return Err(format!("unknown FILESTORAGE_RENAME_STRATEGY `{other}`").into());
+            }
+        };
+        let symlink_policy = match env::var("FILESTORAGE_SYMLINK_POLICY").as_deref() {
+            Ok("reject") | Err(_) => SymlinkPolicy::Reject,
+            Ok("follow") => SymlinkPolicy::Follow,
+            Ok("return-target") => SymlinkPolicy::ReturnTarget,
+            Ok(other) => {
+                return Err(format!("unknown FILESTORAGE_SYMLINK_POLICY `{other}`").into());
+            }
+        };
+        let cache_control = match env::var("FILESTORAGE_CACHE_CONTROL") {
+            Ok(value) => Some(HeaderValue::from_str(&value)?),
+            Err(_) => None,
+        };
+        let mut prefix_quotas = BTreeMap::new();
+        if let Ok(value) = env::var("FILESTORAGE_PREFIX_QUOTAS") {
+            for entry in value.split(',').filter(|entry| !entry.trim().is_empty()) {
+                let Some((prefix, limit)) = entry.split_once('=') else {
+                    return Err(format!(
+                        "FILESTORAGE_PREFIX_QUOTAS entry `{entry}` is not `prefix=bytes`"
+                    )
+                    .into());
+                };
+                prefix_quotas.insert(prefix.trim().to_string(), limit.trim().parse()?);
+            }
+        }
+        let replica_url = match env::var("FILESTORAGE_REPLICA_URL") {
+            Ok(value) => {
+                let url = reqwest::Url::parse(&value)?;
+                if url.cannot_be_a_base() {
+                    let message = format!("FILESTORAGE_REPLICA_URL `{value}` is not an HTTP URL");
+                    return Err(message.into());
+                }
+                Some(url)
+            }
+            Err(_) => None,
+        };
+        let replica_policy = match env::var("FILESTORAGE_REPLICA_POLICY").as_deref() {
+            Ok("fail") | Err(_) => ReplicaPolicy::Fail,
+            Ok("log-and-continue") => ReplicaPolicy::LogAndContinue,
+            Ok(other) => {
+                return Err(format!("unknown FILESTORAGE_REPLICA_POLICY `{other}`").into());
+            }
+        };
+        let replica_concurrency = match env::var("FILESTORAGE_REPLICA_CONCURRENCY") {
+            Ok(value) => value.parse()?,
+            Err(_) => DEFAULT_REPLICA_CONCURRENCY,
+        };
+        let overwrite_policy = match env::var("FILESTORAGE_OVERWRITE_POLICY").as_deref() {
+            Ok("allow") | Err(_) => OverwritePolicy::Allow,
+            Ok("deny") => OverwritePolicy::Deny,
+            Ok("require-if-match") => OverwritePolicy::RequireIfMatch,
+            Ok(other) => {
+                return Err(format!("unknown FILESTORAGE_OVERWRITE_POLICY `{other}`").into());
+            }
+        };
+        let gzip = match env::var("FILESTORAGE_GZIP") {
+            Ok(value) => value.parse()?,
+            Err(_) => false,
+        };
+        let access_log = env::var_os("FILESTORAGE_ACCESS_LOG").map(PathBuf::from);
+        let access_log_max_bytes = match env::var("FILESTORAGE_ACCESS_LOG_MAX_BYTES") {
+            Ok(value) => value.parse()?,
+            Err(_) => DEFAULT_ACCESS_LOG_MAX_BYTES,
+        };
+        let mut header_limits = HeaderLimits::default();
+        if let Ok(value) = env::var("FILESTORAGE_MAX_HEADER_BYTES") {
+            header_limits.max_header_bytes = value.parse()?;
+        }
+        if let Ok(value) = env::var("FILESTORAGE_MAX_RANGES") {
+            header_limits.max_ranges = value.parse()?;
+        }
         Ok(Self {
             bind_address,
             storage_root,
+            default_content_type,
+            not_found_fallback,
+            directory_index,
+            op_timeout,
+            http,
+            allowed_content_types,
+            rename_strategy,
+            symlink_policy,
+            cache_control,
+            prefix_quotas,
+            replica_url,
+            replica_policy,
+            replica_concurrency,
+            overwrite_policy,
+            gzip,
+            access_log,
+            access_log_max_bytes,
+            header_limits,
+        })
+    }
+
+    fn storage_options(&self) -> StorageOptions {
+        StorageOptions {
+            op_timeout: self.op_timeout,
+            rename_strategy: self.rename_strategy,
+            symlink_policy: self.symlink_policy,
+            prefix_quotas: self.prefix_quotas.clone().into_iter().collect(),
+            ..StorageOptions::default()
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            bind_address: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            storage_root: PathBuf::from("data"),
+            default_content_type: HeaderValue::from_static(DEFAULT_CONTENT_TYPE),
+            not_found_fallback: None,
+            directory_index: None,
+            op_timeout: None,
+            http: HttpOptions::default(),
+            allowed_content_types: None,
+            rename_strategy: RenameStrategy::default(),
+            symlink_policy: SymlinkPolicy::default(),
+            cache_control: None,
+            prefix_quotas: BTreeMap::new(),
+            replica_url: None,
+            replica_policy: ReplicaPolicy::default(),
+            replica_concurrency: DEFAULT_REPLICA_CONCURRENCY,
+            overwrite_policy: OverwritePolicy::default(),
+            gzip: false,
+            access_log: None,
+            access_log_max_bytes: DEFAULT_ACCESS_LOG_MAX_BYTES,
+            header_limits: HeaderLimits::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use tempfile::TempDir;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn test_router() -> (TempDir, Router) {
+        test_router_with(Settings::default()).await
+    }
+
+    async fn test_router_with(settings: Settings) -> (TempDir, Router) {
+        let tmp = tempfile::tempdir().unwrap();
+        let storage = FileStorage::with_options(tmp.path(), settings.storage_options())
+            .await
+            .unwrap();
+        (tmp, build_router(AppState::new(storage, &settings)))
+    }
+
+    fn put_request(uri: &str, body: &'static [u8]) -> Request<Body> {
+        Request::builder()
+            .method(Method::PUT)
+            .uri(uri)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    fn request(method: Method, uri: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn json_body(response: Response) -> serde_json::Value {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn get_on_a_prefix_explains_it_is_not_an_object() {
+        let (_tmp, router) = test_router().await;
+        let response = router
+            .clone()
+            .oneshot(put_request("/objects/a/b", b"child"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        for uri in ["/objects/a", "/objects/a?metadata"] {
+            let response = router
+                .clone()
+                .oneshot(request(Method::GET, uri))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CONFLICT, "{uri}");
+            let body = json_body(response).await;
+            let error = body["error"].as_str().unwrap();
+            assert!(error.contains("prefix of other keys"), "{error}");
+            assert!(error.contains("`a/`"), "{error}");
+        }
+
+        let response = router
+            .oneshot(request(Method::GET, "/objects/a/missing"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn info_reports_version_and_configuration() {
+        let (tmp, router) = test_router_with(Settings {
+            directory_index: Some("index.html".to_string()),
+            op_timeout: Some(Duration::from_secs(2)),
+            rename_strategy: RenameStrategy::Fallback,
+            gzip: true,
+            header_limits: HeaderLimits {
+                max_header_bytes: 512,
+                max_ranges: 3,
+            },
+            ..Settings::default()
+        })
+        .await;
+
+        let response = router.oneshot(request(Method::GET, "/info")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(body["storage_root"], tmp.path().display().to_string());
+        assert_eq!(body["default_content_type"], DEFAULT_CONTENT_TYPE);
+        assert_eq!(body["directory_index"], "index.html");
+        assert_eq!(body["op_timeout_ms"], 2000);
+        assert_eq!(body["rename_strategy"], "fallback");
+        assert_eq!(body["symlink_policy"], "reject");
+        assert!(body["cache_control"].is_null());
+        assert_eq!(body["prefix_quotas"], serde_json::json!({}));
+        assert_eq!(body["gzip"], true);
+        assert_eq!(body["max_header_bytes"], 512);
+        assert_eq!(body["max_ranges"], 3);
+        assert!(body.get("access_log").is_none());
+    }
+
+    #[tokio::test]
+    async fn options_advertises_allowed_methods() {
+        let (_tmp, router) = test_router().await;
+
+        let response = router
+            .oneshot(request(Method::OPTIONS, "/objects/foo"))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers()[header::ALLOW],
+            "GET, HEAD, PUT, PATCH, POST, DELETE, OPTIONS"
+        );
+    }
+
+    #[tokio::test]
+    async fn untyped_objects_get_a_sniffed_content_type() {
+        let (_tmp, router) = test_router().await;
+        for (key, data, expected) in [
+            ("image", &b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"[..], "image/png"),
+            ("document", b"%PDF-1.7\n%\xe2\xe3\n", "application/pdf"),
+            ("notes", b"just some text", DEFAULT_CONTENT_TYPE),
+        ] {
+            let uri = format!("/objects/{key}");
+            let response = router
+                .clone()
+                .oneshot(put_request(&uri, data))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+
+            let response = router
+                .clone()
+                .oneshot(request(Method::GET, &uri))
+                .await
+                .unwrap();
+            assert_eq!(response.headers()[header::CONTENT_TYPE], expected, "{key}");
+        }
+    }
+
+    #[tokio::test]
+    async fn puts_are_forwarded_to_the_remote_replica() {
+        let (replica_tmp, replica_router) = test_router().await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            server::serve(listener, replica_router, &HttpOptions::default()).await
+        });
+        let (tmp, router) = test_router_with(Settings {
+            replica_url: Some(format!("http://{addr}/").parse().unwrap()),
+            ..Settings::default()
+        })
+        .await;
+
+        let upload = Request::put("/objects/docs/a%20b.txt")
+            .header(header::CONTENT_TYPE, "text/plain")
+            .header("x-meta-owner", "ops")
+            .body(Body::from("replicated"))
+            .unwrap();
+        let response = router.oneshot(upload).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        for root in [tmp.path(), replica_tmp.path()] {
+            let storage = FileStorage::new(root).await.unwrap();
+            assert_eq!(storage.get("docs/a b.txt").await.unwrap(), b"replicated");
+            let content_type = storage.content_type("docs/a b.txt").await.unwrap();
+            assert_eq!(content_type.as_deref(), Some("text/plain"));
+            let metadata = storage.user_metadata("docs/a b.txt").await.unwrap();
+            assert_eq!(metadata["owner"], "ops");
+        }
+    }
+
+    #[tokio::test]
+    async fn imports_are_announced_and_forwarded_to_the_remote_replica() {
+        let (replica_tmp, replica_router) = test_router().await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            server::serve(listener, replica_router, &HttpOptions::default()).await
+        });
+        let (_tmp, router) = test_router_with(Settings {
+            replica_url: Some(format!("http://{addr}/").parse().unwrap()),
+            ..Settings::default()
+        })
+        .await;
+        let mut archive = tar::Builder::new(Vec::new());
+        for (name, contents) in [("a.txt", &b"alpha"[..]), ("nested/b.txt", &b"beta"[..])] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            archive.append_data(&mut header, name, contents).unwrap();
+        }
+        let import = Request::builder()
+            .method(Method::POST)
+            .uri("/import.tar")
+            .body(Body::from(archive.into_inner().unwrap()))
+            .unwrap();
+        let response = router.clone().oneshot(import).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let replica = FileStorage::new(replica_tmp.path()).await.unwrap();
+        assert_eq!(replica.get("a.txt").await.unwrap(), b"alpha");
+        assert_eq!(replica.get("nested/b.txt").await.unwrap(), b"beta");
+        let events = router
+            .oneshot(request(Method::GET, "/events?since=0"))
+            .await
+            .unwrap();
+        let mut frames = events.into_body().into_data_stream();
+        let mut text = String::new();
+        while text.matches("\n\n").count() < 2 {
+            let chunk = tokio::time::timeout(Duration::from_secs(5), frames.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            text.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        let received: Vec<&str> = text.split_terminator("\n\n").collect();
+        assert_eq!(
+            received,
+            [
+                "event: put\nid: 1\ndata: {\"seq\":1,\"kind\":\"put\",\"key\":\"a.txt\"}",
+                "event: put\nid: 2\ndata: {\"seq\":2,\"kind\":\"put\",\"key\":\"nested/b.txt\"}",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn unreachable_replica_fails_puts_only_under_the_fail_policy() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        for (policy, expected) in [
+            (ReplicaPolicy::Fail, StatusCode::BAD_GATEWAY),
+            (ReplicaPolicy::LogAndContinue, StatusCode::CREATED),
+        ] {
+            let (tmp, router) = test_router_with(Settings {
+                replica_url: Some(format!("http://{addr}").parse().unwrap()),
+                replica_policy: policy,
+                ..Settings::default()
+            })
+            .await;
+            let response = router
+                .oneshot(put_request("/objects/a.txt", b"local"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected, "{policy:?}");
+            // The local write is kept either way.
+            assert_eq!(std::fs::read(tmp.path().join("a.txt")).unwrap(), b"local");
+        }
+    }
+
+    #[tokio::test]
+    async fn soft_deleted_objects_can_be_restored_until_purged() {
+        let (tmp, router) = test_router().await;
+        let status = |method: Method, uri: &'static str| {
+            let router = router.clone();
+            async move { router.oneshot(request(method, uri)).await.unwrap().status() }
+        };
+        let response = router
+            .clone()
+            .oneshot(put_request("/objects/docs/a.txt", b"keep me"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let soft_delete = "/objects/docs/a.txt?soft=true";
+        let restore = "/objects/docs/a.txt:restore";
+        assert_eq!(
+            status(Method::DELETE, soft_delete).await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            status(Method::GET, "/objects/docs/a.txt").await,
+            StatusCode::NOT_FOUND
+        );
+        let storage = FileStorage::new(tmp.path()).await.unwrap();
+        assert!(storage.list("").await.unwrap().is_empty());
+
+        assert_eq!(status(Method::POST, restore).await, StatusCode::NO_CONTENT);
+        assert_eq!(storage.get("docs/a.txt").await.unwrap(), b"keep me");
+        assert_eq!(status(Method::POST, restore).await, StatusCode::NOT_FOUND);
+
+        // Purging leaves objects deleted too recently, then empties the trash.
+        assert_eq!(
+            status(Method::DELETE, soft_delete).await,
+            StatusCode::NO_CONTENT
+        );
+        let purge_old = "/trash?older_than=3600";
+        assert_eq!(
+            status(Method::DELETE, purge_old).await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(status(Method::POST, restore).await, StatusCode::NO_CONTENT);
+        assert_eq!(
+            status(Method::DELETE, soft_delete).await,
+            StatusCode::NO_CONTENT
+        );
+        // Emptying the whole trash has to be asked for explicitly.
+        for uri in ["/trash", "/trash?all=true&older_than=0"] {
+            assert_eq!(status(Method::DELETE, uri).await, StatusCode::BAD_REQUEST);
+        }
+        assert_eq!(status(Method::POST, restore).await, StatusCode::NO_CONTENT);
+        assert_eq!(
+            status(Method::DELETE, soft_delete).await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            status(Method::DELETE, "/trash?all=true").await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(status(Method::POST, restore).await, StatusCode::NOT_FOUND);
+
+        let missing = "/objects/missing?soft=true";
+        assert_eq!(status(Method::DELETE, missing).await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn export_streams_a_tar_of_the_prefix() {
+        let (_tmp, router) = test_router().await;
+        let objects: [(&str, &'static [u8]); 3] = [
+            ("docs/a.txt", b"alpha"),
+            ("docs/nested/b.bin", &[0xAB; 2000]),
+            ("other.txt", b"skipped"),
+        ];
+        for (key, body) in objects {
+            let uri = format!("/objects/{key}");
+            let response = router
+                .clone()
+                .oneshot(put_request(&uri, body))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+        }
+
+        let response = router
+            .oneshot(request(Method::GET, "/export.tar?prefix=docs/"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/x-tar"
+        );
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"export.tar\""
+        );
+        let archive = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        let mut entries = Vec::new();
+        for entry in tar::Archive::new(&archive[..]).entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_str().unwrap().to_string();
+            let mut contents = Vec::new();
+            std::io::Read::read_to_end(&mut entry, &mut contents).unwrap();
+            entries.push((name, contents));
+        }
+        let expected: Vec<(String, Vec<u8>)> = objects[..2]
+            .iter()
+            .map(|(key, body)| (key.to_string(), body.to_vec()))
+            .collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[tokio::test]
+    async fn overwrite_policy_governs_puts_over_existing_keys() {
+        let cases = [
+            (
+                OverwritePolicy::Allow,
+                [
+                    StatusCode::CREATED,
+                    StatusCode::PRECONDITION_FAILED,
+                    StatusCode::CREATED,
+                ],
+            ),
+            (
+                OverwritePolicy::Deny,
+                [
+                    StatusCode::CONFLICT,
+                    StatusCode::CONFLICT,
+                    StatusCode::CONFLICT,
+                ],
+            ),
+            (
+                OverwritePolicy::RequireIfMatch,
+                [
+                    StatusCode::PRECONDITION_REQUIRED,
+                    StatusCode::PRECONDITION_FAILED,
+                    StatusCode::CREATED,
+                ],
+            ),
+        ];
+        for (policy, [plain, stale, current]) in cases {
+            let (_tmp, router) = test_router_with(Settings {
+                overwrite_policy: policy,
+                ..Settings::default()
+            })
+            .await;
+            let put = |if_match: Option<&str>| {
+                let mut request = put_request("/objects/a.txt", b"new");
+                if let Some(etag) = if_match {
+                    let value = HeaderValue::from_str(etag).unwrap();
+                    request.headers_mut().insert(header::IF_MATCH, value);
+                }
+                router.clone().oneshot(request)
+            };
+            assert_eq!(put(None).await.unwrap().status(), StatusCode::CREATED);
+
+            assert_eq!(put(None).await.unwrap().status(), plain, "{policy:?}");
+            let response = put(Some("\"stale\"")).await.unwrap();
+            assert_eq!(response.status(), stale, "{policy:?}");
+            let response = router
+                .clone()
+                .oneshot(request(Method::HEAD, "/objects/a.txt"))
+                .await
+                .unwrap();
+            let etag = response.headers()[header::ETAG]
+                .to_str()
+                .unwrap()
+                .to_string();
+            assert_eq!(
+                put(Some(&etag)).await.unwrap().status(),
+                current,
+                "{policy:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn delete_with_if_match_refuses_a_stale_etag() {
+        let (_tmp, router) = test_router().await;
+        let response = router
+            .clone()
+            .oneshot(put_request("/objects/a.txt", b"1"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let delete = |etag: HeaderValue| {
+            let mut request = request(Method::DELETE, "/objects/a.txt");
+            request.headers_mut().insert(header::IF_MATCH, etag);
+            router.clone().oneshot(request)
+        };
+
+        let response = delete(HeaderValue::from_static("\"stale\"")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let response = router
+            .clone()
+            .oneshot(request(Method::HEAD, "/objects/a.txt"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[header::ETAG].clone();
+        let response = delete(etag).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn events_resume_from_a_cursor_before_going_live() {
+        let (_tmp, router) = test_router().await;
+        for uri in ["/objects/a.txt", "/objects/b.txt"] {
+            let response = router
+                .clone()
+                .oneshot(put_request(uri, b"x"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+        }
+        let response = router
+            .clone()
+            .oneshot(request(Method::DELETE, "/objects/a.txt"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let events = router
+            .clone()
+            .oneshot(request(Method::GET, "/events?since=1"))
+            .await
+            .unwrap();
+        assert_eq!(events.status(), StatusCode::OK);
+        assert_eq!(events.headers()[header::CONTENT_TYPE], "text/event-stream");
+        let response = router
+            .oneshot(put_request("/objects/c.txt", b"x"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let mut frames = events.into_body().into_data_stream();
+        let mut text = String::new();
+        while text.matches("\n\n").count() < 3 {
+            let chunk = tokio::time::timeout(Duration::from_secs(5), frames.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            text.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        let received: Vec<&str> = text.split_terminator("\n\n").collect();
+        assert_eq!(
+            received,
+            [
+                "event: put\nid: 2\ndata: {\"seq\":2,\"kind\":\"put\",\"key\":\"b.txt\"}",
+                "event: delete\nid: 3\ndata: {\"seq\":3,\"kind\":\"delete\",\"key\":\"a.txt\"}",
+                "event: put\nid: 4\ndata: {\"seq\":4,\"kind\":\"put\",\"key\":\"c.txt\"}",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn sync_flushes_existing_objects() {
+        let (_tmp, router) = test_router().await;
+        let response = router
+            .clone()
+            .oneshot(put_request("/objects/dir/a.txt", b"durable"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = router
+            .clone()
+            .oneshot(request(Method::POST, "/objects/dir/a.txt:sync"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let response = router
+            .oneshot(request(Method::POST, "/objects/dir/a.txt"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn sync_of_missing_object_is_not_found() {
+        let (_tmp, router) = test_router().await;
+
+        let response = router
+            .oneshot(request(Method::POST, "/objects/missing.txt:sync"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn digest_hashes_the_stored_object() {
+        let (_tmp, router) = test_router().await;
+        let response = router
+            .clone()
+            .oneshot(put_request("/objects/dir/a.txt", b"hello"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let cases = [
+            (
+                "/objects/dir/a.txt:digest",
+                "sha256",
+                "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+            ),
+            ("/objects/dir/a.txt:digest?algo=crc32", "crc32", "3610a686"),
+        ];
+        for (uri, algorithm, hex) in cases {
+            let response = router
+                .clone()
+                .oneshot(request(Method::GET, uri))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = json_body(response).await;
+            assert_eq!(body["algorithm"], algorithm);
+            assert_eq!(body["hex"], hex);
+        }
+    }
+
+    #[tokio::test]
+    async fn digest_rejects_unknown_algorithms_and_missing_objects() {
+        let (_tmp, router) = test_router().await;
+        let response = router
+            .clone()
+            .oneshot(put_request("/objects/a.txt", b"hello"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, "/objects/a.txt:digest?algo=md4"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = router
+            .oneshot(request(Method::GET, "/objects/missing.txt:digest"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn upload_digests_are_verified_before_storing() {
+        let (_tmp, router) = test_router().await;
+        let upload = |uri: &str, name: &'static str, value: &'static str| {
+            Request::builder()
+                .method(Method::PUT)
+                .uri(uri)
+                .header(name, value)
+                .body(Body::from("hello"))
+                .unwrap()
+        };
+
+        let response = router
+            .clone()
+            .oneshot(upload("/objects/a.txt", "content-md5", "XUFAKrxLKna5cZ2REBfFkg=="))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()["content-md5"], "XUFAKrxLKna5cZ2REBfFkg==");
+
+        let sha256 = "sha-256=LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=";
+        let response = router
+            .clone()
+            .oneshot(upload("/objects/b.txt", "digest", sha256))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()["digest"], sha256);
+
+        let wrong = "sha-256=47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+        let response = router
+            .clone()
+            .oneshot(upload("/objects/c.txt", "digest", wrong))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, "/objects/c.txt"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // Bodies arriving in several chunks are hashed across all of them.
+        let chunks = ["hel", "lo"].map(|chunk| Ok::<_, std::io::Error>(Bytes::from(chunk)));
+        let streamed = Request::builder()
+            .method(Method::PUT)
+            .uri("/objects/d.txt")
+            .header("content-md5", "XUFAKrxLKna5cZ2REBfFkg==")
+            .body(Body::from_stream(stream::iter(chunks)))
+            .unwrap();
+        let response = router.clone().oneshot(streamed).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let oversized = Request::builder()
+            .method(Method::PUT)
+            .uri("/objects/e.bin")
+            .body(Body::from(vec![0; 3 * 1024 * 1024]))
+            .unwrap();
+        let response = router.oneshot(oversized).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn patch_overwrites_only_the_given_range() {
+        let (_tmp, router) = test_router().await;
+        router
+            .clone()
+            .oneshot(put_request("/objects/doc.txt", b"0123456789"))
+            .await
+            .unwrap();
+        let patch = |content_range: Option<&str>, body: &'static [u8]| {
+            let mut builder = Request::builder().method(Method::PATCH).uri("/objects/doc.txt");
+            if let Some(content_range) = content_range {
+                builder = builder.header(header::CONTENT_RANGE, content_range);
+            }
+            builder.body(Body::from(body)).unwrap()
+        };
+
+        let response = router
+            .clone()
+            .oneshot(patch(Some("bytes 3-5/*"), b"abc"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, "/objects/doc.txt"))
+            .await
+            .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&bytes[..], b"012abc6789");
+
+        for (content_range, body) in [
+            (None, &b"abc"[..]),
+            (Some("bytes=3-5"), b"abc"),
+            (Some("bytes 3-5/*"), b"abcd"),
+        ] {
+            let response = router
+                .clone()
+                .oneshot(patch(content_range, body))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{content_range:?}");
+        }
+
+        // Patches may extend the object but not start past its end.
+        let response = router
+            .clone()
+            .oneshot(patch(Some("bytes 10-11/*"), b"ab"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = router
+            .clone()
+            .oneshot(patch(Some("bytes 13-14/*"), b"cd"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */12");
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, "/objects/doc.txt"))
+            .await
+            .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&bytes[..], b"012abc6789ab");
+    }
+
+    #[tokio::test]
+    async fn unsupported_method_returns_405_with_allow() {
+        let (_tmp, router) = test_router().await;
+
+        let response = router
+            .oneshot(request(Method::POST, "/objects/foo"))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            response.headers()[header::ALLOW],
+            "GET, HEAD, PUT, PATCH, POST, DELETE, OPTIONS"
+        );
+        let body = json_body(response).await;
+        assert_eq!(body["error"], "method POST is not allowed on objects");
+    }
+
+    #[tokio::test]
+    async fn get_uses_configured_default_content_type() {
+        let (_tmp, router) = test_router_with(Settings {
+            default_content_type: HeaderValue::from_static("application/pdf"),
+            ..Settings::default()
+        })
+        .await;
+
+        let response = router
+            .clone()
+            .oneshot(put_request("/objects/report", b"%PDF-1.7"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = router
+            .oneshot(request(Method::GET, "/objects/report"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/pdf");
+    }
+
+    #[tokio::test]
+    async fn get_attaches_configured_cache_control() {
+        let (_tmp, router) = test_router_with(Settings {
+            cache_control: Some(HeaderValue::from_static("public, max-age=3600")),
+            ..Settings::default()
         })
+        .await;
+        router
+            .clone()
+            .oneshot(put_request("/objects/app.js", b"console.log(1)"))
+            .await
+            .unwrap();
+
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, "/objects/app.js"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CACHE_CONTROL],
+            "public, max-age=3600"
+        );
+        assert!(response.headers().contains_key(header::ETAG));
+
+        let response = router
+            .oneshot(request(Method::GET, "/objects/missing.js"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(!response.headers().contains_key(header::CACHE_CONTROL));
+    }
+
+    #[tokio::test]
+    async fn metadata_query_returns_object_metadata() {
+        let (_tmp, router) = test_router().await;
+        let upload = Request::builder()
+            .method(Method::PUT)
+            .uri("/objects/docs/readme")
+            .header(header::CONTENT_TYPE, "text/markdown")
+            .header("x-meta-owner", "alice")
+            .body(Body::from("# hello"))
+            .unwrap();
+        let response = router.clone().oneshot(upload).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, "/objects/docs/readme?metadata=1"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body["key"], "docs/readme");
+        assert_eq!(body["size"], 7);
+        assert_eq!(body["content_type"], "text/markdown");
+        assert!(body["etag"].as_str().unwrap().starts_with('"'));
+        assert!(body["last_modified"].as_str().unwrap().ends_with("GMT"));
+        assert_eq!(body["user_metadata"], serde_json::json!({ "owner": "alice" }));
+
+        let response = router
+            .oneshot(request(Method::GET, "/objects/missing?metadata=1"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn expiring_objects_report_expires_then_disappear() {
+        let (_tmp, router) = test_router().await;
+        let upload = Request::builder()
+            .method(Method::PUT)
+            .uri("/objects/session")
+            .header("x-expires-in", "1")
+            .body(Body::from("token"))
+            .unwrap();
+        let response = router.clone().oneshot(upload).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        for method in [Method::GET, Method::HEAD] {
+            let response = router
+                .clone()
+                .oneshot(request(method, "/objects/session"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let expires = response.headers()[header::EXPIRES].to_str().unwrap();
+            let expires = httpdate::parse_http_date(expires).unwrap();
+            assert!(expires <= SystemTime::now() + Duration::from_secs(2));
+        }
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let response = router
+            .oneshot(request(Method::GET, "/objects/session"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn huge_expiry_offsets_are_rejected() {
+        let (_tmp, router) = test_router().await;
+        for seconds in ["18446744073709551615", "300000000000"] {
+            let upload = Request::builder()
+                .method(Method::PUT)
+                .uri("/objects/session")
+                .header("x-expires-in", seconds)
+                .body(Body::from("token"))
+                .unwrap();
+            let response = router.clone().oneshot(upload).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{seconds}");
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_keys_are_served_the_not_found_fallback() {
+        let (_tmp, router) = test_router_with(Settings {
+            not_found_fallback: Some("404.html".to_string()),
+            ..Settings::default()
+        })
+        .await;
+        let upload = Request::builder()
+            .method(Method::PUT)
+            .uri("/objects/404.html")
+            .header(header::CONTENT_TYPE, "text/html")
+            .body(Body::from("<h1>gone</h1>"))
+            .unwrap();
+        router.clone().oneshot(upload).await.unwrap();
+        router
+            .clone()
+            .oneshot(put_request("/objects/index.html", b"<h1>home</h1>"))
+            .await
+            .unwrap();
+
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, "/objects/index.html"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, "/objects/missing.html"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"<h1>gone</h1>");
+    }
+
+    #[tokio::test]
+    async fn missing_fallback_returns_json_not_found() {
+        let (_tmp, router) = test_router_with(Settings {
+            not_found_fallback: Some("404.html".to_string()),
+            ..Settings::default()
+        })
+        .await;
+
+        let response = router
+            .oneshot(request(Method::GET, "/objects/missing.html"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = json_body(response).await;
+        assert_eq!(body["error"], "object `missing.html` not found");
+    }
+
+    #[tokio::test]
+    async fn trailing_slash_keys_resolve_to_directory_index() {
+        let (_tmp, router) = test_router_with(Settings {
+            directory_index: Some("index.html".to_string()),
+            ..Settings::default()
+        })
+        .await;
+        router
+            .clone()
+            .oneshot(put_request("/objects/docs/index.html", b"<h1>docs</h1>"))
+            .await
+            .unwrap();
+
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, "/objects/docs/"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"<h1>docs</h1>");
+
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, "/objects/blog/"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = json_body(response).await;
+        assert_eq!(body["error"], "object `blog/index.html` not found");
+
+        // Writes through a trailing slash land on the index object too.
+        let response = router
+            .clone()
+            .oneshot(put_request("/objects/guide/", b"<h1>guide</h1>"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, "/objects/guide/index.html"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = router
+            .oneshot(request(Method::DELETE, "/objects/guide/"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    fn range_request(uri: &str, range: &str) -> Request<Body> {
+        Request::builder()
+            .uri(uri)
+            .header(header::RANGE, range)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn single_range_returns_partial_content() {
+        let (_tmp, router) = test_router().await;
+        router
+            .clone()
+            .oneshot(put_request("/objects/digits", b"0123456789"))
+            .await
+            .unwrap();
+
+        let response = router
+            .clone()
+            .oneshot(range_request("/objects/digits", "bytes=2-4"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 2-4/10");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"234");
+
+        let response = router
+            .oneshot(range_request("/objects/digits", "bytes=50-"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */10");
+    }
+
+    #[tokio::test]
+    async fn large_objects_are_streamed_with_their_length() {
+        let (tmp, router) = test_router().await;
+        let data: Vec<u8> = (0..8 * 1024 * 1024u32).map(|i| (i % 253) as u8).collect();
+        let storage = FileStorage::new(tmp.path()).await.unwrap();
+        storage.put("large.bin", &data).await.unwrap();
+
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, "/objects/large.bin"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "8388608");
+        assert!(response.headers().contains_key(header::ETAG));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body[..] == data[..]);
+
+        let response = router
+            .oneshot(range_request("/objects/large.bin", "bytes=5000000-5999999"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "1000000");
+        assert_eq!(
+            response.headers()[header::CONTENT_RANGE],
+            "bytes 5000000-5999999/8388608"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body[..] == data[5_000_000..6_000_000]);
+    }
+
+    #[tokio::test]
+    async fn if_range_applies_the_range_only_to_an_unchanged_object() {
+        let (_tmp, router) = test_router().await;
+        router
+            .clone()
+            .oneshot(put_request("/objects/digits", b"0123456789"))
+            .await
+            .unwrap();
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, "/objects/digits"))
+            .await
+            .unwrap();
+        let etag = response.headers()[header::ETAG].clone();
+        let last_modified = response.headers()[header::LAST_MODIFIED].clone();
+
+        for validator in [etag, last_modified] {
+            let mut conditional = range_request("/objects/digits", "bytes=2-4");
+            conditional.headers_mut().insert(header::IF_RANGE, validator);
+            let response = router.clone().oneshot(conditional).await.unwrap();
+            assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        }
+
+        for stale in ["\"stale-etag\"", "Thu, 01 Jan 1970 00:00:00 GMT"] {
+            let mut conditional = range_request("/objects/digits", "bytes=2-4");
+            conditional
+                .headers_mut()
+                .insert(header::IF_RANGE, HeaderValue::from_static(stale));
+            let response = router.clone().oneshot(conditional).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(&body[..], b"0123456789");
+        }
+    }
+
+    #[tokio::test]
+    async fn gzip_responses_carry_their_own_weak_etag() {
+        use std::io::Read;
+
+        let (_tmp, router) = test_router_with(Settings {
+            gzip: true,
+            ..Settings::default()
+        })
+        .await;
+        let text = "a line of text that compresses well\n".repeat(100);
+        let upload = Request::builder()
+            .method(Method::PUT)
+            .uri("/objects/notes.txt")
+            .header(header::CONTENT_TYPE, "text/plain")
+            .body(Body::from(text.clone()))
+            .unwrap();
+        router.clone().oneshot(upload).await.unwrap();
+        let get = |accept: Option<&'static str>, if_none_match: Option<HeaderValue>| {
+            let mut get = request(Method::GET, "/objects/notes.txt");
+            if let Some(accept) = accept {
+                let accept = HeaderValue::from_static(accept);
+                get.headers_mut().insert(header::ACCEPT_ENCODING, accept);
+            }
+            if let Some(etag) = if_none_match {
+                get.headers_mut().insert(header::IF_NONE_MATCH, etag);
+            }
+            router.clone().oneshot(get)
+        };
+
+        let plain = get(None, None).await.unwrap();
+        assert_eq!(plain.status(), StatusCode::OK);
+        assert!(!plain.headers().contains_key(header::CONTENT_ENCODING));
+        assert_eq!(plain.headers()[header::VARY], "accept-encoding");
+        let strong = plain.headers()[header::ETAG].clone();
+        assert!(strong.to_str().unwrap().starts_with('"'));
+
+        let gzipped = get(Some("gzip, br"), None).await.unwrap();
+        assert_eq!(gzipped.status(), StatusCode::OK);
+        assert_eq!(gzipped.headers()[header::CONTENT_ENCODING], "gzip");
+        let weak = gzipped.headers()[header::ETAG].clone();
+        assert!(weak.to_str().unwrap().starts_with("W/\""));
+        assert_ne!(weak, strong);
+        let body = axum::body::to_bytes(gzipped.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.len() < text.len());
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, text);
+
+        // Each tag only validates the encoding it was sent with.
+        let response = get(Some("gzip"), Some(weak.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], weak);
+        let response = get(Some("gzip"), Some(strong.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = get(None, Some(strong.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        let response = get(None, Some(weak)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn multiple_ranges_return_multipart_byteranges() {
+        let (_tmp, router) = test_router().await;
+        let data: Vec<u8> = (0..=255).cycle().take(400).collect();
+        let upload = Request::builder()
+            .method(Method::PUT)
+            .uri("/objects/doc.pdf")
+            .header(header::CONTENT_TYPE, "application/pdf")
+            .body(Body::from(data.clone()))
+            .unwrap();
+        router.clone().oneshot(upload).await.unwrap();
+
+        let response = router
+            .oneshot(range_request("/objects/doc.pdf", "bytes=0-99,200-299"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        let content_type = response.headers()[header::CONTENT_TYPE].to_str().unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap()
+            .to_string();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        let delimiter = format!("\r\n--{boundary}");
+        let mut parts = Vec::new();
+        let mut rest = &body[..];
+        while let Some(at) = find(rest, delimiter.as_bytes()) {
+            rest = &rest[at + delimiter.len()..];
+            if rest.starts_with(b"--") {
+                break;
+            }
+            let headers_end = find(rest, b"\r\n\r\n").unwrap();
+            let headers = std::str::from_utf8(&rest[..headers_end]).unwrap().to_string();
+            rest = &rest[headers_end + 4..];
+            let part_end = find(rest, delimiter.as_bytes()).unwrap();
+            parts.push((headers, rest[..part_end].to_vec()));
+        }
+
+        assert_eq!(parts.len(), 2);
+        assert!(parts[0].0.contains("Content-Type: application/pdf"));
+        assert!(parts[0].0.contains("Content-Range: bytes 0-99/400"));
+        assert_eq!(parts[0].1, &data[0..100]);
+        assert!(parts[1].0.contains("Content-Range: bytes 200-299/400"));
+        assert_eq!(parts[1].1, &data[200..300]);
+    }
+
+    fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack
+            .windows(needle.len())
+            .position(|window| window == needle)
+    }
+
+    #[tokio::test]
+    async fn keys_colliding_with_directories_conflict() {
+        let (_tmp, router) = test_router().await;
+        router
+            .clone()
+            .oneshot(put_request("/objects/a/b/c", b"leaf"))
+            .await
+            .unwrap();
+
+        let response = router
+            .clone()
+            .oneshot(put_request("/objects/a/b", b"clash"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body = json_body(response).await;
+        assert_eq!(body["error"], "`a/b` collides with an existing directory of keys");
+
+        let response = router
+            .oneshot(put_request("/objects/a/b/c/d", b"under a file"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn hung_reads_time_out_with_504() {
+        let (tmp, router) = test_router_with(Settings {
+            op_timeout: Some(Duration::from_millis(200)),
+            ..Settings::default()
+        })
+        .await;
+        router
+            .clone()
+            .oneshot(put_request("/objects/fast.txt", b"quick"))
+            .await
+            .unwrap();
+        // Opening a FIFO for reading blocks until a writer shows up, like a hung mount.
+        let fifo = tmp.path().join("stuck");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, "/objects/stuck"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        // Unblock the abandoned read so the runtime can shut down.
+        drop(std::fs::OpenOptions::new().write(true).open(&fifo).unwrap());
+
+        let response = router.oneshot(request(Method::GET, "/objects/fast.txt")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn deadline_header_bounds_each_request() {
+        let (tmp, router) = test_router().await;
+        let with_deadline = |method: Method, uri: &str, millis: &str, body: &'static [u8]| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(DEADLINE_HEADER, millis)
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let response = router
+            .clone()
+            .oneshot(with_deadline(Method::PUT, "/objects/a.txt", "10000", b"hello"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = router
+            .clone()
+            .oneshot(with_deadline(Method::GET, "/objects/a.txt", "10000", b""))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Opening a FIFO for reading blocks until a writer shows up, like a hung mount.
+        let fifo = tmp.path().join("stuck");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let response = router
+            .clone()
+            .oneshot(with_deadline(Method::GET, "/objects/stuck", "50", b""))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        // Unblock the abandoned read so the runtime can shut down.
+        drop(std::fs::OpenOptions::new().write(true).open(&fifo).unwrap());
+
+        let response = router
+            .oneshot(with_deadline(Method::GET, "/objects/a.txt", "soon", b""))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn descriptor_exhaustion_asks_clients_to_retry() {
+        let emfile = std::io::Error::from_raw_os_error(if cfg!(unix) { 24 } else { 4 });
+        let err = StorageError::from(emfile);
+        assert!(matches!(err, StorageError::ResourceExhausted(_)));
+
+        let response = ApiError::from(err).into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], RETRY_AFTER_SECS);
+    }
+
+    #[tokio::test]
+    async fn invalid_keys_report_machine_readable_codes() {
+        let (_tmp, router) = test_router().await;
+        let cases = [
+            ("/objects//etc/passwd", "key_absolute"),
+            ("/objects/a/%2E%2E/b", "key_parent_traversal"),
+            ("/objects/bad%00name", "key_disallowed_char"),
+            ("/objects/.filestorage-meta.x", "key_reserved"),
+            ("/objects/foo/", "key_trailing_slash"),
+        ];
+        for (uri, code) in cases {
+            let response = router.clone().oneshot(put_request(uri, b"x")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{uri}");
+            let body = json_body(response).await;
+            assert_eq!(body["code"], code, "{uri}");
+            assert!(body["error"].as_str().is_some_and(|error| !error.is_empty()));
+        }
+
+        let long = format!("/objects/{}", "k".repeat(300));
+        let response = router.clone().oneshot(put_request(&long, b"x")).await.unwrap();
+        assert_eq!(json_body(response).await["code"], "key_too_long");
+
+        let response = router.oneshot(request(Method::GET, "/objects/missing")).await.unwrap();
+        assert!(json_body(response).await.get("code").is_none());
+    }
+
+    #[tokio::test]
+    async fn storage_errors_map_to_their_status_codes() {
+        let cases = [
+            (
+                StorageError::VersionMismatch("a.txt".to_string()),
+                StatusCode::PRECONDITION_FAILED,
+            ),
+            (StorageError::Locked("a.txt".to_string()), StatusCode::LOCKED),
+            (
+                StorageError::QuotaExceeded("tenant-a is over its 1024-byte limit".to_string()),
+                StatusCode::INSUFFICIENT_STORAGE,
+            ),
+            (
+                StorageError::TooLarge {
+                    key: "a.txt".to_string(),
+                    size: 20,
+                    max: 10,
+                },
+                StatusCode::PAYLOAD_TOO_LARGE,
+            ),
+        ];
+        for (err, status) in cases {
+            let message = err.to_string();
+            let response = ApiError::from(err).into_response();
+            assert_eq!(response.status(), status);
+            assert_eq!(json_body(response).await["error"], message);
+        }
+    }
+
+    #[tokio::test]
+    async fn content_type_allowlist_rejects_other_media_types() {
+        let (_tmp, router) = test_router_with(Settings {
+            allowed_content_types: Some(vec!["image/png".to_string(), "text/plain".to_string()]),
+            ..Settings::default()
+        })
+        .await;
+        let upload = |key: &str, content_type: &str, body: &'static [u8]| {
+            Request::builder()
+                .method(Method::PUT)
+                .uri(format!("/objects/{key}"))
+                .header(header::CONTENT_TYPE, content_type)
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let response = router
+            .clone()
+            .oneshot(upload("notes.txt", "text/plain; charset=utf-8", b"hi"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = router
+            .clone()
+            .oneshot(upload("page.html", "text/html", b"<html>"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let response = router
+            .clone()
+            .oneshot(upload("fake.png", "image/png", b"<html>"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        for key in ["page.html", "fake.png"] {
+            let response = router
+                .clone()
+                .oneshot(request(Method::GET, &format!("/objects/{key}")))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[tokio::test]
+    async fn access_log_records_each_request() {
+        let logs = tempfile::tempdir().unwrap();
+        let path = logs.path().join("access.log");
+        let (_tmp, router) = test_router_with(Settings {
+            access_log: Some(path.clone()),
+            ..Settings::default()
+        })
+        .await;
+
+        let response = router
+            .clone()
+            .oneshot(put_request("/objects/logged", b"hello"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = router
+            .oneshot(request(Method::GET, "/objects/missing"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // Lines are written by a background task, so give it a moment.
+        let text = access_log::read_when(&path, |text| text.lines().count() == 2).await;
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 2, "{text}");
+        assert!(
+            lines[0].contains("\"PUT /objects/logged\" 201 "),
+            "{}",
+            lines[0]
+        );
+        assert!(
+            lines[1].contains("\"GET /objects/missing\" 404 "),
+            "{}",
+            lines[1]
+        );
+    }
+
+    #[tokio::test]
+    async fn import_tar_stores_each_file() {
+        let (_tmp, router) = test_router().await;
+        let mut archive = tar::Builder::new(Vec::new());
+        for (name, contents) in [("a.txt", &b"alpha"[..]), ("nested/b.txt", &b"beta"[..])] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            archive.append_data(&mut header, name, contents).unwrap();
+        }
+        let archive = archive.into_inner().unwrap();
+
+        let import = Request::builder()
+            .method(Method::POST)
+            .uri("/import.tar")
+            .body(Body::from(archive.clone()))
+            .unwrap();
+        let response = router.clone().oneshot(import).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body, serde_json::json!({ "imported": 2, "skipped": 0 }));
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, "/objects/nested/b.txt"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&bytes[..], b"beta");
+
+        let import = Request::builder()
+            .method(Method::POST)
+            .uri("/import.tar")
+            .body(Body::from(archive[..600].to_vec()))
+            .unwrap();
+        let response = router.oneshot(import).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn import_tar_overwrites_only_when_the_policy_allows() {
+        let mut archive = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(3);
+        header.set_mode(0o644);
+        archive
+            .append_data(&mut header, "a.txt", &b"new"[..])
+            .unwrap();
+        let archive = archive.into_inner().unwrap();
+
+        let cases = [
+            (OverwritePolicy::Allow, StatusCode::OK, &b"new"[..]),
+            (OverwritePolicy::Deny, StatusCode::CONFLICT, &b"old"[..]),
+            (
+                OverwritePolicy::RequireIfMatch,
+                StatusCode::PRECONDITION_REQUIRED,
+                &b"old"[..],
+            ),
+        ];
+        for (policy, status, stored) in cases {
+            let (_tmp, router) = test_router_with(Settings {
+                overwrite_policy: policy,
+                ..Settings::default()
+            })
+            .await;
+            let response = router
+                .clone()
+                .oneshot(put_request("/objects/a.txt", b"old"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+
+            let import = |uri: &str| {
+                let request = Request::builder()
+                    .method(Method::POST)
+                    .uri(uri)
+                    .body(Body::from(archive.clone()))
+                    .unwrap();
+                router.clone().oneshot(request)
+            };
+            let response = import("/import.tar?overwrite=true").await.unwrap();
+            assert_eq!(response.status(), status, "{policy:?}");
+            let response = import("/import.tar").await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{policy:?}");
+            let body = json_body(response).await;
+            assert_eq!(body, serde_json::json!({ "imported": 0, "skipped": 1 }));
+
+            let response = router
+                .clone()
+                .oneshot(request(Method::GET, "/objects/a.txt"))
+                .await
+                .unwrap();
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(&bytes[..], stored, "{policy:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_range_headers_are_rejected() {
+        let (_tmp, router) = test_router_with(Settings {
+            header_limits: HeaderLimits {
+                max_header_bytes: 64,
+                max_ranges: 3,
+            },
+            ..Settings::default()
+        })
+        .await;
+        let response = router
+            .clone()
+            .oneshot(put_request("/objects/a.txt", b"0123456789"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let get = |range: String| {
+            let mut request = request(Method::GET, "/objects/a.txt");
+            let value = HeaderValue::from_str(&range).unwrap();
+            request.headers_mut().insert(header::RANGE, value);
+            router.clone().oneshot(request)
+        };
+
+        let response = get("bytes=0-0,2-2,4-4".to_string()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        let long = format!("bytes=0-{}", "9".repeat(64));
+        let response = get(long).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = get("bytes=0-0,2-2,4-4,6-6".to_string()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = json_body(response).await;
+        assert!(
+            body["error"]
+                .as_str()
+                .unwrap()
+                .contains("more than 3 ranges")
+        );
+    }
+
+    #[tokio::test]
+    async fn access_log_tags_requests_with_their_tenant() {
+        let logs = tempfile::tempdir().unwrap();
+        let path = logs.path().join("access.log");
+        let (_tmp, router) = test_router_with(Settings {
+            access_log: Some(path.clone()),
+            ..Settings::default()
+        })
+        .await;
+
+        let response = router
+            .oneshot(put_request("/objects/acme/reports/q1.csv", b"1"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let text = access_log::read_when(&path, |text| !text.is_empty()).await;
+        assert!(text.trim_end().ends_with(" tenant=acme"), "{text}");
     }
 }
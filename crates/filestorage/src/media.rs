@@ -0,0 +1,83 @@
+//! Media type checks applied to uploads, and detection for downloads without a type.
+
+/// Leading bytes of formats whose content can be checked against their declared type.
+const SIGNATURES: &[(&str, &[u8])] = &[
+    ("image/png", b"\x89PNG\r\n\x1a\n"),
+    ("image/jpeg", b"\xff\xd8\xff"),
+    ("image/gif", b"GIF8"),
+    ("application/pdf", b"%PDF-"),
+    ("application/zip", b"PK\x03\x04"),
+    ("application/gzip", b"\x1f\x8b"),
+];
+
+/// Number of leading bytes [`sniff`] is given to recognize a format; some,
+/// such as tar, carry their signature past the first block.
+pub const SNIFF_LEN: usize = 8 * 1024;
+
+/// Returns the lowercase media type of a `Content-Type` value, without parameters.
+pub fn essence(content_type: &str) -> String {
+    let media_type = content_type.split(';').next().unwrap_or_default();
+    media_type.trim().to_ascii_lowercase()
+}
+
+/// Returns whether content of `media_type`, as returned by [`essence`], is
+/// text that compression shrinks, rather than an already compressed format.
+pub fn is_compressible(media_type: &str) -> bool {
+    media_type.starts_with("text/")
+        || media_type.ends_with("+json")
+        || media_type.ends_with("+xml")
+        || matches!(
+            media_type,
+            "application/json" | "application/xml" | "application/javascript"
+        )
+}
+
+/// Returns whether `body` starts with the signature of `media_type`.
+///
+/// Types without a known signature always match.
+pub fn matches_signature(media_type: &str, body: &[u8]) -> bool {
+    SIGNATURES
+        .iter()
+        .find(|(known, _)| *known == media_type)
+        .is_none_or(|(_, signature)| body.starts_with(signature))
+}
+
+/// Returns the media type of the format `prefix`, the start of an object,
+/// is recognized as, if any.
+pub fn sniff(prefix: &[u8]) -> Option<&'static str> {
+    infer::get(prefix).map(|kind| kind.mime_type())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn essence_drops_parameters_and_case() {
+        assert_eq!(essence("Text/HTML; charset=utf-8"), "text/html");
+        assert_eq!(essence("image/png"), "image/png");
+    }
+
+    #[test]
+    fn only_text_formats_are_compressible() {
+        assert!(is_compressible("text/csv"));
+        assert!(is_compressible("application/ld+json"));
+        assert!(!is_compressible("image/png"));
+        assert!(!is_compressible("application/gzip"));
+    }
+
+    #[test]
+    fn signatures_are_checked_only_for_known_types() {
+        assert!(matches_signature("application/pdf", b"%PDF-1.7 ..."));
+        assert!(!matches_signature("application/pdf", b"<html>"));
+        assert!(matches_signature("text/plain", b"anything"));
+    }
+
+    #[test]
+    fn sniffing_recognizes_known_signatures() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n...."), Some("image/png"));
+        assert_eq!(sniff(b"\x1f\x8b\x08"), Some("application/gzip"));
+        assert_eq!(sniff(b"%PDF-1.7\n"), Some("application/pdf"));
+        assert_eq!(sniff(b"plain text"), None);
+    }
+}
@@ -0,0 +1,132 @@
+//! Parsing of HTTP `Range` and `Content-Range` request headers.
+
+/// Inclusive span of bytes within an object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Formats the `Content-Range` value for this span of an object of `size` bytes.
+    pub fn content_range(&self, size: u64) -> String {
+        format!("bytes {}-{}/{size}", self.start, self.end)
+    }
+}
+
+/// Result of evaluating a `Range` header against an object.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// At least one requested range overlaps the object.
+    Satisfiable(Vec<ByteRange>),
+    /// None of the requested ranges overlap the object.
+    Unsatisfiable,
+}
+
+/// Evaluates a `Range` header against an object of `size` bytes.
+///
+/// Returns `None` for malformed headers and units other than `bytes`, which
+/// clients expect servers to ignore in favour of a full response.
+pub fn parse(header: &str, size: u64) -> Option<RangeRequest> {
+    let specs = header.trim().strip_prefix("bytes=")?;
+    let mut ranges = Vec::new();
+    for spec in specs.split(',') {
+        let (first, last) = spec.trim().split_once('-')?;
+        if first.is_empty() {
+            let suffix: u64 = last.parse().ok()?;
+            if suffix > 0 && size > 0 {
+                ranges.push(ByteRange {
+                    start: size.saturating_sub(suffix),
+                    end: size - 1,
+                });
+            }
+            continue;
+        }
+
+        let start: u64 = first.parse().ok()?;
+        let end = match last {
+            "" => None,
+            last => Some(last.parse::<u64>().ok()?),
+        };
+        if end.is_some_and(|end| end < start) {
+            return None;
+        }
+        if start < size {
+            ranges.push(ByteRange {
+                start,
+                end: end.map_or(size - 1, |end| end.min(size - 1)),
+            });
+        }
+    }
+
+    if ranges.is_empty() {
+        Some(RangeRequest::Unsatisfiable)
+    } else {
+        Some(RangeRequest::Satisfiable(ranges))
+    }
+}
+
+/// Parses a request `Content-Range` header such as `bytes 10-19/*`.
+///
+/// The complete length after the `/` may be `*` or a number, and is not checked.
+pub fn parse_content_range(header: &str) -> Option<ByteRange> {
+    let spec = header.trim().strip_prefix("bytes ")?;
+    let (span, complete) = spec.split_once('/')?;
+    if complete != "*" && complete.parse::<u64>().is_err() {
+        return None;
+    }
+    let (first, last) = span.split_once('-')?;
+    let start = first.parse().ok()?;
+    let end = last.parse().ok()?;
+    (start <= end).then_some(ByteRange { start, end })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(start: u64, end: u64) -> ByteRange {
+        ByteRange { start, end }
+    }
+
+    #[test]
+    fn parses_bounded_open_and_suffix_ranges() {
+        assert_eq!(
+            parse("bytes=0-9, 90-, -5", 100),
+            Some(RangeRequest::Satisfiable(vec![
+                span(0, 9),
+                span(90, 99),
+                span(95, 99),
+            ]))
+        );
+        assert_eq!(
+            parse("bytes=50-500", 100),
+            Some(RangeRequest::Satisfiable(vec![span(50, 99)]))
+        );
+    }
+
+    #[test]
+    fn reports_unsatisfiable_and_ignores_malformed_ranges() {
+        assert_eq!(
+            parse("bytes=200-300", 100),
+            Some(RangeRequest::Unsatisfiable)
+        );
+        assert_eq!(parse("bytes=9-1", 100), None);
+        assert_eq!(parse("items=0-1", 100), None);
+        assert_eq!(parse("bytes=a-b", 100), None);
+    }
+
+    #[test]
+    fn parses_content_ranges_of_uploads() {
+        assert_eq!(parse_content_range("bytes 3-5/*"), Some(span(3, 5)));
+        assert_eq!(parse_content_range("bytes 0-0/100"), Some(span(0, 0)));
+        assert_eq!(parse_content_range("bytes 5-3/*"), None);
+        assert_eq!(parse_content_range("bytes 3-/*"), None);
+        assert_eq!(parse_content_range("items 3-5/*"), None);
+        assert_eq!(parse_content_range("bytes 3-5"), None);
+    }
+}
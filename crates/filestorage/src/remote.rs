@@ -0,0 +1,89 @@
+//! Forwarding of uploads to another storage node acting as a replica.
+
+use std::sync::Arc;
+
+use axum::{body::Bytes, http::HeaderMap};
+use filestorage_core::ReplicaPolicy;
+use reqwest::{Client, Url};
+use tokio::sync::Semaphore;
+
+use crate::{EXPIRES_IN_HEADER, USER_METADATA_PREFIX};
+
+/// Another node that receives a copy of every object stored through `PUT`.
+///
+/// Reads are always served locally.
+#[derive(Clone, Debug)]
+pub struct RemoteReplica {
+    client: Client,
+    /// Base URL of the node; objects are sent to `<base>/objects/<key>`.
+    base_url: Url,
+    policy: ReplicaPolicy,
+    /// Caps the uploads in flight. Once they are all taken, new uploads wait
+    /// for one to finish, so a slow replica slows writers down instead of
+    /// piling up background tasks.
+    slots: Arc<Semaphore>,
+}
+
+impl RemoteReplica {
+    /// `base_url` must be able to carry a path, as `http` and `https` URLs can.
+    pub fn new(base_url: Url, policy: ReplicaPolicy, max_in_flight: usize) -> Self {
+        Self {
+            client: Client::new(),
+            base_url,
+            policy,
+            slots: Arc::new(Semaphore::new(max_in_flight.max(1))),
+        }
+    }
+
+    /// Sends `body` to the replica under `key`, along with the content type,
+    /// expiry, and user metadata headers of the original upload.
+    ///
+    /// With [`ReplicaPolicy::Fail`] the upload finishes before this returns and
+    /// its failure is reported. With [`ReplicaPolicy::LogAndContinue`] it
+    /// runs in the background once a slot is free, and failures are only logged.
+    pub async fn forward(&self, key: &str, headers: &HeaderMap, body: Bytes) -> Result<(), String> {
+        let slot = self
+            .slots
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("replica slots are never closed");
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .expect("replica URL can carry a path")
+            .pop_if_empty()
+            .push("objects")
+            .extend(key.split('/'));
+        let forwarded = headers
+            .iter()
+            .filter(|(name, _)| {
+                *name == axum::http::header::CONTENT_TYPE
+                    || *name == EXPIRES_IN_HEADER
+                    || name.as_str().starts_with(USER_METADATA_PREFIX)
+            })
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        let upload = self.client.put(url).headers(forwarded).body(body).send();
+        let upload = async move {
+            let result = match upload.await {
+                Ok(response) if response.status().is_success() => Ok(()),
+                Ok(response) => Err(format!("replica answered {}", response.status())),
+                Err(err) => Err(format!("replica unreachable: {err}")),
+            };
+            drop(slot);
+            result
+        };
+        match self.policy {
+            ReplicaPolicy::Fail => upload.await,
+            ReplicaPolicy::LogAndContinue => {
+                let key = key.to_string();
+                tokio::spawn(async move {
+                    if let Err(err) = upload.await {
+                        eprintln!("replica upload of `{key}` failed: {err}");
+                    }
+                });
+                Ok(())
+            }
+        }
+    }
+}
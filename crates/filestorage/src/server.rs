@@ -0,0 +1,146 @@
+//! Connection handling for the HTTP listener.
+//!
+//! Each connection is served with HTTP/1.1 or, when the client opens with the
+//! HTTP/2 preface (prior knowledge, as TLS is not terminated here), HTTP/2.
+
+use std::time::Duration;
+
+use axum::Router;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use tokio::net::TcpListener;
+
+/// Pause after a failed accept before trying again.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Connection tuning applied to every accepted connection.
+#[derive(Clone, Debug)]
+pub struct HttpOptions {
+    /// Keeps HTTP/1.1 connections open between requests.
+    pub keep_alive: bool,
+    /// Limit on concurrently open streams per HTTP/2 connection.
+    pub max_concurrent_streams: Option<u32>,
+    /// Interval between HTTP/2 keep-alive pings; `None` disables them.
+    pub keep_alive_interval: Option<Duration>,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        Self {
+            keep_alive: true,
+            max_concurrent_streams: Some(200),
+            keep_alive_interval: None,
+        }
+    }
+}
+
+/// Accepts connections from `listener` forever, serving each with `router`.
+///
+/// Accept errors such as running out of file descriptors are logged and
+/// retried after a pause instead of stopping the server.
+pub async fn serve(listener: TcpListener, router: Router, options: &HttpOptions) {
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .keep_alive(options.keep_alive);
+    builder
+        .http2()
+        .timer(TokioTimer::new())
+        .max_concurrent_streams(options.max_concurrent_streams)
+        .keep_alive_interval(options.keep_alive_interval);
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                eprintln!("failed to accept connection: {err}");
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let builder = builder.clone();
+        let service = TowerToHyperService::new(router.clone());
+        tokio::spawn(async move {
+            if let Err(err) = builder
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                eprintln!("connection from {peer} failed: {err}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{self, Body},
+        http::{Request, StatusCode},
+        routing::get,
+    };
+    use hyper::client::conn::{http1, http2};
+    use tokio::{net::TcpStream, task::JoinSet};
+
+    use super::*;
+
+    async fn spawn_server() -> std::net::SocketAddr {
+        let router = Router::new().route(
+            "/echo/:n",
+            get(|path: axum::extract::Path<u32>| async move { path.0.to_string() }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { serve(listener, router, &HttpOptions::default()).await });
+        addr
+    }
+
+    #[tokio::test]
+    async fn multiplexes_requests_over_one_http2_connection() {
+        let addr = spawn_server().await;
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (sender, connection) = http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+
+        let mut requests = JoinSet::new();
+        for n in 0..16u32 {
+            let mut sender = sender.clone();
+            requests.spawn(async move {
+                let request = Request::get(format!("http://{addr}/echo/{n}"))
+                    .body(Body::empty())
+                    .unwrap();
+                let response = sender.send_request(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = body::to_bytes(Body::new(response.into_body()), usize::MAX)
+                    .await
+                    .unwrap();
+                assert_eq!(body, n.to_string());
+            });
+        }
+        while let Some(result) = requests.join_next().await {
+            result.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn still_serves_http1_clients() {
+        let addr = spawn_server().await;
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut sender, connection) = http1::handshake(TokioIo::new(stream)).await.unwrap();
+        tokio::spawn(connection);
+
+        for n in 0..3u32 {
+            let request = Request::get(format!("/echo/{n}"))
+                .header("host", addr.to_string())
+                .body(Body::empty())
+                .unwrap();
+            let response = sender.send_request(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+}